@@ -70,7 +70,7 @@ impl Extension for ClaudeCodeExtension {
                 let options = serde_json::json!({
                     "workspaceFolders": [{
                         "uri": format!("file://{}", worktree.root_path()),
-                        "name": worktree.root_path().split('/').last().unwrap_or("workspace")
+                        "name": worktree.root_path().split('/').next_back().unwrap_or("workspace")
                     }],
                     "claudeCode": {
                         "enabled": true,
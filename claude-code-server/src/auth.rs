@@ -0,0 +1,189 @@
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::mcp::{ResumableSession, SharedSession};
+use crate::tools::to_hex;
+
+/// Header Claude Code uses to present the token from the lock file
+pub const AUTH_HEADER: &str = "x-claude-code-ide-authorization";
+/// Header a reconnecting client uses to present its `reconnectToken` instead
+pub const RECONNECT_HEADER: &str = "x-claude-code-ide-reconnect-token";
+
+/// Decides whether a connecting client may use the server
+#[async_trait]
+pub trait Authenticator: Debug + Send + Sync {
+    /// Check the token presented by a client during the WebSocket handshake
+    async fn authenticate(&self, token: &str) -> bool;
+}
+
+/// Accepts exactly the token written to the lock file
+#[derive(Debug, Clone)]
+pub struct StaticTokenAuthenticator {
+    token: String,
+}
+
+impl StaticTokenAuthenticator {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for StaticTokenAuthenticator {
+    async fn authenticate(&self, token: &str) -> bool {
+        constant_time_eq(self.token.as_bytes(), token.as_bytes())
+    }
+}
+
+/// Accepts the lock file token while it is periodically replaced.
+///
+/// After a rotation the previous token stays valid for a grace window, so a client
+/// that read the lock file just before it changed can still connect.
+#[derive(Debug)]
+pub struct RotatingTokenAuthenticator {
+    tokens: RwLock<Tokens>,
+    grace: Duration,
+}
+
+#[derive(Debug)]
+struct Tokens {
+    current: String,
+    /// The replaced token and when it stops being accepted
+    previous: Option<(String, Instant)>,
+}
+
+impl RotatingTokenAuthenticator {
+    pub fn new(token: impl Into<String>, grace: Duration) -> Self {
+        Self {
+            tokens: RwLock::new(Tokens {
+                current: token.into(),
+                previous: None,
+            }),
+            grace,
+        }
+    }
+
+    /// Make `token` current, accepting the old one until the grace window ends
+    pub fn rotate(&self, token: impl Into<String>) {
+        let mut tokens = self.tokens.write().unwrap();
+        let old = std::mem::replace(&mut tokens.current, token.into());
+        tokens.previous = Some((old, Instant::now() + self.grace));
+    }
+}
+
+#[async_trait]
+impl Authenticator for RotatingTokenAuthenticator {
+    async fn authenticate(&self, token: &str) -> bool {
+        let tokens = self.tokens.read().unwrap();
+        if constant_time_eq(tokens.current.as_bytes(), token.as_bytes()) {
+            return true;
+        }
+        match &tokens.previous {
+            Some((previous, expires)) => {
+                Instant::now() < *expires && constant_time_eq(previous.as_bytes(), token.as_bytes())
+            }
+            None => false,
+        }
+    }
+}
+
+/// Issues and checks the short-lived `reconnectToken`s returned by `initialize`.
+///
+/// A token reads `<session>.<expiry>.<signature>`: a random session id, the Unix time
+/// in milliseconds it stops being accepted and an HMAC-SHA256 of both under a key generated at
+/// startup, so tokens can't be forged or carried over from an earlier server run.
+/// The session each token resumes is kept only until the token expires, and follows
+/// the connection that issued it, so resuming restores the session as it was last left.
+#[derive(Debug)]
+pub struct ReconnectTokens {
+    key: [u8; 32],
+    window: Duration,
+    sessions: Mutex<HashMap<String, (SharedSession, Instant)>>,
+}
+
+impl ReconnectTokens {
+    /// Tokens accepted for `window` after they are issued
+    pub fn new(window: Duration) -> Self {
+        Self {
+            key: rand::random(),
+            window,
+            sessions: Mutex::default(),
+        }
+    }
+
+    /// A token that resumes `session` until the window passes
+    pub fn issue(&self, session: SharedSession) -> String {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, (_, expires)| *expires > now);
+
+        let id = Uuid::new_v4().simple().to_string();
+        let expiry = unix_millis() + self.window.as_millis();
+        let payload = format!("{}.{}", id, expiry);
+        let signature = self.sign(&payload);
+        sessions.insert(id, (session, now + self.window));
+        format!("{}.{}", payload, signature)
+    }
+
+    /// The session `token` resumes, if it is genuine and has not expired
+    pub fn validate(&self, token: &str) -> Option<ResumableSession> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        if !constant_time_eq(self.sign(payload).as_bytes(), signature.as_bytes()) {
+            return None;
+        }
+        let (id, expiry) = payload.split_once('.')?;
+        if expiry.parse::<u128>().ok()? <= unix_millis() {
+            return None;
+        }
+
+        let sessions = self.sessions.lock().unwrap();
+        let (session, expires) = sessions.get(id)?;
+        (*expires > Instant::now()).then(|| session.lock().unwrap().clone())
+    }
+
+    /// Hex HMAC-SHA256 of `payload` under this server's key
+    fn sign(&self, payload: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(payload.as_bytes());
+        to_hex(&mac.finalize().into_bytes())
+    }
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis())
+}
+
+/// Compare secrets without leaking the length of the matching prefix through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rotated_tokens_expire_after_the_grace_window() {
+        let authenticator = RotatingTokenAuthenticator::new("old", Duration::from_millis(100));
+        authenticator.rotate("new");
+
+        assert!(authenticator.authenticate("new").await);
+        assert!(authenticator.authenticate("old").await);
+        assert!(!authenticator.authenticate("other").await);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(authenticator.authenticate("new").await);
+        assert!(!authenticator.authenticate("old").await);
+    }
+}
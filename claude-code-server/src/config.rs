@@ -0,0 +1,72 @@
+//! Settings read from the file passed with `--config`.
+//!
+//! The file is JSON and every field is optional. It is read at startup and again
+//! whenever a client sends `reloadConfig`, layered over the command-line flags:
+//!
+//! ```json
+//! {
+//!   "disabledTools": ["runTests"],
+//!   "allowExec": false,
+//!   "exposeEnv": ["JAVA_HOME"],
+//!   "allowPaths": ["/tmp/out"],
+//!   "formatOnEdit": true
+//! }
+//! ```
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::tools::{create_default_registry, SecurityPolicy, ToolRegistry};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Tools removed from the registry, and so from `tools/list`
+    #[serde(default)]
+    pub disabled_tools: Vec<String>,
+    /// Overrides `--no-exec` when present
+    pub allow_exec: Option<bool>,
+    /// Variables `getEnvironment` may report besides those named with `--expose-env`
+    #[serde(default)]
+    pub expose_env: Vec<String>,
+    /// Directories write tools may change besides those named with `--allow-paths`
+    #[serde(default)]
+    pub allow_paths: Vec<PathBuf>,
+    /// Run the language's formatter over files written by saveDocument and createFile
+    #[serde(default)]
+    pub format_on_edit: bool,
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config {}: {}", path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| anyhow!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// `base`, the policy given on the command line, with this file's settings applied
+    pub fn security(&self, base: &SecurityPolicy) -> SecurityPolicy {
+        let mut exposed_env_vars = base.exposed_env_vars.clone();
+        exposed_env_vars.extend(self.expose_env.iter().cloned());
+        let mut allowed_paths = base.allowed_paths.clone();
+        allowed_paths.extend(self.allow_paths.iter().cloned());
+        SecurityPolicy {
+            allow_exec: self.allow_exec.unwrap_or(base.allow_exec),
+            exposed_env_vars,
+            allowed_paths,
+        }
+    }
+
+    /// The built-in tools less those this file disables
+    pub fn registry(&self) -> Result<ToolRegistry> {
+        let mut registry = create_default_registry()?;
+        for name in &self.disabled_tools {
+            if registry.unregister_tool(name).is_none() {
+                return Err(anyhow!("Cannot disable unknown tool {}", name));
+            }
+        }
+        Ok(registry)
+    }
+}
@@ -0,0 +1,200 @@
+use lsp_types::{Diagnostic, DiagnosticSeverity, Range, Url};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Diagnostics the editor has reported, grouped by document
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsStore {
+    diagnostics: Arc<RwLock<HashMap<Url, Vec<Diagnostic>>>>,
+    /// Incremented on every update, so readers can tell their copy is stale
+    generation: Arc<AtomicU64>,
+}
+
+/// Error and warning counts for one document
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileErrorCount {
+    pub uri: Url,
+    pub error_count: usize,
+}
+
+/// Totals across every document in a `DiagnosticsStore`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsSummary {
+    pub total_errors: usize,
+    pub total_warnings: usize,
+    pub files_with_errors: usize,
+    /// Documents with errors, most errors first
+    pub top_files: Vec<FileErrorCount>,
+}
+
+impl DiagnosticsStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the diagnostics of `uri` that overlap `range` with `diagnostics`.
+    ///
+    /// Editors report diagnostics alongside requests for a range, such as code
+    /// actions, so this is how the store learns about them piecemeal.
+    pub fn update_range(&self, uri: &Url, range: Range, diagnostics: Vec<Diagnostic>) {
+        let mut store = self.diagnostics.write().unwrap();
+        let entry = store.entry(uri.clone()).or_default();
+        entry.retain(|diagnostic| !overlaps(diagnostic.range, range));
+        entry.extend(diagnostics);
+        if entry.is_empty() {
+            store.remove(uri);
+        }
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Changes whenever the stored diagnostics do
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    pub fn get(&self, uri: &Url) -> Vec<Diagnostic> {
+        self.diagnostics
+            .read()
+            .unwrap()
+            .get(uri)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Diagnostics of `uri` that overlap `range`
+    pub fn in_range(&self, uri: &Url, range: Range) -> Vec<Diagnostic> {
+        let mut diagnostics = self.get(uri);
+        diagnostics.retain(|diagnostic| overlaps(diagnostic.range, range));
+        diagnostics
+    }
+
+    /// Every document with diagnostics, ordered by URI
+    pub fn all(&self) -> Vec<(Url, Vec<Diagnostic>)> {
+        let mut all: Vec<_> = self
+            .diagnostics
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(uri, diagnostics)| (uri.clone(), diagnostics.clone()))
+            .collect();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        all
+    }
+
+    /// Count errors and warnings, listing at most `top` of the documents with errors
+    pub fn summary(&self, top: usize) -> DiagnosticsSummary {
+        let store = self.diagnostics.read().unwrap();
+        let mut total_warnings = 0;
+        let mut files = Vec::new();
+        for (uri, diagnostics) in store.iter() {
+            let error_count = diagnostics.iter().filter(|d| is_error(d)).count();
+            total_warnings += diagnostics
+                .iter()
+                .filter(|d| d.severity == Some(DiagnosticSeverity::WARNING))
+                .count();
+            if error_count > 0 {
+                files.push(FileErrorCount {
+                    uri: uri.clone(),
+                    error_count,
+                });
+            }
+        }
+        files.sort_by(|a, b| b.error_count.cmp(&a.error_count).then(a.uri.cmp(&b.uri)));
+
+        DiagnosticsSummary {
+            total_errors: files.iter().map(|file| file.error_count).sum(),
+            total_warnings,
+            files_with_errors: files.len(),
+            top_files: files.into_iter().take(top).collect(),
+        }
+    }
+}
+
+/// Clients treat a diagnostic without a severity as an error
+fn is_error(diagnostic: &Diagnostic) -> bool {
+    matches!(diagnostic.severity, None | Some(DiagnosticSeverity::ERROR))
+}
+
+/// Lowercase name of a diagnostic's severity, such as `error` or `warning`
+pub fn severity_name(diagnostic: &Diagnostic) -> &'static str {
+    match diagnostic.severity {
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "information",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ if is_error(diagnostic) => "error",
+        _ => "unknown",
+    }
+}
+
+fn overlaps(a: Range, b: Range) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::Position;
+
+    fn diagnostic(line: u32, severity: Option<DiagnosticSeverity>) -> Diagnostic {
+        Diagnostic {
+            range: Range::new(Position::new(line, 0), Position::new(line, 1)),
+            severity,
+            message: format!("problem on line {}", line),
+            ..Diagnostic::default()
+        }
+    }
+
+    #[test]
+    fn summaries_count_across_files_and_rank_by_errors() {
+        let store = DiagnosticsStore::new();
+        let whole_file = Range::new(Position::new(0, 0), Position::new(100, 0));
+        let uri = |name: &str| Url::parse(&format!("file:///project/{}", name)).unwrap();
+        let (error, warning) = (
+            Some(DiagnosticSeverity::ERROR),
+            Some(DiagnosticSeverity::WARNING),
+        );
+        store.update_range(
+            &uri("few.rs"),
+            whole_file,
+            vec![
+                diagnostic(0, error),
+                diagnostic(1, warning),
+                diagnostic(2, warning),
+            ],
+        );
+        // A diagnostic without a severity counts as an error
+        store.update_range(
+            &uri("many.rs"),
+            whole_file,
+            vec![
+                diagnostic(0, error),
+                diagnostic(1, error),
+                diagnostic(2, None),
+            ],
+        );
+        store.update_range(&uri("clean.rs"), whole_file, vec![diagnostic(0, warning)]);
+
+        let summary = store.summary(10);
+        assert_eq!(summary.total_errors, 4);
+        assert_eq!(summary.total_warnings, 3);
+        assert_eq!(summary.files_with_errors, 2);
+        let ranking: Vec<(&str, usize)> = summary
+            .top_files
+            .iter()
+            .map(|file| (file.uri.path(), file.error_count))
+            .collect();
+        assert_eq!(ranking, [("/project/many.rs", 3), ("/project/few.rs", 1)]);
+
+        let top = store.summary(1);
+        assert_eq!(top.files_with_errors, 2);
+        assert_eq!(top.top_files.len(), 1);
+        assert_eq!(top.top_files[0].uri, uri("many.rs"));
+        let json = serde_json::to_value(&top).unwrap();
+        assert_eq!(json["totalErrors"], 4);
+        assert_eq!(json["topFiles"][0]["errorCount"], 3);
+    }
+}
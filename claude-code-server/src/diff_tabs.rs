@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A diff opened with `openDiff`
+#[derive(Debug, Clone)]
+pub struct DiffTab {
+    pub old_file_path: String,
+    pub new_file_path: String,
+}
+
+/// Diff tabs opened with `openDiff`, keyed by the `tab_name` they were given
+#[derive(Debug, Clone, Default)]
+pub struct DiffTabStore {
+    tabs: Arc<Mutex<HashMap<String, DiffTab>>>,
+}
+
+impl DiffTabStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track `tab` as `name`, replacing a tab opened earlier under the same name
+    pub fn open(&self, name: impl Into<String>, tab: DiffTab) {
+        self.tabs.lock().unwrap().insert(name.into(), tab);
+    }
+
+    /// Stop tracking the tab named `name`, returning it if there was one
+    pub fn close(&self, name: &str) -> Option<DiffTab> {
+        self.tabs.lock().unwrap().remove(name)
+    }
+
+    /// Stop tracking every tab, returning how many there were
+    pub fn close_all(&self) -> usize {
+        let mut tabs = self.tabs.lock().unwrap();
+        let count = tabs.len();
+        tabs.clear();
+        count
+    }
+}
@@ -0,0 +1,198 @@
+use lsp_types::{Position, TextDocumentContentChangeEvent, Url};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+/// Chars between the checkpoints a `Utf16Index` records
+const UTF16_CHECKPOINT_STRIDE: usize = 1024;
+
+/// In-memory copy of a document opened in the editor
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub text: String,
+    pub version: i32,
+    pub language_id: String,
+    /// Set by edits and cleared when the editor saves or reopens the document. An
+    /// undone edit leaves it set, so a set bit means "maybe unsaved".
+    pub dirty: bool,
+}
+
+/// Buffers currently open in the editor, kept in sync via LSP text document notifications
+#[derive(Debug, Clone, Default)]
+pub struct DocumentStore {
+    documents: Arc<RwLock<HashMap<Url, Document>>>,
+}
+
+impl DocumentStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(&self, uri: Url, language_id: String, version: i32, text: String) {
+        let document = Document {
+            text,
+            version,
+            language_id,
+            dirty: false,
+        };
+        self.documents.write().unwrap().insert(uri, document);
+    }
+
+    pub fn apply_changes(
+        &self,
+        uri: &Url,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) {
+        let mut documents = self.documents.write().unwrap();
+        let Some(document) = documents.get_mut(uri) else {
+            warn!("Received changes for untracked document: {}", uri);
+            return;
+        };
+
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = offset_at(&document.text, range.start);
+                    let end = offset_at(&document.text, range.end);
+                    match (start, end) {
+                        (Some(start), Some(end)) if start <= end => {
+                            document.text.replace_range(start..end, &change.text);
+                        }
+                        _ => warn!("Ignoring change with invalid range {:?} in {}", range, uri),
+                    }
+                }
+                None => document.text = change.text,
+            }
+        }
+        document.version = version;
+        document.dirty = true;
+    }
+
+    /// Record that the editor wrote the document to disk
+    pub fn mark_saved(&self, uri: &Url) {
+        if let Some(document) = self.documents.write().unwrap().get_mut(uri) {
+            document.dirty = false;
+        }
+    }
+
+    /// Move a tracked document to a new URI, returning whether it was tracked
+    pub fn rename(&self, old_uri: &Url, new_uri: Url) -> bool {
+        let mut documents = self.documents.write().unwrap();
+        match documents.remove(old_uri) {
+            Some(document) => {
+                documents.insert(new_uri, document);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn close(&self, uri: &Url) {
+        self.documents.write().unwrap().remove(uri);
+    }
+
+    pub fn get(&self, uri: &Url) -> Option<Document> {
+        self.documents.read().unwrap().get(uri).cloned()
+    }
+
+    pub fn contains(&self, uri: &Url) -> bool {
+        self.documents.read().unwrap().contains_key(uri)
+    }
+
+    /// Every tracked document with its URI
+    pub fn all(&self) -> Vec<(Url, Document)> {
+        self.documents
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(uri, document)| (uri.clone(), document.clone()))
+            .collect()
+    }
+}
+
+/// Convert an LSP position into a byte offset within `text`
+pub fn offset_at(text: &str, position: Position) -> Option<usize> {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        line_start += text[line_start..].find('\n')? + 1;
+    }
+
+    let line_end = text[line_start..]
+        .find('\n')
+        .map_or(text.len(), |i| line_start + i);
+    let line = &text[line_start..line_end];
+
+    char_pos_to_byte_pos(line, position.character as usize).map(|byte_pos| line_start + byte_pos)
+}
+
+// Convert LSP UTF-16 code unit position to Rust UTF-8 byte position
+// LSP uses UTF-16 code units for character positions per the specification
+pub fn char_pos_to_byte_pos(line: &str, utf16_pos: usize) -> Option<usize> {
+    let mut current_utf16_pos = 0;
+
+    for (byte_pos, ch) in line.char_indices() {
+        if current_utf16_pos == utf16_pos {
+            return Some(byte_pos);
+        }
+
+        let char_utf16_len = ch.len_utf16();
+
+        // If utf16_pos falls within this character's UTF-16 span, return this char's byte position
+        if utf16_pos < current_utf16_pos + char_utf16_len {
+            return Some(byte_pos);
+        }
+
+        current_utf16_pos += char_utf16_len;
+    }
+
+    // If utf16_pos is at the end of the string
+    if current_utf16_pos == utf16_pos {
+        return Some(line.len());
+    }
+
+    None
+}
+
+/// Resolves several UTF-16 positions in one line without rescanning it from the start.
+///
+/// `char_pos_to_byte_pos` walks the line up to the position, which adds up on
+/// minified files that put megabytes on a single line. ASCII lines map positions
+/// to bytes directly; other lines record a checkpoint every
+/// `UTF16_CHECKPOINT_STRIDE` chars so a lookup only scans from the nearest one.
+pub struct Utf16Index<'a> {
+    line: &'a str,
+    /// `(utf16_pos, byte_pos)` at char boundaries, ascending; empty for ASCII lines
+    checkpoints: Vec<(usize, usize)>,
+}
+
+impl<'a> Utf16Index<'a> {
+    pub fn new(line: &'a str) -> Self {
+        let mut checkpoints = Vec::new();
+        if !line.is_ascii() {
+            let mut utf16_pos = 0;
+            for (i, (byte_pos, ch)) in line.char_indices().enumerate() {
+                if i % UTF16_CHECKPOINT_STRIDE == 0 {
+                    checkpoints.push((utf16_pos, byte_pos));
+                }
+                utf16_pos += ch.len_utf16();
+            }
+        }
+        Self { line, checkpoints }
+    }
+
+    /// Same result as `char_pos_to_byte_pos` on the indexed line
+    pub fn byte_pos(&self, utf16_pos: usize) -> Option<usize> {
+        if self.checkpoints.is_empty() {
+            return (utf16_pos <= self.line.len()).then_some(utf16_pos);
+        }
+
+        let nearest = self
+            .checkpoints
+            .partition_point(|&(checkpoint, _)| checkpoint <= utf16_pos)
+            - 1;
+        let (checkpoint_utf16, checkpoint_byte) = self.checkpoints[nearest];
+        char_pos_to_byte_pos(&self.line[checkpoint_byte..], utf16_pos - checkpoint_utf16)
+            .map(|byte_pos| checkpoint_byte + byte_pos)
+    }
+}
@@ -0,0 +1,153 @@
+use lsp_types::notification::Notification;
+use lsp_types::request::Request;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "lsp")]
+use std::sync::{Arc, RwLock};
+#[cfg(feature = "lsp")]
+use std::time::Duration;
+#[cfg(feature = "lsp")]
+use tower_lsp::Client;
+
+/// How long to wait for the editor to answer a request
+#[cfg(feature = "lsp")]
+const EDITOR_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Text placed on or read from the editor's clipboard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardContent {
+    pub text: String,
+}
+
+/// Asks the editor to write text to the system clipboard
+pub enum ClipboardWrite {}
+
+impl Notification for ClipboardWrite {
+    type Params = ClipboardContent;
+    const METHOD: &'static str = "clipboard_write";
+}
+
+/// Asks the editor for the current clipboard contents
+pub enum ClipboardRead {}
+
+impl Request for ClipboardRead {
+    type Params = ();
+    type Result = ClipboardContent;
+    const METHOD: &'static str = "clipboard_read";
+}
+
+/// Where and how the editor should open a file for `openFile`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenFileParams {
+    pub file_path: String,
+    /// Open in a preview tab that the next preview replaces
+    pub preview: bool,
+    /// Focus the file; when false it opens without stealing focus
+    pub make_frontmost: bool,
+}
+
+/// Asks the editor to open a file
+pub enum OpenFile {}
+
+impl Notification for OpenFile {
+    type Params = OpenFileParams;
+    const METHOD: &'static str = "open_file";
+}
+
+/// Why an action could not be carried out by the editor
+#[derive(Debug)]
+pub enum EditorError {
+    /// No editor is attached to the bridge
+    NotConnected,
+    /// The editor did not answer within `EDITOR_REQUEST_TIMEOUT`
+    Timeout,
+    /// The editor answered with an error
+    Failed(String),
+}
+
+/// Handle to the editor connected over LSP, for actions only Zed itself can perform.
+///
+/// The LSP server attaches its client when it starts, so tools running on the
+/// WebSocket side can reach the editor in hybrid mode.
+#[derive(Debug, Clone, Default)]
+pub struct EditorBridge {
+    #[cfg(feature = "lsp")]
+    client: Arc<RwLock<Option<Client>>>,
+}
+
+impl EditorBridge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "lsp")]
+impl EditorBridge {
+    pub fn attach(&self, client: Client) {
+        *self.client.write().unwrap() = Some(client);
+    }
+
+    pub fn detach(&self) {
+        *self.client.write().unwrap() = None;
+    }
+
+    /// Whether an editor is attached, as it is in hybrid mode once Zed has started the server
+    pub fn is_connected(&self) -> bool {
+        self.client.read().unwrap().is_some()
+    }
+
+    /// The connected editor's LSP client, if any
+    fn client(&self) -> Result<Client, EditorError> {
+        self.client
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or(EditorError::NotConnected)
+    }
+
+    /// Ask the editor to put `text` on the clipboard
+    pub async fn write_clipboard(&self, text: String) -> Result<(), EditorError> {
+        self.client()?
+            .send_notification::<ClipboardWrite>(ClipboardContent { text })
+            .await;
+        Ok(())
+    }
+
+    /// Ask the editor to open a file
+    pub async fn open_file(&self, params: OpenFileParams) -> Result<(), EditorError> {
+        self.client()?.send_notification::<OpenFile>(params).await;
+        Ok(())
+    }
+
+    /// Ask the editor for the clipboard contents
+    pub async fn read_clipboard(&self) -> Result<String, EditorError> {
+        let client = self.client()?;
+        let content = tokio::time::timeout(
+            EDITOR_REQUEST_TIMEOUT,
+            client.send_request::<ClipboardRead>(()),
+        )
+        .await
+        .map_err(|_| EditorError::Timeout)?
+        .map_err(|e| EditorError::Failed(e.message.to_string()))?;
+        Ok(content.text)
+    }
+}
+
+#[cfg(not(feature = "lsp"))]
+impl EditorBridge {
+    pub fn is_connected(&self) -> bool {
+        false
+    }
+
+    pub async fn write_clipboard(&self, _text: String) -> Result<(), EditorError> {
+        Err(EditorError::NotConnected)
+    }
+
+    pub async fn open_file(&self, _params: OpenFileParams) -> Result<(), EditorError> {
+        Err(EditorError::NotConnected)
+    }
+
+    pub async fn read_clipboard(&self) -> Result<String, EditorError> {
+        Err(EditorError::NotConnected)
+    }
+}
@@ -0,0 +1,113 @@
+//! Failures that callers may need to tell apart, such as to retry a timeout but not
+//! a policy refusal.
+//!
+//! Each kind maps to one JSON-RPC error code, and converting a `ServerError` into a
+//! `ToolError` names the kind in the error's `reason`. Handlers raise these kinds and
+//! let `?` turn them into the `ToolError` sent on the wire; failures that carry
+//! tool-specific data, such as a conflicting hash, still build a `ToolError` directly.
+
+use serde_json::json;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::tools::{
+    ToolError, TOOL_ERROR_FORBIDDEN, TOOL_ERROR_INTERNAL, TOOL_ERROR_INVALID_PARAMS,
+};
+
+/// JSON-RPC error code for requests that fail to parse
+pub const ERROR_PARSE: i32 = -32700;
+/// JSON-RPC error code for requests without valid credentials
+pub const ERROR_UNAUTHORIZED: i32 = -32001;
+/// JSON-RPC error code for operations that ran out of time
+pub const ERROR_TIMEOUT: i32 = -32048;
+/// JSON-RPC error code for requests refused until the client releases what it holds
+pub const ERROR_RATE_LIMITED: i32 = -32049;
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A value the server failed to (de)serialize while handling a request. Requests
+    /// that do not parse are answered with `ERROR_PARSE` by `mcp::request_parse_error`.
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    InvalidParams(String),
+    #[error("{0}")]
+    Auth(String),
+    #[error("{0}")]
+    RateLimited(String),
+    #[error("{operation} did not finish within {limit:?}")]
+    Timeout { operation: String, limit: Duration },
+    #[error("{0}")]
+    PolicyDenied(String),
+    #[error("{} is outside the workspace", .0.display())]
+    PathOutsideWorkspace(PathBuf),
+}
+
+impl ServerError {
+    /// Wrap an I/O error, described by `context` such as "Failed to read a.txt"
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let context = context.into();
+        move |source| Self::Io { context, source }
+    }
+
+    /// The JSON-RPC error code reported for this kind of failure
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Io { .. } | Self::Json(_) => TOOL_ERROR_INTERNAL,
+            Self::InvalidParams(_) => TOOL_ERROR_INVALID_PARAMS,
+            Self::Auth(_) => ERROR_UNAUTHORIZED,
+            Self::RateLimited(_) => ERROR_RATE_LIMITED,
+            Self::Timeout { .. } => ERROR_TIMEOUT,
+            Self::PolicyDenied(_) | Self::PathOutsideWorkspace(_) => TOOL_ERROR_FORBIDDEN,
+        }
+    }
+
+    /// Short name of this kind of failure, reported as the error's `reason`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Io { .. } => "io",
+            Self::Json(_) => "json",
+            Self::InvalidParams(_) => "invalid_params",
+            Self::Auth(_) => "unauthorized",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Timeout { .. } => "timeout",
+            Self::PolicyDenied(_) => "policy_denied",
+            Self::PathOutsideWorkspace(_) => "path_outside_workspace",
+        }
+    }
+}
+
+impl From<ServerError> for ToolError {
+    fn from(error: ServerError) -> Self {
+        let reason = error.reason();
+        let retryable = matches!(
+            error,
+            ServerError::Timeout { .. } | ServerError::RateLimited(_)
+        );
+        ToolError::new(error.code(), error.to_string())
+            .with_data(json!({ "reason": reason, "retryable": retryable }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialization_failures_are_internal_errors() {
+        let failure = serde_json::from_str::<u32>("\"not a number\"").unwrap_err();
+        let error = ToolError::from(ServerError::from(failure));
+
+        assert_eq!(error.code, TOOL_ERROR_INTERNAL);
+        assert_ne!(error.code, ERROR_PARSE);
+        assert_eq!(error.data.unwrap()["reason"], "json");
+    }
+}
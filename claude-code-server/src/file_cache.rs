@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::tools::FileContent;
+
+/// Files kept by a `FileCache` before the least recently read is evicted
+const MAX_ENTRIES: usize = 64;
+/// Combined size of the files kept by a `FileCache`; larger files are never cached
+const MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// A file as `getFileContents` returns it
+#[derive(Debug)]
+pub struct CachedFile {
+    pub content: FileContent,
+    pub hash: String,
+}
+
+/// Recently read files, reused while their modification time and size are unchanged
+#[derive(Debug, Clone, Default)]
+pub struct FileCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<PathBuf, Entry>,
+    bytes: u64,
+    /// Incremented on every hit or insert to order entries by recency
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    modified: SystemTime,
+    len: u64,
+    file: Arc<CachedFile>,
+    last_used: u64,
+}
+
+impl FileCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cached contents of `path` if `metadata` shows it unchanged since it was cached
+    pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<Arc<CachedFile>> {
+        let modified = metadata.modified().ok()?;
+        let mut state = self.state.lock().unwrap();
+        let entry = state.entries.get(path)?;
+        if entry.modified != modified || entry.len != metadata.len() {
+            state.remove(path);
+            return None;
+        }
+
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(path)?;
+        entry.last_used = clock;
+        Some(entry.file.clone())
+    }
+
+    /// Remember `file` as the contents of `path` when it had `metadata`
+    pub fn insert(&self, path: PathBuf, metadata: &Metadata, file: Arc<CachedFile>) {
+        let Ok(modified) = metadata.modified() else {
+            return;
+        };
+        let len = metadata.len();
+        if len > MAX_BYTES {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.remove(&path);
+        state.clock += 1;
+        let entry = Entry {
+            modified,
+            len,
+            file,
+            last_used: state.clock,
+        };
+        state.entries.insert(path, entry);
+        state.bytes += len;
+
+        while state.entries.len() > MAX_ENTRIES || state.bytes > MAX_BYTES {
+            let Some(oldest) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            state.remove(&oldest);
+        }
+    }
+
+    /// Forget `path`, for writes that may not change its modification time or size
+    pub fn invalidate(&self, path: &Path) {
+        self.state.lock().unwrap().remove(path);
+    }
+
+    /// Forget every file, for when changes may have gone unreported
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.bytes = 0;
+    }
+}
+
+impl CacheState {
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.bytes -= entry.len;
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// File changes held for Claude clients before the oldest are dropped
+pub const FILE_CHANGE_CAPACITY: usize = 64;
+
+/// A change reported by the editor's file watcher
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    /// `created`, `changed` or `deleted`
+    pub change_type: &'static str,
+}
+
+/// Bounded queue of file changes waiting to be sent to Claude clients.
+///
+/// A burst of changes, such as a build touching thousands of files, keeps only the
+/// latest change to each path and drops the oldest once full, counting what was
+/// dropped so clients can be told to resync.
+#[derive(Debug)]
+pub struct FileChangeQueue {
+    state: Mutex<QueueState>,
+    ready: Notify,
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    changes: VecDeque<FileChange>,
+    dropped: usize,
+}
+
+impl FileChangeQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::default(),
+            ready: Notify::new(),
+            capacity,
+        }
+    }
+
+    /// Queue `changes` together, so a reader never sees part of them
+    pub fn push_all(&self, changes: impl IntoIterator<Item = FileChange>) {
+        let mut state = self.state.lock().unwrap();
+        for change in changes {
+            if let Some(index) = state.changes.iter().position(|c| c.path == change.path) {
+                state.changes.remove(index);
+            } else if state.changes.len() >= self.capacity {
+                state.changes.pop_front();
+                state.dropped += 1;
+            }
+            state.changes.push_back(change);
+        }
+        drop(state);
+        self.ready.notify_one();
+    }
+
+    /// Wait for queued changes, returning them with how many were dropped since the
+    /// last call
+    pub async fn next_batch(&self) -> (Vec<FileChange>, usize) {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if !state.changes.is_empty() || state.dropped > 0 {
+                    let changes = state.changes.drain(..).collect();
+                    return (changes, std::mem::take(&mut state.dropped));
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+}
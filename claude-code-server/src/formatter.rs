@@ -0,0 +1,106 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::debug;
+
+/// Resolve the stdin/stdout formatter command for a language, if one is known
+fn formatter_command(language_id: &str, file_path: &Path) -> Option<(&'static str, Vec<String>)> {
+    match language_id {
+        "rust" => Some(("rustfmt", vec!["--edition".to_string(), "2021".to_string()])),
+        "python" => Some(("black", vec!["--quiet".to_string(), "-".to_string()])),
+        "elixir" => Some(("mix", vec!["format".to_string(), "-".to_string()])),
+        "javascript" | "typescript" | "tsx" | "markdown" => Some((
+            "prettier",
+            vec![
+                "--stdin-filepath".to_string(),
+                file_path.to_string_lossy().to_string(),
+            ],
+        )),
+        _ => None,
+    }
+}
+
+/// Whether a formatter is known for `language_id`
+pub fn has_formatter(language_id: &str) -> bool {
+    formatter_command(language_id, Path::new("")).is_some()
+}
+
+/// Run the language formatter over `text`, returning the formatted output
+pub async fn format_text(
+    language_id: &str,
+    file_path: &Path,
+    text: &str,
+    cwd: Option<&Path>,
+) -> Result<String> {
+    let (program, args) = formatter_command(language_id, file_path)
+        .ok_or_else(|| anyhow!("No formatter configured for language: {}", language_id))?;
+
+    debug!("Running formatter: {} {:?}", program, args);
+
+    let mut command = Command::new(program);
+    command
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).await?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Format a block of whole lines that may be indented inside a larger file.
+///
+/// The common indentation is stripped before formatting and restored afterwards
+/// so that nested items (e.g. methods inside an impl) format like top-level code.
+pub async fn format_fragment(
+    language_id: &str,
+    file_path: &Path,
+    fragment: &str,
+    cwd: Option<&Path>,
+) -> Result<String> {
+    let indent = fragment
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .min_by_key(|indent| indent.len())
+        .unwrap_or("");
+
+    let dedented = fragment
+        .lines()
+        .map(|line| line.strip_prefix(indent).unwrap_or(line.trim_start()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let formatted = format_text(language_id, file_path, &dedented, cwd).await?;
+
+    Ok(formatted
+        .trim_end_matches('\n')
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
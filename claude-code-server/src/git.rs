@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tracing::debug;
+
+/// Separates fields within a formatted `git log` record
+const FIELD_SEPARATOR: char = '\x1f';
+/// Terminates each formatted `git log` record
+const RECORD_SEPARATOR: char = '\x1e';
+
+/// A commit as reported by `git log`
+#[derive(Debug, Serialize)]
+pub struct Commit {
+    pub commit: String,
+    pub author: String,
+    pub date: String,
+    pub message: String,
+}
+
+/// Lines added and removed in one file, as reported by `git diff --numstat`
+#[derive(Debug, Serialize)]
+pub struct FileChange {
+    pub path: String,
+    /// `None` for binary files
+    pub additions: Option<u64>,
+    pub deletions: Option<u64>,
+}
+
+/// Run git with `cwd` as the working directory
+pub fn run_git(cwd: &Path, args: &[&str]) -> Result<Output> {
+    debug!("Running git {:?} in {}", args, cwd.display());
+    Command::new("git")
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .map_err(|e| anyhow!("Failed to run git: {}", e))
+}
+
+/// Root of the repository containing `dir`, or `None` outside a git repository
+pub fn repository_root(dir: &Path) -> Result<Option<PathBuf>> {
+    let output = run_git(dir, &["rev-parse", "--show-toplevel"])?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(Some(PathBuf::from(root)))
+}
+
+/// Whether `dir` or one of its ancestors has a `.git`, without running git
+pub fn has_repository(dir: &Path) -> bool {
+    dir.ancestors().any(|dir| dir.join(".git").exists())
+}
+
+/// Whether git tracks `file` in the repository rooted at `root`
+pub fn is_tracked(root: &Path, file: &Path) -> Result<bool> {
+    let file = file.to_string_lossy();
+    let output = run_git(root, &["ls-files", "--error-unmatch", "--", &file])?;
+    Ok(output.status.success())
+}
+
+/// The most recent commits touching `file`, newest first, following renames
+pub fn file_history(root: &Path, file: &Path, limit: usize) -> Result<Vec<Commit>> {
+    let file = file.to_string_lossy();
+    let limit = format!("--max-count={}", limit);
+    let format = format!(
+        "--format=%H{sep}%an{sep}%aI{sep}%B{end}",
+        sep = "%x1f",
+        end = "%x1e"
+    );
+    let output = run_git(root, &["log", "--follow", &limit, &format, "--", &file])?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git log failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split(RECORD_SEPARATOR)
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').split(FIELD_SEPARATOR);
+            Some(Commit {
+                commit: fields.next().filter(|hash| !hash.is_empty())?.to_string(),
+                author: fields.next()?.to_string(),
+                date: fields.next()?.to_string(),
+                message: fields.next()?.trim().to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Arguments shared by the `git diff` invocations of `diff`
+fn diff_args<'a>(staged: bool, pathspec: &'a str, format: &'a str) -> Vec<&'a str> {
+    let mut args = vec!["diff", "--no-color", "--no-ext-diff", format];
+    if staged {
+        args.push("--cached");
+    }
+    args.extend(["--", pathspec]);
+    args
+}
+
+/// Uncommitted changes below `path` in the repository rooted at `root`, as a unified
+/// diff and a per-file summary, comparing the index with the worktree or, if `staged`,
+/// `HEAD` with the index
+pub fn diff(root: &Path, staged: bool, path: &Path) -> Result<(String, Vec<FileChange>)> {
+    let pathspec = path.to_string_lossy();
+    let run = |format| -> Result<String> {
+        let output = run_git(root, &diff_args(staged, &pathspec, format))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git diff failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    let unified = run("--patch")?;
+    let files = run("--numstat")?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let additions = fields.next()?.parse().ok();
+            let deletions = fields.next()?.parse().ok();
+            Some(FileChange {
+                path: fields.next()?.to_string(),
+                additions,
+                deletions,
+            })
+        })
+        .collect();
+    Ok((unified, files))
+}
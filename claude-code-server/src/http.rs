@@ -0,0 +1,559 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::auth::AUTH_HEADER;
+use crate::error::ServerError;
+use crate::listener::{ClientStream, PeerAddr};
+use crate::mcp::{self, MCPError, MCPRequest, MCPResponse, MCPServer};
+use crate::notifications::{JsonRpcNotification, NotificationReceiver};
+use crate::tail::NotificationSink;
+use crate::tools::{ToolError, TOOL_ERROR_FORBIDDEN, TOOL_ERROR_INTERNAL};
+use crate::websocket::ServerState;
+
+/// Path serving MCP requests (`POST`) and the notification stream (`GET`)
+pub const MCP_PATH: &str = "/mcp";
+/// Header naming the session created by `initialize`
+pub const SESSION_HEADER: &str = "mcp-session-id";
+
+/// Headers accepted in a single request
+const MAX_HEADERS: usize = 64;
+/// Upper bound on the size of a request line and headers
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+/// Upper bound on the size of a request body
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+/// Interval between comments keeping an idle event stream open
+const SSE_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Notifications a session holds for its event stream; a client that falls this far
+/// behind has stopped reading, so its session is dropped
+const HTTP_SESSION_QUEUE_LEN: usize = 256;
+/// How long a session may go without requests or an open event stream before it is
+/// dropped, for clients that leave without sending `DELETE`
+const HTTP_SESSION_IDLE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// An MCP session of an HTTP client
+struct HttpSession {
+    mcp: MCPServer,
+    /// Queue of the session's event stream
+    queue: mpsc::Sender<JsonRpcNotification>,
+    /// Notifications for the session's event stream, taken while a stream is open
+    pending: Mutex<Option<mpsc::Receiver<JsonRpcNotification>>>,
+    /// When the client last sent a request or was last written an event
+    last_active: Mutex<Instant>,
+}
+
+impl HttpSession {
+    fn touch(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    /// Whether the session has had no requests and no event stream for the idle TTL
+    fn is_idle(&self) -> bool {
+        let streaming = self.pending.lock().unwrap().is_none();
+        !streaming && self.last_active.lock().unwrap().elapsed() >= HTTP_SESSION_IDLE_TTL
+    }
+}
+
+/// MCP sessions of HTTP clients, keyed by their `Mcp-Session-Id`
+#[derive(Default)]
+pub struct HttpSessions {
+    sessions: RwLock<HashMap<String, Arc<HttpSession>>>,
+}
+
+impl HttpSessions {
+    /// Start a session for a client that sent `initialize`
+    fn create(&self, state: &Arc<ServerState>, peer_addr: PeerAddr) -> (String, Arc<HttpSession>) {
+        self.evict_idle();
+        let id = Uuid::new_v4().to_string();
+        let (queue, pending) = mpsc::channel(HTTP_SESSION_QUEUE_LEN);
+        let sink = forward_to_queue(id.clone(), queue.clone());
+        // Sessions are owned by the state, so only a weak handle goes back the other way
+        let status_state: Weak<ServerState> = Arc::downgrade(state);
+        let reload_state = status_state.clone();
+        let mcp = MCPServer::new(state.tool_context.clone())
+            .with_notification_sink(sink)
+            .with_status_provider(Arc::new(move || {
+                status_state
+                    .upgrade()
+                    .map(|state| state.status(&peer_addr))
+                    .unwrap_or_default()
+            }))
+            .with_config_reloader(Arc::new(move || match reload_state.upgrade() {
+                Some(state) => state.reload_config(),
+                None => Err(ToolError::new(
+                    TOOL_ERROR_INTERNAL,
+                    "Server is shutting down",
+                )),
+            }));
+        let session = Arc::new(HttpSession {
+            mcp,
+            queue,
+            pending: Mutex::new(Some(pending)),
+            last_active: Mutex::new(Instant::now()),
+        });
+
+        info!("Started HTTP session {} for {}", id, peer_addr);
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(id.clone(), session.clone());
+        (id, session)
+    }
+
+    /// The session named `id`, which counts as using it
+    fn get(&self, id: &str) -> Option<Arc<HttpSession>> {
+        let session = self.sessions.read().unwrap().get(id).cloned()?;
+        session.touch();
+        Some(session)
+    }
+
+    fn remove(&self, id: &str) -> bool {
+        self.sessions.write().unwrap().remove(id).is_some()
+    }
+
+    /// Queue `notification` for every session's event stream, dropping sessions
+    /// whose queue is full
+    pub fn broadcast(&self, notification: &JsonRpcNotification) {
+        let overflowed: Vec<String> = self
+            .sessions
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, session)| session.queue.try_send(notification.clone()).is_err())
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !overflowed.is_empty() {
+            let mut sessions = self.sessions.write().unwrap();
+            for id in overflowed {
+                warn!(
+                    "Dropping HTTP session {}: {} notifications were never read",
+                    id, HTTP_SESSION_QUEUE_LEN
+                );
+                sessions.remove(&id);
+            }
+        }
+        self.evict_idle();
+    }
+
+    /// Forget sessions whose client went away without ending them
+    fn evict_idle(&self) {
+        self.sessions.write().unwrap().retain(|id, session| {
+            let idle = session.is_idle();
+            if idle {
+                info!(
+                    "Dropping HTTP session {} after {:?} unused",
+                    id, HTTP_SESSION_IDLE_TTL
+                );
+            }
+            !idle
+        });
+    }
+}
+
+/// A sink for the session's tools, whose notifications are moved into its bounded
+/// queue and dropped when it is full; tools only know unbounded sinks
+fn forward_to_queue(id: String, queue: mpsc::Sender<JsonRpcNotification>) -> NotificationSink {
+    let (sink, mut notifications) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(notification) = notifications.recv().await {
+            match queue.try_send(notification) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    debug!("Dropped a notification for HTTP session {}: queue full", id)
+                }
+                // The session is gone, and dropping the sink stops what feeds it
+                Err(TrySendError::Closed(_)) => break,
+            }
+        }
+    });
+    sink
+}
+
+/// A request read off the connection
+struct HttpRequest {
+    method: String,
+    path: String,
+    /// Header values keyed by lowercase name
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// A complete response, sent with `Connection: close`
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn empty(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn json(status: u16, body: &Value) -> Self {
+        Self::json_text(status, body.to_string())
+    }
+
+    /// A response whose `body` is already serialized JSON
+    fn json_text(status: u16, body: String) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", "application/json".to_string())],
+            body: body.into_bytes(),
+        }
+    }
+
+    /// A JSON-RPC error response with no request id
+    fn error(status: u16, code: i32, message: impl Into<String>) -> Self {
+        let response = MCPResponse {
+            jsonrpc: "2.0".to_string(),
+            id: None,
+            result: None,
+            error: Some(MCPError {
+                code,
+                message: message.into(),
+                data: None,
+            }),
+        };
+        Self::json(status, &serde_json::to_value(response).unwrap_or_default())
+    }
+
+    /// A JSON-RPC error response with no request id, naming the kind of `error` as tool
+    /// failures do
+    fn server_error(status: u16, error: ServerError) -> Self {
+        let error = ToolError::from(error);
+        let response = MCPResponse {
+            jsonrpc: "2.0".to_string(),
+            id: None,
+            result: None,
+            error: Some(MCPError {
+                code: error.code,
+                message: error.message,
+                data: error.data,
+            }),
+        };
+        Self::json(status, &serde_json::to_value(response).unwrap_or_default())
+    }
+
+    fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    async fn write_to(self, stream: &mut ClientStream) -> Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status,
+            reason_phrase(self.status),
+            self.body.len()
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Serve one request from an MCP client connected over HTTP
+pub async fn handle_http_connection(
+    mut stream: ClientStream,
+    peer_addr: PeerAddr,
+    notification_receiver: Option<NotificationReceiver>,
+    state: Arc<ServerState>,
+) -> Result<()> {
+    let request = match read_request(&mut stream).await {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            warn!("Rejecting malformed HTTP request from {}: {}", peer_addr, e);
+            let response = HttpResponse::error(400, -32600, e.to_string());
+            return response.write_to(&mut stream).await;
+        }
+    };
+    debug!(
+        "HTTP {} {} from {}",
+        request.method, request.path, peer_addr
+    );
+    state.record_http_activity();
+
+    if !state.allows_origin(request.header("origin")) {
+        warn!(
+            "Rejecting HTTP request from {} with disallowed origin {}",
+            peer_addr,
+            request.header("origin").unwrap_or_default()
+        );
+        let response = HttpResponse::error(403, TOOL_ERROR_FORBIDDEN, "Origin not allowed");
+        return response.write_to(&mut stream).await;
+    }
+
+    let authenticated = match request.header(AUTH_HEADER) {
+        Some(token) => state.authenticator.authenticate(token).await,
+        None => false,
+    };
+    if !authenticated {
+        warn!("Rejecting unauthenticated HTTP request from {}", peer_addr);
+        let response =
+            HttpResponse::server_error(401, ServerError::Auth("Unauthorized".to_string()));
+        return response.write_to(&mut stream).await;
+    }
+
+    if request.path != MCP_PATH {
+        let response = HttpResponse::error(404, -32601, format!("No endpoint at {}", request.path));
+        return response.write_to(&mut stream).await;
+    }
+
+    let response = match request.method.as_str() {
+        "POST" => handle_post(&request, &state, peer_addr).await,
+        "GET" => {
+            return stream_notifications(stream, &request, notification_receiver, &state, peer_addr)
+                .await
+        }
+        "DELETE" => match request.header(SESSION_HEADER) {
+            Some(id) if state.http_sessions.remove(id) => {
+                info!("Ended HTTP session {}", id);
+                HttpResponse::empty(200)
+            }
+            _ => HttpResponse::error(404, -32600, "Unknown session"),
+        },
+        _ => HttpResponse::error(405, -32600, format!("{} is not supported", request.method))
+            .with_header("Allow", "GET, POST, DELETE"),
+    };
+    response.write_to(&mut stream).await
+}
+
+/// Look up the session named by the request's `Mcp-Session-Id` header
+fn session_for(
+    request: &HttpRequest,
+    state: &ServerState,
+) -> Result<(String, Arc<HttpSession>), HttpResponse> {
+    let id = request
+        .header(SESSION_HEADER)
+        .ok_or_else(|| HttpResponse::error(400, -32600, "Missing Mcp-Session-Id header"))?;
+    let session = state
+        .http_sessions
+        .get(id)
+        .ok_or_else(|| HttpResponse::error(404, -32600, "Unknown session"))?;
+    Ok((id.to_string(), session))
+}
+
+async fn handle_post(
+    request: &HttpRequest,
+    state: &Arc<ServerState>,
+    peer_addr: PeerAddr,
+) -> HttpResponse {
+    // The same checks as WebSocket text frames get before parsing
+    let rejection = std::str::from_utf8(&request.body)
+        .ok()
+        .and_then(mcp::control_character_error)
+        .or_else(|| mcp::nesting_error(&request.body));
+    if let Some(response) = rejection {
+        warn!("Rejecting unparseable request from {}", peer_addr);
+        return HttpResponse::json(400, &serde_json::to_value(response).unwrap_or_default());
+    }
+    let mcp_request: MCPRequest = match serde_json::from_slice(&request.body) {
+        Ok(mcp_request) => mcp_request,
+        Err(e) => {
+            warn!("Failed to parse MCP request from {}: {}", peer_addr, e);
+            let response = mcp::request_parse_error(&request.body, &e);
+            return HttpResponse::json(400, &serde_json::to_value(response).unwrap_or_default());
+        }
+    };
+    info!("Processing MCP request over HTTP: {}", mcp_request.method);
+
+    let (session_id, session) = if mcp_request.method == "initialize" {
+        state.http_sessions.create(state, peer_addr)
+    } else {
+        match session_for(request, state) {
+            Ok(session) => session,
+            Err(response) => return response,
+        }
+    };
+
+    // Notifications don't get responses
+    if mcp::is_notification(&mcp_request) {
+        session.mcp.handle_notification(mcp_request);
+        return HttpResponse::empty(202);
+    }
+
+    let request_id = mcp_request.id.clone();
+    match session.mcp.handle_request(mcp_request).await {
+        Ok(response) => {
+            let body = state.encode(&response).unwrap_or_default();
+            HttpResponse::json_text(200, body).with_header("Mcp-Session-Id", session_id)
+        }
+        Err(e) => {
+            warn!("Error handling MCP request from {}: {}", peer_addr, e);
+            let body = state
+                .encode(&mcp::internal_error(request_id, &e))
+                .unwrap_or_default();
+            HttpResponse::json_text(500, body)
+        }
+    }
+}
+
+/// Send the session's notifications as server-sent events until the client goes away
+async fn stream_notifications(
+    mut stream: ClientStream,
+    request: &HttpRequest,
+    mut ide_notifications: Option<NotificationReceiver>,
+    state: &ServerState,
+    peer_addr: PeerAddr,
+) -> Result<()> {
+    let (session_id, session) = match session_for(request, state) {
+        Ok(session) => session,
+        Err(response) => return response.write_to(&mut stream).await,
+    };
+    let Some(mut pending) = session.pending.lock().unwrap().take() else {
+        let response = HttpResponse::error(409, -32600, "Session already has an event stream");
+        return response.write_to(&mut stream).await;
+    };
+
+    info!(
+        "Opened event stream for session {} ({})",
+        session_id, peer_addr
+    );
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    let mut result = stream.write_all(head.as_bytes()).await;
+
+    let mut keepalive = tokio::time::interval(SSE_KEEPALIVE_INTERVAL);
+    while result.is_ok() {
+        let notification = tokio::select! {
+            Some(notification) = pending.recv() => Some(notification),
+            notification = async {
+                match ide_notifications.as_mut() {
+                    Some(receiver) => receiver.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => match notification {
+                Ok(notification) => Some(notification),
+                Err(e) => {
+                    debug!("Notification channel error: {}", e);
+                    ide_notifications = None;
+                    continue;
+                }
+            },
+            _ = keepalive.tick() => None,
+        };
+
+        let event = match notification {
+            Some(notification) => format!(
+                "event: message\ndata: {}\n\n",
+                serde_json::to_string(&notification)?
+            ),
+            None => {
+                state.record_http_activity();
+                ": keepalive\n\n".to_string()
+            }
+        };
+        result = stream.write_all(event.as_bytes()).await;
+        session.touch();
+    }
+
+    info!("Event stream for session {} closed", session_id);
+    // The idle TTL counts from when the client went away
+    session.touch();
+    // Keep queued notifications for a client that reconnects
+    *session.pending.lock().unwrap() = Some(pending);
+    Ok(())
+}
+
+/// Read a request head and its body, or `None` if the client closed without sending one
+async fn read_request(stream: &mut ClientStream) -> Result<Option<HttpRequest>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let (mut request, head_len, content_length) = loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            return Err(anyhow!("Connection closed mid-request"));
+        }
+        buf.extend_from_slice(&chunk[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let httparse::Status::Complete(head_len) = parsed.parse(&buf)? else {
+            if buf.len() > MAX_HEAD_BYTES {
+                return Err(anyhow!("Request head exceeds {} bytes", MAX_HEAD_BYTES));
+            }
+            continue;
+        };
+
+        let path = parsed.path.unwrap_or("/");
+        let request = HttpRequest {
+            method: parsed.method.unwrap_or_default().to_string(),
+            path: path.split('?').next().unwrap_or(path).to_string(),
+            headers: parsed
+                .headers
+                .iter()
+                .map(|header| {
+                    let value = String::from_utf8_lossy(header.value).into_owned();
+                    (header.name.to_ascii_lowercase(), value)
+                })
+                .collect(),
+            body: Vec::new(),
+        };
+        let content_length = match request.header("content-length") {
+            Some(length) => length
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow!("Invalid Content-Length"))?,
+            None => 0,
+        };
+        break (request, head_len, content_length);
+    };
+
+    if content_length > MAX_BODY_BYTES {
+        return Err(anyhow!("Request body exceeds {} bytes", MAX_BODY_BYTES));
+    }
+    let mut body = buf.split_off(head_len);
+    while body.len() < content_length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(anyhow!("Connection closed mid-request"));
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+    request.body = body;
+
+    Ok(Some(request))
+}
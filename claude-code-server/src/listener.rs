@@ -0,0 +1,115 @@
+//! Sockets the WebSocket and HTTP transports accept clients on.
+//!
+//! Clients normally connect over TCP on localhost. A Unix domain socket avoids
+//! port discovery altogether and is protected by filesystem permissions, so
+//! connections are handled as boxed byte streams whichever listener they came from.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+#[cfg(unix)]
+use tracing::info;
+
+use crate::error::ServerError;
+
+/// Byte stream of a connected client
+pub trait ClientIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ClientIo for T {}
+
+pub type ClientStream = Box<dyn ClientIo>;
+
+/// Identifies a client in logs and in the server's connection table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Unix socket clients are unnamed, so they are numbered as they connect
+    Unix(u64),
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => addr.fmt(f),
+            PeerAddr::Unix(id) => write!(f, "unix#{}", id),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        accepted: u64,
+    },
+}
+
+impl Listener {
+    /// Listen on a Unix domain socket at `path`, replacing a socket file nobody serves.
+    ///
+    /// Anything at `path` other than a socket is left alone: a regular file refuses
+    /// connections too, but it is not ours to remove.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path) -> Result<Self, ServerError> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let context = || format!("Failed to listen on {}", path.display());
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                return Err(ServerError::io(context())(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                )));
+            }
+            Ok(_) => {
+                // A refused connection means the server that made the socket is gone
+                if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                    return Err(ServerError::io(context())(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is served by another process", path.display()),
+                    )));
+                }
+                info!("Removing stale socket file: {}", path.display());
+                std::fs::remove_file(path).map_err(ServerError::io(context()))?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(ServerError::io(context())(e)),
+        }
+
+        Ok(Listener::Unix {
+            listener: UnixListener::bind(path).map_err(ServerError::io(context()))?,
+            accepted: 0,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind_unix(path: &Path) -> Result<Self, ServerError> {
+        Err(ServerError::io(format!("Failed to listen on {}", path.display()))(
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            ),
+        ))
+    }
+
+    pub async fn accept(&mut self) -> io::Result<(ClientStream, PeerAddr)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Box::new(stream), PeerAddr::Tcp(addr)))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, accepted } => {
+                let (stream, _) = listener.accept().await?;
+                *accepted += 1;
+                Ok((Box::new(stream), PeerAddr::Unix(*accepted)))
+            }
+        }
+    }
+}
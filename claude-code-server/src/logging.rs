@@ -0,0 +1,53 @@
+//! The server's log output on stderr, whose level can be raised while it runs.
+
+use anyhow::{anyhow, Result};
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+/// Replaces the filter installed by `init`, and remembers the level it started at
+struct LevelHandle {
+    initial: LevelFilter,
+    handle: reload::Handle<LevelFilter, Registry>,
+}
+
+static LEVEL: OnceLock<LevelHandle> = OnceLock::new();
+
+/// Log to stderr at `level` until `set_level` says otherwise.
+///
+/// Logs never go to stdout, which carries the LSP messages.
+pub fn init(level: LevelFilter) -> Result<()> {
+    let (filter, handle) = reload::Layer::new(level);
+    let subscriber = tracing_subscriber::registry().with(filter).with(
+        fmt::layer()
+            .with_file(true)
+            .with_line_number(true)
+            .with_thread_ids(true)
+            .with_target(false)
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_writer(std::io::stderr),
+    );
+    tracing::subscriber::set_global_default(subscriber)?;
+    LEVEL
+        .set(LevelHandle {
+            initial: level,
+            handle,
+        })
+        .map_err(|_| anyhow!("Logging was already initialized"))
+}
+
+/// Log at `level`, but never less than the level logging started at, so a client
+/// can turn tracing up and back down without silencing what the user asked for
+pub fn set_level(level: LevelFilter) -> Result<LevelFilter> {
+    let level_handle = LEVEL
+        .get()
+        .ok_or_else(|| anyhow!("Logging is not initialized"))?;
+    let level = level.max(level_handle.initial);
+    level_handle
+        .handle
+        .reload(level)
+        .map_err(|e| anyhow!("Failed to change the log level: {}", e))?;
+    Ok(level)
+}
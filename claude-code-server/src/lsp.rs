@@ -1,34 +1,63 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
+use std::io::IsTerminal;
 use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::sync::broadcast;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tower_lsp::jsonrpc::Result as LspResult;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
 use tracing::{debug, info, warn};
 
-// Notification structures for IDE to Claude communication
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct SelectionChangedNotification {
-    pub text: String,
-    #[serde(rename = "filePath")]
-    pub file_path: String,
-    #[serde(rename = "fileUrl")]
-    pub file_url: String,
-    pub selection: SelectionInfo,
+use crate::diagnostics::{self, DiagnosticsStore};
+use crate::documents::{offset_at, DocumentStore, Utf16Index};
+use crate::editor::EditorBridge;
+use crate::file_changes::{FileChange, FileChangeQueue, FILE_CHANGE_CAPACITY};
+use crate::formatter;
+use crate::logging;
+use crate::notifications::{JsonRpcNotification, NotificationSender};
+use crate::prompts;
+use crate::selection::{SelectionChangedNotification, SelectionInfo, SelectionStore};
+use crate::semantic_tokens;
+use crate::shared::SharedState;
+use crate::symbols::{self, SymbolIndex};
+use crate::version;
+
+/// Quiet period after the last edit before a changed document's symbols are rescanned
+const REINDEX_DEBOUNCE: Duration = Duration::from_millis(300);
+/// Most workspace symbols returned for one query, unless the client sets
+/// `workspaceSymbolLimit` in its initialization options
+const DEFAULT_WORKSPACE_SYMBOL_LIMIT: usize = 200;
+/// Symbols per `$/progress` page when the client asks for partial results
+const WORKSPACE_SYMBOL_PAGE_SIZE: usize = 50;
+/// Registration id of the workspace file watchers
+const WATCHED_FILES_REGISTRATION_ID: &str = "claude-code-watched-files";
+/// Work done progress token of the startup symbol indexing pass
+const STARTUP_INDEX_PROGRESS_TOKEN: &str = "claude-code-symbol-index";
+/// Files indexed between progress reports during the startup indexing pass
+const STARTUP_INDEX_BATCH_SIZE: usize = 200;
+
+/// `$/progress` carrying a page of workspace symbols.
+///
+/// lsp-types only models work-done progress, so partial results need their own type.
+enum WorkspaceSymbolPage {}
+
+impl notification::Notification for WorkspaceSymbolPage {
+    type Params = WorkspaceSymbolPageParams;
+    const METHOD: &'static str = "$/progress";
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct SelectionInfo {
-    pub start: Position,
-    pub end: Position,
-    #[serde(rename = "isEmpty")]
-    pub is_empty: bool,
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkspaceSymbolPageParams {
+    token: ProgressToken,
+    value: Vec<SymbolInformation>,
 }
 
+// Notification structures for IDE to Claude communication
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AtMentionedNotification {
     #[serde(rename = "filePath")]
@@ -39,22 +68,36 @@ pub struct AtMentionedNotification {
     pub line_end: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct JsonRpcNotification {
-    pub jsonrpc: String,
-    pub method: String,
-    pub params: serde_json::Value,
-}
-
-// Channel for sending notifications from LSP to MCP
-pub type NotificationSender = broadcast::Sender<JsonRpcNotification>;
-pub type NotificationReceiver = broadcast::Receiver<JsonRpcNotification>;
-
 #[derive(Debug)]
 pub struct ClaudeCodeLanguageServer {
     client: Client,
     worktree: Option<PathBuf>,
     notification_sender: Option<Arc<NotificationSender>>,
+    editor: Option<EditorBridge>,
+    documents: DocumentStore,
+    diagnostics: DiagnosticsStore,
+    symbols: SymbolIndex,
+    selection: SelectionStore,
+    /// Latest scheduled reindex per document; an older task finding a newer id does nothing
+    pending_reindexes: Arc<Mutex<HashMap<Url, u64>>>,
+    next_reindex_id: AtomicU64,
+    workspace_symbol_limit: AtomicUsize,
+    /// Whether the client accepts file watchers registered after initialize
+    watched_files_registration: AtomicBool,
+    /// Whether the client shows server-initiated `$/progress`
+    work_done_progress: AtomicBool,
+    /// Whether edits before a save run the language's formatter, set by the
+    /// `formatOnSave` initialization option
+    format_on_save: AtomicBool,
+    /// Whether functions get "Explain" inlay hints, unless the `inlayHints`
+    /// initialization option turns them off
+    inlay_hints: AtomicBool,
+    /// Set once an editor has sent `initialize`
+    initialized: Arc<AtomicBool>,
+    /// Range of the latest code action request, the target of commands run without one
+    last_selection: Mutex<Option<(Url, Range)>>,
+    /// Watched file changes waiting to be sent as `file_changed` notifications
+    file_changes: Arc<FileChangeQueue>,
 }
 
 impl ClaudeCodeLanguageServer {
@@ -63,14 +106,50 @@ impl ClaudeCodeLanguageServer {
             client,
             worktree,
             notification_sender: None,
+            editor: None,
+            documents: DocumentStore::new(),
+            diagnostics: DiagnosticsStore::new(),
+            symbols: SymbolIndex::new(),
+            selection: SelectionStore::new(),
+            pending_reindexes: Arc::default(),
+            next_reindex_id: AtomicU64::new(0),
+            workspace_symbol_limit: AtomicUsize::new(DEFAULT_WORKSPACE_SYMBOL_LIMIT),
+            watched_files_registration: AtomicBool::new(false),
+            work_done_progress: AtomicBool::new(false),
+            format_on_save: AtomicBool::new(false),
+            inlay_hints: AtomicBool::new(true),
+            initialized: Arc::default(),
+            last_selection: Mutex::default(),
+            file_changes: Arc::new(FileChangeQueue::new(FILE_CHANGE_CAPACITY)),
         }
     }
 
     pub fn with_notification_sender(mut self, sender: Arc<NotificationSender>) -> Self {
+        tokio::spawn(forward_file_changes(
+            self.file_changes.clone(),
+            sender.clone(),
+        ));
         self.notification_sender = Some(sender);
         self
     }
 
+    /// Record documents, diagnostics, selections and symbols in `shared`, so tools
+    /// sharing the stores can read them
+    pub fn with_shared_state(mut self, shared: SharedState) -> Self {
+        self.documents = shared.documents;
+        self.diagnostics = shared.diagnostics;
+        self.selection = shared.selection;
+        self.symbols = shared.symbols;
+        self
+    }
+
+    /// Make this connection's editor reachable through `editor`
+    pub fn with_editor_bridge(mut self, editor: EditorBridge) -> Self {
+        editor.attach(self.client.clone());
+        self.editor = Some(editor);
+        self
+    }
+
     async fn send_notification(&self, method: &str, params: serde_json::Value) {
         if let Some(sender) = &self.notification_sender {
             let notification = JsonRpcNotification {
@@ -85,40 +164,161 @@ impl ClaudeCodeLanguageServer {
         }
     }
 
-    // Convert LSP UTF-16 code unit position to Rust UTF-8 byte position
-    // LSP uses UTF-16 code units for character positions per the specification
-    fn char_pos_to_byte_pos(line: &str, utf16_pos: usize) -> Option<usize> {
-        let mut current_utf16_pos = 0;
-        
-        for (byte_pos, ch) in line.char_indices() {
-            if current_utf16_pos == utf16_pos {
-                return Some(byte_pos);
+    /// Store `selection` for tools, then tell Claude about it
+    async fn publish_selection(&self, selection: SelectionChangedNotification) {
+        let params = serde_json::to_value(&selection).unwrap();
+        self.selection.set(selection);
+        self.send_notification("selection_changed", params).await;
+    }
+
+    /// Follow the editor's LSP trace setting: `messages` logs at debug level and
+    /// `verbose` at trace level, which includes every message exchanged
+    async fn set_trace(&self, params: SetTraceParams) {
+        self.apply_trace(params.value);
+    }
+
+    fn apply_trace(&self, trace: TraceValue) {
+        let level = match trace {
+            TraceValue::Off => tracing::level_filters::LevelFilter::OFF,
+            TraceValue::Messages => tracing::level_filters::LevelFilter::DEBUG,
+            TraceValue::Verbose => tracing::level_filters::LevelFilter::TRACE,
+        };
+        match logging::set_level(level) {
+            Ok(level) => info!("Trace set to {:?}, logging at {}", trace, level),
+            Err(e) => warn!("Failed to apply trace {:?}: {}", trace, e),
+        }
+    }
+
+    /// Send Claude the `prompt` for the code a command targets: the `{uri, range}` in
+    /// its arguments, or the latest selection
+    async fn ask_claude(&self, prompt: &prompts::CodePrompt, target: Option<&Value>) {
+        let target = target
+            .and_then(|target| {
+                let uri = Url::parse(target.get("uri")?.as_str()?).ok()?;
+                let range = serde_json::from_value(target.get("range")?.clone()).ok()?;
+                Some((uri, range))
+            })
+            .or_else(|| self.last_selection.lock().unwrap().clone());
+        let Some((uri, range)) = target else {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    format!("Claude Code: select code to {} first", prompt.name),
+                )
+                .await;
+            return;
+        };
+
+        let text = self.read_text_from_range(uri.path(), range);
+        let notification = serde_json::json!({
+            "action": prompt.name,
+            "prompt": prompt.render(uri.path(), &text),
+            "filePath": uri.path(),
+            "fileUrl": uri,
+            "range": range,
+            "text": text
+        });
+        info!("Asking Claude to {} {}", prompt.name, uri.path());
+        self.send_notification("ask", notification).await;
+    }
+
+    /// Text and language of `uri`, from its buffer when open and otherwise from disk
+    fn document_text(&self, uri: &Url) -> Option<(String, String)> {
+        if let Some(document) = self.documents.get(uri) {
+            return Some((document.text, document.language_id));
+        }
+        let path = uri.to_file_path().ok()?;
+        let language_id = symbols::language_for_path(&path)?;
+        match fs::read_to_string(&path) {
+            Ok(text) => Some((text, language_id.to_string())),
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                None
             }
-            
-            let char_utf16_len = ch.len_utf16();
-            
-            // If utf16_pos falls within this character's UTF-16 span, return this char's byte position
-            if utf16_pos < current_utf16_pos + char_utf16_len {
-                return Some(byte_pos);
+        }
+    }
+
+    /// Re-scan the symbols of an open document from its buffer contents
+    fn reindex_document(documents: &DocumentStore, symbols: &SymbolIndex, uri: &Url) {
+        let (Ok(path), Some(document)) = (uri.to_file_path(), documents.get(uri)) else {
+            return;
+        };
+        symbols.index_text(&path, &document.text, &document.language_id);
+    }
+
+    /// Reindex a document once edits to it have paused for `REINDEX_DEBOUNCE`.
+    ///
+    /// Each call supersedes any reindex still waiting for the same document, so a
+    /// burst of changes results in a single rescan of the final text.
+    fn schedule_reindex(&self, uri: Url) {
+        let id = self.next_reindex_id.fetch_add(1, Ordering::Relaxed);
+        self.pending_reindexes
+            .lock()
+            .unwrap()
+            .insert(uri.clone(), id);
+
+        let documents = self.documents.clone();
+        let symbols = self.symbols.clone();
+        let pending_reindexes = self.pending_reindexes.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(REINDEX_DEBOUNCE).await;
+            {
+                let mut pending = pending_reindexes.lock().unwrap();
+                if pending.get(&uri) != Some(&id) {
+                    return;
+                }
+                pending.remove(&uri);
             }
-            
-            current_utf16_pos += char_utf16_len;
+            debug!("Reindexing symbols for {}", uri);
+            Self::reindex_document(&documents, &symbols, &uri);
+        });
+    }
+
+    /// Ask the editor to report changes to any file in the workspace.
+    ///
+    /// The editor already watches the worktree, so this keeps the symbol index in
+    /// step with changes made outside open buffers without a watcher of our own.
+    async fn register_file_watchers(client: Client) {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*".to_string()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: WATCHED_FILES_REGISTRATION_ID.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+        match client.register_capability(vec![registration]).await {
+            Ok(()) => info!("Registered workspace file watchers"),
+            Err(e) => warn!("Failed to register workspace file watchers: {}", e),
         }
-        
-        // If utf16_pos is at the end of the string
-        if current_utf16_pos == utf16_pos {
-            return Some(line.len());
+    }
+
+    /// Whether a completion request comes right after an `@` mention.
+    ///
+    /// Editors also ask for completions while an identifier is typed, where the
+    /// `@claude` items would only crowd out the real candidates.
+    fn is_mention_completion(&self, params: &CompletionParams) -> bool {
+        let triggered_by_at = params.context.as_ref().is_some_and(|context| {
+            context.trigger_kind == CompletionTriggerKind::TRIGGER_CHARACTER
+                && context.trigger_character.as_deref() == Some("@")
+        });
+        if triggered_by_at {
+            return true;
         }
-        
-        None
+
+        let position = &params.text_document_position;
+        let Some(document) = self.documents.get(&position.text_document.uri) else {
+            return false;
+        };
+        offset_at(&document.text, position.position)
+            .is_some_and(|offset| document.text[..offset].ends_with('@'))
     }
 
     fn read_text_from_range(&self, file_path: &str, range: Range) -> String {
-        let file_path = if file_path.starts_with("file://") {
-            &file_path[7..] // Remove "file://" prefix
-        } else {
-            file_path
-        };
+        let file_path = file_path.strip_prefix("file://").unwrap_or(file_path);
 
         match fs::read_to_string(file_path) {
             Ok(content) => {
@@ -130,9 +330,10 @@ impl ClaudeCodeLanguageServer {
                         let start_char = range.start.character as usize;
                         let end_char = range.end.character as usize;
 
+                        let index = Utf16Index::new(line);
                         if let (Some(start_byte), Some(end_byte)) = 
-                            (Self::char_pos_to_byte_pos(line, start_char),
-                             Self::char_pos_to_byte_pos(line, end_char)) {
+                            (index.byte_pos(start_char),
+                             index.byte_pos(end_char)) {
                             if start_byte <= end_byte {
                                 return line[start_byte..end_byte].to_string();
                             }
@@ -147,13 +348,15 @@ impl ClaudeCodeLanguageServer {
                             if i == 0 {
                                 // First line - from start character to end
                                 let start_char = range.start.character as usize;
-                                if let Some(start_byte) = Self::char_pos_to_byte_pos(line, start_char) {
+                                let index = Utf16Index::new(line);
+                                if let Some(start_byte) = index.byte_pos(start_char) {
                                     selected_text.push_str(&line[start_byte..]);
                                 }
                             } else if line_index == range.end.line {
                                 // Last line - from start to end character
                                 let end_char = range.end.character as usize;
-                                if let Some(end_byte) = Self::char_pos_to_byte_pos(line, end_char) {
+                                let index = Utf16Index::new(line);
+                                if let Some(end_byte) = index.byte_pos(end_char) {
                                     selected_text.push_str(&line[..end_byte]);
                                 }
                             } else {
@@ -178,51 +381,146 @@ impl ClaudeCodeLanguageServer {
 
         String::new()
     }
+
+    /// Capabilities for the requests this server actually implements.
+    ///
+    /// Only advertise a provider once its handler exists; otherwise clients
+    /// send requests that tower-lsp answers with method-not-found.
+    fn server_capabilities() -> ServerCapabilities {
+        ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Options(
+                TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::INCREMENTAL),
+                    will_save: None,
+                    will_save_wait_until: Some(true),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                },
+            )),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            completion_provider: Some(CompletionOptions {
+                resolve_provider: Some(false),
+                trigger_characters: Some(vec!["@".to_string()]),
+                work_done_progress_options: Default::default(),
+                all_commit_characters: None,
+                completion_item: None,
+            }),
+            selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+            references_provider: Some(OneOf::Left(true)),
+            workspace_symbol_provider: Some(OneOf::Left(true)),
+            semantic_tokens_provider: Some(
+                SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                    work_done_progress_options: Default::default(),
+                    legend: semantic_tokens::legend(),
+                    range: Some(false),
+                    full: Some(SemanticTokensFullOptions::Bool(true)),
+                }),
+            ),
+            document_range_formatting_provider: Some(OneOf::Left(true)),
+            workspace: Some(WorkspaceServerCapabilities {
+                workspace_folders: None,
+                file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                    did_rename: Some(FileOperationRegistrationOptions {
+                        filters: vec![FileOperationFilter {
+                            scheme: Some("file".to_string()),
+                            pattern: FileOperationPattern {
+                                glob: "**/*".to_string(),
+                                matches: None,
+                                options: None,
+                            },
+                        }],
+                    }),
+                    ..Default::default()
+                }),
+            }),
+            code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+            code_lens_provider: Some(CodeLensOptions {
+                resolve_provider: Some(false),
+            }),
+            inlay_hint_provider: Some(OneOf::Left(true)),
+            execute_command_provider: Some(ExecuteCommandOptions {
+                commands: vec![
+                    "claude-code.explain".to_string(),
+                    "claude-code.ask".to_string(),
+                    "claude-code.improve".to_string(),
+                    "claude-code.fix".to_string(),
+                    "claude-code.at-mention".to_string(),
+                ],
+                work_done_progress_options: Default::default(),
+            }),
+            ..ServerCapabilities::default()
+        }
+    }
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for ClaudeCodeLanguageServer {
     async fn initialize(&self, params: InitializeParams) -> LspResult<InitializeResult> {
         info!("LSP Server initializing...");
+        self.initialized.store(true, Ordering::Relaxed);
+        if let Some(worktree) = &self.worktree {
+            info!("Worktree root: {}", worktree.display());
+        }
         if let Some(workspace_folders) = &params.workspace_folders {
             for folder in workspace_folders {
                 info!("Workspace folder: {}", folder.uri);
             }
         }
+        if let Some(limit) = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("workspaceSymbolLimit"))
+            .and_then(|limit| limit.as_u64())
+        {
+            info!("Workspace symbol limit: {}", limit);
+            self.workspace_symbol_limit
+                .store(limit as usize, Ordering::Relaxed);
+        }
+        let format_on_save = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("formatOnSave"))
+            .and_then(|enabled| enabled.as_bool())
+            .unwrap_or(false);
+        self.format_on_save.store(format_on_save, Ordering::Relaxed);
+        let inlay_hints = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("inlayHints"))
+            .and_then(|enabled| enabled.as_bool())
+            .unwrap_or(true);
+        self.inlay_hints.store(inlay_hints, Ordering::Relaxed);
+        if let Some(trace) = params.trace {
+            self.apply_trace(trace);
+        }
+        let watched_files_registration = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files)
+            .and_then(|watched_files| watched_files.dynamic_registration)
+            .unwrap_or(false);
+        self.watched_files_registration
+            .store(watched_files_registration, Ordering::Relaxed);
+        let work_done_progress = params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false);
+        self.work_done_progress
+            .store(work_done_progress, Ordering::Relaxed);
+
+        let mut capabilities = Self::server_capabilities();
+        if !inlay_hints {
+            capabilities.inlay_hint_provider = None;
+        }
 
         Ok(InitializeResult {
-            capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::INCREMENTAL,
-                )),
-                hover_provider: Some(HoverProviderCapability::Simple(true)),
-                completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
-                    trigger_characters: Some(vec!["@".to_string()]),
-                    work_done_progress_options: Default::default(),
-                    all_commit_characters: None,
-                    completion_item: None,
-                }),
-                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
-                definition_provider: Some(OneOf::Left(true)),
-                references_provider: Some(OneOf::Left(true)),
-                document_symbol_provider: Some(OneOf::Left(true)),
-                workspace_symbol_provider: Some(OneOf::Left(true)),
-                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
-                execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec![
-                        "claude-code.explain".to_string(),
-                        "claude-code.improve".to_string(),
-                        "claude-code.fix".to_string(),
-                        "claude-code.at-mention".to_string(),
-                    ],
-                    work_done_progress_options: Default::default(),
-                }),
-                ..ServerCapabilities::default()
-            },
+            capabilities,
             server_info: Some(ServerInfo {
                 name: "Claude Code Language Server".to_string(),
-                version: Some("0.1.0".to_string()),
+                version: Some(version::VERSION.to_string()),
             }),
         })
     }
@@ -233,16 +531,42 @@ impl LanguageServer for ClaudeCodeLanguageServer {
         self.client
             .log_message(MessageType::INFO, "Claude Code Language Server is ready!")
             .await;
+
+        // Registered in the background like the index: an editor that shuts down right
+        // after `initialized` never answers, and the server only stops once every
+        // handler has returned
+        if self.watched_files_registration.load(Ordering::Relaxed) {
+            tokio::spawn(Self::register_file_watchers(self.client.clone()));
+        }
+
+        // Index unopened files in the background so requests are answered meanwhile
+        tokio::spawn(index_workspace(
+            self.client.clone(),
+            self.symbols.clone(),
+            self.worktree.iter().cloned().collect(),
+            self.work_done_progress.load(Ordering::Relaxed),
+        ));
     }
 
     async fn shutdown(&self) -> LspResult<()> {
         info!("LSP Server shutting down...");
+        if let Some(editor) = &self.editor {
+            editor.detach();
+        }
         Ok(())
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         info!("Document opened: {}", params.text_document.uri);
 
+        self.documents.open(
+            params.text_document.uri.clone(),
+            params.text_document.language_id,
+            params.text_document.version,
+            params.text_document.text,
+        );
+        Self::reindex_document(&self.documents, &self.symbols, &params.text_document.uri);
+
         self.client
             .log_message(
                 MessageType::INFO,
@@ -253,24 +577,251 @@ impl LanguageServer for ClaudeCodeLanguageServer {
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         info!("Document changed: {}", params.text_document.uri);
+
+        self.documents.apply_changes(
+            &params.text_document.uri,
+            params.text_document.version,
+            params.content_changes,
+        );
+        self.schedule_reindex(params.text_document.uri);
+    }
+
+    async fn will_save_wait_until(
+        &self,
+        params: WillSaveTextDocumentParams,
+    ) -> LspResult<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        info!("Document about to be saved: {} ({:?})", uri, params.reason);
+
+        let Some(document) = self.documents.get(&uri) else {
+            warn!("Cannot prepare untracked document for saving: {}", uri);
+            return Ok(None);
+        };
+
+        // Running a formatter is opt-in, as it may rewrite much more than whitespace
+        if self.format_on_save.load(Ordering::Relaxed) {
+            let file_path = PathBuf::from(uri.path());
+            match formatter::format_text(
+                &document.language_id,
+                &file_path,
+                &document.text,
+                self.worktree.as_deref(),
+            )
+            .await
+            {
+                Ok(formatted) if formatted == document.text => return Ok(Some(vec![])),
+                Ok(formatted) => {
+                    return Ok(Some(vec![TextEdit {
+                        range: whole_document_range(&document.text),
+                        new_text: formatted,
+                    }]))
+                }
+                Err(e) => warn!("Formatting on save failed for {}: {}", uri, e),
+            }
+        }
+
+        Ok(Some(save_cleanup_edits(
+            &document.text,
+            &document.language_id,
+        )))
     }
 
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
         info!("Document saved: {}", params.text_document.uri);
+        self.documents.mark_saved(&params.text_document.uri);
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         info!("Document closed: {}", params.text_document.uri);
+
+        self.pending_reindexes
+            .lock()
+            .unwrap()
+            .remove(&params.text_document.uri);
+        self.documents.close(&params.text_document.uri);
+    }
+
+    async fn did_rename_files(&self, params: RenameFilesParams) {
+        for file in params.files {
+            info!("File renamed: {} -> {}", file.old_uri, file.new_uri);
+
+            let (Ok(old_uri), Ok(new_uri)) = (Url::parse(&file.old_uri), Url::parse(&file.new_uri))
+            else {
+                warn!("Ignoring rename with invalid URI: {:?}", file);
+                continue;
+            };
+
+            if self.documents.rename(&old_uri, new_uri.clone()) {
+                debug!("Moved open document {} to {}", old_uri, new_uri);
+            }
+            if let (Ok(old_path), Ok(new_path)) = (old_uri.to_file_path(), new_uri.to_file_path()) {
+                self.symbols.rename_file(&old_path, &new_path);
+            }
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let mut changes = Vec::with_capacity(params.changes.len());
+        for change in params.changes {
+            let Ok(path) = change.uri.to_file_path() else {
+                continue;
+            };
+            let change_type = match change.typ {
+                FileChangeType::CREATED => "created",
+                FileChangeType::DELETED => "deleted",
+                _ => "changed",
+            };
+            debug!("Watched file {}: {}", change_type, path.display());
+
+            if change.typ == FileChangeType::DELETED {
+                self.symbols.remove_file(&path);
+            } else if self.documents.get(&change.uri).is_none() {
+                // Open documents are indexed from their buffers, which lead the disk
+                self.symbols.refresh_file(&path);
+            }
+
+            changes.push(FileChange { path, change_type });
+        }
+        self.file_changes.push_all(changes);
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> LspResult<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        info!(
+            "Range formatting requested for {} at {:?}",
+            uri, params.range
+        );
+
+        let Some(document) = self.documents.get(&uri) else {
+            warn!("Cannot format untracked document: {}", uri);
+            return Ok(None);
+        };
+
+        // Formatters need syntactically complete input, so expand to the enclosing lines
+        let lines: Vec<&str> = document
+            .text
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .collect();
+        let start_line = params.range.start.line as usize;
+        let mut end_line = params.range.end.line as usize;
+        if params.range.end.character == 0 && end_line > start_line {
+            end_line -= 1;
+        }
+        let end_line = end_line.min(lines.len() - 1);
+        if start_line > end_line {
+            return Ok(None);
+        }
+
+        let fragment = lines[start_line..=end_line].join("\n");
+        let file_path = PathBuf::from(uri.path());
+        let formatted = match formatter::format_fragment(
+            &document.language_id,
+            &file_path,
+            &fragment,
+            self.worktree.as_deref(),
+        )
+        .await
+        {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                warn!("Range formatting failed for {}: {}", uri, e);
+                return Ok(None);
+            }
+        };
+
+        if formatted == fragment {
+            return Ok(Some(vec![]));
+        }
+
+        Ok(Some(vec![TextEdit {
+            range: Range {
+                start: Position {
+                    line: start_line as u32,
+                    character: 0,
+                },
+                end: Position {
+                    line: end_line as u32,
+                    character: lines[end_line].encode_utf16().count() as u32,
+                },
+            },
+            new_text: formatted,
+        }]))
     }
 
     async fn hover(&self, params: HoverParams) -> LspResult<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
         info!(
             "Hover requested at {}:{}",
             position.line, position.character
         );
 
-        Ok(None)
+        let mut sections: Vec<String> = self
+            .diagnostics
+            .in_range(
+                &uri,
+                Range {
+                    start: position,
+                    end: position,
+                },
+            )
+            .iter()
+            .map(|diagnostic| {
+                let source = diagnostic
+                    .source
+                    .as_ref()
+                    .map(|source| format!(" ({})", source))
+                    .unwrap_or_default();
+                format!(
+                    "**{}**{}: {}",
+                    diagnostics::severity_name(diagnostic),
+                    source,
+                    diagnostic.message
+                )
+            })
+            .collect();
+
+        let name = uri.to_file_path().ok().and_then(|path| {
+            symbols::identifier_in_file(
+                &self.documents,
+                &path,
+                position.line as usize,
+                position.character as usize,
+            )
+        });
+        if let Some(name) = name {
+            let definitions: Vec<String> = self
+                .symbols
+                .find_definitions(&name)
+                .into_iter()
+                .map(|(path, symbol)| {
+                    format!(
+                        "- {:?} in {}:{}",
+                        symbol.kind,
+                        path.display(),
+                        symbol.range.start.line + 1
+                    )
+                })
+                .collect();
+            if !definitions.is_empty() {
+                sections.push(format!("`{}`\n\n{}", name, definitions.join("\n")));
+            }
+        }
+
+        if sections.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: sections.join("\n\n---\n\n"),
+            }),
+            range: None,
+        }))
     }
 
     async fn completion(&self, params: CompletionParams) -> LspResult<Option<CompletionResponse>> {
@@ -280,6 +831,11 @@ impl LanguageServer for ClaudeCodeLanguageServer {
             position.line, position.character
         );
 
+        if !self.is_mention_completion(&params) {
+            debug!("Skipping Claude completions outside an @ mention");
+            return Ok(None);
+        }
+
         let completions = vec![
             CompletionItem {
                 label: "@claude explain".to_string(),
@@ -316,9 +872,253 @@ impl LanguageServer for ClaudeCodeLanguageServer {
         Ok(Some(CompletionResponse::Array(completions)))
     }
 
+    async fn references(&self, params: ReferenceParams) -> LspResult<Option<Vec<Location>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        info!(
+            "References requested at {}:{}:{}",
+            uri, position.line, position.character
+        );
+
+        let Ok(path) = uri.to_file_path() else {
+            return Ok(None);
+        };
+        let Some(name) = symbols::identifier_in_file(
+            &self.documents,
+            &path,
+            position.line as usize,
+            position.character as usize,
+        ) else {
+            return Ok(None);
+        };
+
+        let documents = self.documents.clone();
+        let roots: Vec<PathBuf> = self.worktree.iter().cloned().collect();
+        let include_declaration = params.context.include_declaration;
+        let locations = tokio::task::spawn_blocking(move || {
+            symbols::find_references(&documents, &roots, &name)
+                .into_iter()
+                .filter(|reference| include_declaration || !reference.is_definition)
+                .filter_map(|reference| {
+                    Some(Location {
+                        uri: Url::from_file_path(&reference.path).ok()?,
+                        range: reference.range,
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+
+        Ok(Some(locations))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> LspResult<Option<Vec<SymbolInformation>>> {
+        let query = params.query;
+        info!("Workspace symbols requested for {:?}", query);
+
+        let symbols = self.symbols.clone();
+        let roots: Vec<PathBuf> = self.worktree.iter().cloned().collect();
+        let search_query = query.clone();
+        let matches = tokio::task::spawn_blocking(move || {
+            for root in &roots {
+                symbols.refresh_workspace(root);
+            }
+            symbols.search(&search_query)
+        })
+        .await
+        .unwrap_or_default();
+
+        let limit = self.workspace_symbol_limit.load(Ordering::Relaxed);
+        if matches.len() > limit {
+            // workspace/symbol has no truncation flag, so say so in the client's log
+            let message = format!(
+                "Showing the best {} of {} workspace symbols matching {:?}",
+                limit,
+                matches.len(),
+                query
+            );
+            info!("{}", message);
+            self.client.log_message(MessageType::INFO, message).await;
+        }
+
+        #[allow(deprecated)]
+        let results: Vec<SymbolInformation> = matches
+            .into_iter()
+            .take(limit)
+            .filter_map(|(path, symbol)| {
+                Some(SymbolInformation {
+                    name: symbol.name,
+                    kind: symbol.kind,
+                    tags: None,
+                    deprecated: None,
+                    location: Location {
+                        uri: Url::from_file_path(&path).ok()?,
+                        range: symbol.range,
+                    },
+                    container_name: None,
+                })
+            })
+            .collect();
+
+        let Some(token) = params.partial_result_params.partial_result_token else {
+            return Ok(Some(results));
+        };
+        for page in results.chunks(WORKSPACE_SYMBOL_PAGE_SIZE) {
+            self.client
+                .send_notification::<WorkspaceSymbolPage>(WorkspaceSymbolPageParams {
+                    token: token.clone(),
+                    value: page.to_vec(),
+                })
+                .await;
+        }
+        // Every result went out as a partial result, so the response itself is empty
+        Ok(Some(Vec::new()))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> LspResult<Option<SemanticTokensResult>> {
+        let uri = params.text_document.uri;
+        debug!("Semantic tokens requested for {}", uri);
+
+        let Some(document) = self.documents.get(&uri) else {
+            return Ok(None);
+        };
+        let data = semantic_tokens::tokenize(&document.text, &document.language_id);
+
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data,
+        })))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> LspResult<Option<Vec<CodeLens>>> {
+        let uri = params.text_document.uri;
+        info!("Code lenses requested for {}", uri);
+
+        let Some((text, language_id)) = self.document_text(&uri) else {
+            return Ok(None);
+        };
+
+        let lines: Vec<&str> = text.lines().collect();
+        let lenses = symbols::scan_symbols(&text, &language_id)
+            .into_iter()
+            // Only definitions starting in the first column, not methods or nested items
+            .filter(|symbol| {
+                lines
+                    .get(symbol.range.start.line as usize)
+                    .is_some_and(|line| !line.starts_with(char::is_whitespace))
+            })
+            .map(|symbol| {
+                let (title, command) = match symbol.kind {
+                    SymbolKind::FUNCTION | SymbolKind::METHOD => {
+                        ("Explain with Claude", "claude-code.explain")
+                    }
+                    _ => ("Ask Claude", "claude-code.ask"),
+                };
+                CodeLens {
+                    range: symbol.range,
+                    command: Some(Command {
+                        title: title.to_string(),
+                        command: command.to_string(),
+                        arguments: Some(vec![serde_json::json!({
+                            "uri": uri,
+                            "name": symbol.name,
+                            "range": symbol.range
+                        })]),
+                    }),
+                    data: None,
+                }
+            })
+            .collect();
+
+        Ok(Some(lenses))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> LspResult<Option<Vec<InlayHint>>> {
+        let uri = params.text_document.uri;
+        debug!("Inlay hints requested for {}", uri);
+
+        if !self.inlay_hints.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let Ok(path) = uri.to_file_path() else {
+            return Ok(None);
+        };
+        // Open documents are indexed from their buffers; others may have changed on disk
+        if !self.documents.contains(&uri) {
+            self.symbols.refresh_file(&path);
+        }
+        let Some((text, _)) = self.document_text(&uri) else {
+            return Ok(None);
+        };
+
+        let lines: Vec<&str> = text.lines().collect();
+        let visible = params.range.start.line..=params.range.end.line;
+        let hints = self
+            .symbols
+            .file_symbols(&path)
+            .into_iter()
+            .filter(|symbol| matches!(symbol.kind, SymbolKind::FUNCTION | SymbolKind::METHOD))
+            .filter(|symbol| visible.contains(&symbol.range.start.line))
+            .filter_map(|symbol| {
+                let line = lines.get(symbol.range.start.line as usize)?;
+                // Before the definition's first keyword, after any indentation
+                let indent = line
+                    .chars()
+                    .take_while(|c| c.is_whitespace())
+                    .map(char::len_utf16)
+                    .sum::<usize>();
+                Some(InlayHint {
+                    position: Position::new(symbol.range.start.line, indent as u32),
+                    label: InlayHintLabel::LabelParts(vec![InlayHintLabelPart {
+                        value: "Explain".to_string(),
+                        tooltip: Some(InlayHintLabelPartTooltip::String(format!(
+                            "Explain {} with Claude",
+                            symbol.name
+                        ))),
+                        location: None,
+                        command: Some(Command {
+                            title: "Explain with Claude".to_string(),
+                            command: "claude-code.explain".to_string(),
+                            arguments: Some(vec![serde_json::json!({
+                                "uri": uri,
+                                "name": symbol.name,
+                                "range": symbol.range
+                            })]),
+                        }),
+                    }]),
+                    kind: None,
+                    text_edits: None,
+                    tooltip: None,
+                    padding_left: None,
+                    padding_right: Some(true),
+                    data: None,
+                })
+            })
+            .collect();
+
+        Ok(Some(hints))
+    }
+
     async fn code_action(&self, params: CodeActionParams) -> LspResult<Option<CodeActionResponse>> {
         info!("Code action requested for range: {:?}", params.range);
 
+        // The editor includes the diagnostics it shows for the requested range
+        self.diagnostics.update_range(
+            &params.text_document.uri,
+            params.range,
+            params.context.diagnostics.clone(),
+        );
+
+        *self.last_selection.lock().unwrap() =
+            Some((params.text_document.uri.clone(), params.range));
+
         // Send selection_changed notification when code action is requested
         let selected_text =
             self.read_text_from_range(params.text_document.uri.path(), params.range);
@@ -337,18 +1137,21 @@ impl LanguageServer for ClaudeCodeLanguageServer {
             "Sending selection_changed notification for range: {:?}",
             params.range
         );
-        self.send_notification(
-            "selection_changed",
-            serde_json::to_value(selection_notification).unwrap(),
-        )
-        .await;
+        self.publish_selection(selection_notification).await;
 
         let actions = vec![CodeActionOrCommand::CodeAction(CodeAction {
             title: "Explain with Claude".to_string(),
             kind: Some(CodeActionKind::REFACTOR),
             diagnostics: None,
             edit: None,
-            command: None,
+            command: Some(Command {
+                title: "Explain with Claude".to_string(),
+                command: "claude-code.explain".to_string(),
+                arguments: Some(vec![serde_json::json!({
+                    "uri": params.text_document.uri,
+                    "range": params.range
+                })]),
+            }),
             is_preferred: Some(false),
             disabled: None,
             data: Some(serde_json::json!({
@@ -364,30 +1167,42 @@ impl LanguageServer for ClaudeCodeLanguageServer {
     async fn execute_command(&self, params: ExecuteCommandParams) -> LspResult<Option<Value>> {
         info!("Execute command: {}", params.command);
 
+        let prompt = params
+            .command
+            .strip_prefix("claude-code.")
+            .and_then(prompts::find);
+        if let Some(prompt) = prompt {
+            self.ask_claude(prompt, params.arguments.first()).await;
+            return Ok(None);
+        }
+
         match params.command.as_str() {
-            "claude-code.explain" => {
-                self.client
-                    .show_message(
-                        MessageType::INFO,
-                        "Claude Code: Explain command executed (not yet implemented)",
-                    )
-                    .await;
-            }
-            "claude-code.improve" => {
-                self.client
-                    .show_message(
-                        MessageType::INFO,
-                        "Claude Code: Improve command executed (not yet implemented)",
-                    )
-                    .await;
-            }
-            "claude-code.fix" => {
-                self.client
-                    .show_message(
-                        MessageType::INFO,
-                        "Claude Code: Fix command executed (not yet implemented)",
+            "claude-code.ask" => {
+                // Mention the symbol's definition line so Claude picks it up as context
+                let symbol = params.arguments.first();
+                let file_path = symbol
+                    .and_then(|s| s.get("uri"))
+                    .and_then(|v| v.as_str())
+                    .and_then(|uri| Url::parse(uri).ok())
+                    .map(|uri| uri.path().to_string());
+                let line = symbol
+                    .and_then(|s| s.pointer("/range/start/line"))
+                    .and_then(|v| v.as_u64());
+
+                if let (Some(file_path), Some(line)) = (file_path, line) {
+                    let at_mention_notification = AtMentionedNotification {
+                        file_path,
+                        line_start: line as u32,
+                        line_end: line as u32,
+                    };
+                    self.send_notification(
+                        "at_mentioned",
+                        serde_json::to_value(at_mention_notification).unwrap(),
                     )
                     .await;
+                } else {
+                    warn!("Ignoring claude-code.ask without a symbol location");
+                }
             }
             "claude-code.at-mention" => {
                 info!(
@@ -503,41 +1318,227 @@ impl LanguageServer for ClaudeCodeLanguageServer {
                 },
             };
 
-            self.send_notification(
-                "selection_changed",
-                serde_json::to_value(selection_notification).unwrap(),
-            )
-            .await;
+            self.publish_selection(selection_notification).await;
         }
 
         Ok(Some(ranges))
     }
 }
 
+/// Range covering all of `text`
+fn whole_document_range(text: &str) -> Range {
+    let last_line = text.split('\n').next_back().unwrap_or("");
+    Range {
+        start: Position::new(0, 0),
+        end: Position::new(
+            text.matches('\n').count() as u32,
+            last_line.encode_utf16().count() as u32,
+        ),
+    }
+}
+
+/// Edits tidying `text` before it is saved: trailing spaces and tabs are removed
+/// and a missing final newline is added.
+///
+/// Markdown keeps its trailing whitespace, where two spaces mark a line break.
+fn save_cleanup_edits(text: &str, language_id: &str) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    if language_id != "markdown" {
+        for (line_number, line) in text.split('\n').enumerate() {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let trimmed = line.trim_end_matches([' ', '\t']);
+            if trimmed.len() == line.len() {
+                continue;
+            }
+            let line_number = line_number as u32;
+            edits.push(TextEdit {
+                range: Range {
+                    start: Position::new(line_number, trimmed.encode_utf16().count() as u32),
+                    end: Position::new(line_number, line.encode_utf16().count() as u32),
+                },
+                new_text: String::new(),
+            });
+        }
+    }
+
+    if !text.is_empty() && !text.ends_with('\n') {
+        let end = whole_document_range(text).end;
+        match edits.last_mut() {
+            // Edits may not overlap, so the newline replaces whitespace trimmed at the end
+            Some(last) if last.range.end == end => last.new_text = "\n".to_string(),
+            _ => edits.push(TextEdit {
+                range: Range { start: end, end },
+                new_text: "\n".to_string(),
+            }),
+        }
+    }
+    edits
+}
+
+/// Index the symbols of every source file below `roots`, reporting progress to the
+/// editor when `report_progress` is set.
+///
+/// Files are indexed in batches on the blocking pool, and the index keeps only each
+/// file's symbols, not its contents.
+async fn index_workspace(
+    client: Client,
+    symbols: SymbolIndex,
+    roots: Vec<PathBuf>,
+    report_progress: bool,
+) {
+    let token = ProgressToken::String(STARTUP_INDEX_PROGRESS_TOKEN.to_string());
+    let report_progress = report_progress
+        && client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })
+            .await
+            .is_ok();
+    let progress = |value: WorkDoneProgress| {
+        let client = client.clone();
+        let token = token.clone();
+        async move {
+            if report_progress {
+                client
+                    .send_notification::<notification::Progress>(ProgressParams {
+                        token,
+                        value: ProgressParamsValue::WorkDone(value),
+                    })
+                    .await;
+            }
+        }
+    };
+
+    progress(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+        title: "Indexing symbols".to_string(),
+        cancellable: Some(false),
+        message: None,
+        percentage: Some(0),
+    }))
+    .await;
+
+    let files = tokio::task::spawn_blocking(move || {
+        roots
+            .iter()
+            .flat_map(|root| symbols::workspace_source_files(root))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+
+    let mut indexed = 0;
+    for batch in files.chunks(STARTUP_INDEX_BATCH_SIZE) {
+        let batch = batch.to_vec();
+        let symbols = symbols.clone();
+        indexed += batch.len();
+        tokio::task::spawn_blocking(move || {
+            for path in &batch {
+                symbols.refresh_file(path);
+            }
+        })
+        .await
+        .ok();
+
+        progress(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(false),
+            message: Some(format!("{}/{} files", indexed, files.len())),
+            percentage: Some((indexed * 100 / files.len()) as u32),
+        }))
+        .await;
+    }
+
+    info!("Indexed symbols of {} workspace files", files.len());
+    progress(WorkDoneProgress::End(WorkDoneProgressEnd {
+        message: Some(format!("Indexed {} files", files.len())),
+    }))
+    .await;
+}
+
+/// Send queued file changes to Claude clients as `file_changed` notifications,
+/// followed by a single `watch_overflow` when some were dropped
+async fn forward_file_changes(queue: Arc<FileChangeQueue>, sender: Arc<NotificationSender>) {
+    loop {
+        let (changes, dropped) = queue.next_batch().await;
+        let mut notifications: Vec<JsonRpcNotification> = changes
+            .into_iter()
+            .map(|change| JsonRpcNotification {
+                jsonrpc: "2.0".to_string(),
+                method: "file_changed".to_string(),
+                params: serde_json::json!({
+                    "filePath": change.path,
+                    "changeType": change.change_type
+                }),
+            })
+            .collect();
+        if dropped > 0 {
+            warn!("Dropped {} file changes during a burst", dropped);
+            notifications.push(JsonRpcNotification {
+                jsonrpc: "2.0".to_string(),
+                method: "watch_overflow".to_string(),
+                params: serde_json::json!({ "dropped": dropped }),
+            });
+        }
+
+        for notification in notifications {
+            if let Err(e) = sender.send(notification) {
+                debug!("Failed to send notification: {}", e);
+            }
+        }
+    }
+}
+
 pub async fn run_lsp_server(worktree: Option<PathBuf>) -> Result<()> {
-    run_lsp_server_with_notifications(worktree, None).await
+    run_lsp_server_with_notifications(worktree, None, None, None).await
 }
 
 pub async fn run_lsp_server_with_notifications(
     worktree: Option<PathBuf>,
     notification_sender: Option<Arc<NotificationSender>>,
+    editor: Option<EditorBridge>,
+    shared: Option<SharedState>,
 ) -> Result<()> {
     info!("Starting LSP server mode");
     if let Some(path) = &worktree {
         info!("Worktree path: {}", path.display());
     }
 
+    // Nothing would ever arrive from a terminal, so fail instead of waiting forever
+    if std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "LSP mode reads JSON-RPC from stdin and must be started by an editor, not from a terminal"
+        ));
+    }
+
     let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+    // tower-lsp writes every response and notification through one framed sink, so
+    // messages never interleave; buffering turns each large message into a few big
+    // writes, and the sink flushes whenever it runs out of queued messages
+    let stdout = tokio::io::BufWriter::new(tokio::io::stdout());
 
-    let (service, socket) = LspService::new(|client| {
+    let (service, socket) = LspService::build(|client| {
         let mut server = ClaudeCodeLanguageServer::new(client, worktree.clone());
         if let Some(sender) = notification_sender.clone() {
             server = server.with_notification_sender(sender);
         }
+        if let Some(editor) = editor.clone() {
+            server = server.with_editor_bridge(editor);
+        }
+        if let Some(shared) = shared.clone() {
+            server = server.with_shared_state(shared);
+        }
         server
-    });
+    })
+    .custom_method("$/setTrace", ClaudeCodeLanguageServer::set_trace)
+    .finish();
+    let initialized = service.inner().initialized.clone();
     Server::new(stdin, stdout, socket).serve(service).await;
 
+    // An input that ends before `initialize` was never connected to an editor
+    if !initialized.load(Ordering::Relaxed) {
+        return Err(anyhow!(
+            "stdin closed before an editor sent initialize; LSP mode must be started by an editor"
+        ));
+    }
+
     Ok(())
 }
@@ -1,14 +1,96 @@
+// Modules shared by the LSP and WebSocket halves are only partly used when one is compiled out
+#![cfg_attr(not(all(feature = "lsp", feature = "websocket")), allow(dead_code))]
+
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
-use tracing::{error, info};
+#[cfg(feature = "websocket")]
+use std::time::Duration;
+#[cfg(all(feature = "lsp", feature = "websocket"))]
+use tracing::error;
+use tracing::info;
+
+#[cfg(not(any(feature = "lsp", feature = "websocket")))]
+compile_error!("enable at least one of the `lsp` and `websocket` features");
 
+#[cfg(feature = "websocket")]
+mod auth;
+#[cfg(feature = "websocket")]
+mod config;
+#[cfg(any(feature = "lsp", feature = "websocket"))]
+mod diagnostics;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod diff_tabs;
+#[cfg(any(feature = "lsp", feature = "websocket"))]
+mod documents;
+#[cfg(any(feature = "lsp", feature = "websocket"))]
+mod editor;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod error;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod file_cache;
+#[cfg(feature = "lsp")]
+mod file_changes;
+#[cfg(any(feature = "lsp", feature = "mcp"))]
+mod formatter;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod git;
+#[cfg(feature = "websocket")]
+mod http;
+#[cfg(feature = "websocket")]
+mod listener;
+mod logging;
+#[cfg(feature = "lsp")]
 mod lsp;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
 mod mcp;
+mod notifications;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod paths;
+mod prompts;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod resources;
+#[cfg(any(feature = "lsp", feature = "websocket"))]
+mod selection;
+#[cfg(feature = "lsp")]
+mod semantic_tokens;
+#[cfg(any(feature = "lsp", feature = "websocket"))]
+mod shared;
+mod startup;
+#[cfg(any(feature = "lsp", feature = "websocket"))]
+mod symbols;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod tail;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod test_runner;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod tool_cache;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod tools;
+mod version;
+#[cfg(feature = "websocket")]
 mod websocket;
+#[cfg(all(feature = "mcp", any(feature = "lsp", feature = "websocket")))]
+mod write_locks;
 
-use lsp::{run_lsp_server, run_lsp_server_with_notifications};
-use websocket::{run_websocket_server, run_websocket_server_with_notifications};
+#[cfg(all(feature = "lsp", feature = "websocket"))]
+use editor::EditorBridge;
+#[cfg(feature = "lsp")]
+use lsp::run_lsp_server;
+#[cfg(all(feature = "lsp", feature = "websocket"))]
+use lsp::run_lsp_server_with_notifications;
+#[cfg(all(feature = "lsp", feature = "websocket"))]
+use shared::SharedState;
+#[cfg(feature = "lsp")]
+use startup::StartupInfo;
+#[cfg(feature = "websocket")]
+use tools::{PathStyle, SecurityPolicy};
+#[cfg(all(feature = "lsp", feature = "websocket"))]
+use websocket::run_websocket_server_with_notifications;
+#[cfg(feature = "websocket")]
+use websocket::{
+    run_websocket_server, run_websocket_server_with_worktree, Transport, WebSocketOptions,
+};
 
 #[derive(Parser)]
 #[command(name = "claude-code-server")]
@@ -24,25 +106,150 @@ struct Cli {
     /// Worktree root path (for LSP mode)
     #[arg(long)]
     worktree: Option<PathBuf>,
+
+    /// Server to run when no subcommand is given (default: lsp with --worktree, else hybrid)
+    #[cfg(all(feature = "lsp", feature = "websocket"))]
+    #[arg(long, value_enum, value_name = "MODE")]
+    default_mode: Option<DefaultMode>,
+
+    /// Don't log WebSocket handshake headers, even at debug level
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    no_handshake_logging: bool,
+
+    /// Indent JSON-RPC responses and notifications sent to clients, for debugging
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    pretty_responses: bool,
+
+    /// Disable tools that execute code or run external commands
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    no_exec: bool,
+
+    /// Shut down after this many seconds without a connected Claude client
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "SECS")]
+    idle_timeout: Option<u64>,
+
+    /// Serve MCP over HTTP (POST /mcp, with server-sent events on GET /mcp) instead of WebSocket
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    http: bool,
+
+    /// Let the getEnvironment tool report this variable too; secret-looking names stay hidden
+    #[cfg(feature = "websocket")]
+    #[arg(long = "expose-env", value_name = "NAME")]
+    expose_env: Vec<String>,
+
+    /// Address to listen on; anything but loopback makes the port reachable from other hosts
+    #[cfg(feature = "websocket")]
+    #[arg(long = "bind", value_name = "ADDR", default_value = "127.0.0.1")]
+    bind_address: std::net::IpAddr,
+
+    /// Comma-separated browser origins allowed to connect when bound to a non-loopback address
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "ORIGINS", value_delimiter = ',')]
+    allowed_origins: Vec<String>,
+
+    /// Let write tools change files below this directory even though it is outside the workspace
+    #[cfg(feature = "websocket")]
+    #[arg(long = "allow-paths", value_name = "PATH")]
+    allow_paths: Vec<PathBuf>,
+
+    /// JSON file of disabled tools and security settings, re-read on `reloadConfig`
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Refuse to read files larger than this many bytes in read tools
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "BYTES", default_value_t = tools::DEFAULT_MAX_FILE_SIZE, value_parser = clap::value_parser!(u64).range(1..))]
+    max_file_size: u64,
+
+    /// Reuse results of identical getDiagnostics, getWorkspaceFolders and statFile calls
+    /// for this many milliseconds; 0 disables the cache
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "MS", default_value_t = tool_cache::DEFAULT_TOOL_CACHE_TTL.as_millis() as u64)]
+    tool_cache_ttl_ms: u64,
+
+    /// Write workspace file paths in tool responses in full or relative to the workspace folder
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_enum, value_name = "STYLE", default_value_t = PathStyle::Absolute)]
+    path_style: PathStyle,
+
+    /// Replace the auth token in the lock file every this many seconds
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    rotate_token_secs: Option<u64>,
+
+    /// Send each client a keepalive ping every this many seconds
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "SECS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    ping_interval_secs: u64,
+
+    /// Close connections that have sent nothing for this many seconds; must exceed the ping interval
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "SECS", default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    ping_timeout_secs: u64,
+
+    /// Let a reconnecting client resume its session with its reconnectToken for this many seconds
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "SECS", default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    reconnect_window_secs: u64,
+
+    /// Close connections that haven't sent initialize within this many seconds
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "SECS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    initialize_timeout_secs: u64,
+}
+
+/// Server `--default-mode` can start in place of a subcommand
+#[cfg(all(feature = "lsp", feature = "websocket"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DefaultMode {
+    Lsp,
+    Websocket,
+    Hybrid,
+}
+
+#[cfg(all(feature = "lsp", feature = "websocket"))]
+impl DefaultMode {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Lsp => "lsp",
+            Self::Websocket => "websocket",
+            Self::Hybrid => "hybrid",
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Mode {
     /// Run as LSP server for Zed extension communication
+    #[cfg(feature = "lsp")]
     Lsp {
         /// Worktree root path
         #[arg(long)]
         worktree: Option<PathBuf>,
     },
     /// Run as standalone WebSocket server for Claude Code CLI
+    #[cfg(feature = "websocket")]
     Websocket {
-        /// WebSocket server port (default: 59791)
+        /// WebSocket server port (default: 59791, 0 picks a free port)
         #[arg(long, short)]
         port: Option<u16>,
+        /// Print the startup report to stdout as JSON once the server is listening
+        #[arg(long)]
+        print_startup_info: bool,
+        /// Listen on a Unix domain socket at this path instead of a TCP port
+        #[arg(long, value_name = "PATH", conflicts_with = "port")]
+        socket: Option<PathBuf>,
     },
     /// Run both LSP and WebSocket servers
+    #[cfg(all(feature = "lsp", feature = "websocket"))]
     Hybrid {
-        /// WebSocket server port (default: 59791)
+        /// WebSocket server port (default: 59791, 0 picks a free port)
         #[arg(long, short)]
         port: Option<u16>,
         /// Worktree root path
@@ -70,44 +277,128 @@ async fn main() -> Result<()> {
         }
     };
 
-    let subscriber = tracing_subscriber::fmt()
-        .with_max_level(log_level)
-        .with_file(true)
-        .with_line_number(true)
-        .with_thread_ids(true)
-        .with_target(false)
-        .with_writer(std::io::stderr) // Force all logs to stderr for LSP compatibility
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+    logging::init(log_level.into())?;
 
     info!("Logging initialized at level: {:?}", log_level);
 
     info!("Claude Code Server starting...");
 
+    #[cfg(feature = "websocket")]
+    let websocket_options = WebSocketOptions {
+        log_handshakes: !cli.no_handshake_logging,
+        security: SecurityPolicy {
+            allow_exec: !cli.no_exec,
+            exposed_env_vars: cli.expose_env,
+            allowed_paths: cli.allow_paths,
+        },
+        config: cli.config,
+        max_file_size: cli.max_file_size,
+        tool_cache_ttl: Duration::from_millis(cli.tool_cache_ttl_ms),
+        path_style: cli.path_style,
+        idle_timeout: cli.idle_timeout.map(Duration::from_secs),
+        token_rotation: cli.rotate_token_secs.map(Duration::from_secs),
+        ping_interval: Duration::from_secs(cli.ping_interval_secs),
+        ping_timeout: Duration::from_secs(cli.ping_timeout_secs),
+        initialize_timeout: Duration::from_secs(cli.initialize_timeout_secs),
+        reconnect_window: Duration::from_secs(cli.reconnect_window_secs),
+        transport: if cli.http {
+            Transport::Http
+        } else {
+            Transport::WebSocket
+        },
+        bind_address: cli.bind_address,
+        allowed_origins: cli.allowed_origins,
+        pretty_responses: cli.pretty_responses,
+        ..WebSocketOptions::default()
+    };
+
     match cli.mode {
+        #[cfg(feature = "lsp")]
         Some(Mode::Lsp { worktree }) => {
             let worktree_path = cli.worktree.or(worktree);
+            StartupInfo::new("lsp", worktree_path.as_deref()).log();
             run_lsp_server(worktree_path).await
         }
-        Some(Mode::Websocket { port }) => run_websocket_server(port).await,
+        #[cfg(feature = "websocket")]
+        Some(Mode::Websocket {
+            port,
+            print_startup_info,
+            socket,
+        }) => {
+            let websocket_options = WebSocketOptions {
+                print_startup_info,
+                socket,
+                ..websocket_options
+            };
+            run_websocket_server(port, websocket_options).await
+        }
+        #[cfg(all(feature = "lsp", feature = "websocket"))]
         Some(Mode::Hybrid { port, worktree }) => {
             let worktree_path = cli.worktree.or(worktree);
-            run_hybrid_server(port, worktree_path).await
+            run_hybrid_server(port, worktree_path, websocket_options).await
+        }
+        // Such builds stop at the `compile_error!` above; this arm keeps it the only error
+        #[cfg(not(any(feature = "lsp", feature = "websocket")))]
+        None => unreachable!(),
+        #[cfg(all(feature = "lsp", not(feature = "websocket")))]
+        None => {
+            info!("No mode specified, running lsp mode, the only one in this build");
+            StartupInfo::new("lsp", cli.worktree.as_deref()).log();
+            run_lsp_server(cli.worktree).await
+        }
+        #[cfg(all(feature = "websocket", not(feature = "lsp")))]
+        None => {
+            info!("No mode specified, running websocket mode, the only one in this build");
+            run_websocket_server_with_worktree(None, cli.worktree, websocket_options).await
         }
+        #[cfg(all(feature = "lsp", feature = "websocket"))]
         None => {
-            // Default mode: try to detect what we should run based on arguments
-            if cli.worktree.is_some() {
-                info!("No mode specified but worktree provided, running LSP mode...");
-                run_lsp_server(cli.worktree).await
-            } else {
-                info!("No mode specified, running in hybrid mode...");
-                run_hybrid_server(None, cli.worktree).await
+            let mode = match cli.default_mode {
+                Some(mode) => {
+                    info!(
+                        "No mode specified, running {} mode as --default-mode asks",
+                        mode.name()
+                    );
+                    mode
+                }
+                // Editors configured before --default-mode pass only a worktree and expect LSP
+                None if cli.worktree.is_some() => {
+                    info!(
+                        "No mode specified but a worktree was given, defaulting to lsp mode; \
+                         pass a subcommand or --default-mode to choose"
+                    );
+                    DefaultMode::Lsp
+                }
+                None => {
+                    info!(
+                        "No mode specified, defaulting to hybrid mode, which serves both LSP on stdio \
+                         and WebSocket for Claude; pass a subcommand or --default-mode to choose"
+                    );
+                    DefaultMode::Hybrid
+                }
+            };
+            match mode {
+                DefaultMode::Lsp => {
+                    StartupInfo::new("lsp", cli.worktree.as_deref()).log();
+                    run_lsp_server(cli.worktree).await
+                }
+                DefaultMode::Websocket => {
+                    run_websocket_server_with_worktree(None, cli.worktree, websocket_options).await
+                }
+                DefaultMode::Hybrid => {
+                    run_hybrid_server(None, cli.worktree, websocket_options).await
+                }
             }
         }
     }
 }
 
-async fn run_hybrid_server(port: Option<u16>, worktree: Option<PathBuf>) -> Result<()> {
+#[cfg(all(feature = "lsp", feature = "websocket"))]
+async fn run_hybrid_server(
+    port: Option<u16>,
+    worktree: Option<PathBuf>,
+    websocket_options: WebSocketOptions,
+) -> Result<()> {
     info!("Starting hybrid server (LSP + WebSocket)");
     if let Some(path) = &worktree {
         info!("Worktree path: {}", path.display());
@@ -116,16 +407,29 @@ async fn run_hybrid_server(port: Option<u16>, worktree: Option<PathBuf>) -> Resu
     // Create notification channel for LSP -> WebSocket communication
     let (notification_sender, notification_receiver) = tokio::sync::broadcast::channel(100);
     let notification_sender = std::sync::Arc::new(notification_sender);
+    // Lets WebSocket clients ask the editor to act on their behalf
+    let editor = EditorBridge::new();
+    // One set of stores: what the editor reports over LSP is what WebSocket tools read
+    let shared = SharedState::new();
+    let websocket_options = WebSocketOptions {
+        mode: "hybrid",
+        ..websocket_options
+    };
 
     // In hybrid mode, we run both servers with notification bridge
     let websocket_handle = tokio::spawn(run_websocket_server_with_notifications(
         port, 
         worktree.clone(), 
-        Some(notification_receiver)
+        Some(notification_receiver),
+        editor.clone(),
+        shared.clone(),
+        websocket_options,
     ));
     let lsp_handle = tokio::spawn(run_lsp_server_with_notifications(
         worktree, 
-        Some(notification_sender)
+        Some(notification_sender),
+        Some(editor),
+        Some(shared),
     ));
 
     // Wait for either to complete (or fail)
@@ -1,7 +1,66 @@
 use anyhow::Result;
+use lsp_types::Url;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tracing::{debug, info};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+
+use crate::error::{ServerError, ERROR_PARSE};
+use crate::paths;
+use crate::prompts;
+use crate::resources::ResourceSubscriptions;
+use crate::selection::SelectionChangedNotification;
+use crate::tail::{self, NotificationSink, TailSessions, DEFAULT_TAIL_LINES, MAX_TAIL_LINES};
+use crate::tools::{self, ToolContext, ToolError, TOOL_ERROR_INTERNAL, TOOL_ERROR_INVALID_PARAMS};
+use crate::version;
+
+/// MCP protocol revisions this server speaks, newest first
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+/// First protocol revision whose tool results may carry `structuredContent`
+const STRUCTURED_CONTENT_VERSION: &str = "2025-06-18";
+
+/// Suggested client reconnect backoff, advertised by `get_capabilities`
+const RECONNECT_INITIAL_DELAY_MS: u64 = 500;
+const RECONNECT_MAX_DELAY_MS: u64 = 30_000;
+const RECONNECT_BACKOFF_MULTIPLIER: f64 = 2.0;
+/// Fraction of each delay to randomize so clients don't reconnect in lockstep
+const RECONNECT_JITTER: f64 = 0.2;
+
+/// Deepest nesting of arrays and objects accepted in a message
+const MAX_JSON_DEPTH: usize = 64;
+
+/// Methods clients send as notifications besides those under `notifications/`
+const NOTIFICATION_METHODS: &[&str] = &["selection_changed", "at_mentioned"];
+
+/// Reports transport-level state, such as connection round-trip times, for `get_status`
+pub type StatusProvider = Arc<dyn Fn() -> Value + Send + Sync>;
+
+/// Re-reads the server's configuration file for `reloadConfig`
+pub type ConfigReloader = Arc<dyn Fn() -> Result<Value, ToolError> + Send + Sync>;
+
+/// Issues the `reconnectToken` that resumes the given session
+pub type ReconnectIssuer = Arc<dyn Fn(SharedSession) -> String + Send + Sync>;
+
+/// What a client set up on its connection, restored when it resumes the session on
+/// another connection with a `reconnectToken`
+#[derive(Debug, Clone, Default)]
+pub struct ResumableSession {
+    pub protocol_version: String,
+    /// `clientInfo` sent with `initialize`
+    pub client_info: Option<Value>,
+    /// `capabilities` sent with `initialize`
+    pub client_capabilities: Option<Value>,
+    /// URIs of subscribed resources, keyed by normalized path
+    pub subscriptions: BTreeMap<PathBuf, String>,
+}
+
+/// A connection's session, shared with the reconnect token issued for it so the token
+/// resumes the session as it stood when the connection ended
+pub type SharedSession = Arc<Mutex<ResumableSession>>;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MCPRequest {
@@ -32,6 +91,7 @@ pub struct MCPError {
 pub struct ServerCapabilities {
     pub tools: Option<ToolsCapability>,
     pub prompts: Option<PromptsCapability>,
+    pub resources: Option<ResourcesCapability>,
     pub logging: Option<LoggingCapability>,
 }
 
@@ -48,13 +108,14 @@ pub struct PromptsCapability {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct LoggingCapability {}
+pub struct ResourcesCapability {
+    pub subscribe: Option<bool>,
+    #[serde(rename = "listChanged")]
+    pub list_changed: Option<bool>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ServerInfo {
-    pub name: String,
-    pub version: String,
-}
+pub struct LoggingCapability {}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Tool {
@@ -73,10 +134,20 @@ pub struct TextContent {
 
 pub struct MCPServer {
     capabilities: ServerCapabilities,
+    tools: ToolContext,
+    notifications: Option<NotificationSink>,
+    status: Option<StatusProvider>,
+    reload: Option<ConfigReloader>,
+    reconnect: Option<ReconnectIssuer>,
+    tails: TailSessions,
+    subscriptions: ResourceSubscriptions,
+    session: SharedSession,
+    /// Whether the session negotiated a protocol version with structured tool results
+    structured_content: AtomicBool,
 }
 
 impl MCPServer {
-    pub fn new() -> Self {
+    pub fn new(tools: ToolContext) -> Self {
         let capabilities = ServerCapabilities {
             tools: Some(ToolsCapability {
                 list_changed: Some(true),
@@ -84,356 +155,491 @@ impl MCPServer {
             prompts: Some(PromptsCapability {
                 list_changed: Some(false),
             }),
+            resources: Some(ResourcesCapability {
+                subscribe: Some(true),
+                list_changed: Some(false),
+            }),
             logging: Some(LoggingCapability {}),
         };
 
-        Self { capabilities }
+        Self {
+            capabilities,
+            tools,
+            notifications: None,
+            status: None,
+            reload: None,
+            reconnect: None,
+            tails: TailSessions::default(),
+            subscriptions: ResourceSubscriptions::default(),
+            session: SharedSession::default(),
+            structured_content: AtomicBool::new(false),
+        }
+    }
+
+    /// Deliver server-initiated notifications for this connection through `sink`
+    pub fn with_notification_sink(mut self, sink: NotificationSink) -> Self {
+        self.notifications = Some(sink);
+        self
+    }
+
+    /// Answer `get_status` with the state reported by `status`
+    pub fn with_status_provider(mut self, status: StatusProvider) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Answer `reloadConfig` by running `reload`
+    pub fn with_config_reloader(mut self, reload: ConfigReloader) -> Self {
+        self.reload = Some(reload);
+        self
+    }
+
+    /// Include a `reconnectToken` from `issue` in the `initialize` result
+    pub fn with_reconnect_issuer(mut self, issue: ReconnectIssuer) -> Self {
+        self.reconnect = Some(issue);
+        self
+    }
+
+    /// Continue `session`, started on an earlier connection, including its subscriptions
+    pub fn resume(&self, session: ResumableSession) {
+        self.set_protocol_version(&session.protocol_version);
+        {
+            let mut current = self.session.lock().unwrap();
+            current.protocol_version = session.protocol_version;
+            current.client_info = session.client_info;
+            current.client_capabilities = session.client_capabilities;
+        }
+        for uri in session.subscriptions.values() {
+            if let Err(e) = self.subscribe(uri) {
+                warn!("Not resuming subscription to {}: {}", uri, e.message);
+            }
+        }
+    }
+
+    fn set_protocol_version(&self, protocol_version: &str) {
+        // Versions are ISO dates, so lexical order is chronological order
+        self.structured_content.store(
+            protocol_version >= STRUCTURED_CONTENT_VERSION,
+            Ordering::Relaxed,
+        );
     }
 
+    /// Handle one request inside its `request_span`, logging how long it took
     pub async fn handle_request(&self, request: MCPRequest) -> Result<MCPResponse> {
+        let span = request_span(&request);
+        async {
+            let started = Instant::now();
+            let response = self.dispatch(request).await;
+            let latency = started.elapsed();
+            info!(
+                latency_us = latency.as_micros() as u64,
+                "Handled MCP request in {:?}", latency
+            );
+            response
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn dispatch(&self, request: MCPRequest) -> Result<MCPResponse> {
         info!("Handling MCP request: {}", request.method);
         debug!("Request params: {:?}", request.params);
 
         let result = match request.method.as_str() {
-            "initialize" => self.handle_initialize(request.params).await?,
-            "tools/list" => self.handle_tools_list().await?,
-            "tools/call" => self.handle_tools_call(request.params).await?,
-            "logging/setLevel" => self.handle_logging_set_level(request.params).await?,
-            "prompts/list" => self.handle_prompts_list().await?,
-            "prompts/get" => self.handle_prompts_get(request.params).await?,
-            _ => {
-                return Ok(MCPResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id,
-                    result: None,
-                    error: Some(MCPError {
-                        code: -32601,
-                        message: format!("Method not found: {}", request.method),
-                        data: None,
-                    }),
-                });
+            // Reached only when a client sent a notification with an id by mistake
+            method if is_notification_method(method) => {
+                self.apply_notification(method, request.params)
             }
+            "initialize" => self.handle_initialize(request.params).await,
+            "tools/list" => self.handle_tools_list().await.map_err(Into::into),
+            "tools/call" => self.handle_tools_call(request.params).await,
+            "logging/setLevel" => self
+                .handle_logging_set_level(request.params)
+                .await
+                .map_err(Into::into),
+            "prompts/list" => self.handle_prompts_list().await.map_err(Into::into),
+            "set_clipboard" => self.handle_set_clipboard(request.params).await,
+            "get_clipboard" => self.handle_get_clipboard().await,
+            "get_status" => self.handle_get_status(),
+            "get_capabilities" => Ok(self.handle_get_capabilities()),
+            "reloadConfig" => self.handle_reload_config(),
+            "resources/subscribe" => self.handle_resources_subscribe(request.params),
+            "resources/unsubscribe" => self.handle_resources_unsubscribe(request.params),
+            "prompts/get" => self.handle_prompts_get(request.params),
+            _ => Err(ToolError::new(
+                -32601,
+                format!("Method not found: {}", request.method),
+            )),
         };
 
-        Ok(MCPResponse {
-            jsonrpc: "2.0".to_string(),
-            id: request.id,
-            result: Some(result),
-            error: None,
+        Ok(match result {
+            Ok(result) => MCPResponse {
+                jsonrpc: "2.0".to_string(),
+                id: request.id,
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => MCPResponse {
+                jsonrpc: "2.0".to_string(),
+                id: request.id,
+                result: None,
+                error: Some(MCPError {
+                    code: e.code,
+                    message: e.message,
+                    data: e.data,
+                }),
+            },
         })
     }
 
-    async fn handle_initialize(&self, params: Option<Value>) -> Result<Value> {
+    async fn handle_initialize(&self, params: Option<Value>) -> Result<Value, ToolError> {
         info!("Initializing MCP session");
 
-        if let Some(params) = params {
+        if let Some(params) = &params {
             debug!("Initialize params: {}", params);
         }
 
-        Ok(serde_json::json!({
-            "protocolVersion": "2025-03-26",
+        let requested = params
+            .as_ref()
+            .and_then(|p| p.get("protocolVersion"))
+            .and_then(|v| v.as_str());
+        let protocol_version = negotiate_protocol_version(requested)?;
+        info!("Using MCP protocol version {}", protocol_version);
+        self.set_protocol_version(protocol_version);
+        {
+            let mut session = self.session.lock().unwrap();
+            session.protocol_version = protocol_version.to_string();
+            session.client_info = params.as_ref().and_then(|p| p.get("clientInfo")).cloned();
+            session.client_capabilities =
+                params.as_ref().and_then(|p| p.get("capabilities")).cloned();
+        }
+
+        let mut result = serde_json::json!({
+            "protocolVersion": protocol_version,
             "capabilities": self.capabilities,
-            "serverInfo": ServerInfo {
-                name: "claude-code-server".to_string(),
-                version: "0.1.0".to_string()
-            }
-        }))
+            "serverInfo": version::BUILD_INFO
+        });
+        if let Some(issue) = &self.reconnect {
+            result["reconnectToken"] = Value::String(issue(self.session.clone()));
+        }
+        Ok(result)
     }
 
     async fn handle_tools_list(&self) -> Result<Value> {
         info!("Listing available tools");
 
-        let tools: Vec<Tool> = vec![];
+        let tools = tools::tool_definitions_for(&self.tools);
 
         Ok(serde_json::json!({
             "tools": tools
         }))
     }
 
-    async fn handle_tools_call(&self, params: Option<Value>) -> Result<Value> {
-        let params = params.ok_or_else(|| anyhow::anyhow!("Missing parameters for tools/call"))?;
+    async fn handle_tools_call(&self, params: Option<Value>) -> Result<Value, ToolError> {
+        let params = params.ok_or_else(|| {
+            ServerError::InvalidParams("Missing params for tools/call".to_string())
+        })?;
 
         let tool_name = params
             .get("name")
             .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing tool name"))?;
-
-        let default_args = serde_json::json!({});
-        let arguments = params.get("arguments").unwrap_or(&default_args);
+            .ok_or_else(|| ServerError::InvalidParams("Missing tool name".to_string()))?;
+
+        let arguments = match params.get("arguments") {
+            Some(arguments) if arguments.is_object() => arguments.clone(),
+            None | Some(Value::Null) => {
+                // Tools with no required inputs run on their defaults
+                let required = tools::required_arguments(tool_name);
+                if !required.is_empty() {
+                    return Err(ToolError::new(
+                        TOOL_ERROR_INVALID_PARAMS,
+                        format!(
+                            "Missing arguments object for {}, which requires {}",
+                            tool_name,
+                            required.join(", ")
+                        ),
+                    )
+                    .with_data(serde_json::json!({ "required": required })));
+                }
+                serde_json::json!({})
+            }
+            Some(_) => {
+                return Err(ServerError::InvalidParams(format!(
+                    "Arguments for {} must be an object",
+                    tool_name
+                ))
+                .into())
+            }
+        };
 
         info!("Calling tool: {}", tool_name);
         debug!("Tool arguments: {}", arguments);
 
-        let content = match tool_name {
-            "echo" => {
-                let text = arguments
-                    .get("text")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No text provided");
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: format!("Echo: {}", text),
-                }]
-            }
-            "get_workspace_info" => {
-                let workspace_info = std::env::current_dir()
-                    .map(|path| path.to_string_lossy().to_string())
-                    .unwrap_or_else(|_| "Unknown workspace".to_string());
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: format!("Current workspace: {}", workspace_info),
-                }]
-            }
-            "closeAllDiffTabs" => {
-                info!("Closing all diff tabs");
+        let argument_keys: Vec<String> = arguments
+            .as_object()
+            .map(|arguments| arguments.keys().cloned().collect())
+            .unwrap_or_default();
+        let content = self
+            .call_tool(tool_name, arguments)
+            .await
+            .map_err(|e| with_call_context(e, tool_name, argument_keys))?;
+
+        Ok(tools::tool_result(
+            content,
+            self.structured_content.load(Ordering::Relaxed),
+        ))
+    }
 
-                // Return the count of closed diff tabs according to protocol
-                let closed_count = 0; // Simulate no diff tabs to close
+    /// Run a tool, returning the content of its result
+    async fn call_tool(&self, tool_name: &str, arguments: Value) -> Result<Value, ToolError> {
+        if !tools::WORKSPACE_INDEPENDENT_TOOLS.contains(&tool_name) {
+            self.tools.require_workspace()?;
+        }
 
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: format!("CLOSED_{}_DIFF_TABS", closed_count),
-                }]
-            }
-            "openFile" => {
-                let file_path = arguments
-                    .get("filePath")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No file path provided");
-                let preview = arguments
-                    .get("preview")
-                    .and_then(|v| v.as_bool())
-                    .unwrap_or(false);
-                let _start_text = arguments.get("startText").and_then(|v| v.as_str());
-                let _end_text = arguments.get("endText").and_then(|v| v.as_str());
-                let make_frontmost = arguments
-                    .get("makeFrontmost")
-                    .and_then(|v| v.as_bool())
-                    .unwrap_or(true);
-
-                info!("Opening file: {} (preview: {})", file_path, preview);
-
-                if make_frontmost {
-                    // Simple response when making frontmost
-                    vec![TextContent {
-                        type_: "text".to_string(),
-                        text: format!("Opened file: {}", file_path),
-                    }]
-                } else {
-                    // Detailed JSON response when not making frontmost
-                    let response = serde_json::json!({
-                        "success": true,
-                        "filePath": std::path::Path::new(file_path).canonicalize()
-                            .map(|p| p.to_string_lossy().to_string())
-                            .unwrap_or_else(|_| file_path.to_string()),
-                        "languageId": "text",
-                        "lineCount": 0
-                    });
-
-                    vec![TextContent {
-                        type_: "text".to_string(),
-                        text: response.to_string(),
-                    }]
+        // Follows outlive the call and belong to this connection, so they bypass the handler table
+        let content = match tool_name {
+            "tailFile" => self.handle_tail_file(&arguments).await?,
+            "untailFile" => self.handle_untail_file(&arguments)?,
+            _ => {
+                let handler = self
+                    .tools
+                    .registry()
+                    .handler(tool_name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown tool: {}", tool_name))?;
+
+                // Read before the handler runs, so a change during the call leaves the entry stale
+                let cache_key = self.tools.cache_key(tool_name, &arguments);
+                let cache_state = self.tools.cache_state();
+                if let Some(content) = cache_key
+                    .as_deref()
+                    .and_then(|key| self.tools.tool_cache.get(key, cache_state))
+                {
+                    debug!("Reusing the cached result of {}", tool_name);
+                    return Ok(content);
                 }
+
+                // Handlers do blocking I/O and may run external processes
+                let context = self.tools.clone();
+                let result = tokio::task::spawn_blocking(move || handler(&context, &arguments))
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Tool {} panicked: {}", tool_name, e))?;
+                match (cache_key, &result) {
+                    (Some(key), Ok(content)) => {
+                        self.tools
+                            .tool_cache
+                            .insert(key, cache_state, content.clone())
+                    }
+                    (Some(_), Err(_)) => {}
+                    // Any other tool may have written what the cached ones read
+                    (None, _) => self.tools.tool_cache.clear(),
+                }
+                result?
             }
-            "getCurrentSelection" => {
-                info!("Getting current selection");
-
-                // Return JSON-stringified response according to protocol
-                let response = serde_json::json!({
-                    "success": false,
-                    "message": "No active editor found"
-                });
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: response.to_string(),
-                }]
-            }
-            "getOpenEditors" => {
-                info!("Getting open editors");
-
-                // Return JSON-stringified response according to protocol
-                let response = serde_json::json!({
-                    "tabs": []
-                });
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: response.to_string(),
-                }]
-            }
-            "getWorkspaceFolders" => {
-                let workspace_info = std::env::current_dir()
-                    .map(|path| path.to_string_lossy().to_string())
-                    .unwrap_or_else(|_| "Unknown workspace".to_string());
-
-                info!("Getting workspace folders");
-
-                // Return JSON-stringified response according to protocol
-                let response = serde_json::json!({
-                    "success": true,
-                    "folders": [{
-                        "name": std::path::Path::new(&workspace_info)
-                            .file_name()
-                            .and_then(|n| n.to_str())
-                            .unwrap_or("workspace"),
-                        "uri": format!("file://{}", workspace_info),
-                        "path": workspace_info
-                    }],
-                    "rootPath": workspace_info
-                });
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: response.to_string(),
-                }]
-            }
-            "openDiff" => {
-                let old_file_path = arguments
-                    .get("old_file_path")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No old file path provided");
-                let new_file_path = arguments
-                    .get("new_file_path")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No new file path provided");
-                let new_file_contents = arguments
-                    .get("new_file_contents")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No new file contents provided");
-                let _tab_name = arguments
-                    .get("tab_name")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("diff");
-
-                info!("Opening diff for {} vs {}", old_file_path, new_file_path);
-
-                // Always respond with FILE_SAVED to simulate accepting the diff
-                vec![
-                    TextContent {
-                        type_: "text".to_string(),
-                        text: "FILE_SAVED".to_string(),
-                    },
-                    TextContent {
-                        type_: "text".to_string(),
-                        text: new_file_contents.to_string(),
-                    },
-                ]
-            }
-            "getLatestSelection" => {
-                info!("Getting latest selection");
-
-                // Return JSON-stringified response according to protocol
-                let response = serde_json::json!({
-                    "success": false,
-                    "message": "No selection available"
-                });
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: response.to_string(),
-                }]
-            }
-            "getDiagnostics" => {
-                let uri = arguments.get("uri").and_then(|v| v.as_str());
-
-                info!("Getting diagnostics for: {:?}", uri);
-
-                // Return JSON-stringified array of diagnostics per file
-                let response = if let Some(uri) = uri {
-                    serde_json::json!([{
-                        "uri": uri,
-                        "diagnostics": []
-                    }])
-                } else {
-                    serde_json::json!([])
-                };
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: response.to_string(),
-                }]
-            }
-            "checkDocumentDirty" => {
-                let file_path = arguments
-                    .get("filePath")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No file path provided");
-
-                info!("Checking if document is dirty: {}", file_path);
-
-                // Return JSON-stringified response according to protocol
-                let response = serde_json::json!({
-                    "success": true,
-                    "filePath": file_path,
-                    "isDirty": false,
-                    "isUntitled": false
-                });
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: response.to_string(),
-                }]
-            }
-            "saveDocument" => {
-                let file_path = arguments
-                    .get("filePath")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No file path provided");
-
-                info!("Saving document: {}", file_path);
-
-                // Return JSON-stringified response according to protocol
-                let response = serde_json::json!({
-                    "success": true,
-                    "filePath": file_path,
-                    "saved": true,
-                    "message": "Document saved successfully"
-                });
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: response.to_string(),
-                }]
-            }
-            "close_tab" => {
-                let tab_name = arguments
-                    .get("tab_name")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No tab name provided");
-
-                info!("Closing tab: {}", tab_name);
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: "TAB_CLOSED".to_string(),
-                }]
-            }
-            "executeCode" => {
-                let code = arguments
-                    .get("code")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("No code provided");
-
-                info!(
-                    "Executing code: {}",
-                    code.chars().take(50).collect::<String>()
-                );
-
-                vec![TextContent {
-                    type_: "text".to_string(),
-                    text: format!("Code executed successfully. Output: (simulated execution of {} characters)", code.len()),
-                }]
-            }
-            _ => return Err(anyhow::anyhow!("Unknown tool: {}", tool_name)),
         };
 
+        Ok(content)
+    }
+
+    async fn handle_tail_file(&self, arguments: &Value) -> Result<Value, ToolError> {
+        let path = arguments
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+        let count = arguments
+            .get("lines")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(DEFAULT_TAIL_LINES)
+            .min(MAX_TAIL_LINES) as usize;
+        let follow = arguments
+            .get("follow")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let path = self.tools.resolve_path(path);
+        if !self.tools.contains_path(&path) {
+            return Err(ServerError::PathOutsideWorkspace(path).into());
+        }
+
+        info!("Tailing {} (follow: {})", path.display(), follow);
+        let read_path = path.clone();
+        let (lines, offset) =
+            tokio::task::spawn_blocking(move || tail::last_lines(&read_path, count))
+                .await
+                .map_err(|e| anyhow::anyhow!("tailFile panicked: {}", e))?
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+
+        let mut response = serde_json::json!({
+            "success": true,
+            "filePath": path,
+            "lines": lines
+        });
+        if follow {
+            let sink = self.notifications.clone().ok_or_else(|| {
+                ToolError::new(
+                    TOOL_ERROR_INTERNAL,
+                    "This connection cannot receive notifications",
+                )
+            })?;
+            response["tailId"] = Value::String(self.tails.start(path, offset, sink)?);
+        }
+
+        Ok(tools::text_content(response.to_string()))
+    }
+
+    fn handle_untail_file(&self, arguments: &Value) -> Result<Value, ToolError> {
+        let tail_id = arguments
+            .get("tailId")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ServerError::InvalidParams("Missing tailId".to_string()))?;
+
+        if !self.tails.stop(tail_id) {
+            return Err(ServerError::InvalidParams(format!("Unknown tailId: {}", tail_id)).into());
+        }
+
+        let response = serde_json::json!({ "success": true, "tailId": tail_id });
+        Ok(tools::text_content(response.to_string()))
+    }
+
+    /// Act on a notification from the client, which gets no response; malformed ones are
+    /// logged and ignored
+    pub fn handle_notification(&self, request: MCPRequest) {
+        if let Err(e) = self.apply_notification(&request.method, request.params) {
+            warn!("Ignoring malformed {}: {}", request.method, e.message);
+        }
+    }
+
+    fn apply_notification(&self, method: &str, params: Option<Value>) -> Result<Value, ToolError> {
+        if method == "selection_changed" {
+            let selection = parse_selection(params)?;
+            self.tools.selection.set(selection);
+        }
+        Ok(serde_json::json!({}))
+    }
+
+    fn handle_resources_subscribe(&self, params: Option<Value>) -> Result<Value, ToolError> {
+        self.subscribe(resource_uri(params.as_ref())?)?;
+        Ok(serde_json::json!({}))
+    }
+
+    /// Watch the file behind `uri` for this connection and remember it in the session
+    fn subscribe(&self, uri: &str) -> Result<(), ToolError> {
+        let path = resource_path(uri).ok_or_else(|| {
+            ServerError::InvalidParams(format!(
+                "Only file:// resources can be subscribed to: {}",
+                uri
+            ))
+        })?;
+        if !self.tools.contains_path(&path) {
+            return Err(ServerError::PathOutsideWorkspace(path).into());
+        }
+
+        let sink = self.notifications.clone().ok_or_else(|| {
+            ToolError::new(
+                TOOL_ERROR_INTERNAL,
+                "This connection cannot receive notifications",
+            )
+        })?;
+        self.subscriptions
+            .subscribe(uri.to_string(), path.clone(), sink);
+        self.session
+            .lock()
+            .unwrap()
+            .subscriptions
+            .entry(path)
+            .or_insert_with(|| uri.to_string());
+        Ok(())
+    }
+
+    fn handle_resources_unsubscribe(&self, params: Option<Value>) -> Result<Value, ToolError> {
+        let uri = resource_uri(params.as_ref())?;
+        let unsubscribed = resource_path(uri).is_some_and(|path| {
+            self.session.lock().unwrap().subscriptions.remove(&path);
+            self.subscriptions.unsubscribe(&path)
+        });
+        if !unsubscribed {
+            debug!("Ignoring unsubscribe from {}, which is not subscribed", uri);
+        }
+        Ok(serde_json::json!({}))
+    }
+
+    fn handle_get_status(&self) -> Result<Value, ToolError> {
+        let mut status = self
+            .status
+            .as_ref()
+            .map(|status| status())
+            .unwrap_or_else(|| serde_json::json!({}));
+        status["serverInfo"] = serde_json::json!(version::BUILD_INFO);
+        status["toolCache"] = self.tools.tool_cache.stats();
+        let session = self.session.lock().unwrap();
+        if !session.protocol_version.is_empty() {
+            status["session"] = serde_json::json!({
+                "protocolVersion": session.protocol_version,
+                "clientInfo": session.client_info,
+                "clientCapabilities": session.client_capabilities,
+                "subscriptions": session.subscriptions.values().collect::<Vec<_>>()
+            });
+        }
+        Ok(status)
+    }
+
+    fn handle_reload_config(&self) -> Result<Value, ToolError> {
+        let reload = self
+            .reload
+            .as_ref()
+            .ok_or_else(|| ToolError::new(-32601, "Method not found: reloadConfig"))?;
+        reload()
+    }
+
+    /// Describe what this build actually implements, so clients don't probe for it
+    fn handle_get_capabilities(&self) -> Value {
+        let registry = self.tools.registry();
+        let mut tools: Vec<&str> = registry
+            .names()
+            .into_iter()
+            .filter(|name| !tools::SIMULATED_TOOLS.contains(name))
+            .chain(tools::CONNECTION_TOOLS.iter().copied())
+            .collect();
+        tools.sort_unstable();
+
+        serde_json::json!({
+            "tools": tools,
+            "features": {
+                "lsp": cfg!(feature = "lsp"),
+                "websocket": cfg!(feature = "websocket"),
+                "codeExecution": false,
+            },
+            "reconnect": {
+                "initialDelayMs": RECONNECT_INITIAL_DELAY_MS,
+                "maxDelayMs": RECONNECT_MAX_DELAY_MS,
+                "multiplier": RECONNECT_BACKOFF_MULTIPLIER,
+                "jitter": RECONNECT_JITTER,
+            }
+        })
+    }
+
+    async fn handle_set_clipboard(&self, params: Option<Value>) -> Result<Value, ToolError> {
+        let text = params
+            .as_ref()
+            .and_then(|p| p.get("text"))
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ServerError::InvalidParams("Missing text".to_string()))?;
+
+        info!(
+            "Writing {} characters to the clipboard",
+            text.chars().count()
+        );
+        self.tools.editor.write_clipboard(text.to_string()).await?;
+
+        Ok(serde_json::json!({ "success": true }))
+    }
+
+    async fn handle_get_clipboard(&self) -> Result<Value, ToolError> {
+        info!("Reading the clipboard from the editor");
+        let text = self.tools.editor.read_clipboard().await?;
+
         Ok(serde_json::json!({
-            "content": content,
-            "isError": false
+            "success": true,
+            "text": text
         }))
     }
 
@@ -452,30 +658,258 @@ impl MCPServer {
     async fn handle_prompts_list(&self) -> Result<Value> {
         info!("Listing available prompts");
 
+        let prompts: Vec<Value> = prompts::CODE_PROMPTS
+            .iter()
+            .map(|prompt| {
+                serde_json::json!({
+                    "name": prompt.name,
+                    "description": prompt.description,
+                    "arguments": [
+                        {"name": "filePath", "description": "File the code comes from", "required": true},
+                        {"name": "code", "description": "Code the prompt is about", "required": true}
+                    ]
+                })
+            })
+            .collect();
         Ok(serde_json::json!({
-            "prompts": []
+            "prompts": prompts
         }))
     }
 
-    async fn handle_prompts_get(&self, params: Option<Value>) -> Result<Value> {
-        let params = params.ok_or_else(|| anyhow::anyhow!("Missing parameters for prompts/get"))?;
-
+    fn handle_prompts_get(&self, params: Option<Value>) -> Result<Value, ToolError> {
         let prompt_name = params
-            .get("name")
+            .as_ref()
+            .and_then(|p| p.get("name"))
             .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing prompt name"))?;
+            .ok_or_else(|| ServerError::InvalidParams("Missing prompt name".to_string()))?;
 
         info!("Getting prompt: {}", prompt_name);
 
+        let prompt = prompts::find(prompt_name).ok_or_else(|| {
+            ServerError::InvalidParams(format!("Unknown prompt: {}", prompt_name))
+        })?;
+        let argument = |name: &str| {
+            params
+                .as_ref()
+                .and_then(|p| p.pointer(&format!("/arguments/{}", name)))
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+        };
+        let text = prompt.render(argument("filePath"), argument("code"));
+
         Ok(serde_json::json!({
-            "description": format!("Prompt: {}", prompt_name),
-            "messages": []
+            "description": prompt.description,
+            "messages": [{
+                "role": "user",
+                "content": {"type": "text", "text": text}
+            }]
         }))
     }
 }
 
-impl Default for MCPServer {
-    fn default() -> Self {
-        Self::new()
+/// The `uri` of a `resources/subscribe` or `resources/unsubscribe` request
+fn resource_uri(params: Option<&Value>) -> Result<&str, ToolError> {
+    params
+        .and_then(|p| p.get("uri"))
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing uri".to_string()).into())
+}
+
+/// The normalized file behind a `file://` resource URI, so spellings of the same
+/// file share one subscription
+fn resource_path(uri: &str) -> Option<PathBuf> {
+    let path = Url::parse(uri).ok()?.to_file_path().ok()?;
+    Some(paths::normalize(&path))
+}
+
+/// Span tying a request's log lines, and those of its response, to its method and id
+pub fn request_span(request: &MCPRequest) -> Span {
+    let id = request
+        .id
+        .as_ref()
+        .map_or_else(|| "none".to_string(), Value::to_string);
+    info_span!("request", method = %request.method, id = %id)
+}
+
+/// Pick the protocol version for a session.
+///
+/// The client's version is echoed back when supported. Otherwise the newest
+/// supported version that is not newer than the client's is used, so a client
+/// ahead of this server is downgraded. A client older than every supported
+/// version is rejected.
+pub fn negotiate_protocol_version(requested: Option<&str>) -> Result<&'static str, ToolError> {
+    let Some(requested) = requested else {
+        return Ok(SUPPORTED_PROTOCOL_VERSIONS[0]);
+    };
+
+    // Versions are ISO dates, so lexical order is chronological order
+    SUPPORTED_PROTOCOL_VERSIONS
+        .iter()
+        .find(|version| **version <= requested)
+        .copied()
+        .ok_or_else(|| {
+            ToolError::new(
+                TOOL_ERROR_INVALID_PARAMS,
+                format!("Unsupported protocol version: {}", requested),
+            )
+            .with_data(serde_json::json!({
+                "requested": requested,
+                "supported": SUPPORTED_PROTOCOL_VERSIONS
+            }))
+        })
+}
+
+/// `selection_changed` params, rejected unless they are a well-formed selection
+fn parse_selection(params: Option<Value>) -> Result<SelectionChangedNotification, ToolError> {
+    let invalid = |reason: String| {
+        ServerError::InvalidParams(format!("Invalid selection_changed params: {}", reason))
+    };
+    let selection: SelectionChangedNotification =
+        serde_json::from_value(params.unwrap_or(Value::Null))
+            .map_err(|e| invalid(e.to_string()))?;
+    selection.validate().map_err(invalid)?;
+    Ok(selection)
+}
+
+/// Whether `method` is one clients send as a notification
+fn is_notification_method(method: &str) -> bool {
+    method.starts_with("notifications/") || NOTIFICATION_METHODS.contains(&method)
+}
+
+/// Whether `request` expects no response: a notification method sent without an id
+pub fn is_notification(request: &MCPRequest) -> bool {
+    request.id.is_none() && is_notification_method(&request.method)
+}
+
+/// Parse error for a message holding a control character JSON only allows escaped.
+///
+/// serde_json rejects these too, but naming the character and its offset makes an
+/// embedded NUL far easier to track down than a generic syntax error.
+pub fn control_character_error(message: &str) -> Option<MCPResponse> {
+    let (offset, character) = message
+        .char_indices()
+        .find(|(_, c)| *c < ' ' && !matches!(c, '\t' | '\n' | '\r'))?;
+
+    Some(parse_error(format!(
+        "Unescaped control character U+{:04X} at byte {}",
+        character as u32, offset
+    )))
+}
+
+/// Parse error for a message whose arrays and objects nest deeper than `MAX_JSON_DEPTH`.
+///
+/// Checked before parsing so no handler ever recurses through such a payload.
+pub fn nesting_error(message: &[u8]) -> Option<MCPResponse> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (offset, byte) in message.iter().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > MAX_JSON_DEPTH {
+                    return Some(parse_error(format!(
+                        "Nesting exceeds {} levels at byte {}",
+                        MAX_JSON_DEPTH, offset
+                    )));
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_error(details: String) -> MCPResponse {
+    MCPResponse {
+        jsonrpc: "2.0".to_string(),
+        id: None,
+        result: None,
+        error: Some(MCPError {
+            code: ERROR_PARSE,
+            message: "Parse error".to_string(),
+            data: Some(serde_json::json!({
+                "details": details,
+                "reason": "json",
+                "retryable": false
+            })),
+        }),
+    }
+}
+
+/// Error response for a message that could not be parsed as an `MCPRequest`.
+///
+/// Malformed JSON is a parse error with no id. Well-formed JSON that is not a
+/// valid request is an invalid request, and keeps its `id` when that field is a
+/// string or number so the client can tell which call failed.
+pub fn request_parse_error(message: &[u8], error: &serde_json::Error) -> MCPResponse {
+    let Ok(value) = serde_json::from_slice::<Value>(message) else {
+        return parse_error(error.to_string());
+    };
+    let id = value
+        .get("id")
+        .filter(|id| id.is_string() || id.is_number())
+        .cloned();
+
+    MCPResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(MCPError {
+            code: -32600,
+            message: "Invalid Request".to_string(),
+            data: Some(serde_json::json!({ "details": error.to_string() })),
+        }),
+    }
+}
+
+/// The response for a request whose handler failed outright, answered under the request's own id
+pub fn internal_error(id: Option<Value>, error: &anyhow::Error) -> MCPResponse {
+    MCPResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(MCPError {
+            code: -32603,
+            message: "Internal error".to_string(),
+            data: Some(serde_json::json!({ "details": error.to_string() })),
+        }),
+    }
+}
+
+/// Say which tool an internal error came from and which arguments it was given.
+///
+/// Only argument names are included; values may hold file contents or secrets. The
+/// context is added to the data the error already carries, such as the failure kind
+/// and retryability a `ServerError` records, and data that is not an object is kept
+/// under `details`.
+fn with_call_context(
+    mut error: ToolError,
+    tool_name: &str,
+    argument_keys: Vec<String>,
+) -> ToolError {
+    if error.code != TOOL_ERROR_INTERNAL {
+        return error;
     }
+    let mut data = match error.data.take() {
+        Some(Value::Object(fields)) => fields,
+        Some(details) => serde_json::Map::from_iter([("details".to_string(), details)]),
+        None => serde_json::Map::new(),
+    };
+    data.insert("tool".to_string(), Value::from(tool_name));
+    data.insert("argumentKeys".to_string(), Value::from(argument_keys));
+    data.insert("cause".to_string(), Value::from(error.message.clone()));
+    error.data = Some(Value::Object(data));
+    error
 }
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JsonRpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+// Channel for sending notifications from LSP to MCP
+pub type NotificationSender = broadcast::Sender<JsonRpcNotification>;
+pub type NotificationReceiver = broadcast::Receiver<JsonRpcNotification>;
@@ -0,0 +1,287 @@
+use lsp_types::Url;
+use std::borrow::Cow;
+use std::env;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Expand a leading `~` and environment variables in a user-supplied path.
+///
+/// `$VAR` and `${VAR}` are expanded everywhere, `%VAR%` only on Windows; unset
+/// variables are left as written. This only rewrites text, so callers must still
+/// apply the workspace guards to the expanded path.
+pub fn expand(path: &str) -> String {
+    expand_vars(&expand_home(path))
+}
+
+fn expand_home(path: &str) -> Cow<'_, str> {
+    let Some(rest) = path.strip_prefix('~') else {
+        return Cow::Borrowed(path);
+    };
+    // `~user` forms are not supported
+    if !(rest.is_empty() || rest.starts_with('/') || rest.starts_with(std::path::MAIN_SEPARATOR)) {
+        return Cow::Borrowed(path);
+    }
+    match env::var("HOME").or_else(|_| env::var("USERPROFILE")) {
+        Ok(home) => Cow::Owned(format!("{}{}", home, rest)),
+        Err(_) => Cow::Borrowed(path),
+    }
+}
+
+fn expand_vars(path: &str) -> String {
+    let is_sigil = |ch: char| ch == '$' || (cfg!(windows) && ch == '%');
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+
+    while let Some(start) = rest.find(is_sigil) {
+        expanded.push_str(&rest[..start]);
+        let sigil = &rest[start..start + 1];
+        let after = &rest[start + 1..];
+
+        // The variable name and how many bytes after the sigil it spans
+        let (name, len) = if sigil == "%" {
+            after
+                .find('%')
+                .map_or(("", 0), |end| (&after[..end], end + 1))
+        } else if let Some(braced) = after.strip_prefix('{') {
+            braced
+                .find('}')
+                .map_or(("", 0), |end| (&braced[..end], end + 2))
+        } else {
+            let end = after
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+
+        let value = is_variable_name(name)
+            .then(|| env::var(name).ok())
+            .flatten();
+        match value {
+            Some(value) => {
+                expanded.push_str(&value);
+                rest = &after[len..];
+            }
+            None => {
+                expanded.push_str(sigil);
+                rest = after;
+            }
+        }
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+fn is_variable_name(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// A path given either plainly or as a `file://` URL, as a plain path
+pub fn from_uri_or_path(path: &str) -> Cow<'_, str> {
+    if !path.starts_with("file://") {
+        return Cow::Borrowed(path);
+    }
+    // A URL may percent-encode characters, so decode it properly where it parses
+    match Url::parse(path)
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+    {
+        Some(decoded) => Cow::Owned(decoded.to_string_lossy().into_owned()),
+        None => Cow::Borrowed(&path["file://".len()..]),
+    }
+}
+
+/// The one spelling of `path` used wherever paths are compared or used as keys.
+///
+/// Symlinks, `.` and `..` are resolved as far as the path exists on disk,
+/// trailing separators are dropped and, on Windows, `/` becomes `\`. A path
+/// that cannot be canonicalized is only cleaned up lexically.
+pub fn normalize(path: &Path) -> PathBuf {
+    let cleaned = clean(path);
+    canonicalize_lenient(&cleaned).unwrap_or(cleaned)
+}
+
+/// Unify separators and drop `.` components and trailing separators, without touching the disk
+fn clean(path: &Path) -> PathBuf {
+    let unified = if cfg!(windows) {
+        Cow::Owned(PathBuf::from(path.to_string_lossy().replace('/', "\\")))
+    } else {
+        Cow::Borrowed(path)
+    };
+    unified.components().collect()
+}
+
+/// How many symlinks `canonicalize_lenient` follows by hand before giving up, as the
+/// OS does for loops
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Canonicalize `path`, which may not exist yet.
+///
+/// Symlinks and `..` are resolved in the longest prefix that exists on disk. The
+/// rest is applied one component at a time: a `..` can lead back to components that
+/// do exist, and a dangling symlink still points somewhere, so each component that
+/// turns out to be a symlink is resolved before the next one is applied.
+pub fn canonicalize_lenient(path: &Path) -> io::Result<PathBuf> {
+    canonicalize_with_hops(path, MAX_SYMLINK_HOPS)
+}
+
+fn canonicalize_with_hops(path: &Path, hops: usize) -> io::Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    let mut base = loop {
+        match existing.canonicalize() {
+            Ok(base) => break base,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let (Some(parent), Some(name)) =
+                    (existing.parent(), existing.components().next_back())
+                else {
+                    return Err(e);
+                };
+                missing.push(name);
+                existing = parent;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    for component in missing.into_iter().rev() {
+        match component {
+            // `base` never contains a symlink, so its parent is the real one
+            Component::ParentDir => {
+                base.pop();
+            }
+            Component::Normal(name) => {
+                base.push(name);
+                let is_symlink = base
+                    .symlink_metadata()
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink());
+                if is_symlink {
+                    let Some(hops) = hops.checked_sub(1) else {
+                        return Err(io::Error::other(format!(
+                            "Too many levels of symbolic links at {}",
+                            base.display()
+                        )));
+                    };
+                    let target = std::fs::read_link(&base)?;
+                    base.pop();
+                    base = canonicalize_with_hops(&clean(&base.join(target)), hops)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(base)
+}
+
+/// Whether `path` lies inside one of `roots` once both are canonicalized
+pub fn is_within(path: &Path, roots: &[PathBuf]) -> bool {
+    // Unlike `normalize`, fail closed: an unresolved `..` could step outside a root
+    let Ok(path) = canonicalize_lenient(&clean(path)) else {
+        return false;
+    };
+    roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| path.starts_with(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A fresh directory holding `workspace/` and, beside it, `outside/`
+    fn sandbox() -> (PathBuf, PathBuf, PathBuf) {
+        let dir =
+            env::temp_dir().join(format!("claude-code-server-paths-{}", uuid::Uuid::new_v4()));
+        let workspace = dir.join("workspace");
+        let outside = dir.join("outside");
+        fs::create_dir_all(&workspace).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        (dir, workspace, outside)
+    }
+
+    #[test]
+    fn the_root_itself_is_within() {
+        let (dir, workspace, _) = sandbox();
+        let roots = [workspace.clone()];
+
+        assert!(is_within(&workspace, &roots));
+        assert!(is_within(&workspace.join("."), &roots));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn missing_tails_are_judged_by_their_existing_prefix() {
+        let (dir, workspace, _) = sandbox();
+        let roots = [workspace.clone()];
+
+        assert!(is_within(&workspace.join("new/dir/file.txt"), &roots));
+        assert!(is_within(&workspace.join("new/../file.txt"), &roots));
+        assert_eq!(
+            canonicalize_lenient(&workspace.join("new/dir/file.txt")).unwrap(),
+            workspace.canonicalize().unwrap().join("new/dir/file.txt")
+        );
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn parent_components_cannot_step_outside() {
+        let (dir, workspace, _) = sandbox();
+        let roots = [workspace.clone()];
+
+        assert!(!is_within(&workspace.join("../outside/secret.txt"), &roots));
+        assert!(!is_within(
+            &workspace.join("nope/../../outside/secret.txt"),
+            &roots
+        ));
+        assert!(!is_within(
+            &workspace.join("nope/../../../../etc/passwd"),
+            &roots
+        ));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_workspace_are_followed() {
+        let (dir, workspace, outside) = sandbox();
+        std::os::unix::fs::symlink(&outside, workspace.join("link")).unwrap();
+        std::os::unix::fs::symlink(outside.join("later.txt"), workspace.join("dangling")).unwrap();
+        let roots = [workspace.clone()];
+
+        assert!(!is_within(&workspace.join("link/secret.txt"), &roots));
+        // Reached through a missing directory, the link must still be resolved
+        assert!(!is_within(
+            &workspace.join("nope/../link/secret.txt"),
+            &roots
+        ));
+        // Writing through a dangling link would create its target outside
+        assert!(!is_within(&workspace.join("dangling"), &roots));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlinked_root_accepts_its_files() {
+        let (dir, workspace, _) = sandbox();
+        let linked = dir.join("linked");
+        std::os::unix::fs::symlink(&workspace, &linked).unwrap();
+        fs::write(workspace.join("main.rs"), "fn main() {}\n").unwrap();
+        let roots = [linked.clone()];
+
+        assert!(is_within(&linked.join("main.rs"), &roots));
+        assert!(is_within(&workspace.join("main.rs"), &roots));
+        assert!(is_within(&linked.join("new.rs"), &roots));
+        assert!(!is_within(&linked.join("../outside/secret.txt"), &roots));
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
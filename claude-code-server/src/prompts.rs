@@ -0,0 +1,46 @@
+//! Prompts behind the editor's Explain, Improve and Fix commands.
+//!
+//! The same text is sent to Claude when a command runs in the editor and returned by
+//! `prompts/get`, so both paths ask the same question about a piece of code.
+
+/// A prompt about a piece of code
+#[derive(Debug)]
+pub struct CodePrompt {
+    /// Name used by `prompts/get` and in `claude-code.<name>` commands
+    pub name: &'static str,
+    pub description: &'static str,
+    instruction: &'static str,
+}
+
+pub const CODE_PROMPTS: &[CodePrompt] = &[
+    CodePrompt {
+        name: "explain",
+        description: "Explain what a piece of code does",
+        instruction: "Explain what this code does and how it works.",
+    },
+    CodePrompt {
+        name: "improve",
+        description: "Suggest improvements to a piece of code",
+        instruction: "Suggest improvements to this code, such as clearer naming, simpler logic or better error handling.",
+    },
+    CodePrompt {
+        name: "fix",
+        description: "Find and fix problems in a piece of code",
+        instruction: "Find bugs or other problems in this code and fix them.",
+    },
+];
+
+/// The prompt named `name`
+pub fn find(name: &str) -> Option<&'static CodePrompt> {
+    CODE_PROMPTS.iter().find(|prompt| prompt.name == name)
+}
+
+impl CodePrompt {
+    /// The prompt's text for `code` taken from `file_path`
+    pub fn render(&self, file_path: &str, code: &str) -> String {
+        format!(
+            "{}\n\nFrom {}:\n\n```\n{}\n```",
+            self.instruction, file_path, code
+        )
+    }
+}
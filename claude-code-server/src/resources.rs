@@ -0,0 +1,97 @@
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+use crate::notifications::JsonRpcNotification;
+use crate::tail::NotificationSink;
+
+/// How often a subscribed file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Resources one client connection has subscribed to, keyed by normalized path.
+///
+/// Every subscription is cancelled when this is dropped, so they end with the connection.
+#[derive(Debug, Default)]
+pub struct ResourceSubscriptions {
+    watches: Mutex<HashMap<PathBuf, JoinHandle<()>>>,
+}
+
+impl ResourceSubscriptions {
+    /// Report changes to the file at `path` as `notifications/resources/updated` for `uri`.
+    ///
+    /// Subscribing to the same file twice, however its URI is spelled, keeps the
+    /// existing subscription.
+    pub fn subscribe(&self, uri: String, path: PathBuf, sink: NotificationSink) {
+        let mut watches = self.watches.lock().unwrap();
+        if watches.contains_key(&path) {
+            debug!("Already subscribed to {}", path.display());
+            return;
+        }
+        info!("Subscribed to {}", uri);
+        // Changes made as soon as the subscription is acknowledged must still count
+        let initial = fingerprint(std::fs::metadata(&path));
+        let handle = tokio::spawn(watch(uri, path.clone(), initial, sink));
+        watches.insert(path, handle);
+    }
+
+    /// Cancel the subscription to `path`, returning whether there was one
+    pub fn unsubscribe(&self, path: &Path) -> bool {
+        match self.watches.lock().unwrap().remove(path) {
+            Some(handle) => {
+                info!("Unsubscribed from {}", path.display());
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for ResourceSubscriptions {
+    fn drop(&mut self) {
+        for (path, handle) in self.watches.get_mut().unwrap().drain() {
+            debug!("Dropping subscription to {} on disconnect", path.display());
+            handle.abort();
+        }
+    }
+}
+
+/// Modification time and size of a file, or `None` while it doesn't exist
+type Fingerprint = Option<(Option<SystemTime>, u64)>;
+
+fn fingerprint(metadata: io::Result<Metadata>) -> Fingerprint {
+    let metadata = metadata.ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
+
+/// Poll `path` and notify whenever its modification time, size or existence changes
+async fn watch(uri: String, path: PathBuf, mut last: Fingerprint, sink: NotificationSink) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let current = fingerprint(tokio::fs::metadata(&path).await);
+        if current == last {
+            continue;
+        }
+        last = current;
+
+        debug!("Subscribed resource {} changed", uri);
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/resources/updated".to_string(),
+            params: json!({ "uri": uri }),
+        };
+        if sink.send(notification).is_err() {
+            debug!("Client subscribed to {} is gone", uri);
+            break;
+        }
+    }
+}
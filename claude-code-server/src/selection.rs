@@ -0,0 +1,62 @@
+use lsp_types::Position;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+/// Params of the `selection_changed` notification sent to Claude clients
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelectionChangedNotification {
+    pub text: String,
+    #[serde(rename = "filePath")]
+    pub file_path: String,
+    #[serde(rename = "fileUrl")]
+    pub file_url: String,
+    pub selection: SelectionInfo,
+}
+
+impl SelectionChangedNotification {
+    /// Check what the types alone don't: a selection names a file and doesn't end
+    /// before it starts
+    pub fn validate(&self) -> Result<(), String> {
+        if self.file_path.is_empty() {
+            return Err("filePath is empty".to_string());
+        }
+        if self.selection.end < self.selection.start {
+            return Err(format!(
+                "selection ends at {}:{} before it starts at {}:{}",
+                self.selection.end.line,
+                self.selection.end.character,
+                self.selection.start.line,
+                self.selection.start.character
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelectionInfo {
+    pub start: Position,
+    pub end: Position,
+    #[serde(rename = "isEmpty")]
+    pub is_empty: bool,
+}
+
+/// The selection the editor reported most recently
+#[derive(Debug, Clone, Default)]
+pub struct SelectionStore {
+    latest: Arc<RwLock<Option<SelectionChangedNotification>>>,
+}
+
+impl SelectionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, selection: SelectionChangedNotification) {
+        *self.latest.write().unwrap() = Some(selection);
+    }
+
+    pub fn get(&self) -> Option<SelectionChangedNotification> {
+        self.latest.read().unwrap().clone()
+    }
+}
@@ -0,0 +1,429 @@
+use lsp_types::{SemanticToken, SemanticTokenType, SemanticTokensLegend};
+
+use crate::symbols::is_identifier_char;
+
+/// Token types reported by `tokenize`, in legend order
+const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::STRING,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::VARIABLE,
+];
+
+const KEYWORD: u32 = 0;
+const STRING: u32 = 1;
+const COMMENT: u32 = 2;
+const NUMBER: u32 = 3;
+const IDENTIFIER: u32 = 4;
+
+/// Lexical conventions of a language, just enough for coarse highlighting
+struct Syntax {
+    keywords: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    string_delimiters: &'static [char],
+    /// `'` starts a character literal only when it closes right away, as lifetimes also use it
+    char_literals: bool,
+}
+
+fn syntax(language_id: &str) -> Option<Syntax> {
+    let syntax = match language_id {
+        "rust" => Syntax {
+            keywords: &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+                "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match",
+                "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+                "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+            ],
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            string_delimiters: &['"'],
+            char_literals: true,
+        },
+        "python" => Syntax {
+            keywords: &[
+                "and", "as", "assert", "async", "await", "break", "class", "continue", "def",
+                "del", "elif", "else", "except", "False", "finally", "for", "from", "global", "if",
+                "import", "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise",
+                "return", "True", "try", "while", "with", "yield",
+            ],
+            line_comments: &["#"],
+            block_comment: None,
+            string_delimiters: &['"', '\''],
+            char_literals: false,
+        },
+        "javascript" | "typescript" | "tsx" => Syntax {
+            keywords: &[
+                "abstract",
+                "as",
+                "async",
+                "await",
+                "break",
+                "case",
+                "catch",
+                "class",
+                "const",
+                "continue",
+                "declare",
+                "default",
+                "delete",
+                "do",
+                "else",
+                "enum",
+                "export",
+                "extends",
+                "false",
+                "finally",
+                "for",
+                "function",
+                "if",
+                "implements",
+                "import",
+                "in",
+                "instanceof",
+                "interface",
+                "keyof",
+                "let",
+                "new",
+                "null",
+                "of",
+                "private",
+                "protected",
+                "public",
+                "readonly",
+                "return",
+                "static",
+                "super",
+                "switch",
+                "this",
+                "throw",
+                "true",
+                "try",
+                "type",
+                "typeof",
+                "undefined",
+                "var",
+                "void",
+                "while",
+                "yield",
+            ],
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            string_delimiters: &['"', '\'', '`'],
+            char_literals: false,
+        },
+        "ruby" => Syntax {
+            keywords: &[
+                "alias", "and", "begin", "break", "case", "class", "def", "do", "else", "elsif",
+                "end", "ensure", "false", "for", "if", "in", "module", "next", "nil", "not", "or",
+                "redo", "rescue", "retry", "return", "self", "super", "then", "true", "undef",
+                "unless", "until", "when", "while", "yield",
+            ],
+            line_comments: &["#"],
+            block_comment: None,
+            string_delimiters: &['"', '\''],
+            char_literals: false,
+        },
+        "elixir" => Syntax {
+            keywords: &[
+                "after",
+                "alias",
+                "and",
+                "case",
+                "catch",
+                "cond",
+                "def",
+                "defmacro",
+                "defmodule",
+                "defp",
+                "defstruct",
+                "do",
+                "else",
+                "end",
+                "false",
+                "fn",
+                "for",
+                "if",
+                "import",
+                "in",
+                "nil",
+                "not",
+                "or",
+                "quote",
+                "raise",
+                "receive",
+                "require",
+                "rescue",
+                "true",
+                "try",
+                "unless",
+                "unquote",
+                "use",
+                "when",
+                "with",
+            ],
+            line_comments: &["#"],
+            block_comment: None,
+            string_delimiters: &['"', '\''],
+            char_literals: false,
+        },
+        "kotlin" => Syntax {
+            keywords: &[
+                "as",
+                "break",
+                "class",
+                "continue",
+                "data",
+                "do",
+                "else",
+                "false",
+                "for",
+                "fun",
+                "if",
+                "import",
+                "in",
+                "interface",
+                "is",
+                "null",
+                "object",
+                "open",
+                "override",
+                "package",
+                "private",
+                "public",
+                "return",
+                "sealed",
+                "super",
+                "this",
+                "throw",
+                "true",
+                "try",
+                "typealias",
+                "val",
+                "var",
+                "when",
+                "while",
+            ],
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            string_delimiters: &['"', '\''],
+            char_literals: false,
+        },
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: Vec::new(),
+    }
+}
+
+/// A construct left unterminated at the end of a line
+#[derive(Clone, Copy)]
+enum Open {
+    BlockComment,
+    String(char),
+}
+
+/// Classify keywords, strings, comments, numbers and identifiers in `text`.
+///
+/// This is a lexer, not a parser, so it can be fooled by unusual syntax such as
+/// raw strings. Tokens never span lines, as not every client supports that.
+pub fn tokenize(text: &str, language_id: &str) -> Vec<SemanticToken> {
+    let Some(syntax) = syntax(language_id) else {
+        return Vec::new();
+    };
+
+    let mut encoder = Encoder::default();
+    let mut open = None;
+    for (line_number, line) in text.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        // UTF-16 column of each char, plus the end of the line
+        let mut columns = Vec::with_capacity(chars.len() + 1);
+        let mut column = 0;
+        for ch in &chars {
+            columns.push(column);
+            column += ch.len_utf16() as u32;
+        }
+        columns.push(column);
+
+        let mut push = |start: usize, end: usize, token_type: u32| {
+            encoder.push(
+                line_number as u32,
+                columns[start],
+                columns[end] - columns[start],
+                token_type,
+            );
+        };
+
+        let mut i = 0;
+        // Finish whatever the previous line left open
+        match open.take() {
+            Some(Open::BlockComment) => {
+                let (_, close) = syntax.block_comment.unwrap_or_default();
+                match find(&chars, 0, close) {
+                    Some(end) => {
+                        push(0, end + close.len(), COMMENT);
+                        i = end + close.len();
+                    }
+                    None => {
+                        push(0, chars.len(), COMMENT);
+                        open = Some(Open::BlockComment);
+                        continue;
+                    }
+                }
+            }
+            Some(Open::String(quote)) => match string_end(&chars, 0, quote) {
+                Some(end) => {
+                    push(0, end + 1, STRING);
+                    i = end + 1;
+                }
+                None => {
+                    push(0, chars.len(), STRING);
+                    open = Some(Open::String(quote));
+                    continue;
+                }
+            },
+            None => {}
+        }
+
+        while i < chars.len() {
+            let ch = chars[i];
+            if syntax
+                .line_comments
+                .iter()
+                .any(|prefix| starts_with(&chars, i, prefix))
+            {
+                push(i, chars.len(), COMMENT);
+                break;
+            }
+
+            if let Some((start, close)) = syntax.block_comment {
+                if starts_with(&chars, i, start) {
+                    match find(&chars, i + start.len(), close) {
+                        Some(end) => {
+                            push(i, end + close.len(), COMMENT);
+                            i = end + close.len();
+                        }
+                        None => {
+                            push(i, chars.len(), COMMENT);
+                            open = Some(Open::BlockComment);
+                            i = chars.len();
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            if syntax.string_delimiters.contains(&ch) {
+                match string_end(&chars, i + 1, ch) {
+                    Some(end) => {
+                        push(i, end + 1, STRING);
+                        i = end + 1;
+                    }
+                    None => {
+                        push(i, chars.len(), STRING);
+                        open = Some(Open::String(ch));
+                        i = chars.len();
+                    }
+                }
+            } else if ch == '\'' && syntax.char_literals {
+                let end = match chars.get(i + 1) {
+                    Some('\\') => string_end(&chars, i + 1, '\''),
+                    Some(_) if chars.get(i + 2) == Some(&'\'') => Some(i + 2),
+                    _ => None,
+                };
+                match end {
+                    Some(end) => {
+                        push(i, end + 1, STRING);
+                        i = end + 1;
+                    }
+                    None => i += 1,
+                }
+            } else if ch.is_ascii_digit() {
+                let end = scan(&chars, i, |c| c.is_alphanumeric() || c == '_' || c == '.');
+                push(i, end, NUMBER);
+                i = end;
+            } else if is_identifier_char(ch) {
+                let end = scan(&chars, i, is_identifier_char);
+                let word: String = chars[i..end].iter().collect();
+                let token_type = if syntax.keywords.contains(&word.as_str()) {
+                    KEYWORD
+                } else {
+                    IDENTIFIER
+                };
+                push(i, end, token_type);
+                i = end;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    encoder.tokens
+}
+
+/// Builds the relative encoding LSP uses for semantic tokens
+#[derive(Default)]
+struct Encoder {
+    tokens: Vec<SemanticToken>,
+    line: u32,
+    start: u32,
+}
+
+impl Encoder {
+    fn push(&mut self, line: u32, start: u32, length: u32, token_type: u32) {
+        if length == 0 {
+            return;
+        }
+        let delta_line = line - self.line;
+        let delta_start = if delta_line == 0 {
+            start - self.start
+        } else {
+            start
+        };
+        self.tokens.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type,
+            token_modifiers_bitset: 0,
+        });
+        self.line = line;
+        self.start = start;
+    }
+}
+
+fn starts_with(chars: &[char], at: usize, prefix: &str) -> bool {
+    let mut rest = chars[at..].iter();
+    prefix.chars().all(|ch| rest.next() == Some(&ch))
+}
+
+/// Index of the first occurrence of `needle` at or after `from`
+fn find(chars: &[char], from: usize, needle: &str) -> Option<usize> {
+    (from..chars.len()).find(|&i| starts_with(chars, i, needle))
+}
+
+/// Index of the quote closing a string, skipping backslash escapes
+fn string_end(chars: &[char], from: usize, quote: char) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            ch if ch == quote => return Some(i),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// End of the run of characters matching `matches` that starts at `from`
+fn scan(chars: &[char], from: usize, matches: impl Fn(char) -> bool) -> usize {
+    (from..chars.len())
+        .find(|&i| !matches(chars[i]))
+        .unwrap_or(chars.len())
+}
@@ -0,0 +1,28 @@
+//! State the LSP server and the WebSocket tools share in hybrid mode.
+
+use crate::diagnostics::DiagnosticsStore;
+use crate::documents::DocumentStore;
+use crate::selection::SelectionStore;
+use crate::symbols::SymbolIndex;
+
+/// What the editor reports over LSP and tools read over WebSocket.
+///
+/// Each store sits behind its own `Arc<RwLock>`, so clones share the data and a
+/// writer on one store never blocks readers of another.
+#[derive(Debug, Clone, Default)]
+pub struct SharedState {
+    /// Buffers the editor has open
+    pub documents: DocumentStore,
+    /// Diagnostics the editor has published
+    pub diagnostics: DiagnosticsStore,
+    /// The editor's latest selection
+    pub selection: SelectionStore,
+    /// Definitions across the workspace
+    pub symbols: SymbolIndex,
+}
+
+impl SharedState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,84 @@
+use serde::Serialize;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+use tracing::info;
+
+/// Stands in for the auth token wherever the startup report is written
+const REDACTED: &str = "[redacted]";
+
+/// One-glance summary of how the server was started, for bug reports
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupInfo {
+    pub version: &'static str,
+    pub mode: &'static str,
+    pub pid: u32,
+    pub platform: String,
+    pub port: Option<u16>,
+    pub socket: Option<PathBuf>,
+    pub worktree: Option<PathBuf>,
+    pub lock_path: Option<PathBuf>,
+    /// `[redacted]` when clients must present a token, never the token itself
+    pub auth_token: Option<&'static str>,
+}
+
+impl StartupInfo {
+    pub fn new(mode: &'static str, worktree: Option<&Path>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            mode,
+            pid: process::id(),
+            platform: format!("{}-{}", env::consts::OS, env::consts::ARCH),
+            port: None,
+            socket: None,
+            worktree: worktree.map(Path::to_path_buf),
+            lock_path: None,
+            auth_token: None,
+        }
+    }
+
+    /// Record the bound port and the lock file advertising it
+    pub fn with_listener(mut self, port: u16, lock_path: PathBuf) -> Self {
+        self.port = Some(port);
+        self.lock_path = Some(lock_path);
+        self
+    }
+
+    /// Record the Unix socket listened on and the lock file advertising it
+    pub fn with_socket(mut self, socket: PathBuf, lock_path: PathBuf) -> Self {
+        self.socket = Some(socket);
+        self.lock_path = Some(lock_path);
+        self
+    }
+
+    /// Note that clients must authenticate, without keeping the token
+    pub fn with_auth_token(mut self) -> Self {
+        self.auth_token = Some(REDACTED);
+        self
+    }
+
+    /// Emit the report as a single structured log event
+    pub fn log(&self) {
+        info!(
+            version = self.version,
+            mode = self.mode,
+            pid = self.pid,
+            platform = %self.platform,
+            port = %display_or_none(self.port),
+            socket = %display_or_none(self.socket.as_ref().map(|p| p.display())),
+            worktree = %display_or_none(self.worktree.as_ref().map(|p| p.display())),
+            lock_path = %display_or_none(self.lock_path.as_ref().map(|p| p.display())),
+            auth_token = self.auth_token.unwrap_or("none"),
+            "startup"
+        );
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+fn display_or_none(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| "none".to_string(), |v| v.to_string())
+}
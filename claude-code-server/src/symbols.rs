@@ -0,0 +1,779 @@
+use lsp_types::{Position, Range, SymbolKind, Url};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tracing::debug;
+
+use crate::documents::{char_pos_to_byte_pos, DocumentStore};
+
+/// Directories that never contain project sources worth indexing
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules", "dist", "build", "__pycache__"];
+
+/// Upper bound on files visited by a single workspace scan
+const MAX_WORKSPACE_FILES: usize = 10_000;
+
+/// A top-level definition found by the line scanner
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Range of the symbol's name on its definition line
+    pub range: Range,
+}
+
+/// A whole-word occurrence of an identifier found by `find_references`
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub path: PathBuf,
+    pub range: Range,
+    /// Full text of the line containing the occurrence
+    pub line_text: String,
+    /// Whether the occurrence is the name of a definition found by the line scanner
+    pub is_definition: bool,
+}
+
+#[derive(Debug, Clone)]
+struct IndexedFile {
+    modified: Option<SystemTime>,
+    symbols: Vec<Symbol>,
+}
+
+/// Map a file extension to the LSP language id used by Zed
+pub fn language_for_path(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?;
+    match extension {
+        "rs" => Some("rust"),
+        "py" => Some("python"),
+        "js" | "mjs" | "cjs" | "jsx" => Some("javascript"),
+        "ts" | "mts" | "cts" => Some("typescript"),
+        "tsx" => Some("tsx"),
+        "rb" => Some("ruby"),
+        "ex" | "exs" => Some("elixir"),
+        "kt" | "kts" => Some("kotlin"),
+        "md" => Some("markdown"),
+        _ => None,
+    }
+}
+
+/// Every language `language_for_path` recognises
+pub const LANGUAGES: &[&str] = &[
+    "rust",
+    "python",
+    "javascript",
+    "typescript",
+    "tsx",
+    "ruby",
+    "elixir",
+    "kotlin",
+    "markdown",
+];
+
+/// Whether `scan_symbols` finds definitions in `language_id`
+pub fn has_symbols(language_id: &str) -> bool {
+    !definition_keywords(language_id).is_empty()
+}
+
+/// Definition keywords recognised for each language
+fn definition_keywords(language_id: &str) -> &'static [(&'static str, SymbolKind)] {
+    match language_id {
+        "rust" => &[
+            ("fn", SymbolKind::FUNCTION),
+            ("struct", SymbolKind::STRUCT),
+            ("enum", SymbolKind::ENUM),
+            ("trait", SymbolKind::INTERFACE),
+            ("type", SymbolKind::TYPE_PARAMETER),
+            ("mod", SymbolKind::MODULE),
+            ("const", SymbolKind::CONSTANT),
+            ("static", SymbolKind::CONSTANT),
+            ("macro_rules!", SymbolKind::FUNCTION),
+        ],
+        "python" => &[("def", SymbolKind::FUNCTION), ("class", SymbolKind::CLASS)],
+        "javascript" | "typescript" | "tsx" => &[
+            ("function", SymbolKind::FUNCTION),
+            ("function*", SymbolKind::FUNCTION),
+            ("class", SymbolKind::CLASS),
+            ("interface", SymbolKind::INTERFACE),
+            ("enum", SymbolKind::ENUM),
+            ("type", SymbolKind::TYPE_PARAMETER),
+        ],
+        "ruby" => &[
+            ("def", SymbolKind::METHOD),
+            ("class", SymbolKind::CLASS),
+            ("module", SymbolKind::MODULE),
+        ],
+        "elixir" => &[
+            ("def", SymbolKind::FUNCTION),
+            ("defp", SymbolKind::FUNCTION),
+            ("defmacro", SymbolKind::FUNCTION),
+            ("defmodule", SymbolKind::MODULE),
+        ],
+        "kotlin" => &[
+            ("fun", SymbolKind::FUNCTION),
+            ("class", SymbolKind::CLASS),
+            ("object", SymbolKind::OBJECT),
+            ("interface", SymbolKind::INTERFACE),
+        ],
+        _ => &[],
+    }
+}
+
+/// Modifiers that may precede a definition keyword
+const MODIFIERS: &[&str] = &[
+    "pub",
+    "pub(crate)",
+    "pub(super)",
+    "async",
+    "unsafe",
+    "extern",
+    "export",
+    "default",
+    "declare",
+    "abstract",
+    "private",
+    "protected",
+    "public",
+    "internal",
+    "open",
+    "data",
+    "sealed",
+    "suspend",
+    "override",
+    "inline",
+];
+
+pub fn is_identifier_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Words that cannot name a symbol in the given language
+fn reserved_words(language_id: &str) -> &'static [&'static str] {
+    match language_id {
+        "rust" => &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        "python" => &[
+            "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+            "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global",
+            "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise",
+            "return", "try", "while", "with", "yield",
+        ],
+        "javascript" | "typescript" | "tsx" => &[
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "debugger",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "enum",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "new",
+            "null",
+            "return",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typeof",
+            "var",
+            "void",
+            "while",
+            "with",
+        ],
+        "ruby" => &[
+            "alias", "and", "begin", "break", "case", "class", "def", "defined?", "do", "else",
+            "elsif", "end", "ensure", "false", "for", "if", "in", "module", "next", "nil", "not",
+            "or", "redo", "rescue", "retry", "return", "self", "super", "then", "true", "undef",
+            "unless", "until", "when", "while", "yield",
+        ],
+        "elixir" => &[
+            "after", "and", "catch", "do", "else", "end", "false", "fn", "in", "nil", "not", "or",
+            "rescue", "true", "when",
+        ],
+        "kotlin" => &[
+            "as",
+            "break",
+            "class",
+            "continue",
+            "do",
+            "else",
+            "false",
+            "for",
+            "fun",
+            "if",
+            "in",
+            "interface",
+            "is",
+            "null",
+            "object",
+            "package",
+            "return",
+            "super",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typealias",
+            "typeof",
+            "val",
+            "var",
+            "when",
+            "while",
+        ],
+        _ => &[],
+    }
+}
+
+/// Whether `name` can name a symbol in the given language.
+///
+/// Names must not start with a digit or be a reserved word. JavaScript and TypeScript
+/// names may also contain `$`, and Ruby and Elixir names may end in `?` or `!`.
+pub fn is_valid_identifier(name: &str, language_id: &str) -> bool {
+    let body = match language_id {
+        "ruby" | "elixir" => name.strip_suffix(['?', '!']).unwrap_or(name),
+        _ => name,
+    };
+    let allows_dollar = matches!(language_id, "javascript" | "typescript" | "tsx");
+    let valid_char = |ch: char| is_identifier_char(ch) || (allows_dollar && ch == '$');
+
+    match body.chars().next() {
+        Some(first) if !first.is_numeric() => {}
+        _ => return false,
+    }
+    body.chars().all(valid_char) && !reserved_words(language_id).contains(&name)
+}
+
+/// Scan `text` line by line for definitions of the given language
+pub fn scan_symbols(text: &str, language_id: &str) -> Vec<Symbol> {
+    let keywords = definition_keywords(language_id);
+    if keywords.is_empty() {
+        return Vec::new();
+    }
+
+    let mut symbols = Vec::new();
+    for (line_number, line) in text.lines().enumerate() {
+        let mut rest = line.trim_start();
+        if rest.starts_with("//") || rest.starts_with('#') || rest.starts_with('*') {
+            continue;
+        }
+
+        while let Some(modifier) = MODIFIERS
+            .iter()
+            .find(|m| rest.strip_prefix(**m).is_some_and(|r| r.starts_with(' ')))
+        {
+            rest = rest[modifier.len()..].trim_start();
+        }
+        // `const fn` is a function, not a constant
+        if rest.starts_with("const fn ") {
+            rest = &rest["const ".len()..];
+        }
+
+        let Some((keyword_end, kind)) = keywords.iter().find_map(|(keyword, kind)| {
+            rest.strip_prefix(keyword)
+                .filter(|r| r.starts_with(char::is_whitespace))
+                .map(|_| (keyword.len(), *kind))
+        }) else {
+            continue;
+        };
+
+        let after_keyword = rest[keyword_end..].trim_start();
+        // Ruby class methods are declared as `def self.name`
+        let after_keyword = after_keyword.strip_prefix("self.").unwrap_or(after_keyword);
+        let name: String = after_keyword
+            .chars()
+            .take_while(|ch| is_identifier_char(*ch) || *ch == '?' || *ch == '!')
+            .collect();
+        if name.is_empty() || name.starts_with(|ch: char| ch.is_ascii_digit()) {
+            continue;
+        }
+
+        let name_byte_start = line.len() - after_keyword.len();
+        let character = line[..name_byte_start].encode_utf16().count() as u32;
+        symbols.push(Symbol {
+            range: Range {
+                start: Position {
+                    line: line_number as u32,
+                    character,
+                },
+                end: Position {
+                    line: line_number as u32,
+                    character: character + name.encode_utf16().count() as u32,
+                },
+            },
+            name,
+            kind,
+        });
+    }
+
+    symbols
+}
+
+/// Collect indexable source files below `root`, skipping hidden and build directories
+pub fn workspace_source_files(root: &Path) -> Vec<PathBuf> {
+    walk_workspace(root, MAX_WORKSPACE_FILES, |path| {
+        language_for_path(path).is_some()
+    })
+}
+
+/// Collect every file below `root` that the ignore files and the hidden and build
+/// directory rules leave in, sorted
+pub fn workspace_files(root: &Path) -> Vec<PathBuf> {
+    walk_workspace(root, usize::MAX, |_| true)
+}
+
+/// Walk `root` for files accepted by `keep`, stopping once `limit` are found
+fn walk_workspace(root: &Path, limit: usize, keep: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let ignore = IgnoreRules::load(root);
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            if ignore.is_ignored(relative, file_type.is_dir()) {
+                continue;
+            }
+            if file_type.is_dir() {
+                if !SKIPPED_DIRS.contains(&name.as_ref()) {
+                    pending.push(path);
+                }
+            } else if file_type.is_file() && keep(&path) {
+                files.push(path);
+                if files.len() >= limit {
+                    return files;
+                }
+            }
+        }
+    }
+
+    files.sort();
+    files
+}
+
+/// Files to ignore at the workspace root, from `.gitignore` and `.claudeignore`.
+///
+/// Patterns use gitignore syntax as far as `glob::Pattern` covers it: `*`, `?`,
+/// `[...]` and `**` wildcards, a `/` anchoring a pattern to the root, a trailing `/`
+/// matching only directories and a leading `!` taking matching paths back in. The
+/// last matching pattern decides, with `.claudeignore` read after `.gitignore`.
+/// Nested ignore files are not read.
+#[derive(Debug, Default)]
+pub struct IgnoreRules {
+    patterns: Vec<IgnorePattern>,
+}
+
+#[derive(Debug)]
+struct IgnorePattern {
+    glob: glob::Pattern,
+    /// Matched against the whole relative path rather than just the file name
+    anchored: bool,
+    dir_only: bool,
+    /// Takes matching paths back in rather than ignoring them
+    negated: bool,
+}
+
+/// `*` and `?` stay within one path component, as in gitignore
+const IGNORE_MATCH_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+impl IgnoreRules {
+    pub fn load(root: &Path) -> Self {
+        let mut patterns = Vec::new();
+        for name in [".gitignore", ".claudeignore"] {
+            let Ok(text) = fs::read_to_string(root.join(name)) else {
+                continue;
+            };
+            patterns.extend(text.lines().filter_map(IgnorePattern::parse));
+        }
+        Self { patterns }
+    }
+
+    /// Whether `relative`, a path below the root, is ignored
+    pub fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        let path = relative.to_string_lossy().replace('\\', "/");
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| {
+                (is_dir || !pattern.dir_only)
+                    && pattern.glob.matches_with(
+                        if pattern.anchored { &path } else { name },
+                        IGNORE_MATCH_OPTIONS,
+                    )
+            })
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+impl IgnorePattern {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (line, negated) = match line.strip_prefix('!') {
+            Some(line) => (line, true),
+            None => (line.strip_prefix('\\').unwrap_or(line), false),
+        };
+        let (line, dir_only) = match line.strip_suffix('/') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        // `**/name` matches `name` at any depth, as a pattern without a slash does
+        let line = match line.strip_prefix("**/") {
+            Some(rest) if !rest.contains('/') => rest,
+            _ => line,
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        let glob = match glob::Pattern::new(line) {
+            Ok(glob) => glob,
+            Err(e) => {
+                debug!("Skipping ignore pattern {:?}: {}", line, e);
+                return None;
+            }
+        };
+        Some(Self {
+            glob,
+            anchored,
+            dir_only,
+            negated,
+        })
+    }
+}
+
+/// How a symbol name matches a workspace symbol query, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchQuality {
+    Exact,
+    Prefix,
+    Substring,
+    /// The query's characters appear in order, but not next to each other
+    Fuzzy,
+}
+
+/// Match `name` against `query`, ignoring case
+pub fn match_quality(name: &str, query: &str) -> Option<MatchQuality> {
+    let name = name.to_lowercase();
+    let query = query.to_lowercase();
+    if name == query {
+        Some(MatchQuality::Exact)
+    } else if name.starts_with(&query) {
+        Some(MatchQuality::Prefix)
+    } else if name.contains(&query) {
+        Some(MatchQuality::Substring)
+    } else {
+        let mut name_chars = name.chars();
+        query
+            .chars()
+            .all(|ch| name_chars.any(|c| c == ch))
+            .then_some(MatchQuality::Fuzzy)
+    }
+}
+
+/// Index of top-level definitions across workspace files
+#[derive(Debug, Clone, Default)]
+pub struct SymbolIndex {
+    files: Arc<RwLock<HashMap<PathBuf, IndexedFile>>>,
+}
+
+impl SymbolIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the symbols recorded for `path` with those scanned from `text`
+    pub fn index_text(&self, path: &Path, text: &str, language_id: &str) {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let symbols = scan_symbols(text, language_id);
+        self.files
+            .write()
+            .unwrap()
+            .insert(path.to_path_buf(), IndexedFile { modified, symbols });
+    }
+
+    /// Re-scan `path` from disk if it changed since it was last indexed
+    pub fn refresh_file(&self, path: &Path) {
+        let Some(language_id) = language_for_path(path) else {
+            return;
+        };
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let is_current = self
+            .files
+            .read()
+            .unwrap()
+            .get(path)
+            .is_some_and(|file| file.modified.is_some() && file.modified == modified);
+        if is_current {
+            return;
+        }
+
+        match fs::read_to_string(path) {
+            Ok(text) => self.index_text(path, &text, language_id),
+            Err(_) => self.remove_file(path),
+        }
+    }
+
+    /// Bring every source file below `root` up to date
+    pub fn refresh_workspace(&self, root: &Path) {
+        let files = workspace_source_files(root);
+        debug!("Refreshing symbol index for {} files", files.len());
+        for path in files {
+            self.refresh_file(&path);
+        }
+    }
+
+    pub fn remove_file(&self, path: &Path) {
+        self.files.write().unwrap().remove(path);
+    }
+
+    /// Definitions recorded for `path`, in the order they appear
+    pub fn file_symbols(&self, path: &Path) -> Vec<Symbol> {
+        self.files
+            .read()
+            .unwrap()
+            .get(path)
+            .map(|file| file.symbols.clone())
+            .unwrap_or_default()
+    }
+
+    /// Re-key the symbols of a renamed file
+    pub fn rename_file(&self, old_path: &Path, new_path: &Path) {
+        let mut files = self.files.write().unwrap();
+        if let Some(file) = files.remove(old_path) {
+            files.insert(new_path.to_path_buf(), file);
+        }
+    }
+
+    /// Find every definition named exactly `name`
+    pub fn find_definitions(&self, name: &str) -> Vec<(PathBuf, Symbol)> {
+        let files = self.files.read().unwrap();
+        let mut definitions: Vec<(PathBuf, Symbol)> = files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.symbols
+                    .iter()
+                    .filter(|symbol| symbol.name == name)
+                    .map(move |symbol| (path.clone(), symbol.clone()))
+            })
+            .collect();
+        definitions.sort_by(|a, b| a.0.cmp(&b.0));
+        definitions
+    }
+
+    /// Every definition matching `query`, best matches and then shorter names first
+    pub fn search(&self, query: &str) -> Vec<(PathBuf, Symbol)> {
+        let files = self.files.read().unwrap();
+        let mut matches: Vec<(MatchQuality, PathBuf, Symbol)> = files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.symbols.iter().filter_map(move |symbol| {
+                    let quality = match_quality(&symbol.name, query)?;
+                    Some((quality, path.clone(), symbol.clone()))
+                })
+            })
+            .collect();
+        matches.sort_by(|a, b| {
+            a.0.cmp(&b.0)
+                .then(a.2.name.len().cmp(&b.2.name.len()))
+                .then_with(|| a.2.name.cmp(&b.2.name))
+                .then_with(|| a.1.cmp(&b.1))
+                .then(a.2.range.start.line.cmp(&b.2.range.start.line))
+        });
+        matches
+            .into_iter()
+            .map(|(_, path, symbol)| (path, symbol))
+            .collect()
+    }
+}
+
+/// Extract the identifier touching the given UTF-16 position in `line`
+pub fn identifier_at(line: &str, character: usize) -> Option<String> {
+    let byte_pos = crate::documents::char_pos_to_byte_pos(line, character)?;
+
+    let start = line[..byte_pos]
+        .char_indices()
+        .rev()
+        .take_while(|(_, ch)| is_identifier_char(*ch))
+        .last()
+        .map_or(byte_pos, |(i, _)| i);
+    let end = line[byte_pos..]
+        .char_indices()
+        .find(|(_, ch)| !is_identifier_char(*ch))
+        .map_or(line.len(), |(i, _)| byte_pos + i);
+
+    (start < end).then(|| line[start..end].to_string())
+}
+
+/// The identifier at a position in `path`, preferring the open buffer over the file on disk
+pub fn identifier_in_file(
+    documents: &DocumentStore,
+    path: &Path,
+    line: usize,
+    character: usize,
+) -> Option<String> {
+    let text = match Url::from_file_path(path)
+        .ok()
+        .and_then(|uri| documents.get(&uri))
+    {
+        Some(document) => document.text,
+        None => fs::read_to_string(path).ok()?,
+    };
+    identifier_at(text.lines().nth(line)?, character)
+}
+
+/// Whole-word occurrences of `name` in `text`, with the line each one is on
+fn occurrences<'a>(text: &'a str, name: &str) -> Vec<(Range, &'a str)> {
+    let mut found = Vec::new();
+    if name.is_empty() {
+        return found;
+    }
+
+    let name_len = name.encode_utf16().count() as u32;
+    for (line_number, line) in text.lines().enumerate() {
+        for (byte_pos, _) in line.match_indices(name) {
+            let before = line[..byte_pos].chars().next_back();
+            let after = line[byte_pos + name.len()..].chars().next();
+            if before.is_some_and(is_identifier_char) || after.is_some_and(is_identifier_char) {
+                continue;
+            }
+
+            let character = line[..byte_pos].encode_utf16().count() as u32;
+            let range = Range {
+                start: Position {
+                    line: line_number as u32,
+                    character,
+                },
+                end: Position {
+                    line: line_number as u32,
+                    character: character + name_len,
+                },
+            };
+            found.push((range, line));
+        }
+    }
+    found
+}
+
+/// Find every whole-word occurrence of `name` in source files below `roots`.
+///
+/// Open documents are searched using their buffer contents, including those outside
+/// `roots`. Matching is textual, so occurrences in comments and strings are included.
+pub fn find_references(documents: &DocumentStore, roots: &[PathBuf], name: &str) -> Vec<Reference> {
+    // Buffer contents take precedence over the files they were opened from
+    let mut sources: BTreeMap<PathBuf, (String, String)> = documents
+        .all()
+        .into_iter()
+        .filter_map(|(uri, document)| {
+            let path = uri.to_file_path().ok()?;
+            Some((path, (document.text, document.language_id)))
+        })
+        .collect();
+    for root in roots {
+        let Ok(root) = root.canonicalize() else {
+            continue;
+        };
+        for path in workspace_source_files(&root) {
+            if sources.contains_key(&path) {
+                continue;
+            }
+            let (Some(language_id), Ok(text)) =
+                (language_for_path(&path), fs::read_to_string(&path))
+            else {
+                continue;
+            };
+            sources.insert(path, (text, language_id.to_string()));
+        }
+    }
+
+    let mut references = Vec::new();
+    for (path, (text, language_id)) in &sources {
+        let found = occurrences(text, name);
+        if found.is_empty() {
+            continue;
+        }
+
+        let definitions: Vec<Range> = scan_symbols(text, language_id)
+            .into_iter()
+            .filter(|symbol| symbol.name == name)
+            .map(|symbol| symbol.range)
+            .collect();
+        references.extend(found.into_iter().map(|(range, line)| Reference {
+            path: path.clone(),
+            is_definition: definitions.contains(&range),
+            line_text: line.to_string(),
+            range,
+        }));
+    }
+
+    debug!(
+        "Found {} references to `{}` in {} files",
+        references.len(),
+        name,
+        sources.len()
+    );
+    references
+}
+
+/// Find textual call sites of `name`: occurrences followed by `(`.
+///
+/// Lines that define `name` are left out, so a definition such as `fn name(` is never
+/// reported as its own caller. Like `find_references`, this is approximate: calls in
+/// comments and strings are included, and calls through aliases are missed.
+pub fn find_callers(documents: &DocumentStore, roots: &[PathBuf], name: &str) -> Vec<Reference> {
+    let references = find_references(documents, roots, name);
+    let definition_lines: Vec<(PathBuf, u32)> = references
+        .iter()
+        .filter(|reference| reference.is_definition)
+        .map(|reference| (reference.path.clone(), reference.range.start.line))
+        .collect();
+
+    references
+        .into_iter()
+        .filter(|reference| {
+            !definition_lines.contains(&(reference.path.clone(), reference.range.start.line))
+        })
+        .filter(|reference| {
+            let end = reference.range.end.character as usize;
+            char_pos_to_byte_pos(&reference.line_text, end)
+                .is_some_and(|end| reference.line_text[end..].trim_start().starts_with('('))
+        })
+        .collect()
+}
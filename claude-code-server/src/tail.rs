@@ -0,0 +1,192 @@
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+use crate::error::ServerError;
+use crate::notifications::JsonRpcNotification;
+
+/// Lines returned by `tailFile` when no count is given
+pub const DEFAULT_TAIL_LINES: u64 = 20;
+/// Upper bound on the `lines` accepted by `tailFile`
+pub const MAX_TAIL_LINES: u64 = 10_000;
+
+/// Files one connection may follow at once
+pub const MAX_FOLLOWS: usize = 32;
+
+/// How often a followed file is checked for new content
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Block size used when reading backwards from the end of a file
+const CHUNK_SIZE: u64 = 8 * 1024;
+
+/// Channel delivering notifications to a single client connection
+pub type NotificationSink = mpsc::UnboundedSender<JsonRpcNotification>;
+
+/// The last `count` lines of `path`, and the file length they were read at
+pub fn last_lines(path: &Path, count: usize) -> io::Result<(Vec<String>, u64)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if count == 0 {
+        return Ok((Vec::new(), len));
+    }
+
+    // Read whole blocks from the end until enough complete lines are buffered
+    let mut pos = len;
+    let mut buf = Vec::new();
+    while pos > 0 {
+        let newlines = buf.iter().filter(|&&b| b == b'\n').count();
+        let trailing = usize::from(buf.last() == Some(&b'\n'));
+        if newlines - trailing >= count {
+            break;
+        }
+        let start = pos.saturating_sub(CHUNK_SIZE);
+        let mut chunk = vec![0; (pos - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
+        pos = start;
+    }
+
+    let lines = split_lines(&buf);
+    let skip = lines.len().saturating_sub(count);
+    Ok((lines.into_iter().skip(skip).collect(), len))
+}
+
+/// Split raw file content into lines, ignoring a final line terminator
+fn split_lines(bytes: &[u8]) -> Vec<String> {
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    if bytes.is_empty() {
+        return Vec::new();
+    }
+    String::from_utf8_lossy(bytes)
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+        .collect()
+}
+
+/// Files followed on behalf of one client connection.
+///
+/// Every follow is stopped when this is dropped, so follows end with the connection.
+#[derive(Debug, Default)]
+pub struct TailSessions {
+    follows: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl TailSessions {
+    /// Follow `path` from byte `offset`, sending appended lines to `sink`; returns the tail id.
+    ///
+    /// Refused once the connection already follows `MAX_FOLLOWS` files.
+    pub fn start(
+        &self,
+        path: PathBuf,
+        offset: u64,
+        sink: NotificationSink,
+    ) -> Result<String, ServerError> {
+        let mut follows = self.follows.lock().unwrap();
+        if follows.len() >= MAX_FOLLOWS {
+            return Err(ServerError::RateLimited(format!(
+                "Already following {} files; untail one before following {}",
+                MAX_FOLLOWS,
+                path.display()
+            )));
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        info!("Following {} as tail {}", path.display(), id);
+        let handle = tokio::spawn(follow(id.clone(), path, offset, sink));
+        follows.insert(id.clone(), handle);
+        Ok(id)
+    }
+
+    /// Stop the follow with `id`, returning whether it existed
+    pub fn stop(&self, id: &str) -> bool {
+        match self.follows.lock().unwrap().remove(id) {
+            Some(handle) => {
+                info!("Stopped tail {}", id);
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for TailSessions {
+    fn drop(&mut self) {
+        for (id, handle) in self.follows.get_mut().unwrap().drain() {
+            debug!("Stopping tail {} on disconnect", id);
+            handle.abort();
+        }
+    }
+}
+
+/// Poll `path` for content past `offset` and report each batch of complete lines
+async fn follow(id: String, path: PathBuf, mut offset: u64, sink: NotificationSink) {
+    let mut partial = Vec::new();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let len = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                debug!("Cannot stat followed file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if len < offset {
+            info!("{} was truncated, following from the start", path.display());
+            offset = 0;
+            partial.clear();
+        }
+        if len == offset {
+            continue;
+        }
+
+        let appended = match read_range(&path, offset, len).await {
+            Ok(appended) => appended,
+            Err(e) => {
+                debug!("Failed to read followed file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        offset += appended.len() as u64;
+        partial.extend_from_slice(&appended);
+
+        // Hold back a line that is still being written
+        let Some(end) = partial.iter().rposition(|&b| b == b'\n') else {
+            continue;
+        };
+        let complete: Vec<u8> = partial.drain(..=end).collect();
+
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "file_appended".to_string(),
+            params: json!({
+                "tailId": id,
+                "filePath": path,
+                "lines": split_lines(&complete)
+            }),
+        };
+        if sink.send(notification).is_err() {
+            debug!("Client for tail {} is gone", id);
+            break;
+        }
+    }
+}
+
+async fn read_range(path: &Path, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut buf = Vec::new();
+    file.take(end - start).read_to_end(&mut buf).await?;
+    Ok(buf)
+}
@@ -0,0 +1,298 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::error::ServerError;
+
+/// Longest a single test run may take before it is killed
+pub const TEST_RUN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Output kept from the end of an unparseable run
+const RAW_OUTPUT_LIMIT: usize = 64 * 1024;
+
+/// Test frameworks `runTests` knows how to drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestFramework {
+    Cargo,
+    Pytest,
+    Npm,
+}
+
+impl TestFramework {
+    /// Detect the framework from the project files in `root`
+    pub fn detect(root: &Path) -> Option<Self> {
+        if root.join("Cargo.toml").is_file() {
+            Some(Self::Cargo)
+        } else if ["pyproject.toml", "pytest.ini", "setup.py", "setup.cfg"]
+            .iter()
+            .any(|file| root.join(file).is_file())
+        {
+            Some(Self::Pytest)
+        } else if root.join("package.json").is_file() {
+            Some(Self::Npm)
+        } else {
+            None
+        }
+    }
+
+    /// Languages whose tests the framework runs
+    pub fn languages(self) -> &'static [&'static str] {
+        match self {
+            Self::Cargo => &["rust"],
+            Self::Pytest => &["python"],
+            Self::Npm => &["javascript", "typescript", "tsx"],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cargo => "cargo",
+            Self::Pytest => "pytest",
+            Self::Npm => "npm",
+        }
+    }
+
+    fn command(self, report_path: &Path, filter: Option<&str>) -> (&'static str, Vec<String>) {
+        let mut args: Vec<String> = match self {
+            Self::Cargo => vec!["test".into(), "--message-format=json".into()],
+            Self::Pytest => vec![
+                "--json-report".into(),
+                format!("--json-report-file={}", report_path.display()),
+                "-q".into(),
+            ],
+            Self::Npm => vec!["test".into()],
+        };
+        if let Some(filter) = filter {
+            match self {
+                Self::Cargo => args.push(filter.to_string()),
+                Self::Pytest => args.extend(["-k".to_string(), filter.to_string()]),
+                Self::Npm => args.extend(["--".to_string(), filter.to_string()]),
+            }
+        }
+
+        let program = match self {
+            Self::Cargo => "cargo",
+            Self::Pytest => "pytest",
+            Self::Npm => "npm",
+        };
+        (program, args)
+    }
+}
+
+/// Outcome of a single test
+#[derive(Debug, Serialize)]
+pub struct TestCase {
+    pub name: String,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Test results parsed from a framework's machine-readable output
+#[derive(Debug, Default, Serialize)]
+pub struct TestReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    pub tests: Vec<TestCase>,
+}
+
+impl TestReport {
+    fn push(&mut self, name: String, status: &'static str, message: Option<String>) {
+        match status {
+            "passed" => self.passed += 1,
+            "failed" => self.failed += 1,
+            _ => self.ignored += 1,
+        }
+        self.tests.push(TestCase {
+            name,
+            status,
+            message,
+        });
+    }
+}
+
+/// A finished test run
+#[derive(Debug)]
+pub struct TestRun {
+    pub framework: TestFramework,
+    pub command: String,
+    pub exit_code: Option<i32>,
+    /// Parsed results, or `None` when the output could not be understood
+    pub report: Option<TestReport>,
+    /// Human-readable output, kept for runs that could not be parsed
+    pub output: String,
+}
+
+/// Run the project's tests in `root` and parse the results, killing the run after `timeout`
+pub async fn run_tests(
+    root: &Path,
+    framework: TestFramework,
+    filter: Option<&str>,
+    timeout: Duration,
+) -> Result<TestRun, ServerError> {
+    let report_path = std::env::temp_dir().join(format!(
+        "claude-code-pytest-{}-{}.json",
+        std::process::id(),
+        rand::random::<u32>()
+    ));
+    let (program, args) = framework.command(&report_path, filter);
+    let command_line = format!("{} {}", program, args.join(" "));
+    info!("Running tests in {}: {}", root.display(), command_line);
+
+    let child = Command::new(program)
+        .args(&args)
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(ServerError::io(format!("Failed to run {}", program)))?;
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| ServerError::Timeout {
+            operation: command_line.clone(),
+            limit: timeout,
+        })?
+        .map_err(ServerError::io(format!("Failed to run {}", program)))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    debug!("Test run exited with {:?}", output.status.code());
+
+    let (report, raw_output) = match framework {
+        TestFramework::Cargo => {
+            let (report, compiler_errors, test_output) = parse_cargo_output(&stdout);
+            let raw = format!("{}{}{}", compiler_errors, test_output, stderr);
+            (report, raw)
+        }
+        TestFramework::Pytest => {
+            let report = fs::read_to_string(&report_path)
+                .ok()
+                .and_then(|json| parse_pytest_report(&json));
+            let _ = fs::remove_file(&report_path);
+            (report, format!("{}{}", stdout, stderr))
+        }
+        TestFramework::Npm => (None, format!("{}{}", stdout, stderr)),
+    };
+
+    Ok(TestRun {
+        framework,
+        command: command_line,
+        exit_code: output.status.code(),
+        report,
+        output: tail(&raw_output, RAW_OUTPUT_LIMIT),
+    })
+}
+
+/// Keep at most `limit` bytes from the end of `text`
+fn tail(text: &str, limit: usize) -> String {
+    if text.len() <= limit {
+        return text.to_string();
+    }
+    let mut start = text.len() - limit;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("...{}", &text[start..])
+}
+
+/// Parse `cargo test --message-format=json` output.
+///
+/// Cargo's own messages are JSON lines while the test harness still prints its
+/// human-readable report, so both are read from the same stream. Returns the
+/// parsed report, the rendered compiler errors, and the non-JSON output.
+pub fn parse_cargo_output(stdout: &str) -> (Option<TestReport>, String, String) {
+    let mut report = TestReport::default();
+    let mut compiler_errors = String::new();
+    let mut test_output = String::new();
+    let mut failure_messages: Vec<(String, String)> = Vec::new();
+    let mut current_failure: Option<(String, String)> = None;
+
+    for line in stdout.lines() {
+        if line.starts_with('{') {
+            if let Ok(message) = serde_json::from_str::<Value>(line) {
+                let is_error = message["reason"] == "compiler-message"
+                    && message["message"]["level"] == "error";
+                if let Some(rendered) = message["message"]["rendered"].as_str().filter(|_| is_error)
+                {
+                    compiler_errors.push_str(rendered);
+                }
+                continue;
+            }
+        }
+
+        test_output.push_str(line);
+        test_output.push('\n');
+
+        if let Some(name) = line
+            .strip_prefix("---- ")
+            .and_then(|rest| rest.strip_suffix(" stdout ----"))
+        {
+            failure_messages.extend(current_failure.take());
+            current_failure = Some((name.to_string(), String::new()));
+            continue;
+        }
+        if line == "failures:" {
+            failure_messages.extend(current_failure.take());
+            continue;
+        }
+        if let Some((_, message)) = current_failure.as_mut() {
+            message.push_str(line);
+            message.push('\n');
+            continue;
+        }
+
+        let Some((name, result)) = line
+            .strip_prefix("test ")
+            .and_then(|rest| rest.rsplit_once(" ... "))
+        else {
+            continue;
+        };
+        let status = match result {
+            "ok" => "passed",
+            "FAILED" => "failed",
+            result if result.starts_with("ignored") => "ignored",
+            _ => continue,
+        };
+        report.push(name.to_string(), status, None);
+    }
+    failure_messages.extend(current_failure);
+
+    for (name, message) in failure_messages {
+        if let Some(test) = report.tests.iter_mut().find(|test| test.name == name) {
+            test.message = Some(message.trim().to_string());
+        }
+    }
+
+    let report = (!report.tests.is_empty()).then_some(report);
+    (report, compiler_errors, test_output)
+}
+
+/// Parse the report written by the `pytest-json-report` plugin
+pub fn parse_pytest_report(json: &str) -> Option<TestReport> {
+    let report_json: Value = serde_json::from_str(json).ok()?;
+    let mut report = TestReport::default();
+
+    for test in report_json["tests"].as_array()? {
+        let name = test["nodeid"].as_str().unwrap_or_default().to_string();
+        let status = match test["outcome"].as_str() {
+            Some("passed") | Some("xpassed") => "passed",
+            Some("failed") | Some("error") => "failed",
+            _ => "ignored",
+        };
+        let message = ["setup", "call", "teardown"]
+            .iter()
+            .find_map(|phase| test[phase]["longrepr"].as_str())
+            .map(str::to_string);
+        report.push(name, status, message);
+    }
+
+    Some(report)
+}
@@ -0,0 +1,99 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a read tool's result is reused unless `--tool-cache-ttl-ms` says otherwise
+pub const DEFAULT_TOOL_CACHE_TTL: Duration = Duration::from_millis(500);
+
+/// Recent results of idempotent read tools, so a burst of identical calls does the
+/// work once.
+///
+/// Entries live for the TTL at most and are dropped early when the server state
+/// they were computed from changes: a different `state` fingerprint on lookup, or
+/// an explicit `clear` after a write or a reported file change.
+#[derive(Debug, Clone)]
+pub struct ToolCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    stored: Instant,
+    state: u64,
+    content: Value,
+}
+
+impl Default for ToolCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOOL_CACHE_TTL)
+    }
+}
+
+impl ToolCache {
+    /// A cache reusing results for `ttl`; a zero TTL disables it
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Arc::default(),
+            hits: Arc::default(),
+            misses: Arc::default(),
+        }
+    }
+
+    /// The result stored under `key` if it is younger than the TTL and was computed
+    /// from `state`
+    pub fn get(&self, key: &str, state: u64) -> Option<Value> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let fresh = entries
+            .get(key)
+            .filter(|entry| entry.state == state && entry.stored.elapsed() < self.ttl)
+            .map(|entry| entry.content.clone());
+        match fresh {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => {
+                entries.remove(key);
+                self.misses.fetch_add(1, Ordering::Relaxed)
+            }
+        };
+        fresh
+    }
+
+    /// Remember `content` as the result for `key` computed from `state`
+    pub fn insert(&self, key: String, state: u64, content: Value) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.stored.elapsed() < self.ttl);
+        entries.insert(
+            key,
+            Entry {
+                stored: Instant::now(),
+                state,
+                content,
+            },
+        );
+    }
+
+    /// Forget every result, for when the state they were computed from may have changed
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Lookups answered from the cache and lookups that had to recompute, so far
+    pub fn stats(&self) -> Value {
+        serde_json::json!({
+            "ttlMs": self.ttl.as_millis() as u64,
+            "hits": self.hits.load(Ordering::Relaxed),
+            "misses": self.misses.load(Ordering::Relaxed)
+        })
+    }
+}
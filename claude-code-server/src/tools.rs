@@ -0,0 +1,3137 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use lsp_types::{Position, Range, Url};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use crate::diagnostics::DiagnosticsStore;
+use crate::diff_tabs::{DiffTab, DiffTabStore};
+use crate::documents::{self, DocumentStore};
+use crate::editor::{EditorBridge, EditorError, OpenFileParams};
+use crate::error::ServerError;
+use crate::file_cache::{CachedFile, FileCache};
+use crate::formatter;
+use crate::git;
+use crate::mcp::{TextContent, Tool};
+use crate::paths;
+use crate::selection::SelectionStore;
+use crate::shared::SharedState;
+use crate::symbols::{self, SymbolIndex};
+use crate::test_runner::{self, TestFramework, TEST_RUN_TIMEOUT};
+use crate::tool_cache::ToolCache;
+use crate::version;
+use crate::write_locks::WriteLocks;
+
+/// JSON-RPC error code for malformed tool arguments
+pub const TOOL_ERROR_INVALID_PARAMS: i32 = -32602;
+/// JSON-RPC error code for internal tool failures
+pub const TOOL_ERROR_INTERNAL: i32 = -32603;
+/// JSON-RPC error code for writes rejected because the file changed on disk
+pub const TOOL_ERROR_CONFLICT: i32 = -32040;
+/// JSON-RPC error code for calls rejected by the security policy
+pub const TOOL_ERROR_FORBIDDEN: i32 = -32041;
+/// JSON-RPC error code for calls that need an editor when none is connected
+pub const TOOL_ERROR_EDITOR_UNAVAILABLE: i32 = -32042;
+/// JSON-RPC error code for calls that need a workspace folder removed from disk
+pub const TOOL_ERROR_WORKSPACE_UNAVAILABLE: i32 = -32043;
+/// JSON-RPC error code for creating a file that already exists
+pub const TOOL_ERROR_FILE_EXISTS: i32 = -32044;
+/// JSON-RPC error code for multi-file edits undone because one of the writes failed
+pub const TOOL_ERROR_ROLLED_BACK: i32 = -32045;
+/// JSON-RPC error code for text that would overwrite a binary file with decoding damage
+pub const TOOL_ERROR_CONTENT_CORRUPTION_RISK: i32 = -32046;
+/// JSON-RPC error code for reads of files over the configured size limit
+pub const TOOL_ERROR_FILE_TOO_LARGE: i32 = -32047;
+
+/// Largest file read tools load into memory unless `--max-file-size` says otherwise
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Tools that keep working after a workspace folder has been removed
+pub const WORKSPACE_INDEPENDENT_TOOLS: &[&str] = &[
+    "echo",
+    "get_workspace_info",
+    "getWorkspaceFolders",
+    "getEnvironment",
+    "getVersion",
+    "getCurrentSelection",
+    "getLatestSelection",
+    "getOpenEditors",
+    "getDiagnostics",
+    "getWorkspaceDiagnosticsSummary",
+    "closeAllDiffTabs",
+    "close_tab",
+    "untailFile",
+];
+
+/// Registered tools that only simulate their effect, so `get_capabilities` leaves them out
+pub const SIMULATED_TOOLS: &[&str] = &["executeCode"];
+
+/// Tools served by each connection rather than through the registry
+pub const CONNECTION_TOOLS: &[&str] = &["tailFile", "untailFile"];
+
+/// Bytes inspected for NUL characters when sniffing binary content
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// Size of the chunks `statFile` reads while counting lines
+const STAT_CHUNK_LEN: usize = 64 * 1024;
+
+/// Toolchain variables `getEnvironment` reports without extra configuration
+const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "PATH",
+    "SHELL",
+    "LANG",
+    "TERM",
+    "RUSTUP_TOOLCHAIN",
+    "RUSTUP_HOME",
+    "CARGO_HOME",
+    "RUSTFLAGS",
+    "NODE_VERSION",
+    "NODE_ENV",
+    "NVM_BIN",
+    "VIRTUAL_ENV",
+    "CONDA_DEFAULT_ENV",
+    "PYTHONPATH",
+    "JAVA_HOME",
+    "GOPATH",
+    "GOROOT",
+    "GOFLAGS",
+];
+/// Name fragments marking a variable as a secret, which is never reported
+const SECRET_ENV_MARKERS: &[&str] = &["TOKEN", "KEY", "SECRET", "PASSWORD", "PASSWD", "CREDENTIAL"];
+
+/// Files listed by `getWorkspaceDiagnosticsSummary` when no limit is given
+const DEFAULT_DIAGNOSTICS_TOP_FILES: u64 = 10;
+
+/// Lines of code returned on each side of a definition by `getDefinitionContext`
+const DEFAULT_DEFINITION_CONTEXT_LINES: u64 = 10;
+
+/// Lines of code returned on each side of the selection by `getSelectionContext`
+const DEFAULT_SELECTION_CONTEXT_LINES: u64 = 3;
+
+/// Commits returned by `getFileHistory` when no limit is given
+const DEFAULT_FILE_HISTORY_LIMIT: u64 = 10;
+/// Upper bound on the `limit` accepted by `getFileHistory`
+const MAX_FILE_HISTORY_LIMIT: u64 = 100;
+
+/// Unchanged lines around each hunk of a `diffTwoFiles` diff when none is requested
+const DEFAULT_DIFF_CONTEXT_LINES: u64 = 3;
+
+/// Combined size of the files returned by a single `readFiles` call
+const READ_FILES_MAX_TOTAL_BYTES: u64 = 4 * 1024 * 1024;
+/// Maximum number of files a single `readFiles` call may return
+const READ_FILES_MAX_FILES: usize = 200;
+
+/// Paths returned by a `listWorkspaceFiles` page when no limit is given
+const DEFAULT_LIST_FILES_LIMIT: u64 = 500;
+/// Upper bound on the `limit` accepted by `listWorkspaceFiles`
+const MAX_LIST_FILES_LIMIT: u64 = 2000;
+
+/// Signature shared by every tool handler
+pub type ToolHandler = fn(&ToolContext, &Value) -> Result<Value, ToolError>;
+
+/// Error returned by a tool handler, surfaced to the client as a JSON-RPC error
+#[derive(Debug)]
+pub struct ToolError {
+    pub code: i32,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl ToolError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+impl From<EditorError> for ToolError {
+    fn from(error: EditorError) -> Self {
+        match error {
+            EditorError::NotConnected => Self::new(
+                TOOL_ERROR_EDITOR_UNAVAILABLE,
+                "No editor is connected; clipboard access needs the server running in hybrid mode inside Zed",
+            ),
+            EditorError::Timeout => Self::new(TOOL_ERROR_INTERNAL, "Editor did not answer the request"),
+            EditorError::Failed(message) => {
+                Self::new(TOOL_ERROR_INTERNAL, format!("Editor request failed: {}", message))
+            }
+        }
+    }
+}
+
+impl From<anyhow::Error> for ToolError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(TOOL_ERROR_INTERNAL, e.to_string())
+    }
+}
+
+/// Restrictions on what tools may do on the user's machine
+#[derive(Debug, Clone)]
+pub struct SecurityPolicy {
+    /// Allow tools that execute code or spawn external processes
+    pub allow_exec: bool,
+    /// Environment variables `getEnvironment` may report besides `DEFAULT_ENV_ALLOWLIST`
+    pub exposed_env_vars: Vec<String>,
+    /// Directories outside the workspace that write tools may change files in
+    pub allowed_paths: Vec<PathBuf>,
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        Self {
+            allow_exec: true,
+            exposed_env_vars: Vec::new(),
+            allowed_paths: Vec::new(),
+        }
+    }
+}
+
+/// How tools write the paths of workspace files in their responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum PathStyle {
+    /// Full paths, as the server sees them
+    #[default]
+    Absolute,
+    /// Relative to the workspace folder, which is named first when there are several;
+    /// paths outside the workspace stay absolute
+    Relative,
+}
+
+/// Server state made available to every tool handler
+#[derive(Debug, Clone)]
+pub struct ToolContext {
+    pub workspace_folders: Vec<PathBuf>,
+    pub documents: DocumentStore,
+    pub diagnostics: DiagnosticsStore,
+    pub symbols: SymbolIndex,
+    pub selection: SelectionStore,
+    pub file_cache: FileCache,
+    /// Recent results of the read tools `cache_key` accepts
+    pub tool_cache: ToolCache,
+    /// Held by edit tools while they read and rewrite a file
+    pub write_locks: WriteLocks,
+    pub diff_tabs: DiffTabStore,
+    pub editor: EditorBridge,
+    /// Files larger than this many bytes are refused by read tools
+    pub max_file_size: u64,
+    /// How paths of workspace files are written in responses
+    pub path_style: PathStyle,
+    /// Shared by every clone so a reloaded configuration reaches live connections
+    policy: Arc<RwLock<ToolPolicy>>,
+    /// Whether files written by edit tools are run through the language's formatter
+    format_on_edit: Arc<AtomicBool>,
+}
+
+/// Security policy and the tools it admits, replaced together when the configuration is reloaded
+#[derive(Debug)]
+struct ToolPolicy {
+    security: SecurityPolicy,
+    registry: Arc<ToolRegistry>,
+}
+
+impl ToolContext {
+    /// Build a context rooted at `worktree`, or the current directory if none was given
+    pub fn new(
+        worktree: Option<PathBuf>,
+        security: SecurityPolicy,
+        editor: EditorBridge,
+    ) -> Result<Self> {
+        let workspace_folder = match worktree {
+            Some(worktree) => worktree,
+            None => env::current_dir()?,
+        };
+
+        Ok(Self {
+            workspace_folders: vec![workspace_folder],
+            documents: DocumentStore::new(),
+            diagnostics: DiagnosticsStore::new(),
+            symbols: SymbolIndex::new(),
+            selection: SelectionStore::new(),
+            file_cache: FileCache::new(),
+            tool_cache: ToolCache::default(),
+            write_locks: WriteLocks::new(),
+            diff_tabs: DiffTabStore::new(),
+            editor,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            path_style: PathStyle::default(),
+            policy: Arc::new(RwLock::new(ToolPolicy {
+                security,
+                registry: Arc::new(create_default_registry()?),
+            })),
+            format_on_edit: Arc::default(),
+        })
+    }
+
+    /// Read documents, diagnostics, the selection and symbols from `shared`, the stores
+    /// the LSP server fills in hybrid mode
+    pub fn with_shared_state(mut self, shared: SharedState) -> Self {
+        self.documents = shared.documents;
+        self.diagnostics = shared.diagnostics;
+        self.selection = shared.selection;
+        self.symbols = shared.symbols;
+        self
+    }
+
+    pub fn with_tool_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.tool_cache = ToolCache::new(ttl);
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub fn with_path_style(mut self, path_style: PathStyle) -> Self {
+        self.path_style = path_style;
+        self
+    }
+
+    /// Refuse to read `path` if its `size` is over the read limit
+    pub fn check_file_size(&self, path: &Path, size: u64) -> Result<(), ToolError> {
+        if size <= self.max_file_size {
+            return Ok(());
+        }
+        Err(ToolError::new(
+            TOOL_ERROR_FILE_TOO_LARGE,
+            format!(
+                "{} is {} bytes, over the {}-byte read limit",
+                path.display(),
+                size,
+                self.max_file_size
+            ),
+        )
+        .with_data(serde_json::json!({
+            "reason": "file_too_large",
+            "path": path,
+            "size": size,
+            "limit": self.max_file_size,
+            "suggestion": "Read the lines you need with tailFile, or raise --max-file-size"
+        })))
+    }
+
+    /// Read a text file, refusing files over the read limit
+    pub fn read_to_string(&self, path: &Path) -> Result<String, ToolError> {
+        let metadata = fs::metadata(path).map_err(ServerError::io(format!(
+            "Failed to read {}",
+            path.display()
+        )))?;
+        self.check_file_size(path, metadata.len())?;
+        fs::read_to_string(path)
+            .map_err(|e| ServerError::io(format!("Failed to read {}", path.display()))(e).into())
+    }
+
+    /// Read a file's bytes, or `None` if it cannot be read; only a file over the read
+    /// limit is an error
+    pub fn try_read(&self, path: &Path) -> Result<Option<Vec<u8>>, ToolError> {
+        let Ok(metadata) = fs::metadata(path) else {
+            return Ok(None);
+        };
+        self.check_file_size(path, metadata.len())?;
+        Ok(fs::read(path).ok())
+    }
+
+    pub fn security(&self) -> SecurityPolicy {
+        self.policy.read().unwrap().security.clone()
+    }
+
+    pub fn registry(&self) -> Arc<ToolRegistry> {
+        self.policy.read().unwrap().registry.clone()
+    }
+
+    /// Turn formatting of edited files on or off for every clone of this context
+    pub fn set_format_on_edit(&self, enabled: bool) {
+        self.format_on_edit.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Replace the security policy and registry for every clone of this context,
+    /// returning whether the set of tool names changed
+    pub fn replace_policy(&self, security: SecurityPolicy, registry: ToolRegistry) -> bool {
+        let mut policy = self.policy.write().unwrap();
+        let changed = policy.registry.names() != registry.names();
+        *policy = ToolPolicy {
+            security,
+            registry: Arc::new(registry),
+        };
+        changed
+    }
+
+    /// Resolve a tool-supplied path or `file://` URL, expanding `~` and environment
+    /// variables, treating relative paths as relative to the workspace and
+    /// normalizing the result so equal files compare equal
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        let path = PathBuf::from(paths::expand(&paths::from_uri_or_path(path)));
+        let path = match self.workspace_folders.first() {
+            Some(root) if path.is_relative() => root.join(path),
+            _ => path,
+        };
+        paths::normalize(&path)
+    }
+
+    /// Key under which `ToolCache` stores a call to `tool_name`, or `None` if its result
+    /// must not be reused. Paths are resolved first, so `./a.rs` and `a.rs` share an
+    /// entry.
+    pub fn cache_key(&self, tool_name: &str, arguments: &Value) -> Option<String> {
+        let argument = match tool_name {
+            "getWorkspaceFolders" => String::new(),
+            // The URI is echoed back as given, so spellings of it don't share an entry
+            "getDiagnostics" => arguments
+                .get("uri")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            "statFile" => {
+                let path = arguments.get("path").and_then(|v| v.as_str())?;
+                self.resolve_path(path).to_string_lossy().into_owned()
+            }
+            _ => return None,
+        };
+        Some(format!("{}:{}", tool_name, argument))
+    }
+
+    /// Fingerprint of the server state cached results depend on besides the disk,
+    /// whose changes clear the cache instead
+    pub fn cache_state(&self) -> u64 {
+        self.diagnostics.generation()
+    }
+
+    /// `path` as responses should show it, following the configured `PathStyle`
+    pub fn display_path(&self, path: &Path) -> String {
+        if self.path_style == PathStyle::Relative {
+            let normalized = paths::normalize(path);
+            for folder in &self.workspace_folders {
+                let relative = path
+                    .strip_prefix(folder)
+                    .or_else(|_| normalized.strip_prefix(paths::normalize(folder)));
+                if let Ok(relative) = relative {
+                    let relative = relative.to_string_lossy().replace('\\', "/");
+                    if self.workspace_folders.len() > 1 {
+                        return format!("{}/{}", folder_name(folder), relative);
+                    }
+                    return relative;
+                }
+            }
+        }
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Workspace folders that no longer exist on disk
+    pub fn missing_workspace_folders(&self) -> Vec<&PathBuf> {
+        self.workspace_folders
+            .iter()
+            .filter(|folder| !folder.is_dir())
+            .collect()
+    }
+
+    /// Fail with a `workspace_unavailable` error if a workspace folder was removed
+    pub fn require_workspace(&self) -> Result<(), ToolError> {
+        match self.missing_workspace_folders().first() {
+            Some(folder) => Err(ToolError::new(
+                TOOL_ERROR_WORKSPACE_UNAVAILABLE,
+                format!("Workspace folder {} no longer exists", folder.display()),
+            )
+            .with_data(serde_json::json!({
+                "reason": "workspace_unavailable",
+                "path": folder
+            }))),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `path` is inside a workspace folder, following symlinks on both sides
+    pub fn contains_path(&self, path: &Path) -> bool {
+        paths::is_within(path, &self.workspace_folders)
+    }
+
+    /// Refuse to read `path` under the same rule as writes, so a `~` or `$VAR` that
+    /// `resolve_path` expanded cannot reach files outside the workspace
+    pub fn check_readable(&self, path: &Path) -> Result<(), ServerError> {
+        self.check_allowed(path)
+    }
+
+    /// Refuse to write `path` unless it lies inside the workspace or a directory the
+    /// security policy allows, after resolving `..` and symlinks
+    pub fn check_writable(&self, path: &Path) -> Result<(), ServerError> {
+        self.check_allowed(path)
+    }
+
+    fn check_allowed(&self, path: &Path) -> Result<(), ServerError> {
+        if self.contains_path(path) || paths::is_within(path, &self.security().allowed_paths) {
+            return Ok(());
+        }
+        Err(ServerError::PathOutsideWorkspace(path.to_path_buf()))
+    }
+}
+
+/// Name of a workspace folder as shown to clients
+fn folder_name(folder: &Path) -> &str {
+    folder
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("workspace")
+}
+
+/// File contents encoded for transport in a JSON string
+#[derive(Debug)]
+pub struct FileContent {
+    pub content: String,
+    pub encoding: &'static str,
+    pub is_binary: bool,
+}
+
+impl FileContent {
+    /// Encode raw bytes as UTF-8 text, falling back to base64 for binary data
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        if is_binary(&bytes) {
+            return Self {
+                content: BASE64.encode(&bytes),
+                encoding: "base64",
+                is_binary: true,
+            };
+        }
+
+        Self {
+            content: String::from_utf8(bytes).unwrap_or_default(),
+            encoding: "utf8",
+            is_binary: false,
+        }
+    }
+}
+
+/// Treat content as binary if it contains NUL bytes or isn't valid UTF-8
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0) || std::str::from_utf8(bytes).is_err()
+}
+
+/// Decode tool-supplied content according to its declared `encoding`
+pub fn decode_content(content: &str, encoding: Option<&str>) -> Result<Vec<u8>, ToolError> {
+    match encoding {
+        None | Some("utf8") => Ok(content.as_bytes().to_vec()),
+        Some("base64") => BASE64.decode(content).map_err(|e| {
+            ServerError::InvalidParams(format!("Invalid base64 content: {}", e)).into()
+        }),
+        Some(other) => {
+            Err(ServerError::InvalidParams(format!("Unsupported encoding: {}", other)).into())
+        }
+    }
+}
+
+/// Compute the hex-encoded SHA-256 hash used for external modification checks
+pub fn content_hash(content: &[u8]) -> String {
+    to_hex(&Sha256::digest(content))
+}
+
+/// Lowercase hex encoding of `bytes`
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reject a write when the file on disk no longer matches the hash the client last saw
+fn check_expected_hash(
+    ctx: &ToolContext,
+    file_path: &Path,
+    expected_hash: &str,
+) -> Result<(), ToolError> {
+    let current = ctx.try_read(file_path)?;
+    let current_hash = current.as_deref().map(content_hash);
+    let current = current.map(FileContent::from_bytes);
+
+    if current_hash.as_deref() == Some(expected_hash) {
+        return Ok(());
+    }
+
+    warn!(
+        "Rejecting write to {}: file changed on disk",
+        file_path.display()
+    );
+
+    Err(ToolError::new(
+        TOOL_ERROR_CONFLICT,
+        format!(
+            "File {} was modified since it was last read",
+            file_path.display()
+        ),
+    )
+    .with_data(serde_json::json!({
+        "filePath": file_path,
+        "expectedHash": expected_hash,
+        "currentHash": current_hash,
+        "currentEncoding": current.as_ref().map(|c| c.encoding),
+        "currentContent": current.map(|c| c.content),
+    })))
+}
+
+/// Reject the call unless the security policy allows running code
+fn require_exec(ctx: &ToolContext, tool_name: &str) -> Result<(), ServerError> {
+    if ctx.security().allow_exec {
+        return Ok(());
+    }
+    Err(ServerError::PolicyDenied(format!(
+        "{} is disabled by the security policy",
+        tool_name
+    )))
+}
+
+/// The `tools/call` result for a tool's `content`.
+///
+/// Tools answer with their JSON serialized into a text block. When `structured` is
+/// set, that JSON object is also returned as `structuredContent`, so the client need
+/// not parse it out of the text; other clients only get the text.
+pub fn tool_result(content: Value, structured: bool) -> Value {
+    let structured_content = structured
+        .then(|| match content.as_array().map(Vec::as_slice) {
+            Some([block]) => block.get("text").and_then(|text| text.as_str()),
+            _ => None,
+        })
+        .flatten()
+        .and_then(|text| serde_json::from_str::<Value>(text).ok())
+        .filter(Value::is_object);
+
+    let mut result = serde_json::json!({
+        "content": content,
+        "isError": false
+    });
+    if let Some(structured_content) = structured_content {
+        result["structuredContent"] = structured_content;
+    }
+    result
+}
+
+/// Wrap a single string as tool result content
+pub fn text_content(text: impl Into<String>) -> Value {
+    serde_json::json!([TextContent {
+        type_: "text".to_string(),
+        text: text.into(),
+    }])
+}
+
+/// Tool handlers by name
+#[derive(Debug, Clone, Default)]
+pub struct ToolRegistry {
+    handlers: HashMap<String, ToolHandler>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `handler` as `name`, replacing and returning any handler already registered
+    // For deliberate overrides; no built-in tool is replaced yet
+    #[allow(dead_code)]
+    pub fn register_tool(
+        &mut self,
+        name: impl Into<String>,
+        handler: ToolHandler,
+    ) -> Option<ToolHandler> {
+        self.handlers.insert(name.into(), handler)
+    }
+
+    /// Remove the tool registered as `name`, returning its handler
+    pub fn unregister_tool(&mut self, name: &str) -> Option<ToolHandler> {
+        self.handlers.remove(name)
+    }
+
+    /// Register `handler` as `name`, failing if another tool already uses the name
+    pub fn try_register_tool(
+        &mut self,
+        name: impl Into<String>,
+        handler: ToolHandler,
+    ) -> Result<()> {
+        match self.handlers.entry(name.into()) {
+            Entry::Occupied(entry) => Err(anyhow!("Tool {} is already registered", entry.key())),
+            Entry::Vacant(entry) => {
+                entry.insert(handler);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn handler(&self, name: &str) -> Option<ToolHandler> {
+        self.handlers.get(name).copied()
+    }
+
+    /// Names of every registered tool, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.handlers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Registry of the built-in tools, rejecting accidental duplicate names
+pub fn create_default_registry() -> Result<ToolRegistry> {
+    let builtin: &[(&str, ToolHandler)] = &[
+        ("echo", echo),
+        ("get_workspace_info", get_workspace_info),
+        ("closeAllDiffTabs", close_all_diff_tabs),
+        ("openFile", open_file),
+        ("getCurrentSelection", get_current_selection),
+        ("getOpenEditors", get_open_editors),
+        ("getWorkspaceFolders", get_workspace_folders),
+        ("getEnvironment", get_environment),
+        ("getVersion", get_version),
+        ("getSupportedLanguages", get_supported_languages),
+        ("openDiff", open_diff),
+        ("getLatestSelection", get_latest_selection),
+        ("getSelectionContext", get_selection_context),
+        ("getDiagnostics", get_diagnostics),
+        (
+            "getWorkspaceDiagnosticsSummary",
+            get_workspace_diagnostics_summary,
+        ),
+        ("diagnosticsForSelection", diagnostics_for_selection),
+        ("checkDocumentDirty", check_document_dirty),
+        ("saveDocument", save_document),
+        ("createFile", create_file),
+        ("getFileContents", get_file_contents),
+        ("statFile", stat_file),
+        ("readFiles", read_files),
+        ("listWorkspaceFiles", list_workspace_files),
+        ("getDefinitionContext", get_definition_context),
+        ("findReferences", find_references),
+        ("findCallers", find_callers),
+        ("close_tab", close_tab),
+        ("executeCode", execute_code),
+        ("runTests", run_tests),
+        ("getFileHistory", get_file_history),
+        ("getGitDiff", get_git_diff),
+        ("diffTwoFiles", diff_two_files),
+        ("extractToFile", extract_to_file),
+        ("replaceInFiles", replace_in_files),
+        ("renameSymbol", rename_symbol),
+    ];
+
+    let mut registry = ToolRegistry::new();
+    for (name, handler) in builtin {
+        registry.try_register_tool(*name, *handler)?;
+    }
+    Ok(registry)
+}
+
+/// Arguments `name` cannot run without, as declared in its `tools/list` schema
+pub fn required_arguments(name: &str) -> Vec<String> {
+    tool_definitions()
+        .into_iter()
+        .find(|tool| tool.name == name)
+        .and_then(|tool| serde_json::from_value(tool.input_schema.get("required")?.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Tools advertised through `tools/list` for the workspace of `ctx`.
+///
+/// Tools its project files make irrelevant are left out: `runTests` needs a test
+/// framework and is described in its terms, and the git tools need a repository.
+pub fn tool_definitions_for(ctx: &ToolContext) -> Vec<Tool> {
+    let root = ctx.workspace_folders.first();
+    let framework = root.and_then(|root| TestFramework::detect(root));
+    let has_repository = root.is_some_and(|root| git::has_repository(root));
+    let registry = ctx.registry();
+
+    tool_definitions()
+        .into_iter()
+        .filter(|tool| {
+            registry.handler(&tool.name).is_some() || CONNECTION_TOOLS.contains(&tool.name.as_str())
+        })
+        .filter_map(|mut tool| match tool.name.as_str() {
+            "runTests" => {
+                let framework = framework?;
+                tool.description = Some(format!(
+                    "Run the project's tests with {} and return per-test results",
+                    framework.name()
+                ));
+                Some(tool)
+            }
+            "getGitDiff" | "getFileHistory" if !has_repository => None,
+            _ => Some(tool),
+        })
+        .collect()
+}
+
+/// Tools advertised through `tools/list`
+pub fn tool_definitions() -> Vec<Tool> {
+    vec![
+        Tool {
+            name: "openDiff".to_string(),
+            description: Some(
+                "Show proposed contents for a file as a diff against the current file".to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "old_file_path": {"type": "string", "description": "File the diff starts from"},
+                    "new_file_path": {"type": "string", "description": "File the new contents are for"},
+                    "new_file_contents": {"type": "string", "description": "Proposed contents of the file"},
+                    "tab_name": {"type": "string", "description": "Name of the diff tab, default \"diff\""}
+                },
+                "required": ["old_file_path", "new_file_path", "new_file_contents"]
+            }),
+        },
+        Tool {
+            name: "close_tab".to_string(),
+            description: Some("Close a diff tab opened by openDiff".to_string()),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "tab_name": {"type": "string", "description": "Name the tab was opened with"}
+                },
+                "required": ["tab_name"]
+            }),
+        },
+        Tool {
+            name: "getDiagnostics".to_string(),
+            description: Some(
+                "Return the language server diagnostics for one file, or for every file"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "uri": {"type": "string", "description": "File URI to report on (default: all files)"}
+                }
+            }),
+        },
+        Tool {
+            name: "checkDocumentDirty".to_string(),
+            description: Some(
+                "Check whether a file has changes in the editor that are not saved".to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "filePath": {"type": "string", "description": "File to check"}
+                },
+                "required": ["filePath"]
+            }),
+        },
+        Tool {
+            name: "saveDocument".to_string(),
+            description: Some(
+                "Save a file, writing content to it when given".to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "filePath": {"type": "string", "description": "File to save"},
+                    "content": {"type": "string", "description": "New contents of the file"},
+                    "encoding": {"type": "string", "enum": ["utf8", "base64"], "description": "Encoding of content, default utf8"},
+                    "expectedHash": {"type": "string", "description": "Hash the file must still have, from an earlier read"},
+                    "force": {"type": "boolean", "description": "Overwrite a binary file with text content"}
+                },
+                "required": ["filePath"]
+            }),
+        },
+        Tool {
+            name: "openFile".to_string(),
+            description: Some(
+                "Open a file in the editor, returning its language and line count".to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "filePath": {"type": "string", "description": "File to open"},
+                    "preview": {"type": "boolean", "description": "Open as a preview tab"},
+                    "startText": {"type": "string", "description": "Text starting the range to select"},
+                    "endText": {"type": "string", "description": "Text ending the range to select"},
+                    "makeFrontmost": {"type": "boolean", "description": "Bring the editor to the front, default true"}
+                },
+                "required": ["filePath"]
+            }),
+        },
+        Tool {
+            name: "getFileContents".to_string(),
+            description: Some(
+                "Read a file, returning text as UTF-8 and binary files as base64".to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "filePath": {"type": "string", "description": "Path of the file to read"}
+                },
+                "required": ["filePath"]
+            }),
+        },
+        Tool {
+            name: "statFile".to_string(),
+            description: Some(
+                "Return a file's size, line count and type without reading its contents"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "Path of the file to inspect"}
+                },
+                "required": ["path"]
+            }),
+        },
+        Tool {
+            name: "readFiles".to_string(),
+            description: Some(
+                "Read several files at once, given as a list of paths or a glob pattern"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "paths": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Paths of the files to read"
+                    },
+                    "glob": {"type": "string", "description": "Glob pattern, only matching files inside the workspace"}
+                }
+            }),
+        },
+        Tool {
+            name: "runTests".to_string(),
+            description: Some(
+                "Run the project's tests (cargo, pytest or npm) and return per-test results"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "Project directory, defaults to the workspace root"},
+                    "filter": {"type": "string", "description": "Only run tests matching this name"},
+                    "timeoutSeconds": {"type": "integer", "description": "Stop the run after this many seconds, at most 600"}
+                }
+            }),
+        },
+        Tool {
+            name: "getFileHistory".to_string(),
+            description: Some(
+                "List the most recent git commits touching a file, following renames".to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File to show the history of"},
+                    "limit": {"type": "integer", "description": "Maximum number of commits, default 10"}
+                },
+                "required": ["path"]
+            }),
+        },
+        Tool {
+            name: "getGitDiff".to_string(),
+            description: Some(
+                "Show uncommitted changes to tracked files as a unified diff with per-file line counts"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "staged": {"type": "boolean", "description": "Show changes staged for commit instead of unstaged ones"},
+                    "path": {"type": "string", "description": "Only show changes to this file or directory, defaults to the whole workspace"}
+                }
+            }),
+        },
+        Tool {
+            name: "diffTwoFiles".to_string(),
+            description: Some(
+                "Compare two files and return a unified diff; a missing file diffs as empty"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "leftPath": {"type": "string", "description": "Original file"},
+                    "rightPath": {"type": "string", "description": "Changed file"},
+                    "contextLines": {"type": "integer", "description": "Unchanged lines around each hunk, default 3"}
+                },
+                "required": ["leftPath", "rightPath"]
+            }),
+        },
+        Tool {
+            name: "createFile".to_string(),
+            description: Some(
+                "Create a new file, failing if it already exists unless overwrite is set"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File to create, parent directories are created"},
+                    "content": {"type": "string", "description": "Content of the new file"},
+                    "encoding": {"type": "string", "enum": ["utf8", "base64"], "description": "Encoding of content, default utf8"},
+                    "overwrite": {"type": "boolean", "description": "Replace the file if it already exists"}
+                },
+                "required": ["path", "content"]
+            }),
+        },
+        Tool {
+            name: "extractToFile".to_string(),
+            description: Some(
+                "Move a range of a file into a new file, returning the edits made to both"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "sourcePath": {"type": "string", "description": "File to extract from"},
+                    "range": {
+                        "type": "object",
+                        "description": "LSP range of the text to move, with UTF-16 character offsets"
+                    },
+                    "targetPath": {"type": "string", "description": "New file, parent directories are created"},
+                    "overwrite": {"type": "boolean", "description": "Replace the target if it already exists"}
+                },
+                "required": ["sourcePath", "range", "targetPath"]
+            }),
+        },
+        Tool {
+            name: "replaceInFiles".to_string(),
+            description: Some(
+                "Apply text edits to several files at once, restoring every file if any write fails"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "files": {
+                        "type": "array",
+                        "description": "Files to edit, each as {path, edits}",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "path": {"type": "string", "description": "Existing text file to edit"},
+                                "edits": {
+                                    "type": "array",
+                                    "description": "Non-overlapping {range, newText} edits, ranges as LSP ranges with UTF-16 character offsets"
+                                }
+                            },
+                            "required": ["path", "edits"]
+                        }
+                    }
+                },
+                "required": ["files"]
+            }),
+        },
+        Tool {
+            name: "renameSymbol".to_string(),
+            description: Some(
+                "Rename the symbol at a position everywhere findReferences finds it, restoring every file if any write fails"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File containing the symbol"},
+                    "line": {"type": "integer", "description": "Zero-based line of the symbol"},
+                    "character": {"type": "integer", "description": "Zero-based UTF-16 column of the symbol"},
+                    "newName": {"type": "string", "description": "New name, which must be a valid identifier in the file's language"}
+                },
+                "required": ["path", "line", "character", "newName"]
+            }),
+        },
+        Tool {
+            name: "tailFile".to_string(),
+            description: Some(
+                "Return the last lines of a file, optionally streaming appended lines as file_appended notifications"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File to tail"},
+                    "lines": {"type": "integer", "description": "Number of trailing lines to return, default 20"},
+                    "follow": {"type": "boolean", "description": "Keep streaming new lines until untailFile is called"}
+                },
+                "required": ["path"]
+            }),
+        },
+        Tool {
+            name: "untailFile".to_string(),
+            description: Some("Stop following a file started with tailFile".to_string()),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "tailId": {"type": "string", "description": "Id returned by tailFile"}
+                },
+                "required": ["tailId"]
+            }),
+        },
+        Tool {
+            name: "listWorkspaceFiles".to_string(),
+            description: Some(
+                "List the workspace's files a page at a time, skipping ignored and build directories"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "glob": {"type": "string", "description": "Only list paths matching this pattern; patterns without a / match file names"},
+                    "cursor": {"type": "string", "description": "nextCursor from the previous page"},
+                    "limit": {"type": "integer", "description": "Maximum number of paths per page, default 500, at most 2000"}
+                }
+            }),
+        },
+        Tool {
+            name: "getDefinitionContext".to_string(),
+            description: Some(
+                "Find the definition of the symbol at a position and return it with surrounding lines"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File containing the symbol reference"},
+                    "line": {"type": "integer", "description": "Zero-based line of the reference"},
+                    "character": {"type": "integer", "description": "Zero-based UTF-16 column of the reference"},
+                    "contextLines": {"type": "integer", "description": "Lines of context on each side of the definition"}
+                },
+                "required": ["path", "line", "character"]
+            }),
+        },
+        Tool {
+            name: "getSelectionContext".to_string(),
+            description: Some(
+                "Return the editor's latest selection with the lines around it".to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "contextLines": {"type": "integer", "description": "Lines on each side of the selection, default 3"}
+                }
+            }),
+        },
+        Tool {
+            name: "findReferences".to_string(),
+            description: Some(
+                "Find every occurrence of the symbol at a position across open documents and the workspace"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File containing the symbol"},
+                    "line": {"type": "integer", "description": "Zero-based line of the symbol"},
+                    "character": {"type": "integer", "description": "Zero-based UTF-16 column of the symbol"},
+                    "excludeDefinition": {"type": "boolean", "description": "Leave out occurrences that define the symbol (default: false)"}
+                },
+                "required": ["path", "line", "character"]
+            }),
+        },
+        Tool {
+            name: "findCallers".to_string(),
+            description: Some(
+                "Find textual call sites of a function across open documents and the workspace, leaving out its definition"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "functionName": {"type": "string", "description": "Function to find calls of"},
+                    "path": {"type": "string", "description": "File containing the function's name, used when functionName is not given"},
+                    "line": {"type": "integer", "description": "Zero-based line of the function's name"},
+                    "character": {"type": "integer", "description": "Zero-based UTF-16 column of the function's name"}
+                }
+            }),
+        },
+        Tool {
+            name: "getVersion".to_string(),
+            description: Some(
+                "Report the server's version, the git commit it was built from and when it was built"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        Tool {
+            name: "getSupportedLanguages".to_string(),
+            description: Some(
+                "List the languages the server understands and, for each, which of symbols, format, diagnostics and runTests work in this session"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        Tool {
+            name: "getEnvironment".to_string(),
+            description: Some(
+                "Report allowlisted toolchain environment variables such as PATH and RUSTUP_TOOLCHAIN; secrets are never included"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "names": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Only report these variables (default: every allowlisted variable)"
+                    }
+                }
+            }),
+        },
+        Tool {
+            name: "getWorkspaceDiagnosticsSummary".to_string(),
+            description: Some(
+                "Count errors and warnings across the workspace and list the files with the most errors"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "limit": {"type": "integer", "description": "Maximum number of files to list (default: 10)"}
+                }
+            }),
+        },
+        Tool {
+            name: "diagnosticsForSelection".to_string(),
+            description: Some(
+                "List the diagnostics of a file that overlap a range, by default the current selection"
+                    .to_string(),
+            ),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File whose diagnostics to list"},
+                    "range": {"type": "object", "description": "Zero-based LSP range; defaults to the latest selection in the file"}
+                },
+                "required": ["path"]
+            }),
+        },
+    ]
+}
+
+fn echo(_ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let text = arguments
+        .get("text")
+        .and_then(|v| v.as_str())
+        .unwrap_or("No text provided");
+
+    Ok(text_content(format!("Echo: {}", text)))
+}
+
+fn get_workspace_info(ctx: &ToolContext, _arguments: &Value) -> Result<Value, ToolError> {
+    let workspace_info = ctx
+        .workspace_folders
+        .first()
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Unknown workspace".to_string());
+
+    Ok(text_content(format!(
+        "Current workspace: {}",
+        workspace_info
+    )))
+}
+
+fn close_all_diff_tabs(ctx: &ToolContext, _arguments: &Value) -> Result<Value, ToolError> {
+    info!("Closing all diff tabs");
+
+    // Return the count of closed diff tabs according to protocol
+    let closed_count = ctx.diff_tabs.close_all();
+
+    Ok(text_content(format!("CLOSED_{}_DIFF_TABS", closed_count)))
+}
+
+fn open_file(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("filePath")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing filePath".to_string()))?;
+    let preview = arguments
+        .get("preview")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let _start_text = arguments.get("startText").and_then(|v| v.as_str());
+    let _end_text = arguments.get("endText").and_then(|v| v.as_str());
+    let make_frontmost = arguments
+        .get("makeFrontmost")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+
+    info!("Opening file: {} (preview: {})", file_path, preview);
+
+    // Same response shape whether or not the file was brought to the front
+    let path = ctx.resolve_path(file_path);
+    ctx.check_readable(&path)?;
+    // Prefer the editor's buffer over the file on disk when it is open
+    let document = Url::from_file_path(&path)
+        .ok()
+        .and_then(|uri| ctx.documents.get(&uri));
+    let language_id = document
+        .as_ref()
+        .map(|document| document.language_id.clone())
+        .or_else(|| symbols::language_for_path(&path).map(str::to_string))
+        .unwrap_or_else(|| "text".to_string());
+    // Counted a chunk at a time, so files over the read limit are never loaded whole
+    let line_count = match document {
+        Some(document) => document.text.lines().count(),
+        None => fs::File::open(&path)
+            .ok()
+            .and_then(|file| count_lines(file).ok().flatten())
+            .unwrap_or(0),
+    };
+
+    // Handlers run on a blocking thread, so waiting for the send doesn't stall the runtime
+    let params = OpenFileParams {
+        file_path: path.to_string_lossy().into_owned(),
+        preview,
+        make_frontmost,
+    };
+    let opened = tokio::runtime::Handle::current().block_on(ctx.editor.open_file(params));
+    if let Err(e) = opened {
+        // Without an editor attached the response still describes the file
+        debug!("Editor not told to open {}: {:?}", file_path, e);
+    }
+
+    let response = serde_json::json!({
+        "success": true,
+        "filePath": path.to_string_lossy(),
+        "languageId": language_id,
+        "lineCount": line_count,
+        "makeFrontmost": make_frontmost,
+        "message": format!("Opened file: {}", file_path)
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+fn get_current_selection(ctx: &ToolContext, _arguments: &Value) -> Result<Value, ToolError> {
+    info!("Getting current selection");
+
+    Ok(text_content(
+        selection_response(ctx, "No active editor found").to_string(),
+    ))
+}
+
+/// The stored selection with `success: true`, or `message` when the editor has not
+/// reported one
+fn selection_response(ctx: &ToolContext, message: &str) -> Value {
+    match ctx.selection.get() {
+        Some(selection) => {
+            let file_path = ctx.display_path(Path::new(&selection.file_path));
+            let mut response = serde_json::to_value(selection).unwrap_or_default();
+            response["filePath"] = Value::String(file_path);
+            response["success"] = Value::Bool(true);
+            response
+        }
+        None => serde_json::json!({
+            "success": false,
+            "message": message
+        }),
+    }
+}
+
+fn get_open_editors(_ctx: &ToolContext, _arguments: &Value) -> Result<Value, ToolError> {
+    info!("Getting open editors");
+
+    // Return JSON-stringified response according to protocol
+    let response = serde_json::json!({
+        "tabs": []
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+fn get_workspace_folders(ctx: &ToolContext, _arguments: &Value) -> Result<Value, ToolError> {
+    info!("Getting workspace folders");
+
+    let folders: Vec<Value> = ctx
+        .workspace_folders
+        .iter()
+        .map(|folder| {
+            let path = folder.to_string_lossy();
+            serde_json::json!({
+                "name": folder_name(folder),
+                "uri": format!("file://{}", path),
+                "path": path,
+                "exists": folder.is_dir()
+            })
+        })
+        .collect();
+
+    // Return JSON-stringified response according to protocol
+    let response = serde_json::json!({
+        "success": true,
+        "folders": folders,
+        "rootPath": ctx.workspace_folders.first()
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+/// Whether a variable name looks like it holds a credential
+fn is_secret_env_var(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_ENV_MARKERS
+        .iter()
+        .any(|marker| name.contains(marker))
+}
+
+fn get_environment(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let requested: Option<Vec<&str>> = arguments
+        .get("names")
+        .and_then(|v| v.as_array())
+        .map(|names| names.iter().filter_map(|name| name.as_str()).collect());
+
+    info!("Getting environment variables: {:?}", requested);
+
+    let security = ctx.security();
+    let allowlist = DEFAULT_ENV_ALLOWLIST
+        .iter()
+        .copied()
+        .chain(security.exposed_env_vars.iter().map(String::as_str))
+        .filter(|name| !is_secret_env_var(name))
+        .filter(|name| requested.as_ref().is_none_or(|names| names.contains(name)));
+
+    let mut variables = serde_json::Map::new();
+    for name in allowlist {
+        if let Some(value) = env::var_os(name) {
+            variables.insert(name.to_string(), value.to_string_lossy().into());
+        }
+    }
+    let path_entries: Vec<String> = env::var_os("PATH")
+        .filter(|_| variables.contains_key("PATH"))
+        .map(|path| {
+            env::split_paths(&path)
+                .map(|entry| entry.to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let response = serde_json::json!({
+        "success": true,
+        "variables": variables,
+        "pathEntries": path_entries
+    });
+    Ok(text_content(response.to_string()))
+}
+
+fn get_version(_ctx: &ToolContext, _arguments: &Value) -> Result<Value, ToolError> {
+    let mut response = serde_json::json!(version::BUILD_INFO);
+    response["success"] = serde_json::json!(true);
+    Ok(text_content(response.to_string()))
+}
+
+fn get_supported_languages(ctx: &ToolContext, _arguments: &Value) -> Result<Value, ToolError> {
+    info!("Getting supported languages");
+
+    // Tests run only where a framework is detected and the policy allows spawning it
+    let test_languages: Vec<&str> =
+        if ctx.security().allow_exec && ctx.registry().handler("runTests").is_some() {
+            ctx.workspace_folders
+                .iter()
+                .filter_map(|folder| TestFramework::detect(folder))
+                .flat_map(|framework| framework.languages().iter().copied())
+                .collect()
+        } else {
+            Vec::new()
+        };
+    // Diagnostics come from the editor's language servers, reported over LSP in hybrid mode
+    let diagnostics = ctx.editor.is_connected();
+
+    let languages: BTreeMap<&str, Vec<&str>> = symbols::LANGUAGES
+        .iter()
+        .map(|&language| {
+            let features = [
+                ("symbols", symbols::has_symbols(language)),
+                ("format", formatter::has_formatter(language)),
+                ("diagnostics", diagnostics),
+                ("runTests", test_languages.contains(&language)),
+            ];
+            let available = features
+                .into_iter()
+                .filter(|(_, available)| *available)
+                .map(|(feature, _)| feature)
+                .collect();
+            (language, available)
+        })
+        .collect();
+
+    let response = serde_json::json!({
+        "success": true,
+        "languages": languages
+    });
+    Ok(text_content(response.to_string()))
+}
+
+fn open_diff(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let required = |name: &str| {
+        arguments
+            .get(name)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ServerError::InvalidParams(format!("{} is required", name)))
+    };
+    let old_file_path = required("old_file_path")?;
+    let new_file_path = required("new_file_path")?;
+    let new_file_contents = required("new_file_contents")?;
+    let tab_name = arguments
+        .get("tab_name")
+        .and_then(|v| v.as_str())
+        .unwrap_or("diff");
+
+    info!("Opening diff for {} vs {}", old_file_path, new_file_path);
+    ctx.diff_tabs.open(
+        tab_name,
+        DiffTab {
+            old_file_path: old_file_path.to_string(),
+            new_file_path: new_file_path.to_string(),
+        },
+    );
+
+    // Always respond with FILE_SAVED to simulate accepting the diff
+    Ok(serde_json::json!([
+        TextContent {
+            type_: "text".to_string(),
+            text: "FILE_SAVED".to_string(),
+        },
+        TextContent {
+            type_: "text".to_string(),
+            text: new_file_contents.to_string(),
+        },
+    ]))
+}
+
+fn get_latest_selection(ctx: &ToolContext, _arguments: &Value) -> Result<Value, ToolError> {
+    info!("Getting latest selection");
+
+    Ok(text_content(
+        selection_response(ctx, "No selection available").to_string(),
+    ))
+}
+
+fn get_selection_context(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let context_lines = arguments
+        .get("contextLines")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_SELECTION_CONTEXT_LINES) as usize;
+
+    info!("Getting selection context ({} lines)", context_lines);
+
+    let Some(selection) = ctx.selection.get() else {
+        return Err(
+            ToolError::new(TOOL_ERROR_INVALID_PARAMS, "No active selection")
+                .with_data(serde_json::json!({ "reason": "no_selection" })),
+        );
+    };
+
+    // Prefer the editor's buffer over the file on disk when it is open
+    let document = Url::parse(&selection.file_url)
+        .ok()
+        .and_then(|uri| ctx.documents.get(&uri));
+    let content = match document {
+        Some(document) => document.text,
+        None => ctx.read_to_string(Path::new(&selection.file_path))?,
+    };
+
+    let lines: Vec<&str> = content.lines().collect();
+    let last_line = lines.len().saturating_sub(1);
+    let start_line = (selection.selection.start.line as usize)
+        .saturating_sub(context_lines)
+        .min(last_line);
+    let end_line = (selection.selection.end.line as usize + context_lines).min(last_line);
+    let context: Vec<Value> = lines
+        .get(start_line..=end_line)
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(offset, text)| {
+            serde_json::json!({
+                "line": start_line + offset,
+                "text": text
+            })
+        })
+        .collect();
+
+    let response = serde_json::json!({
+        "success": true,
+        "filePath": ctx.display_path(Path::new(&selection.file_path)),
+        "text": selection.text,
+        "selection": selection.selection,
+        "context": {
+            "startLine": start_line,
+            "endLine": end_line,
+            "lines": context
+        }
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+fn get_diagnostics(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let uri = arguments.get("uri").and_then(|v| v.as_str());
+
+    info!("Getting diagnostics for: {:?}", uri);
+
+    // Return JSON-stringified array of diagnostics per file
+    let response = if let Some(uri) = uri {
+        let url = Url::parse(uri).ok();
+        let diagnostics = url
+            .as_ref()
+            .map(|url| ctx.diagnostics.get(url))
+            .unwrap_or_default();
+        serde_json::json!([{
+            "uri": uri,
+            "filePath": url.and_then(|url| diagnostics_path(ctx, &url)),
+            "diagnostics": diagnostics
+        }])
+    } else {
+        let files: Vec<Value> = ctx
+            .diagnostics
+            .all()
+            .into_iter()
+            .map(|(uri, diagnostics)| {
+                serde_json::json!({
+                    "filePath": diagnostics_path(ctx, &uri),
+                    "uri": uri,
+                    "diagnostics": diagnostics
+                })
+            })
+            .collect();
+        Value::Array(files)
+    };
+
+    Ok(text_content(response.to_string()))
+}
+
+/// Path of the file a diagnostics `uri` names, in the configured style
+fn diagnostics_path(ctx: &ToolContext, uri: &Url) -> Option<String> {
+    uri.to_file_path().ok().map(|path| ctx.display_path(&path))
+}
+
+fn get_workspace_diagnostics_summary(
+    ctx: &ToolContext,
+    arguments: &Value,
+) -> Result<Value, ToolError> {
+    let limit = arguments
+        .get("limit")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_DIAGNOSTICS_TOP_FILES);
+
+    info!("Summarizing workspace diagnostics");
+
+    let summary = ctx.diagnostics.summary(limit as usize);
+    let response = serde_json::to_value(summary).map_err(ServerError::from)?;
+    Ok(text_content(response.to_string()))
+}
+
+fn diagnostics_for_selection(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+    let path = ctx.resolve_path(file_path);
+    let uri = Url::from_file_path(&path).map_err(|_| {
+        ServerError::InvalidParams(format!("Not an absolute path: {}", path.display()))
+    })?;
+
+    let range = match arguments.get("range") {
+        Some(range) => serde_json::from_value(range.clone())
+            .map_err(|e| ServerError::InvalidParams(format!("Invalid range: {}", e)))?,
+        // Only a selection in this file says which part of it Claude is looking at
+        None => ctx
+            .selection
+            .get()
+            .filter(|selection| ctx.resolve_path(&selection.file_url) == path)
+            .map(|selection| Range::new(selection.selection.start, selection.selection.end))
+            .ok_or_else(|| {
+                ToolError::new(
+                    TOOL_ERROR_INVALID_PARAMS,
+                    "No range given and no selection in this file",
+                )
+                .with_data(serde_json::json!({ "reason": "no_selection" }))
+            })?,
+    };
+
+    info!("Getting diagnostics of {} in {:?}", path.display(), range);
+
+    let diagnostics = ctx.diagnostics.in_range(&uri, range);
+    let response = serde_json::json!({
+        "uri": uri,
+        "range": range,
+        "diagnostics": diagnostics
+    });
+    Ok(text_content(response.to_string()))
+}
+
+fn check_document_dirty(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("filePath")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing filePath".to_string()))?;
+
+    info!("Checking if document is dirty: {}", file_path);
+
+    let path = ctx.resolve_path(file_path);
+    ctx.check_readable(&path)?;
+    let document = Url::from_file_path(&path)
+        .ok()
+        .and_then(|uri| ctx.documents.get(&uri));
+    let on_disk = match &document {
+        Some(document) if document.dirty => ctx.try_read(&path)?,
+        _ => None,
+    };
+    let is_untitled = !path.exists();
+    // A set dirty bit may hide an edit that was undone, so it only says to compare
+    let is_dirty = match (&document, &on_disk) {
+        (None, _) => false,
+        (Some(document), _) if !document.dirty => false,
+        (Some(document), Some(disk)) => {
+            content_hash(document.text.as_bytes()) != content_hash(disk)
+        }
+        (Some(_), None) => true,
+    };
+
+    // Return JSON-stringified response according to protocol
+    let response = serde_json::json!({
+        "success": true,
+        "filePath": file_path,
+        "isDirty": is_dirty,
+        "isUntitled": is_untitled
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+/// Refuse text holding U+FFFD for a file that is binary on disk.
+///
+/// Replacement characters are what a failed decode of binary data leaves behind, so
+/// saving them would corrupt the file rather than edit it.
+fn check_binary_overwrite(
+    ctx: &ToolContext,
+    path: &Path,
+    content: &str,
+    encoding: Option<&str>,
+) -> Result<(), ToolError> {
+    if encoding == Some("base64") || !content.contains(char::REPLACEMENT_CHARACTER) {
+        return Ok(());
+    }
+    let Some(current) = ctx.try_read(path)? else {
+        return Ok(());
+    };
+    if !is_binary(&current) {
+        return Ok(());
+    }
+
+    warn!(
+        "Refusing to overwrite binary file {} with decoded text",
+        path.display()
+    );
+    Err(ToolError::new(
+        TOOL_ERROR_CONTENT_CORRUPTION_RISK,
+        format!(
+            "{} is binary and the new content contains replacement characters; set force to save anyway",
+            path.display()
+        ),
+    )
+    .with_data(serde_json::json!({
+        "reason": "content_corruption_risk",
+        "filePath": path
+    })))
+}
+
+fn save_document(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("filePath")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("filePath is required".to_string()))?;
+
+    let content = arguments.get("content").and_then(|v| v.as_str());
+    let encoding = arguments.get("encoding").and_then(|v| v.as_str());
+    let expected_hash = arguments.get("expectedHash").and_then(|v| v.as_str());
+    let force = arguments
+        .get("force")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    info!("Saving document: {}", file_path);
+
+    let path = ctx.resolve_path(file_path);
+    ctx.check_writable(&path)?;
+    let bytes = content
+        .map(|content| decode_content(content, encoding))
+        .transpose()?;
+    let _lock = ctx.write_locks.lock([path.as_path()]);
+    if let (Some(content), false) = (content, force) {
+        check_binary_overwrite(ctx, &path, content, encoding)?;
+    }
+    // Compared last, right before the write and while holding the path's write lock,
+    // so no other tool can change the file between the comparison and the write
+    if let Some(expected_hash) = expected_hash {
+        check_expected_hash(ctx, &path, expected_hash)?;
+    }
+
+    // Return JSON-stringified response according to protocol
+    let mut response = serde_json::json!({
+        "success": true,
+        "filePath": file_path,
+        "saved": true,
+        "message": "Document saved successfully"
+    });
+
+    if let Some(bytes) = bytes {
+        let written = fs::write(&path, &bytes);
+        ctx.file_cache.invalidate(&path);
+        written.map_err(ServerError::io(format!("Failed to write {}", file_path)))?;
+        let bytes = format_after_edit(ctx, &path, bytes, &mut response);
+        response["hash"] = serde_json::json!(content_hash(&bytes));
+    }
+
+    Ok(text_content(response.to_string()))
+}
+
+/// Run the language's formatter over a file an edit tool just wrote, when
+/// `formatOnEdit` is on, returning the bytes now on disk.
+///
+/// The edit stands even if formatting fails: the file keeps the unformatted
+/// content and `response` gets a warning. Otherwise `response` gets the formatted
+/// content.
+fn format_after_edit(
+    ctx: &ToolContext,
+    path: &Path,
+    bytes: Vec<u8>,
+    response: &mut Value,
+) -> Vec<u8> {
+    if !ctx.format_on_edit.load(Ordering::Relaxed) {
+        return bytes;
+    }
+    let Some(language_id) =
+        symbols::language_for_path(path).filter(|l| formatter::has_formatter(l))
+    else {
+        return bytes;
+    };
+    let Ok(text) = std::str::from_utf8(&bytes) else {
+        return bytes;
+    };
+
+    // Handlers run on a blocking thread, where waiting on the runtime is allowed
+    let cwd = ctx.workspace_folders.first().map(PathBuf::as_path);
+    let formatted = tokio::runtime::Handle::current()
+        .block_on(formatter::format_text(language_id, path, text, cwd))
+        .and_then(|formatted| {
+            write_atomically(path, formatted.as_bytes())
+                .map_err(|e| anyhow!("Failed to write formatted {}: {}", path.display(), e))?;
+            Ok(formatted)
+        });
+    ctx.file_cache.invalidate(path);
+
+    match formatted {
+        Ok(formatted) => {
+            response["formatted"] = serde_json::json!(true);
+            response["content"] = serde_json::json!(formatted);
+            formatted.into_bytes()
+        }
+        Err(e) => {
+            warn!("Formatting {} after an edit failed: {}", path.display(), e);
+            response["formatted"] = serde_json::json!(false);
+            response["content"] = serde_json::json!(text);
+            response["warning"] = serde_json::json!(format!("Formatting failed: {}", e));
+            bytes
+        }
+    }
+}
+
+fn create_file(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+    let content = arguments
+        .get("content")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing content".to_string()))?;
+    let encoding = arguments.get("encoding").and_then(|v| v.as_str());
+    let overwrite = arguments
+        .get("overwrite")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    info!("Creating file: {}", file_path);
+
+    let path = ctx.resolve_path(file_path);
+    ctx.check_writable(&path)?;
+    let _lock = ctx.write_locks.lock([path.as_path()]);
+    if path.is_dir() {
+        return Err(
+            ServerError::InvalidParams(format!("{} is a directory", path.display())).into(),
+        );
+    }
+
+    let existed = path.exists();
+    if existed && !overwrite {
+        return Err(ToolError::new(
+            TOOL_ERROR_FILE_EXISTS,
+            format!("{} already exists", path.display()),
+        )
+        .with_data(serde_json::json!({
+            "reason": "file_exists",
+            "path": path
+        })));
+    }
+
+    let bytes = decode_content(content, encoding)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(ServerError::io(format!(
+            "Failed to create {}",
+            parent.display()
+        )))?;
+    }
+    write_atomically(&path, &bytes)
+        .map_err(ServerError::io(format!("Failed to write {}", file_path)))?;
+    ctx.file_cache.invalidate(&path);
+
+    let mut response = serde_json::json!({
+        "success": true,
+        "filePath": path,
+        "overwritten": existed
+    });
+    let bytes = format_after_edit(ctx, &path, bytes, &mut response);
+    response["hash"] = serde_json::json!(content_hash(&bytes));
+
+    Ok(text_content(response.to_string()))
+}
+
+fn get_file_contents(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("filePath")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing filePath".to_string()))?;
+
+    info!("Reading file contents: {}", file_path);
+
+    let path = ctx.resolve_path(file_path);
+    ctx.check_readable(&path)?;
+    let metadata =
+        fs::metadata(&path).map_err(ServerError::io(format!("Failed to read {}", file_path)))?;
+    ctx.check_file_size(&path, metadata.len())?;
+    let (file, cached) = match ctx.file_cache.get(&path, &metadata) {
+        Some(file) => (file, true),
+        None => {
+            let bytes = fs::read(&path)
+                .map_err(ServerError::io(format!("Failed to read {}", file_path)))?;
+            let file = Arc::new(CachedFile {
+                hash: content_hash(&bytes),
+                content: FileContent::from_bytes(bytes),
+            });
+            ctx.file_cache.insert(path, &metadata, file.clone());
+            (file, false)
+        }
+    };
+
+    let response = serde_json::json!({
+        "success": true,
+        "filePath": file_path,
+        "content": file.content.content,
+        "encoding": file.content.encoding,
+        "isBinary": file.content.is_binary,
+        "hash": file.hash,
+        "cached": cached
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+fn stat_file(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+
+    info!("Stat file: {}", file_path);
+
+    let path = ctx.resolve_path(file_path);
+    ctx.check_readable(&path)?;
+    let metadata =
+        fs::metadata(&path).map_err(ServerError::io(format!("Failed to stat {}", file_path)))?;
+    if metadata.is_dir() {
+        return Err(
+            ServerError::InvalidParams(format!("{} is a directory", path.display())).into(),
+        );
+    }
+    let modified_ms = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_millis() as u64);
+
+    let line_count = fs::File::open(&path)
+        .and_then(count_lines)
+        .map_err(ServerError::io(format!("Failed to read {}", file_path)))?;
+    let language_id = Url::from_file_path(&path)
+        .ok()
+        .and_then(|uri| ctx.documents.get(&uri))
+        .map(|document| document.language_id)
+        .or_else(|| symbols::language_for_path(&path).map(str::to_string))
+        .unwrap_or_else(|| "text".to_string());
+
+    let response = serde_json::json!({
+        "success": true,
+        "filePath": path,
+        "size": metadata.len(),
+        "lineCount": line_count,
+        "modifiedMs": modified_ms,
+        "isBinary": line_count.is_none(),
+        "languageId": language_id
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+/// Count lines the way `str::lines` does, a chunk at a time, or `None` if the content is binary
+fn count_lines(mut reader: impl Read) -> std::io::Result<Option<usize>> {
+    let mut chunk = vec![0; STAT_CHUNK_LEN];
+    // Bytes of a UTF-8 sequence split across two chunks
+    let mut unfinished = Vec::new();
+    let mut sniffed = 0;
+    let mut newlines = 0;
+    let mut last_byte = None;
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let bytes = &chunk[..read];
+
+        let sniff = bytes.len().min(BINARY_SNIFF_LEN - sniffed);
+        if bytes[..sniff].contains(&0) {
+            return Ok(None);
+        }
+        sniffed += sniff;
+
+        unfinished.extend_from_slice(bytes);
+        match std::str::from_utf8(&unfinished) {
+            Ok(_) => unfinished.clear(),
+            Err(e) if e.error_len().is_none() => {
+                unfinished.drain(..e.valid_up_to());
+            }
+            Err(_) => return Ok(None),
+        }
+
+        newlines += bytes.iter().filter(|&&byte| byte == b'\n').count();
+        last_byte = bytes.last().copied();
+    }
+    if !unfinished.is_empty() {
+        return Ok(None);
+    }
+
+    // A final line without a trailing newline still counts
+    Ok(Some(
+        newlines + usize::from(last_byte.is_some_and(|byte| byte != b'\n')),
+    ))
+}
+
+fn read_files(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let paths: Vec<PathBuf> = if let Some(paths) = arguments.get("paths") {
+        paths
+            .as_array()
+            .ok_or_else(|| ServerError::InvalidParams("paths must be an array".to_string()))?
+            .iter()
+            .map(|path| {
+                path.as_str()
+                    .map(|path| ctx.resolve_path(path))
+                    .ok_or_else(|| {
+                        ServerError::InvalidParams("paths must contain strings".to_string())
+                    })
+            })
+            .collect::<Result<_, _>>()?
+    } else if let Some(pattern) = arguments.get("glob").and_then(|v| v.as_str()) {
+        let pattern = ctx.resolve_path(pattern);
+        glob::glob(&pattern.to_string_lossy())
+            .map_err(|e| ServerError::InvalidParams(format!("Invalid glob pattern: {}", e)))?
+            .flatten()
+            .filter(|path| path.is_file() && ctx.contains_path(path))
+            .take(READ_FILES_MAX_FILES + 1)
+            .collect()
+    } else {
+        return Err(
+            ServerError::InvalidParams("Either paths or glob is required".to_string()).into(),
+        );
+    };
+
+    if paths.len() > READ_FILES_MAX_FILES {
+        return Err(ServerError::InvalidParams(format!(
+            "readFiles is limited to {} files",
+            READ_FILES_MAX_FILES
+        ))
+        .into());
+    }
+
+    info!("Reading {} files", paths.len());
+
+    let mut total_bytes = 0;
+    let files: Vec<Value> = paths
+        .iter()
+        .map(|path| {
+            let path_str = path.to_string_lossy();
+            if let Err(e) = ctx.check_readable(path) {
+                return serde_json::json!({
+                    "path": path_str,
+                    "error": e.to_string(),
+                    "reason": e.reason()
+                });
+            }
+            let size = match fs::metadata(path) {
+                Ok(metadata) => metadata.len(),
+                Err(e) => return serde_json::json!({ "path": path_str, "error": e.to_string() }),
+            };
+            if let Err(e) = ctx.check_file_size(path, size) {
+                return serde_json::json!({
+                    "path": path_str,
+                    "error": e.message,
+                    "reason": "file_too_large",
+                    "size": size
+                });
+            }
+            if total_bytes + size > READ_FILES_MAX_TOTAL_BYTES {
+                return serde_json::json!({
+                    "path": path_str,
+                    "error": format!(
+                        "Skipped: batch exceeds {} bytes",
+                        READ_FILES_MAX_TOTAL_BYTES
+                    )
+                });
+            }
+
+            match fs::read(path) {
+                Ok(bytes) => {
+                    total_bytes += bytes.len() as u64;
+                    let file_content = FileContent::from_bytes(bytes);
+                    serde_json::json!({
+                        "path": path_str,
+                        "content": file_content.content,
+                        "encoding": file_content.encoding,
+                        "isBinary": file_content.is_binary
+                    })
+                }
+                Err(e) => serde_json::json!({ "path": path_str, "error": e.to_string() }),
+            }
+        })
+        .collect();
+
+    Ok(text_content(Value::Array(files).to_string()))
+}
+
+/// Workspace files as paths relative to their folder, one page at a time.
+///
+/// The cursor is the offset of the page's first path in the sorted listing. With
+/// several workspace folders each path is prefixed with its folder's name.
+fn list_workspace_files(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let pattern = arguments
+        .get("glob")
+        .and_then(|v| v.as_str())
+        .map(glob::Pattern::new)
+        .transpose()
+        .map_err(|e| ServerError::InvalidParams(format!("Invalid glob pattern: {}", e)))?;
+    let offset = match arguments.get("cursor").and_then(|v| v.as_str()) {
+        Some(cursor) => cursor
+            .parse::<usize>()
+            .map_err(|_| ServerError::InvalidParams(format!("Invalid cursor: {}", cursor)))?,
+        None => 0,
+    };
+    let limit = arguments
+        .get("limit")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_LIST_FILES_LIMIT)
+        .clamp(1, MAX_LIST_FILES_LIMIT) as usize;
+
+    info!("Listing workspace files from {} (limit {})", offset, limit);
+
+    // `*` stays within one path component, as in the ignore files
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let mut paths = Vec::new();
+    for folder in &ctx.workspace_folders {
+        for file in symbols::workspace_files(folder) {
+            let relative = file.strip_prefix(folder).unwrap_or(&file);
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            let matches = pattern.as_ref().is_none_or(|pattern| {
+                if pattern.as_str().contains('/') {
+                    pattern.matches_with(&relative, options)
+                } else {
+                    let file_name = relative.rsplit('/').next().unwrap_or(&relative);
+                    pattern.matches_with(file_name, options)
+                }
+            });
+            if matches {
+                paths.push(ctx.display_path(&file));
+            }
+        }
+    }
+
+    let total = paths.len();
+    let page: Vec<String> = paths.into_iter().skip(offset).take(limit).collect();
+    let next_offset = offset + page.len();
+    let mut response = serde_json::json!({
+        "success": true,
+        "files": page,
+        "total": total
+    });
+    if next_offset < total {
+        response["nextCursor"] = Value::String(next_offset.to_string());
+    }
+
+    Ok(text_content(response.to_string()))
+}
+
+fn get_definition_context(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+    let line = arguments
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| ServerError::InvalidParams("Missing line".to_string()))?;
+    let character = arguments
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| ServerError::InvalidParams("Missing character".to_string()))?;
+    let context_lines = arguments
+        .get("contextLines")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_DEFINITION_CONTEXT_LINES);
+
+    info!(
+        "Getting definition context for {}:{}:{}",
+        file_path, line, character
+    );
+
+    let response = definition_context(
+        ctx,
+        file_path,
+        line as usize,
+        character as usize,
+        context_lines as usize,
+    )?;
+
+    Ok(text_content(response.to_string()))
+}
+
+fn definition_context(
+    ctx: &ToolContext,
+    file_path: &str,
+    line: usize,
+    character: usize,
+    context_lines: usize,
+) -> Result<Value, ToolError> {
+    let file_path = ctx.resolve_path(file_path);
+    let content = ctx.read_to_string(&file_path)?;
+
+    let Some(name) = content
+        .lines()
+        .nth(line)
+        .and_then(|text| symbols::identifier_at(text, character))
+    else {
+        return Ok(serde_json::json!({
+            "success": false,
+            "message": format!("No symbol at {}:{}:{}", file_path.display(), line, character)
+        }));
+    };
+
+    // Prefer a definition in the same file before scanning the rest of the workspace
+    ctx.symbols.refresh_file(&file_path);
+    let mut definitions = ctx.symbols.find_definitions(&name);
+    if !definitions.iter().any(|(path, _)| *path == file_path) {
+        for folder in &ctx.workspace_folders {
+            let folder = folder.canonicalize().map_err(ServerError::io(format!(
+                "Failed to resolve {}",
+                folder.display()
+            )))?;
+            ctx.symbols.refresh_workspace(&folder);
+        }
+        definitions = ctx.symbols.find_definitions(&name);
+    }
+
+    let Some((definition_path, symbol)) = definitions
+        .iter()
+        .find(|(path, _)| *path == file_path)
+        .or_else(|| definitions.first())
+        .cloned()
+    else {
+        return Ok(serde_json::json!({
+            "success": false,
+            "message": format!("No definition found for `{}`", name)
+        }));
+    };
+
+    let definition_content = ctx.read_to_string(&definition_path)?;
+    let lines: Vec<&str> = definition_content.lines().collect();
+    let definition_line = symbol.range.start.line as usize;
+    let start_line = definition_line.saturating_sub(context_lines);
+    let end_line = (definition_line + context_lines).min(lines.len().saturating_sub(1));
+    let context: Vec<Value> = lines
+        .get(start_line..=end_line)
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(offset, text)| {
+            serde_json::json!({
+                "line": start_line + offset,
+                "text": text
+            })
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "success": true,
+        "symbol": name,
+        "definition": {
+            "path": ctx.display_path(&definition_path),
+            "kind": symbol.kind,
+            "range": symbol.range
+        },
+        "context": {
+            "startLine": start_line,
+            "endLine": end_line,
+            "lines": context
+        }
+    }))
+}
+
+fn find_references(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+    let line = arguments
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| ServerError::InvalidParams("Missing line".to_string()))?;
+    let character = arguments
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| ServerError::InvalidParams("Missing character".to_string()))?;
+    let exclude_definition = arguments
+        .get("excludeDefinition")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    info!(
+        "Finding references for {}:{}:{}",
+        file_path, line, character
+    );
+
+    let path = ctx.resolve_path(file_path);
+    let Some(name) =
+        symbols::identifier_in_file(&ctx.documents, &path, line as usize, character as usize)
+    else {
+        let response = serde_json::json!({
+            "success": false,
+            "message": format!("No symbol at {}:{}:{}", path.display(), line, character)
+        });
+        return Ok(text_content(response.to_string()));
+    };
+
+    let references: Vec<Value> =
+        symbols::find_references(&ctx.documents, &ctx.workspace_folders, &name)
+            .into_iter()
+            .filter(|reference| !(exclude_definition && reference.is_definition))
+            .map(|reference| {
+                serde_json::json!({
+                    "path": ctx.display_path(&reference.path),
+                    "line": reference.range.start.line,
+                    "character": reference.range.start.character,
+                    "lineText": reference.line_text
+                })
+            })
+            .collect();
+
+    let response = serde_json::json!({
+        "success": true,
+        "symbol": name,
+        "count": references.len(),
+        "references": references
+    });
+    Ok(text_content(response.to_string()))
+}
+
+fn find_callers(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let name = match arguments.get("functionName").and_then(|v| v.as_str()) {
+        Some(name) => name.to_string(),
+        None => {
+            let file_path = arguments
+                .get("path")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| {
+                    ServerError::InvalidParams("Missing functionName or path".to_string())
+                })?;
+            let line = arguments
+                .get("line")
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| ServerError::InvalidParams("Missing line".to_string()))?;
+            let character = arguments
+                .get("character")
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| ServerError::InvalidParams("Missing character".to_string()))?;
+
+            let path = ctx.resolve_path(file_path);
+            let Some(name) = symbols::identifier_in_file(
+                &ctx.documents,
+                &path,
+                line as usize,
+                character as usize,
+            ) else {
+                let response = serde_json::json!({
+                    "success": false,
+                    "message": format!("No symbol at {}:{}:{}", path.display(), line, character)
+                });
+                return Ok(text_content(response.to_string()));
+            };
+            name
+        }
+    };
+
+    info!("Finding callers of {}", name);
+
+    let callers: Vec<Value> = symbols::find_callers(&ctx.documents, &ctx.workspace_folders, &name)
+        .into_iter()
+        .map(|caller| {
+            serde_json::json!({
+                "path": ctx.display_path(&caller.path),
+                "line": caller.range.start.line,
+                "lineText": caller.line_text
+            })
+        })
+        .collect();
+
+    let response = serde_json::json!({
+        "success": true,
+        "functionName": name,
+        "count": callers.len(),
+        "callers": callers
+    });
+    Ok(text_content(response.to_string()))
+}
+
+fn close_tab(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let tab_name = arguments
+        .get("tab_name")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing tab_name".to_string()))?;
+
+    // Only tabs opened with openDiff are known by name
+    let tab = ctx.diff_tabs.close(tab_name).ok_or_else(|| {
+        ToolError::new(
+            TOOL_ERROR_INVALID_PARAMS,
+            format!("No tab named {}", tab_name),
+        )
+        .with_data(serde_json::json!({ "reason": "tab_not_found", "tabName": tab_name }))
+    })?;
+    info!(
+        "Closing tab {}: {} vs {}",
+        tab_name, tab.old_file_path, tab.new_file_path
+    );
+
+    Ok(text_content("TAB_CLOSED"))
+}
+
+fn execute_code(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    require_exec(ctx, "executeCode")?;
+
+    let code = arguments
+        .get("code")
+        .and_then(|v| v.as_str())
+        .unwrap_or("No code provided");
+
+    info!(
+        "Executing code: {}",
+        code.chars().take(50).collect::<String>()
+    );
+
+    Ok(text_content(format!(
+        "Code executed successfully. Output: (simulated execution of {} characters)",
+        code.len()
+    )))
+}
+
+fn run_tests(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    require_exec(ctx, "runTests")?;
+
+    let root = arguments
+        .get("path")
+        .and_then(|v| v.as_str())
+        .map(|path| ctx.resolve_path(path))
+        .or_else(|| ctx.workspace_folders.first().cloned())
+        .ok_or_else(|| {
+            ServerError::InvalidParams("No workspace folder to run tests in".to_string())
+        })?;
+    let filter = arguments.get("filter").and_then(|v| v.as_str());
+    let timeout = arguments
+        .get("timeoutSeconds")
+        .and_then(|v| v.as_u64())
+        .map_or(TEST_RUN_TIMEOUT, |seconds| {
+            Duration::from_secs(seconds.max(1)).min(TEST_RUN_TIMEOUT)
+        });
+
+    let framework = TestFramework::detect(&root).ok_or_else(|| {
+        ServerError::InvalidParams(format!(
+            "No supported test framework found in {}",
+            root.display()
+        ))
+    })?;
+
+    let run = tokio::runtime::Handle::current()
+        .block_on(test_runner::run_tests(&root, framework, filter, timeout))?;
+
+    let mut response = serde_json::json!({
+        "success": true,
+        "framework": run.framework.name(),
+        "command": run.command,
+        "exitCode": run.exit_code
+    });
+    match run.report {
+        Some(report) => {
+            response["passed"] = serde_json::json!(report.passed);
+            response["failed"] = serde_json::json!(report.failed);
+            response["ignored"] = serde_json::json!(report.ignored);
+            response["tests"] = serde_json::json!(report.tests);
+        }
+        // Unknown output formats are handed back verbatim for Claude to read
+        None => response["rawOutput"] = serde_json::json!(run.output),
+    }
+
+    Ok(text_content(response.to_string()))
+}
+
+fn get_file_history(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    require_exec(ctx, "getFileHistory")?;
+
+    let path = arguments
+        .get("path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+    let limit = arguments
+        .get("limit")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_FILE_HISTORY_LIMIT)
+        .clamp(1, MAX_FILE_HISTORY_LIMIT) as usize;
+
+    info!("Getting file history: {} (limit {})", path, limit);
+
+    // git reports the repository root with symlinks resolved
+    let file_path = paths::canonicalize_lenient(&ctx.resolve_path(path))
+        .map_err(|e| ServerError::InvalidParams(format!("{}: {}", path, e)))?;
+    ctx.check_readable(&file_path)?;
+    let Some(dir) = file_path.parent().filter(|dir| dir.is_dir()) else {
+        return Err(
+            ServerError::InvalidParams(format!("Directory of {} does not exist", path)).into(),
+        );
+    };
+
+    let Some(root) = git::repository_root(dir)? else {
+        let response = serde_json::json!({
+            "success": false,
+            "filePath": file_path,
+            "message": format!("{} is not inside a git repository", path)
+        });
+        return Ok(text_content(response.to_string()));
+    };
+
+    let commits = git::file_history(&root, &file_path, limit)?;
+    let response = serde_json::json!({
+        "success": true,
+        "filePath": file_path,
+        "tracked": git::is_tracked(&root, &file_path)?,
+        "commits": commits
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+fn get_git_diff(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    require_exec(ctx, "getGitDiff")?;
+
+    let staged = arguments
+        .get("staged")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let path = arguments.get("path").and_then(|v| v.as_str());
+
+    info!("Getting git diff (staged: {}, path: {:?})", staged, path);
+
+    // Without a path, changes outside the workspace folder are left out
+    let target = match path {
+        Some(path) => ctx.resolve_path(path),
+        None => ctx
+            .workspace_folders
+            .first()
+            .cloned()
+            .ok_or_else(|| ToolError::new(TOOL_ERROR_INTERNAL, "No workspace folder"))?,
+    };
+    // git reports the repository root with symlinks resolved
+    let target = paths::canonicalize_lenient(&target)
+        .map_err(|e| ServerError::InvalidParams(format!("{}: {}", target.display(), e)))?;
+    let Some(dir) = Some(target.as_path())
+        .filter(|target| target.is_dir())
+        .or_else(|| target.parent().filter(|dir| dir.is_dir()))
+    else {
+        return Err(ServerError::InvalidParams(format!(
+            "Directory of {} does not exist",
+            target.display()
+        ))
+        .into());
+    };
+
+    let Some(root) = git::repository_root(dir)? else {
+        let response = serde_json::json!({
+            "success": false,
+            "message": format!("{} is not inside a git repository", target.display())
+        });
+        return Ok(text_content(response.to_string()));
+    };
+
+    let (diff, files) = git::diff(&root, staged, &target)?;
+    let response = serde_json::json!({
+        "success": true,
+        "staged": staged,
+        "repositoryRoot": root,
+        "hasChanges": !files.is_empty(),
+        "files": files,
+        "diff": diff
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+/// Read one side of a diff, treating a missing file as empty
+fn read_diff_side(ctx: &ToolContext, path: &str) -> Result<(PathBuf, Option<Vec<u8>>), ToolError> {
+    let resolved = ctx.resolve_path(path);
+    if !ctx.contains_path(&resolved) {
+        return Err(ServerError::PathOutsideWorkspace(resolved).into());
+    }
+
+    if let Ok(metadata) = fs::metadata(&resolved) {
+        ctx.check_file_size(&resolved, metadata.len())?;
+    }
+    match fs::read(&resolved) {
+        Ok(bytes) => Ok((resolved, Some(bytes))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok((resolved, None)),
+        Err(e) => Err(ServerError::io(format!("Failed to read {}", path))(e).into()),
+    }
+}
+
+fn diff_two_files(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let left_path = arguments
+        .get("leftPath")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing leftPath".to_string()))?;
+    let right_path = arguments
+        .get("rightPath")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing rightPath".to_string()))?;
+    let context_lines = arguments
+        .get("contextLines")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_DIFF_CONTEXT_LINES) as usize;
+
+    info!("Diffing {} against {}", left_path, right_path);
+
+    let (left, left_bytes) = read_diff_side(ctx, left_path)?;
+    let (right, right_bytes) = read_diff_side(ctx, right_path)?;
+    if left_bytes.is_none() && right_bytes.is_none() {
+        return Err(ServerError::InvalidParams(format!(
+            "Neither {} nor {} exists",
+            left_path, right_path
+        ))
+        .into());
+    }
+
+    let identical = left_bytes == right_bytes;
+    let (left_exists, right_exists) = (left_bytes.is_some(), right_bytes.is_some());
+    let mut response = serde_json::json!({
+        "success": true,
+        "leftPath": left,
+        "rightPath": right,
+        "leftExists": left_exists,
+        "rightExists": right_exists,
+        "identical": identical
+    });
+
+    let left_bytes = left_bytes.unwrap_or_default();
+    let right_bytes = right_bytes.unwrap_or_default();
+    if is_binary(&left_bytes) || is_binary(&right_bytes) {
+        response["isBinary"] = Value::Bool(true);
+        response["diff"] = Value::String(String::new());
+        return Ok(text_content(response.to_string()));
+    }
+
+    // Like git, label a missing side as /dev/null
+    let label = |path: &Path, exists: bool| {
+        if exists {
+            path.to_string_lossy().to_string()
+        } else {
+            "/dev/null".to_string()
+        }
+    };
+    let left_label = label(&left, left_exists);
+    let right_label = label(&right, right_exists);
+
+    let left_text = String::from_utf8_lossy(&left_bytes);
+    let right_text = String::from_utf8_lossy(&right_bytes);
+    let diff = if identical {
+        String::new()
+    } else {
+        TextDiff::from_lines(left_text.as_ref(), right_text.as_ref())
+            .unified_diff()
+            .context_radius(context_lines)
+            .header(&left_label, &right_label)
+            .to_string()
+    };
+
+    response["isBinary"] = Value::Bool(false);
+    response["diff"] = Value::String(diff);
+    Ok(text_content(response.to_string()))
+}
+
+/// Position just past the last character of `text`
+fn end_position(text: &str) -> Position {
+    let line = text.matches('\n').count();
+    let last_line = text.rsplit('\n').next().unwrap_or_default();
+    Position {
+        line: line as u32,
+        character: last_line.encode_utf16().count() as u32,
+    }
+}
+
+/// Replace `path` in one step by renaming a fully written sibling over it
+fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+    fs::write(&temp, bytes)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        fs::remove_file(&temp).ok();
+    })
+}
+
+fn extract_to_file(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let source_path = arguments
+        .get("sourcePath")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing sourcePath".to_string()))?;
+    let target_path = arguments
+        .get("targetPath")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing targetPath".to_string()))?;
+    let range: Range = arguments
+        .get("range")
+        .cloned()
+        .and_then(|v| serde_json::from_value(v).ok())
+        .ok_or_else(|| ServerError::InvalidParams("Missing or invalid range".to_string()))?;
+    let overwrite = arguments
+        .get("overwrite")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    info!(
+        "Extracting {:?} of {} into {}",
+        range, source_path, target_path
+    );
+
+    let source = ctx.resolve_path(source_path);
+    let target = ctx.resolve_path(target_path);
+    for path in [&source, &target] {
+        ctx.check_writable(path)?;
+    }
+    if paths::canonicalize_lenient(&source).ok() == paths::canonicalize_lenient(&target).ok() {
+        return Err(ServerError::InvalidParams(
+            "sourcePath and targetPath must differ".to_string(),
+        )
+        .into());
+    }
+    let _lock = ctx.write_locks.lock([source.as_path(), target.as_path()]);
+
+    // Kept to restore on failure, so a target that will be replaced is read whole
+    if let (true, Ok(metadata)) = (overwrite, fs::metadata(&target)) {
+        ctx.check_file_size(&target, metadata.len())?;
+    }
+    let previous_target = match fs::read(&target) {
+        Ok(_) if !overwrite => {
+            return Err(ToolError::new(
+                TOOL_ERROR_CONFLICT,
+                format!("{} already exists", target.display()),
+            )
+            .with_data(serde_json::json!({ "targetPath": target })));
+        }
+        Ok(bytes) => Some(bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(ServerError::io(format!("Failed to read {}", target_path))(e).into()),
+    };
+
+    let text = ctx.read_to_string(&source)?;
+    let (Some(start), Some(end)) = (
+        documents::offset_at(&text, range.start),
+        documents::offset_at(&text, range.end),
+    ) else {
+        return Err(ServerError::InvalidParams(format!(
+            "Range {:?} is outside {}",
+            range, source_path
+        ))
+        .into());
+    };
+    if start >= end {
+        return Err(ServerError::InvalidParams(
+            "range must select at least one character".to_string(),
+        )
+        .into());
+    }
+
+    let extracted = &text[start..end];
+    let mut remaining = text.clone();
+    remaining.replace_range(start..end, "");
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(ServerError::io(format!(
+            "Failed to create {}",
+            parent.display()
+        )))?;
+    }
+    write_atomically(&target, extracted.as_bytes())
+        .map_err(ServerError::io(format!("Failed to write {}", target_path)))?;
+
+    // Put the target back if the source can't be updated, so the text is never in both files
+    if let Err(e) = write_atomically(&source, remaining.as_bytes()) {
+        let rollback = match &previous_target {
+            Some(bytes) => write_atomically(&target, bytes),
+            None => fs::remove_file(&target),
+        };
+        if let Err(rollback_error) = rollback {
+            warn!(
+                "Failed to roll back {}: {}",
+                target.display(),
+                rollback_error
+            );
+        }
+        ctx.file_cache.invalidate(&target);
+        return Err(ServerError::io(format!("Failed to write {}", source_path))(e).into());
+    }
+    ctx.file_cache.invalidate(&source);
+    ctx.file_cache.invalidate(&target);
+
+    let previous_end = previous_target
+        .map(|bytes| end_position(&String::from_utf8_lossy(&bytes)))
+        .unwrap_or_default();
+    let response = serde_json::json!({
+        "success": true,
+        "sourcePath": source,
+        "targetPath": target,
+        "edits": [
+            {
+                "filePath": source,
+                "range": range,
+                "newText": "",
+                "hash": content_hash(remaining.as_bytes())
+            },
+            {
+                "filePath": target,
+                "range": Range::new(Position::default(), previous_end),
+                "newText": extracted,
+                "hash": content_hash(extracted.as_bytes())
+            }
+        ]
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+/// A `{range, newText}` edit of one file passed to `replaceInFiles`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplaceEdit {
+    range: Range,
+    new_text: String,
+}
+
+/// A file `replaceInFiles` or `renameSymbol` is about to rewrite, with its content
+/// before and after
+struct PlannedReplace {
+    path: PathBuf,
+    original: String,
+    replaced: String,
+    edit_count: usize,
+}
+
+/// Apply `edits` to `text`, failing if a range is outside it or ranges overlap
+fn apply_edits(text: &str, edits: &[ReplaceEdit]) -> Result<String, String> {
+    let mut spans = Vec::with_capacity(edits.len());
+    for edit in edits {
+        let (Some(start), Some(end)) = (
+            documents::offset_at(text, edit.range.start),
+            documents::offset_at(text, edit.range.end),
+        ) else {
+            return Err(format!("range {:?} is outside the file", edit.range));
+        };
+        if start > end {
+            return Err(format!("range {:?} ends before it starts", edit.range));
+        }
+        spans.push((start, end, edit.new_text.as_str()));
+    }
+
+    spans.sort_by_key(|&(start, end, _)| (start, end));
+    if spans.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+        return Err("edits overlap".to_string());
+    }
+
+    let mut replaced = text.to_string();
+    for (start, end, new_text) in spans.into_iter().rev() {
+        replaced.replace_range(start..end, new_text);
+    }
+    Ok(replaced)
+}
+
+/// Write every planned file, restoring those already written if any write fails.
+///
+/// Returns the `{filePath, editsApplied, hash}` summary of each file written.
+fn write_planned(ctx: &ToolContext, planned: &[PlannedReplace]) -> Result<Vec<Value>, ToolError> {
+    for (index, file) in planned.iter().enumerate() {
+        let Err(e) = write_atomically(&file.path, file.replaced.as_bytes()) else {
+            continue;
+        };
+
+        // Writes replace files whole, so the failed file still holds its original content
+        let mut restored = Vec::with_capacity(index);
+        for written in &planned[..index] {
+            match write_atomically(&written.path, written.original.as_bytes()) {
+                Ok(()) => restored.push(&written.path),
+                Err(rollback_error) => warn!(
+                    "Failed to roll back {}: {}",
+                    written.path.display(),
+                    rollback_error
+                ),
+            }
+        }
+        for written in &planned[..index] {
+            ctx.file_cache.invalidate(&written.path);
+        }
+        return Err(ToolError::new(
+            TOOL_ERROR_ROLLED_BACK,
+            format!("Failed to write {}: {}", file.path.display(), e),
+        )
+        .with_data(serde_json::json!({
+            "reason": "rolled_back",
+            "failedPath": file.path,
+            "restored": restored
+        })));
+    }
+
+    for file in planned {
+        ctx.file_cache.invalidate(&file.path);
+    }
+
+    Ok(planned
+        .iter()
+        .map(|file| {
+            serde_json::json!({
+                "filePath": file.path,
+                "editsApplied": file.edit_count,
+                "hash": content_hash(file.replaced.as_bytes())
+            })
+        })
+        .collect())
+}
+
+fn replace_in_files(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let files = arguments
+        .get("files")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| ServerError::InvalidParams("Missing files".to_string()))?;
+
+    info!("Replacing text in {} files", files.len());
+
+    let mut requested: Vec<(&str, PathBuf, Vec<ReplaceEdit>)> = Vec::with_capacity(files.len());
+    for file in files {
+        let file_path = file
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+        let edits: Vec<ReplaceEdit> = file
+            .get("edits")
+            .cloned()
+            .and_then(|v| serde_json::from_value(v).ok())
+            .ok_or_else(|| {
+                ServerError::InvalidParams(format!("Missing or invalid edits for {}", file_path))
+            })?;
+
+        let path = ctx.resolve_path(file_path);
+        ctx.check_writable(&path)?;
+        let canonical = paths::canonicalize_lenient(&path).ok();
+        if requested
+            .iter()
+            .any(|(_, other, _)| paths::canonicalize_lenient(other).ok() == canonical)
+        {
+            return Err(ServerError::InvalidParams(format!(
+                "{} is listed more than once",
+                file_path
+            ))
+            .into());
+        }
+        requested.push((file_path, path, edits));
+    }
+
+    // Read and edit every file before writing any, so bad input changes nothing
+    let _lock = ctx
+        .write_locks
+        .lock(requested.iter().map(|(_, path, _)| path.as_path()));
+    let mut planned: Vec<PlannedReplace> = Vec::with_capacity(requested.len());
+    for (file_path, path, edits) in requested {
+        let original = ctx.read_to_string(&path)?;
+        let replaced = apply_edits(&original, &edits).map_err(|reason| {
+            ServerError::InvalidParams(format!("Cannot edit {}: {}", file_path, reason))
+        })?;
+        planned.push(PlannedReplace {
+            path,
+            original,
+            replaced,
+            edit_count: edits.len(),
+        });
+    }
+
+    let files = write_planned(ctx, &planned)?;
+    let response = serde_json::json!({
+        "success": true,
+        "files": files
+    });
+
+    Ok(text_content(response.to_string()))
+}
+
+fn rename_symbol(ctx: &ToolContext, arguments: &Value) -> Result<Value, ToolError> {
+    let file_path = arguments
+        .get("path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing path".to_string()))?;
+    let line = arguments
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| ServerError::InvalidParams("Missing line".to_string()))?;
+    let character = arguments
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| ServerError::InvalidParams("Missing character".to_string()))?;
+    let new_name = arguments
+        .get("newName")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ServerError::InvalidParams("Missing newName".to_string()))?;
+
+    let path = ctx.resolve_path(file_path);
+    let Some(name) =
+        symbols::identifier_in_file(&ctx.documents, &path, line as usize, character as usize)
+    else {
+        return Err(ToolError::new(
+            TOOL_ERROR_INVALID_PARAMS,
+            format!("No symbol at {}:{}:{}", path.display(), line, character),
+        )
+        .with_data(serde_json::json!({ "reason": "no_symbol" })));
+    };
+    let language_id = symbols::language_for_path(&path).unwrap_or_default();
+    if !symbols::is_valid_identifier(new_name, language_id) || new_name == name {
+        return Err(ToolError::new(
+            TOOL_ERROR_INVALID_PARAMS,
+            format!("Cannot rename `{}` to `{}`", name, new_name),
+        )
+        .with_data(serde_json::json!({
+            "reason": "invalid_identifier",
+            "language": language_id
+        })));
+    }
+
+    info!("Renaming `{}` to `{}`", name, new_name);
+
+    let mut edits: BTreeMap<PathBuf, Vec<ReplaceEdit>> = BTreeMap::new();
+    for reference in symbols::find_references(&ctx.documents, &ctx.workspace_folders, &name) {
+        if !ctx.contains_path(&reference.path) {
+            debug!(
+                "Not renaming in {}, which is outside the workspace",
+                reference.path.display()
+            );
+            continue;
+        }
+        edits.entry(reference.path).or_default().push(ReplaceEdit {
+            range: reference.range,
+            new_text: new_name.to_string(),
+        });
+    }
+
+    // References in open buffers must still line up with the files that get written
+    let _lock = ctx.write_locks.lock(edits.keys().map(PathBuf::as_path));
+    let mut planned = Vec::with_capacity(edits.len());
+    for (path, edits) in edits {
+        let original = ctx.read_to_string(&path)?;
+        let matches_disk = edits.iter().all(|edit| {
+            let start = documents::offset_at(&original, edit.range.start);
+            let end = documents::offset_at(&original, edit.range.end);
+            matches!((start, end), (Some(start), Some(end)) if original.get(start..end) == Some(name.as_str()))
+        });
+        if !matches_disk {
+            return Err(ToolError::new(
+                TOOL_ERROR_CONFLICT,
+                format!(
+                    "{} has unsaved changes; save it before renaming",
+                    path.display()
+                ),
+            )
+            .with_data(serde_json::json!({ "reason": "unsaved_changes", "path": path })));
+        }
+        let replaced = apply_edits(&original, &edits)
+            .map_err(|reason| anyhow::anyhow!("Cannot edit {}: {}", path.display(), reason))?;
+        planned.push(PlannedReplace {
+            path,
+            original,
+            replaced,
+            edit_count: edits.len(),
+        });
+    }
+
+    let files = write_planned(ctx, &planned)?;
+    let response = serde_json::json!({
+        "success": true,
+        "symbol": name,
+        "newName": new_name,
+        "editCount": planned.iter().map(|file| file.edit_count).sum::<usize>(),
+        "files": files
+    });
+    Ok(text_content(response.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_names_are_refused_unless_overwritten() {
+        let mut registry = ToolRegistry::new();
+        registry.try_register_tool("echo", echo).unwrap();
+
+        let duplicate = registry.try_register_tool("echo", get_version).unwrap_err();
+        assert_eq!(duplicate.to_string(), "Tool echo is already registered");
+        assert_eq!(registry.names(), ["echo"]);
+
+        assert!(registry.register_tool("echo", get_version).is_some());
+        assert_eq!(registry.names(), ["echo"]);
+    }
+
+    #[test]
+    fn the_default_registry_has_no_duplicates() {
+        let registry = create_default_registry().unwrap();
+        assert!(registry.handler("getFileContents").is_some());
+    }
+}
@@ -0,0 +1,28 @@
+//! Which build of the server is running, as reported in `serverInfo` and by
+//! `getVersion`.
+
+use serde::Serialize;
+
+/// Crate version from Cargo.toml
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Abbreviated commit the server was built from, or `unknown` outside a git checkout
+pub const GIT_SHA: &str = env!("CLAUDE_CODE_SERVER_GIT_SHA");
+/// RFC 3339 UTC time of the build
+pub const BUILD_DATE: &str = env!("CLAUDE_CODE_SERVER_BUILD_DATE");
+
+/// Name and build of this server
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub build_date: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    name: "claude-code-server",
+    version: VERSION,
+    git_sha: GIT_SHA,
+    build_date: BUILD_DATE,
+};
@@ -1,24 +1,530 @@
 use anyhow::{anyhow, Result};
 use dirs::home_dir;
+use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::env;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::process;
-use tokio::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{mpsc, watch, Mutex};
 use tokio_tungstenite::{
     accept_hdr_async,
-    tungstenite::handshake::server::{Request, Response},
+    tungstenite::handshake::server::{ErrorResponse, Request, Response},
+    tungstenite::http::StatusCode,
+    tungstenite::protocol::frame::coding::CloseCode,
+    tungstenite::protocol::CloseFrame,
     tungstenite::Message,
     WebSocketStream,
 };
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::lsp::NotificationReceiver;
-use crate::mcp::{MCPRequest, MCPResponse, MCPServer};
+use crate::auth::{
+    Authenticator, ReconnectTokens, RotatingTokenAuthenticator, StaticTokenAuthenticator,
+    AUTH_HEADER, RECONNECT_HEADER,
+};
+use crate::config::ServerConfig;
+use crate::editor::EditorBridge;
+use crate::http::{self, HttpSessions};
+use crate::listener::{ClientStream, Listener, PeerAddr};
+use crate::mcp::{self, MCPRequest, MCPResponse, MCPServer, ResumableSession};
+use crate::notifications::{JsonRpcNotification, NotificationReceiver};
+use crate::shared::SharedState;
+use crate::startup::StartupInfo;
+use crate::tool_cache;
+use crate::tools::{self, PathStyle, SecurityPolicy, ToolContext, ToolError, TOOL_ERROR_INTERNAL};
+
+/// Default interval between keepalive pings sent to each client
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+/// By default, connections with no inbound traffic for this long are closed
+const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// By default, connections that haven't sent `initialize` after this long are closed
+const DEFAULT_INITIALIZE_TIMEOUT: Duration = Duration::from_secs(10);
+/// JSON-RPC error code for requests sent before `initialize`
+const ERROR_NOT_INITIALIZED: i32 = -32002;
+
+/// IDE notifications buffered for connections that fall behind
+const IDE_NOTIFICATION_CAPACITY: usize = 100;
+
+/// How often workspace folders are checked for removal
+const WORKSPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Ports tried, counting up from the requested one, before giving up
+const PORT_ATTEMPTS: u16 = 10;
+
+/// How long a rotated-out auth token keeps being accepted
+const TOKEN_ROTATION_GRACE: Duration = Duration::from_secs(60);
+/// By default, a `reconnectToken` resumes its session for this long after `initialize`
+const DEFAULT_RECONNECT_WINDOW: Duration = Duration::from_secs(300);
+
+type WsSender = SplitSink<WebSocketStream<ClientStream>, Message>;
+
+/// Message and byte counts for one direction of a connection
+#[derive(Debug, Default)]
+struct TrafficCounter {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl TrafficCounter {
+    fn record(&self, bytes: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "messages": self.messages.load(Ordering::Relaxed),
+            "bytes": self.bytes.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Write half of a client connection, counting the messages sent through it
+struct ClientSender {
+    sink: Mutex<WsSender>,
+    sent: TrafficCounter,
+}
+
+impl ClientSender {
+    fn new(sink: WsSender) -> Self {
+        Self {
+            sink: Mutex::new(sink),
+            sent: TrafficCounter::default(),
+        }
+    }
+
+    async fn send(&self, message: Message) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+        let bytes = message.len();
+        self.sink.lock().await.send(message).await?;
+        self.sent.record(bytes);
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+        self.sink.lock().await.close().await
+    }
+}
+
+/// How MCP clients reach the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    WebSocket,
+    /// `POST /mcp` for requests and a server-sent event stream on `GET /mcp`
+    Http,
+}
+
+impl Transport {
+    /// Name written to the lock file's `transport` field
+    fn lock_file_name(self) -> &'static str {
+        match self {
+            Transport::WebSocket => "ws",
+            Transport::Http => "http",
+        }
+    }
+}
+
+/// Runtime options for the WebSocket server
+#[derive(Debug, Clone)]
+pub struct WebSocketOptions {
+    /// Log each handshake request's headers at debug level, with credentials redacted
+    pub log_handshakes: bool,
+    /// Restrictions applied to tool calls
+    pub security: SecurityPolicy,
+    /// Configuration file layered over `security`, re-read by `reloadConfig`
+    pub config: Option<PathBuf>,
+    /// Files larger than this many bytes are refused by read tools
+    pub max_file_size: u64,
+    /// How long identical calls to idempotent read tools reuse a result; zero disables it
+    pub tool_cache_ttl: Duration,
+    /// How tools write paths of workspace files in responses
+    pub path_style: PathStyle,
+    /// Shut down after this long without any connected client
+    pub idle_timeout: Option<Duration>,
+    /// Custom authentication backend; defaults to checking the lock file token
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    /// Replace the lock file token this often; ignored with a custom authenticator
+    pub token_rotation: Option<Duration>,
+    /// Interval between keepalive pings sent to each client
+    pub ping_interval: Duration,
+    /// Close connections with no inbound traffic for this long; must exceed `ping_interval`
+    pub ping_timeout: Duration,
+    /// Close connections that haven't completed `initialize` within this long
+    pub initialize_timeout: Duration,
+    /// Server mode named in the startup report
+    pub mode: &'static str,
+    /// Also write the startup report to stdout as a line of JSON
+    pub print_startup_info: bool,
+    /// Protocol spoken on the listening port
+    pub transport: Transport,
+    /// Listen on a Unix domain socket at this path instead of a TCP port
+    pub socket: Option<PathBuf>,
+    /// Address the TCP port is bound on
+    pub bind_address: IpAddr,
+    /// Browser origins allowed to connect when `bind_address` is not loopback
+    pub allowed_origins: Vec<String>,
+    /// Indent outgoing JSON-RPC messages, for reading them by eye while debugging
+    pub pretty_responses: bool,
+    /// How long a `reconnectToken` lets a client resume its session without the auth token
+    pub reconnect_window: Duration,
+}
+
+impl Default for WebSocketOptions {
+    fn default() -> Self {
+        Self {
+            log_handshakes: true,
+            security: SecurityPolicy::default(),
+            config: None,
+            max_file_size: tools::DEFAULT_MAX_FILE_SIZE,
+            tool_cache_ttl: tool_cache::DEFAULT_TOOL_CACHE_TTL,
+            path_style: PathStyle::default(),
+            idle_timeout: None,
+            authenticator: None,
+            token_rotation: None,
+            ping_interval: DEFAULT_PING_INTERVAL,
+            ping_timeout: DEFAULT_PING_TIMEOUT,
+            initialize_timeout: DEFAULT_INITIALIZE_TIMEOUT,
+            mode: "websocket",
+            print_startup_info: false,
+            transport: Transport::WebSocket,
+            socket: None,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            allowed_origins: Vec::new(),
+            pretty_responses: false,
+            reconnect_window: DEFAULT_RECONNECT_WINDOW,
+        }
+    }
+}
+
+/// Bookkeeping for a live WebSocket connection
+pub struct ConnectionInfo {
+    pub connected_at: Instant,
+    pub last_activity: Instant,
+    /// Round-trip time of the most recent keepalive ping that was answered
+    pub rtt: Option<Duration>,
+    /// Payload and send time of the keepalive ping awaiting its pong
+    pending_ping: Option<(Vec<u8>, Instant)>,
+    /// Messages received from the client
+    received: TrafficCounter,
+    /// Set once the client's `initialize` succeeds; other requests are refused until then
+    initialized: bool,
+    /// Set while a message from the client is being handled, when its pongs go unread
+    busy: bool,
+    sender: Arc<ClientSender>,
+}
+
+/// State shared by every connection of a WebSocket server.
+///
+/// Lock ordering: the `connections` map is always acquired before any
+/// per-connection `sender` lock, never the other way round. The map uses a
+/// synchronous `RwLock` whose guard cannot be held across an `.await`; code that
+/// needs to write to a client clones its `sender` handle out of the map, drops
+/// the map guard, and only then awaits the sender lock. `idle_since` is only
+/// ever taken while a map guard is held, so the two stay consistent.
+pub struct ServerState {
+    connections: RwLock<HashMap<PeerAddr, ConnectionInfo>>,
+    /// When the last client disconnected, or `None` while any client is connected
+    idle_since: std::sync::Mutex<Option<Instant>>,
+    /// Source of unique keepalive ping payloads
+    next_ping_id: AtomicU64,
+    /// Becomes true once the server stops accepting clients, ending its background tasks
+    shutdown: watch::Sender<bool>,
+    pub(crate) tool_context: ToolContext,
+    pub(crate) authenticator: Arc<dyn Authenticator>,
+    /// Sessions WebSocket clients can resume after reconnecting
+    reconnect_tokens: ReconnectTokens,
+    /// Sessions of clients using the HTTP transport
+    pub(crate) http_sessions: HttpSessions,
+    options: WebSocketOptions,
+}
+
+impl ServerState {
+    fn new(
+        tool_context: ToolContext,
+        authenticator: Arc<dyn Authenticator>,
+        options: WebSocketOptions,
+    ) -> Self {
+        Self {
+            connections: RwLock::default(),
+            idle_since: std::sync::Mutex::new(Some(Instant::now())),
+            next_ping_id: AtomicU64::new(0),
+            shutdown: watch::Sender::new(false),
+            tool_context,
+            authenticator,
+            reconnect_tokens: ReconnectTokens::new(options.reconnect_window),
+            http_sessions: HttpSessions::default(),
+            options,
+        }
+    }
+
+    /// Whether a client sending `origin` as its `Origin` header may connect.
+    ///
+    /// Only a port reachable from other hosts is checked, and only browsers send an
+    /// origin; clients without one still have to present the auth token.
+    pub(crate) fn allows_origin(&self, origin: Option<&str>) -> bool {
+        if self.options.bind_address.is_loopback() {
+            return true;
+        }
+        origin.is_none_or(|origin| {
+            self.options
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed == origin)
+        })
+    }
+
+    /// Serialize an outgoing message, indented if `--pretty-responses` was given
+    pub(crate) fn encode<T: Serialize>(&self, message: &T) -> serde_json::Result<String> {
+        if self.options.pretty_responses {
+            serde_json::to_string_pretty(message)
+        } else {
+            serde_json::to_string(message)
+        }
+    }
+
+    fn add_connection(&self, peer_addr: PeerAddr, sender: Arc<ClientSender>) {
+        let now = Instant::now();
+        let mut connections = self.connections.write().unwrap();
+        *self.idle_since.lock().unwrap() = None;
+        connections.insert(
+            peer_addr,
+            ConnectionInfo {
+                connected_at: now,
+                last_activity: now,
+                rtt: None,
+                pending_ping: None,
+                received: TrafficCounter::default(),
+                initialized: false,
+                busy: false,
+                sender,
+            },
+        );
+    }
+
+    fn remove_connection(&self, peer_addr: &PeerAddr) -> Option<ConnectionInfo> {
+        let mut connections = self.connections.write().unwrap();
+        let removed = connections.remove(peer_addr);
+        if connections.is_empty() {
+            let mut idle_since = self.idle_since.lock().unwrap();
+            idle_since.get_or_insert_with(Instant::now);
+        }
+        removed
+    }
+
+    fn mark_initialized(&self, peer_addr: &PeerAddr) {
+        if let Some(connection) = self.connections.write().unwrap().get_mut(peer_addr) {
+            connection.initialized = true;
+        }
+    }
+
+    fn is_initialized(&self, peer_addr: &PeerAddr) -> bool {
+        self.connections
+            .read()
+            .unwrap()
+            .get(peer_addr)
+            .is_some_and(|connection| connection.initialized)
+    }
+
+    /// How long the server has had no connected clients
+    fn idle_duration(&self) -> Option<Duration> {
+        self.idle_since.lock().unwrap().map(|since| since.elapsed())
+    }
+
+    /// Restart the idle clock for a request from an HTTP client, which holds no connection
+    pub(crate) fn record_http_activity(&self) {
+        let connections = self.connections.read().unwrap();
+        if connections.is_empty() {
+            *self.idle_since.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    /// Note traffic from a client, counting `message` if it was read successfully
+    fn record_activity(&self, peer_addr: &PeerAddr, message: Option<&Message>) {
+        if let Some(connection) = self.connections.write().unwrap().get_mut(peer_addr) {
+            connection.last_activity = Instant::now();
+            if let Some(message) = message {
+                connection.received.record(message.len());
+            }
+        }
+    }
+
+    /// Mark whether a message from the client is being handled, counting either change
+    /// as activity so that time spent on a long request never times the client out
+    fn set_busy(&self, peer_addr: &PeerAddr, busy: bool) {
+        if let Some(connection) = self.connections.write().unwrap().get_mut(peer_addr) {
+            connection.busy = busy;
+            connection.last_activity = Instant::now();
+        }
+    }
+
+    /// Remember the payload of a keepalive ping about to be sent, returning it
+    fn start_ping(&self, peer_addr: &PeerAddr) -> Vec<u8> {
+        let payload = self
+            .next_ping_id
+            .fetch_add(1, Ordering::Relaxed)
+            .to_be_bytes()
+            .to_vec();
+        if let Some(connection) = self.connections.write().unwrap().get_mut(peer_addr) {
+            connection.pending_ping = Some((payload.clone(), Instant::now()));
+        }
+        payload
+    }
+
+    /// Measure the round-trip time if `payload` answers the outstanding keepalive ping
+    fn record_pong(&self, peer_addr: &PeerAddr, payload: &[u8]) {
+        let mut connections = self.connections.write().unwrap();
+        let Some(connection) = connections.get_mut(peer_addr) else {
+            return;
+        };
+        match &connection.pending_ping {
+            Some((expected, sent_at)) if expected.as_slice() == payload => {
+                let rtt = sent_at.elapsed();
+                debug!("Round-trip time to {}: {:?}", peer_addr, rtt);
+                connection.rtt = Some(rtt);
+                connection.pending_ping = None;
+            }
+            _ => debug!("Ignoring unsolicited pong from {}", peer_addr),
+        }
+    }
+
+    /// Summary of the live connections, marking the one at `current`
+    pub(crate) fn status(&self, current: &PeerAddr) -> Value {
+        let connections: Vec<Value> = self
+            .connections
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(addr, connection)| {
+                json!({
+                    "peer": addr.to_string(),
+                    "current": addr == current,
+                    "connectedForMs": connection.connected_at.elapsed().as_millis() as u64,
+                    "idleForMs": connection.last_activity.elapsed().as_millis() as u64,
+                    "rttMs": connection.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                    "sent": connection.sender.sent.to_json(),
+                    "received": connection.received.to_json()
+                })
+            })
+            .collect();
+
+        json!({ "connections": connections })
+    }
+
+    /// Send `notification` to every connected client
+    async fn broadcast(&self, notification: &JsonRpcNotification) {
+        self.http_sessions.broadcast(notification);
+
+        let notification_json = match self.encode(notification) {
+            Ok(json) => json,
+            Err(e) => {
+                error!(
+                    "Failed to serialize {} notification: {}",
+                    notification.method, e
+                );
+                return;
+            }
+        };
+        let senders: Vec<_> = self
+            .connections
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(addr, connection)| (*addr, connection.sender.clone()))
+            .collect();
+
+        for (peer_addr, sender) in senders {
+            let message = Message::Text(notification_json.clone());
+            if let Err(e) = sender.send(message).await {
+                debug!("Failed to notify {}: {}", peer_addr, e);
+            }
+        }
+    }
+
+    /// Re-read the `--config` file, replacing the tools and security policy of every
+    /// connection and telling clients when the set of tools changed
+    pub(crate) fn reload_config(self: &Arc<Self>) -> Result<Value, ToolError> {
+        let path = self.options.config.as_ref().ok_or_else(|| {
+            ToolError::new(
+                tools::TOOL_ERROR_INVALID_PARAMS,
+                "Server was started without --config",
+            )
+        })?;
+        let (security, registry, format_on_edit) = ServerConfig::load(path)
+            .and_then(|config| {
+                Ok((
+                    config.security(&self.options.security),
+                    config.registry()?,
+                    config.format_on_edit,
+                ))
+            })
+            .map_err(|e| {
+                ToolError::new(
+                    TOOL_ERROR_INTERNAL,
+                    format!("{}; keeping the current configuration", e),
+                )
+            })?;
+
+        let tools_changed = self.tool_context.replace_policy(security, registry);
+        self.tool_context.set_format_on_edit(format_on_edit);
+        info!("Reloaded config from {}", path.display());
+        if tools_changed {
+            let state = self.clone();
+            tokio::spawn(async move {
+                state
+                    .broadcast(&JsonRpcNotification {
+                        jsonrpc: "2.0".to_string(),
+                        method: "notifications/tools/list_changed".to_string(),
+                        params: json!({}),
+                    })
+                    .await;
+            });
+        }
+
+        Ok(json!({
+            "success": true,
+            "path": path,
+            "toolsChanged": tools_changed,
+            "tools": self.tool_context.registry().names(),
+        }))
+    }
+
+    /// Tell background tasks the server has stopped
+    fn shut_down(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Resolves once `shut_down` has been called
+    async fn stopped(&self) {
+        let mut shutdown = self.shutdown.subscribe();
+        // The sender lives as long as `self`, so waiting cannot fail
+        shutdown.wait_for(|stopped| *stopped).await.ok();
+    }
+
+    /// Copy out what the keepalive task needs so no lock is held while pinging.
+    ///
+    /// Busy connections are skipped, as their pongs are not read until the message
+    /// being handled is done.
+    fn keepalive_snapshot(&self) -> Vec<(PeerAddr, Instant, Arc<ClientSender>)> {
+        self.connections
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, connection)| !connection.busy)
+            .map(|(addr, connection)| (*addr, connection.last_activity, connection.sender.clone()))
+            .collect()
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LockFile {
@@ -30,99 +536,619 @@ pub struct LockFile {
     pub transport: String,
     #[serde(rename = "authToken")]
     pub auth_token: String,
+    /// Set instead of a port in the lock file name when serving a Unix socket
+    #[serde(rename = "socketPath", skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<PathBuf>,
+}
+
+/// Where clients reach the server, which also names its lock file
+#[derive(Debug, Clone)]
+enum Endpoint {
+    Port(u16),
+    Socket(PathBuf),
+}
+
+impl Endpoint {
+    fn lock_file_name(&self) -> String {
+        match self {
+            Endpoint::Port(port) => format!("{}.lock", port),
+            // A socket has no port, and the pid is unique while the server runs
+            Endpoint::Socket(_) => format!("unix-{}.lock", process::id()),
+        }
+    }
+
+    /// Name written to the lock file's `transport` field
+    fn transport_name(&self, transport: Transport) -> &'static str {
+        match self {
+            Endpoint::Port(_) => transport.lock_file_name(),
+            Endpoint::Socket(_) => "unix",
+        }
+    }
+}
+
+/// Owns the files a running server leaves in place: its lock files and any socket.
+///
+/// Dropping the guard removes them, so returning early, unwinding from a panic or
+/// having the task dropped at runtime shutdown doesn't leave a stale lock behind.
+struct LockGuard {
+    /// Taken once the files have been removed
+    endpoint: Option<Endpoint>,
+}
+
+impl LockGuard {
+    fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint: Some(endpoint),
+        }
+    }
+
+    /// Remove the files now, returning any failure instead of only logging it
+    fn release(mut self) -> Result<()> {
+        match self.endpoint.take() {
+            Some(endpoint) => cleanup_endpoint(&endpoint),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if let Some(endpoint) = self.endpoint.take() {
+            if let Err(e) = cleanup_endpoint(&endpoint) {
+                error!("Failed to remove lock file: {}", e);
+            }
+        }
+    }
+}
+
+/// Subdirectory of the lock directory holding locks named by workspace hash
+const WORKSPACE_LOCK_DIR: &str = "by-workspace";
+/// Hex digits of the workspace path hash used in workspace lock names
+const WORKSPACE_HASH_LEN: usize = 16;
+
+/// Stable name for a workspace, so its lock can be found without reading every lock file
+fn workspace_hash(folder: &Path) -> String {
+    let folder = folder
+        .canonicalize()
+        .unwrap_or_else(|_| folder.to_path_buf());
+    let mut hash = tools::content_hash(folder.to_string_lossy().as_bytes());
+    hash.truncate(WORKSPACE_HASH_LEN);
+    hash
 }
 
-pub async fn run_websocket_server(port: Option<u16>) -> Result<()> {
-    run_websocket_server_with_worktree(port, None).await
+pub async fn run_websocket_server(port: Option<u16>, options: WebSocketOptions) -> Result<()> {
+    run_websocket_server_with_worktree(port, None, options).await
 }
 
 pub async fn run_websocket_server_with_worktree(
     port: Option<u16>,
     worktree: Option<PathBuf>,
+    options: WebSocketOptions,
 ) -> Result<()> {
-    run_websocket_server_with_notifications(port, worktree, None).await
+    run_websocket_server_with_notifications(
+        port,
+        worktree,
+        None,
+        EditorBridge::new(),
+        SharedState::new(),
+        options,
+    )
+    .await
 }
 
 pub async fn run_websocket_server_with_notifications(
     port: Option<u16>,
     worktree: Option<PathBuf>,
-    mut notification_receiver: Option<NotificationReceiver>,
+    notification_receiver: Option<NotificationReceiver>,
+    editor: EditorBridge,
+    shared: SharedState,
+    options: WebSocketOptions,
 ) -> Result<()> {
     info!("Starting WebSocket server...");
 
-    // Use fixed port or provided port, default to 59792
-    let port = port.unwrap_or(59792);
+    // A timeout no longer than the interval would close clients before their pong arrives
+    if options.ping_timeout <= options.ping_interval {
+        return Err(anyhow!(
+            "Ping timeout ({:?}) must be longer than the ping interval ({:?})",
+            options.ping_timeout,
+            options.ping_interval
+        ));
+    }
 
-    // Clean up any existing lock files for this port
-    cleanup_existing_lock_file(port).await?;
+    let tool_context = ToolContext::new(worktree, options.security.clone(), editor)?
+        .with_shared_state(shared)
+        .with_max_file_size(options.max_file_size)
+        .with_tool_cache_ttl(options.tool_cache_ttl)
+        .with_path_style(options.path_style);
+    if let Some(path) = &options.config {
+        let config = ServerConfig::load(path)?;
+        tool_context.replace_policy(config.security(&options.security), config.registry()?);
+        tool_context.set_format_on_edit(config.format_on_edit);
+        info!("Loaded config from {}", path.display());
+    }
 
-    // Create new lock file
     let auth_token = Uuid::new_v4().to_string();
-    create_lock_file(port, worktree.clone(), &auth_token).await?;
-
-    // Start WebSocket server with proper error handling
-    let addr = format!("127.0.0.1:{}", port);
-
-    // Try to bind to the port, with retry logic
-    let listener = match TcpListener::bind(&addr).await {
-        Ok(listener) => {
-            info!("WebSocket server listening on {}", addr);
-            listener
+    let (mut listener, endpoint, lock_file_path) = match options.socket.clone() {
+        Some(path) => {
+            if options.transport == Transport::Http {
+                return Err(anyhow!("--socket serves WebSocket clients only"));
+            }
+            let listener = Listener::bind_unix(&path)?;
+            let endpoint = Endpoint::Socket(path);
+            let lock_file_path = claim_lock_file(
+                &endpoint,
+                &tool_context.workspace_folders,
+                &auth_token,
+                options.transport,
+            )
+            .await?;
+            (listener, endpoint, lock_file_path)
         }
-        Err(e) => {
-            error!("Failed to bind to port {}: {}", port, e);
-            info!("Attempting to force cleanup and retry...");
-
-            // Try to cleanup and retry once
-            cleanup_existing_lock_file(port).await?;
+        None => {
+            // Use fixed port or provided port, default to 59792
+            let (listener, port, lock_file_path) = claim_port(
+                options.bind_address,
+                port.unwrap_or(59792),
+                &tool_context.workspace_folders,
+                &auth_token,
+                options.transport,
+            )
+            .await?;
+            (
+                Listener::Tcp(listener),
+                Endpoint::Port(port),
+                lock_file_path,
+            )
+        }
+    };
 
-            // Wait a moment for the port to be released
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let lock_guard = LockGuard::new(endpoint.clone());
 
-            match TcpListener::bind(&addr).await {
-                Ok(listener) => {
-                    info!("Successfully bound to port {} after cleanup", port);
-                    listener
-                }
-                Err(e2) => {
-                    error!("Failed to bind to port {} even after cleanup: {}", port, e2);
-                    return Err(anyhow!("Port {} is unavailable: {}", port, e2));
-                }
-            }
+    let startup = StartupInfo::new(
+        options.mode,
+        tool_context.workspace_folders.first().map(PathBuf::as_path),
+    );
+    let startup = match &endpoint {
+        Endpoint::Port(port) => {
+            info!(
+                "WebSocket server listening on {}",
+                std::net::SocketAddr::new(options.bind_address, *port)
+            );
+            startup.with_listener(*port, lock_file_path)
         }
-    };
+        Endpoint::Socket(path) => {
+            info!("WebSocket server listening on {}", path.display());
+            startup.with_socket(path.clone(), lock_file_path)
+        }
+    }
+    .with_auth_token();
+    startup.log();
+    if options.print_startup_info {
+        println!("{}", startup.to_json());
+    }
 
-    // Setup graceful shutdown handler
-    let port_for_cleanup = port;
+    // Setup graceful shutdown handler; exiting skips destructors, so it can't rely on the guard
+    let endpoint_for_cleanup = endpoint.clone();
     tokio::spawn(async move {
         tokio::signal::ctrl_c().await.ok();
         info!("Shutdown signal received, cleaning up...");
-        if let Err(e) = cleanup_existing_lock_file(port_for_cleanup).await {
+        if let Err(e) = cleanup_endpoint(&endpoint_for_cleanup) {
             error!("Error during cleanup: {}", e);
         }
         std::process::exit(0);
     });
 
-    while let Ok((stream, peer_addr)) = listener.accept().await {
+    let rotation = match (options.token_rotation, &options.authenticator) {
+        (Some(_), Some(_)) => {
+            warn!("Token rotation is disabled because a custom authenticator is configured");
+            None
+        }
+        (Some(every), None) => Some((
+            every,
+            Arc::new(RotatingTokenAuthenticator::new(
+                auth_token.clone(),
+                TOKEN_ROTATION_GRACE,
+            )),
+        )),
+        (None, _) => None,
+    };
+    let authenticator: Arc<dyn Authenticator> = match (&options.authenticator, &rotation) {
+        (Some(custom), _) => custom.clone(),
+        (None, Some((_, rotating))) => rotating.clone(),
+        (None, None) => Arc::new(StaticTokenAuthenticator::new(auth_token)),
+    };
+    let state = Arc::new(ServerState::new(tool_context, authenticator, options));
+    let mut background = vec![
+        tokio::spawn(ping_keepalive_task(state.clone())),
+        tokio::spawn(workspace_watch_task(state.clone())),
+    ];
+    if let Some((every, rotating)) = rotation {
+        background.push(tokio::spawn(token_rotation_task(
+            state.clone(),
+            rotating,
+            endpoint.clone(),
+            every,
+        )));
+    }
+
+    // Connections hear the editor's notifications from the relay, once it has recorded them
+    let mut notification_receiver = notification_receiver.map(|receiver| {
+        let (relay, relayed) = broadcast::channel(IDE_NOTIFICATION_CAPACITY);
+        background.push(tokio::spawn(relay_ide_notifications(
+            receiver,
+            state.clone(),
+            relay,
+        )));
+        relayed
+    });
+
+    let idle_shutdown = idle_shutdown_task(state.clone());
+    tokio::pin!(idle_shutdown);
+
+    let result = loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => break Ok(()),
+            },
+            () = &mut idle_shutdown => {
+                info!("No clients connected for the idle timeout, shutting down");
+                break Ok(());
+            }
+        };
+
         info!("New connection from {}", peer_addr);
-        let auth_token_clone = auth_token.clone();
-        let notification_receiver_clone = if let Some(ref mut receiver) = notification_receiver {
-            Some(receiver.resubscribe())
+        let notification_receiver_clone = notification_receiver
+            .as_mut()
+            .map(|receiver| receiver.resubscribe());
+        match state.options.transport {
+            Transport::WebSocket => tokio::spawn(handle_connection(
+                stream,
+                peer_addr,
+                notification_receiver_clone,
+                state.clone(),
+            )),
+            Transport::Http => tokio::spawn(http::handle_http_connection(
+                stream,
+                peer_addr,
+                notification_receiver_clone,
+                state.clone(),
+            )),
+        };
+    };
+
+    // Stop the background tasks before returning so none outlives the server
+    state.shut_down();
+    for task in background {
+        task.await.ok();
+    }
+    result.and(lock_guard.release())
+}
+
+/// Pass the editor's notifications on to `relay`, first updating what tools know from
+/// them, so a client told about a selection can already ask tools about it
+async fn relay_ide_notifications(
+    mut receiver: NotificationReceiver,
+    state: Arc<ServerState>,
+    relay: broadcast::Sender<JsonRpcNotification>,
+) {
+    let tool_context = &state.tool_context;
+    loop {
+        let received = tokio::select! {
+            received = receiver.recv() => received,
+            () = state.stopped() => {
+                debug!("IDE notification relay stopped");
+                return;
+            }
+        };
+        let notification = match received {
+            Ok(notification) => notification,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Missed {} IDE notifications", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        // The LSP server stores selections itself before announcing them
+        match notification.method.as_str() {
+            // The file watcher saw a change, possibly without a new modification time
+            "file_changed" => {
+                if let Some(path) = notification.params["filePath"].as_str() {
+                    tool_context
+                        .file_cache
+                        .invalidate(&tool_context.resolve_path(path));
+                }
+                tool_context.tool_cache.clear();
+            }
+            // The file watcher dropped changes, so any cached file may be stale
+            "watch_overflow" => {
+                tool_context.file_cache.clear();
+                tool_context.tool_cache.clear();
+            }
+            _ => {}
+        }
+        // Sending only fails while no client is connected
+        relay.send(notification).ok();
+    }
+}
+
+/// Resolve once the server has had no clients for the configured idle timeout
+async fn idle_shutdown_task(state: Arc<ServerState>) {
+    let Some(idle_timeout) = state.options.idle_timeout else {
+        return std::future::pending().await;
+    };
+
+    loop {
+        let wait = match state.idle_duration() {
+            Some(idle) if idle >= idle_timeout => return,
+            Some(idle) => idle_timeout - idle,
+            None => idle_timeout,
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Replace the auth token every `every`, rewriting the lock file and telling live clients
+async fn token_rotation_task(
+    state: Arc<ServerState>,
+    authenticator: Arc<RotatingTokenAuthenticator>,
+    endpoint: Endpoint,
+    every: Duration,
+) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = state.stopped() => {
+                debug!("Token rotation task stopped");
+                return;
+            }
+        }
+
+        // The old token stays valid while clients catch up with the new lock file
+        let token = Uuid::new_v4().to_string();
+        authenticator.rotate(token.clone());
+        let written = create_lock_file(
+            &endpoint,
+            &state.tool_context.workspace_folders,
+            &token,
+            state.options.transport,
+            LockMode::Replace,
+        )
+        .await;
+        if let Err(e) = written {
+            error!("Failed to write rotated auth token to the lock file: {}", e);
+            continue;
+        }
+        info!("Rotated auth token");
+
+        state
+            .broadcast(&JsonRpcNotification {
+                jsonrpc: "2.0".to_string(),
+                method: "auth_token_rotated".to_string(),
+                params: json!({ "graceSecs": TOKEN_ROTATION_GRACE.as_secs() }),
+            })
+            .await;
+    }
+}
+
+/// Notify clients once when a workspace folder disappears from disk
+async fn workspace_watch_task(state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(WORKSPACE_CHECK_INTERVAL);
+    let mut reported: HashSet<PathBuf> = HashSet::new();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = state.stopped() => {
+                debug!("Workspace watch task stopped");
+                return;
+            }
+        }
+
+        let missing = state.tool_context.missing_workspace_folders();
+        reported.retain(|folder| missing.contains(&folder));
+        for folder in missing {
+            if !reported.insert(folder.clone()) {
+                continue;
+            }
+            warn!("Workspace folder {} was removed", folder.display());
+            state
+                .broadcast(&JsonRpcNotification {
+                    jsonrpc: "2.0".to_string(),
+                    method: "workspace_removed".to_string(),
+                    params: json!({ "path": folder }),
+                })
+                .await;
+        }
+    }
+}
+
+/// Ping every client periodically and close connections that have gone silent
+async fn ping_keepalive_task(state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(state.options.ping_interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = state.stopped() => {
+                debug!("Keepalive task stopped");
+                return;
+            }
+        }
+
+        for (peer_addr, last_activity, sender) in state.keepalive_snapshot() {
+            if last_activity.elapsed() > state.options.ping_timeout {
+                warn!("Connection {} timed out, closing", peer_addr);
+                state.remove_connection(&peer_addr);
+                if let Err(e) = sender.close().await {
+                    debug!("Failed to close timed out connection {}: {}", peer_addr, e);
+                }
+                continue;
+            }
+
+            let payload = state.start_ping(&peer_addr);
+            if let Err(e) = sender.send(Message::Ping(payload)).await {
+                debug!("Failed to ping {}: {}", peer_addr, e);
+            }
+        }
+    }
+}
+
+/// Bind a port and claim its lock file, moving on to the next port when either is taken.
+///
+/// Another server may hold a port's lock without listening on the same address, so a
+/// successful bind alone does not make the port ours.
+async fn claim_port(
+    bind_address: IpAddr,
+    port: u16,
+    workspace_folders: &[PathBuf],
+    auth_token: &str,
+    transport: Transport,
+) -> Result<(TcpListener, u16, PathBuf)> {
+    for attempt in 0..PORT_ATTEMPTS {
+        // Port 0 asks the OS for a free port, so every attempt asks again
+        let candidate = if port == 0 {
+            0
         } else {
-            None
+            match port.checked_add(attempt) {
+                Some(candidate) => candidate,
+                None => break,
+            }
         };
-        tokio::spawn(handle_connection(
-            stream,
-            peer_addr,
-            auth_token_clone,
-            notification_receiver_clone,
-        ));
+
+        let listener = match TcpListener::bind((bind_address, candidate)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Port {} is unavailable: {}", candidate, e);
+                continue;
+            }
+        };
+        let bound = listener.local_addr()?.port();
+
+        match claim_lock_file(&Endpoint::Port(bound), workspace_folders, auth_token, transport)
+            .await
+        {
+            Ok(lock_file_path) => return Ok((listener, bound, lock_file_path)),
+            Err(e) if is_already_exists(&e) => {
+                warn!(
+                    "Port {} is claimed by another lock file, trying the next",
+                    bound
+                );
+            }
+            Err(e) => return Err(e),
+        }
     }
 
+    Err(anyhow!(
+        "No free port found in {} attempts starting at {}",
+        PORT_ATTEMPTS,
+        port
+    ))
+}
+
+/// Create the endpoint's lock file, taking it over if the server that wrote it has exited
+async fn claim_lock_file(
+    endpoint: &Endpoint,
+    workspace_folders: &[PathBuf],
+    auth_token: &str,
+    transport: Transport,
+) -> Result<PathBuf> {
+    match create_lock_file(
+        endpoint,
+        workspace_folders,
+        auth_token,
+        transport,
+        LockMode::CreateNew,
+    )
+    .await
+    {
+        Err(e) if is_already_exists(&e) && remove_stale_lock_file(endpoint)? => {
+            create_lock_file(
+                endpoint,
+                workspace_folders,
+                auth_token,
+                transport,
+                LockMode::CreateNew,
+            )
+            .await
+        }
+        result => result,
+    }
+}
+
+/// Remove the endpoint's lock file if its owner is no longer running, returning whether it was.
+///
+/// A lock without a readable pid may still be being written, so it counts as live.
+fn remove_stale_lock_file(endpoint: &Endpoint) -> Result<bool> {
+    let home = home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    let lock_file_path = home
+        .join(".claude")
+        .join("ide")
+        .join(endpoint.lock_file_name());
+
+    let owner = fs::read(&lock_file_path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<Value>(&data).ok())
+        .and_then(|lock| lock.get("pid")?.as_u64())
+        .and_then(|pid| u32::try_from(pid).ok());
+    match owner {
+        Some(pid) if !process_alive(pid) => {
+            warn!(
+                "Removing stale lock file {} left by exited process {}",
+                lock_file_path.display(),
+                pid
+            );
+            cleanup_existing_lock_file(endpoint)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Whether a process with this pid is running
+fn process_alive(pid: u32) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        Path::new("/proc").join(pid.to_string()).exists()
+    }
+    #[cfg(all(unix, not(target_os = "linux")))]
+    {
+        // Signal 0 only checks that the process exists
+        process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+    #[cfg(windows)]
+    {
+        process::Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+            .unwrap_or(true)
+    }
+}
+
+fn is_already_exists(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::AlreadyExists)
+}
+
+/// Remove the files the server leaves behind: its lock files and any socket
+fn cleanup_endpoint(endpoint: &Endpoint) -> Result<()> {
+    cleanup_existing_lock_file(endpoint)?;
+    if let Endpoint::Socket(path) = endpoint {
+        if path.exists() {
+            info!("Removing socket file: {}", path.display());
+            fs::remove_file(path)?;
+        }
+    }
     Ok(())
 }
 
-async fn cleanup_existing_lock_file(port: u16) -> Result<()> {
+fn cleanup_existing_lock_file(endpoint: &Endpoint) -> Result<()> {
     let home = home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
     let claude_dir = home.join(".claude").join("ide");
 
@@ -131,7 +1157,21 @@ async fn cleanup_existing_lock_file(port: u16) -> Result<()> {
         return Ok(());
     }
 
-    let lock_file_path = claude_dir.join(format!("{}.lock", port));
+    let lock_file_path = claude_dir.join(endpoint.lock_file_name());
+
+    // Workspace locks are links to (or copies of) a port lock, so match them before it goes
+    let lock_data = fs::read(&lock_file_path).ok();
+    if let Ok(entries) = fs::read_dir(claude_dir.join(WORKSPACE_LOCK_DIR)) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_ours = fs::read_link(&path).is_ok_and(|target| target == lock_file_path)
+                || (lock_data.is_some() && fs::read(&path).ok() == lock_data);
+            if is_ours {
+                info!("Removing workspace lock file: {}", path.display());
+                fs::remove_file(&path)?;
+            }
+        }
+    }
 
     if lock_file_path.exists() {
         info!("Removing existing lock file: {}", lock_file_path.display());
@@ -141,7 +1181,22 @@ async fn cleanup_existing_lock_file(port: u16) -> Result<()> {
     Ok(())
 }
 
-async fn create_lock_file(port: u16, worktree: Option<PathBuf>, auth_token: &str) -> Result<()> {
+/// How `create_lock_file` treats a lock file already present for the port
+#[derive(Clone, Copy)]
+enum LockMode {
+    /// Fail with `AlreadyExists`, as the port belongs to another server
+    CreateNew,
+    /// Overwrite it, as when this server rewrites its own lock
+    Replace,
+}
+
+async fn create_lock_file(
+    endpoint: &Endpoint,
+    workspace_folders: &[PathBuf],
+    auth_token: &str,
+    transport: Transport,
+    mode: LockMode,
+) -> Result<PathBuf> {
     let home = home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
     let claude_dir = home.join(".claude").join("ide");
 
@@ -151,39 +1206,96 @@ async fn create_lock_file(port: u16, worktree: Option<PathBuf>, auth_token: &str
         info!("Created directory: {}", claude_dir.display());
     }
 
-    // Get current working directory or use provided worktree
-    let workspace_folder = if let Some(wt) = worktree {
-        wt.to_string_lossy().to_string()
-    } else {
-        env::current_dir()?.to_string_lossy().to_string()
-    };
-
     let lock_file_data = LockFile {
         pid: process::id(),
-        workspace_folders: vec![workspace_folder],
+        workspace_folders: workspace_folders
+            .iter()
+            .map(|folder| folder.to_string_lossy().to_string())
+            .collect(),
         ide_name: "claude-code-server".to_string(),
-        transport: "ws".to_string(),
+        transport: endpoint.transport_name(transport).to_string(),
         auth_token: auth_token.to_string(),
+        socket_path: match endpoint {
+            Endpoint::Port(_) => None,
+            Endpoint::Socket(path) => Some(path.clone()),
+        },
     };
 
-    let lock_file_path = claude_dir.join(format!("{}.lock", port));
+    let lock_file_path = claude_dir.join(endpoint.lock_file_name());
     let json_data = serde_json::to_string_pretty(&lock_file_data)?;
 
-    fs::write(&lock_file_path, json_data)?;
+    match mode {
+        // `create_new` makes claiming the port atomic when servers start together
+        LockMode::CreateNew => fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_file_path)?
+            .write_all(json_data.as_bytes())?,
+        LockMode::Replace => fs::write(&lock_file_path, json_data)?,
+    }
     info!("Created lock file: {}", lock_file_path.display());
 
-    Ok(())
+    if let Some(primary) = workspace_folders.first() {
+        let workspace_dir = claude_dir.join(WORKSPACE_LOCK_DIR);
+        fs::create_dir_all(&workspace_dir)?;
+
+        let workspace_lock_path = workspace_dir.join(format!("{}.lock", workspace_hash(primary)));
+        // The newest server for a workspace takes over its lock
+        if fs::symlink_metadata(&workspace_lock_path).is_ok() {
+            fs::remove_file(&workspace_lock_path)?;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&lock_file_path, &workspace_lock_path)?;
+        #[cfg(not(unix))]
+        fs::copy(&lock_file_path, &workspace_lock_path)?;
+        info!(
+            "Created workspace lock file: {}",
+            workspace_lock_path.display()
+        );
+    }
+
+    Ok(lock_file_path)
 }
 
+// The handshake callback's error type is dictated by tungstenite
+#[allow(clippy::result_large_err)]
 async fn handle_connection(
-    stream: TcpStream,
-    peer_addr: SocketAddr,
-    auth_token: String,
+    stream: ClientStream,
+    peer_addr: PeerAddr,
     notification_receiver: Option<NotificationReceiver>,
+    state: Arc<ServerState>,
 ) -> Result<()> {
     info!("Handling connection from {}", peer_addr);
 
-    let ws_stream = match accept_hdr_async(stream, |req: &Request, mut response: Response| {
+    let log_handshakes = state.options.log_handshakes;
+    let mut presented_token = None;
+    let mut reconnect_token = None;
+    let mut rejected_origin = None;
+    let mut ws_stream = match accept_hdr_async(stream, |req: &Request, mut response: Response| {
+        if log_handshakes {
+            log_handshake_request(peer_addr, req);
+        }
+
+        let origin = req
+            .headers()
+            .get("Origin")
+            .map(|value| value.to_str().unwrap_or("[non-ascii]"));
+        if !state.allows_origin(origin) {
+            rejected_origin = origin.map(str::to_string);
+            let mut error = ErrorResponse::new(Some("Origin not allowed".to_string()));
+            *error.status_mut() = StatusCode::FORBIDDEN;
+            return Err(error);
+        }
+
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        presented_token = header(AUTH_HEADER);
+        reconnect_token = header(RECONNECT_HEADER);
+
         // Check if client requested MCP protocol
         if let Some(protocols) = req.headers().get("Sec-WebSocket-Protocol") {
             if let Ok(protocols_str) = protocols.to_str() {
@@ -204,34 +1316,177 @@ async fn handle_connection(
             info!("WebSocket handshake completed for {}", peer_addr);
             ws
         }
+        Err(_) if rejected_origin.is_some() => {
+            warn!(
+                "Rejecting connection from {} with disallowed origin {}",
+                peer_addr,
+                rejected_origin.unwrap_or_default()
+            );
+            return Ok(());
+        }
         Err(e) => {
             error!("WebSocket handshake failed for {}: {}", peer_addr, e);
             return Err(e.into());
         }
     };
 
-    handle_websocket_connection(ws_stream, peer_addr, auth_token, notification_receiver).await
+    // A valid reconnect token stands in for the auth token the session started with
+    let resumed = reconnect_token
+        .as_deref()
+        .and_then(|token| state.reconnect_tokens.validate(token));
+    if reconnect_token.is_some() && resumed.is_none() {
+        warn!(
+            "Ignoring invalid or expired reconnect token from {}",
+            peer_addr
+        );
+    }
+
+    // The handshake callback is synchronous, so the (possibly async) check runs afterwards
+    let authenticated = resumed.is_some()
+        || match presented_token.as_deref() {
+            Some(token) => state.authenticator.authenticate(token).await,
+            None => false,
+        };
+    if !authenticated {
+        warn!("Rejecting unauthenticated connection from {}", peer_addr);
+        let close = CloseFrame {
+            code: CloseCode::Policy,
+            reason: "Unauthorized".into(),
+        };
+        if let Err(e) = ws_stream.close(Some(close)).await {
+            debug!(
+                "Failed to close unauthenticated connection {}: {}",
+                peer_addr, e
+            );
+        }
+        return Ok(());
+    }
+
+    handle_websocket_connection(ws_stream, peer_addr, notification_receiver, state, resumed).await
+}
+
+/// Whether a header carries credentials that must not be written to logs
+fn is_sensitive_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.contains("authorization")
+        || name.contains("token")
+        || name == "sec-websocket-key"
+        || name == "cookie"
+}
+
+/// Log the request line and headers of a handshake with credentials redacted
+fn log_handshake_request(peer_addr: PeerAddr, req: &Request) {
+    debug!(
+        "Handshake request from {}: {} {} {:?}",
+        peer_addr,
+        req.method(),
+        req.uri(),
+        req.version()
+    );
+    for (name, value) in req.headers() {
+        let value = if is_sensitive_header(name.as_str()) {
+            "[redacted]"
+        } else {
+            value.to_str().unwrap_or("[non-ascii]")
+        };
+        debug!("  {}: {}", name, value);
+    }
 }
 
 async fn handle_websocket_connection(
-    ws_stream: WebSocketStream<TcpStream>,
-    peer_addr: SocketAddr,
-    _auth_token: String,
+    ws_stream: WebSocketStream<ClientStream>,
+    peer_addr: PeerAddr,
     mut notification_receiver: Option<NotificationReceiver>,
+    state: Arc<ServerState>,
+    resumed: Option<ResumableSession>,
 ) -> Result<()> {
-    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-    let mcp_handler = MCPServer::new();
+    let (ws_sender, mut ws_receiver) = ws_stream.split();
+    let ws_sender = Arc::new(ClientSender::new(ws_sender));
+    let (client_sender, mut client_notifications) = mpsc::unbounded_channel();
+    let status_state = state.clone();
+    let reload_state = state.clone();
+    let reconnect_state = state.clone();
+    let mcp_handler = MCPServer::new(state.tool_context.clone())
+        .with_notification_sink(client_sender)
+        .with_status_provider(Arc::new(move || status_state.status(&peer_addr)))
+        .with_config_reloader(Arc::new(move || reload_state.reload_config()))
+        .with_reconnect_issuer(Arc::new(move |session| {
+            reconnect_state.reconnect_tokens.issue(session)
+        }));
 
+    state.add_connection(peer_addr, ws_sender.clone());
     info!("WebSocket connection established with {}", peer_addr);
+    if let Some(session) = resumed {
+        info!(
+            "Resumed MCP session for {} (protocol {}, {} subscriptions)",
+            peer_addr,
+            session.protocol_version,
+            session.subscriptions.len()
+        );
+        mcp_handler.resume(session);
+        state.mark_initialized(&peer_addr);
+    }
+
+    let result = websocket_message_loop(
+        &mut ws_receiver,
+        &ws_sender,
+        &mcp_handler,
+        &mut notification_receiver,
+        &mut client_notifications,
+        &state,
+        peer_addr,
+    )
+    .await;
+
+    if let Some(connection) = state.remove_connection(&peer_addr) {
+        info!(
+            "Connection with {} closed after {:?}",
+            peer_addr,
+            connection.connected_at.elapsed()
+        );
+    }
+    result
+}
+
+async fn websocket_message_loop(
+    ws_receiver: &mut futures_util::stream::SplitStream<WebSocketStream<ClientStream>>,
+    ws_sender: &ClientSender,
+    mcp_handler: &MCPServer,
+    notification_receiver: &mut Option<NotificationReceiver>,
+    client_notifications: &mut mpsc::UnboundedReceiver<JsonRpcNotification>,
+    state: &ServerState,
+    peer_addr: PeerAddr,
+) -> Result<()> {
+    let initialize_deadline = tokio::time::sleep(state.options.initialize_timeout);
+    tokio::pin!(initialize_deadline);
 
     // Main message loop handling both WebSocket messages and IDE notifications
     loop {
         tokio::select! {
+            // Close clients that connect but never start an MCP session
+            () = &mut initialize_deadline, if !state.is_initialized(&peer_addr) => {
+                warn!("{} did not initialize within {:?}, closing", peer_addr, state.options.initialize_timeout);
+                let close = Message::Close(Some(CloseFrame {
+                    code: CloseCode::Policy,
+                    reason: "Initialize timeout".into(),
+                }));
+                if let Err(e) = ws_sender.send(close).await {
+                    debug!("Failed to send close frame to {}: {}", peer_addr, e);
+                }
+                break;
+            }
             // Handle incoming WebSocket messages
             msg = ws_receiver.next() => {
                 match msg {
                     Some(msg) => {
-                        if let Err(e) = handle_websocket_message(msg, &mcp_handler, &mut ws_sender, peer_addr).await {
+                        state.record_activity(&peer_addr, msg.as_ref().ok());
+                        if let Ok(Message::Pong(payload)) = &msg {
+                            state.record_pong(&peer_addr, payload);
+                        }
+                        state.set_busy(&peer_addr, true);
+                        let handled = handle_websocket_message(msg, mcp_handler, ws_sender, state, peer_addr).await;
+                        state.set_busy(&peer_addr, false);
+                        if let Err(e) = handled {
                             error!("Error handling WebSocket message: {}", e);
                             break;
                         }
@@ -244,7 +1499,7 @@ async fn handle_websocket_connection(
             },
             // Handle IDE notifications
             notification = async {
-                if let Some(ref mut receiver) = notification_receiver {
+                if let Some(receiver) = notification_receiver.as_mut() {
                     receiver.recv().await
                 } else {
                     std::future::pending().await
@@ -255,7 +1510,7 @@ async fn handle_websocket_connection(
                         debug!("Received IDE notification: {:?}", notification);
 
                         // Forward the notification to the MCP client
-                        let notification_json = serde_json::to_string(&notification)?;
+                        let notification_json = state.encode(&notification)?;
                         if let Err(e) = ws_sender.send(Message::Text(notification_json)).await {
                             error!("Failed to send IDE notification to {}: {}", peer_addr, e);
                             break;
@@ -264,27 +1519,71 @@ async fn handle_websocket_connection(
                     Err(e) => {
                         debug!("Notification channel error: {}", e);
                         // Channel closed or lagged, continue without notifications
-                        notification_receiver = None;
+                        *notification_receiver = None;
                     }
                 }
             }
+            // Handle notifications raised for this connection, such as followed files
+            Some(notification) = client_notifications.recv() => {
+                let notification_json = state.encode(&notification)?;
+                if let Err(e) = ws_sender.send(Message::Text(notification_json)).await {
+                    error!("Failed to send notification to {}: {}", peer_addr, e);
+                    break;
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+/// `text` with control characters escaped, so echoing client input can't garble the log
+fn escape_control_characters(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(char::is_control) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
 async fn handle_websocket_message(
     msg: Result<Message, tokio_tungstenite::tungstenite::Error>,
     mcp_handler: &MCPServer,
-    ws_sender: &mut futures_util::stream::SplitSink<WebSocketStream<TcpStream>, Message>,
-    peer_addr: SocketAddr,
+    ws_sender: &ClientSender,
+    state: &ServerState,
+    peer_addr: PeerAddr,
 ) -> Result<()> {
     match msg {
         Ok(msg) => {
             if msg.is_text() {
                 let text = msg.to_text().unwrap();
-                debug!("Received message from {}: {}", peer_addr, text);
+                debug!(
+                    "Received message from {}: {}",
+                    peer_addr,
+                    escape_control_characters(text)
+                );
+
+                let rejection = mcp::control_character_error(text)
+                    .or_else(|| mcp::nesting_error(text.as_bytes()));
+                if let Some(error_response) = rejection {
+                    warn!("Rejecting unparseable message from {}", peer_addr);
+                    let error_json = state.encode(&error_response)?;
+                    if let Err(e) = ws_sender.send(Message::Text(error_json)).await {
+                        error!(
+                            "Failed to send parse error response to {}: {}",
+                            peer_addr, e
+                        );
+                        return Err(e.into());
+                    }
+                    return Ok(());
+                }
 
                 // Try to parse as MCP request
                 match serde_json::from_str::<MCPRequest>(text) {
@@ -292,18 +1591,45 @@ async fn handle_websocket_message(
                         info!("Processing MCP request: {}", mcp_request.method);
 
                         // Handle notifications (requests without ID) separately
-                        if mcp_request.id.is_none()
-                            && mcp_request.method.starts_with("notifications/")
-                        {
+                        if mcp::is_notification(&mcp_request) {
                             info!("Processing notification: {}", mcp_request.method);
+                            mcp_handler.handle_notification(mcp_request);
                             // Notifications don't get responses, just return
                             return Ok(());
                         }
 
+                        let is_initialize = mcp_request.method == "initialize";
+                        if !is_initialize && !state.is_initialized(&peer_addr) {
+                            let response = MCPResponse {
+                                jsonrpc: "2.0".to_string(),
+                                id: mcp_request.id,
+                                result: None,
+                                error: Some(crate::mcp::MCPError {
+                                    code: ERROR_NOT_INITIALIZED,
+                                    message: format!(
+                                        "Server not initialized: send initialize before {}",
+                                        mcp_request.method
+                                    ),
+                                    data: None,
+                                }),
+                            };
+                            let response_json = state.encode(&response)?;
+                            if let Err(e) = ws_sender.send(Message::Text(response_json)).await {
+                                error!("Failed to send MCP response to {}: {}", peer_addr, e);
+                                return Err(e.into());
+                            }
+                            return Ok(());
+                        }
+
+                        let span = mcp::request_span(&mcp_request);
+                        let request_id = mcp_request.id.clone();
                         match mcp_handler.handle_request(mcp_request).await {
                             Ok(response) => {
-                                let response_json = serde_json::to_string(&response)?;
-                                debug!("Sending MCP response: {}", response_json);
+                                if is_initialize && response.error.is_none() {
+                                    state.mark_initialized(&peer_addr);
+                                }
+                                let response_json = state.encode(&response)?;
+                                span.in_scope(|| debug!("Sending MCP response: {}", response_json));
 
                                 if let Err(e) = ws_sender.send(Message::Text(response_json)).await {
                                     error!("Failed to send MCP response to {}: {}", peer_addr, e);
@@ -312,18 +1638,9 @@ async fn handle_websocket_message(
                             }
                             Err(e) => {
                                 error!("Error handling MCP request: {}", e);
-                                let error_response = MCPResponse {
-                                    jsonrpc: "2.0".to_string(),
-                                    id: None,
-                                    result: None,
-                                    error: Some(crate::mcp::MCPError {
-                                        code: -32603,
-                                        message: "Internal error".to_string(),
-                                        data: Some(serde_json::json!({"details": e.to_string()})),
-                                    }),
-                                };
-
-                                let error_json = serde_json::to_string(&error_response)?;
+                                let error_response = mcp::internal_error(request_id, &e);
+
+                                let error_json = state.encode(&error_response)?;
                                 if let Err(e) = ws_sender.send(Message::Text(error_json)).await {
                                     error!("Failed to send error response to {}: {}", peer_addr, e);
                                     return Err(e.into());
@@ -333,21 +1650,15 @@ async fn handle_websocket_message(
                     }
                     Err(e) => {
                         warn!("Failed to parse MCP request from {}: {}", peer_addr, e);
-                        debug!("Invalid message content: {}", text);
+                        debug!(
+                            "Invalid message content: {}",
+                            escape_control_characters(text)
+                        );
 
                         // Send back a JSON-RPC error response
-                        let error_response = MCPResponse {
-                            jsonrpc: "2.0".to_string(),
-                            id: None,
-                            result: None,
-                            error: Some(crate::mcp::MCPError {
-                                code: -32700,
-                                message: "Parse error".to_string(),
-                                data: None,
-                            }),
-                        };
-
-                        let error_json = serde_json::to_string(&error_response)?;
+                        let error_response = mcp::request_parse_error(text.as_bytes(), &e);
+
+                        let error_json = state.encode(&error_response)?;
                         if let Err(e) = ws_sender.send(Message::Text(error_json)).await {
                             error!(
                                 "Failed to send parse error response to {}: {}",
@@ -360,6 +1671,23 @@ async fn handle_websocket_message(
             } else if msg.is_close() {
                 info!("Connection closed by {}", peer_addr);
                 return Ok(());
+            } else if let Message::Frame(frame) = msg {
+                // tungstenite reassembles fragmented messages before handing them
+                // out and only produces raw frames for sending, so a raw frame here
+                // means message boundaries can no longer be trusted
+                warn!(
+                    "Unexpected raw frame from {} ({:?}), closing connection",
+                    peer_addr,
+                    frame.header().opcode
+                );
+                let close = Message::Close(Some(CloseFrame {
+                    code: CloseCode::Protocol,
+                    reason: "Unexpected raw frame".into(),
+                }));
+                if let Err(e) = ws_sender.send(close).await {
+                    debug!("Failed to send close frame to {}: {}", peer_addr, e);
+                }
+                return Err(anyhow!("Unexpected raw frame from {}", peer_addr));
             }
         }
         Err(e) => {
@@ -370,3 +1698,76 @@ async fn handle_websocket_message(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use sha2::{Digest, Sha256};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    /// Accepts only a token derived from a shared secret, as a signed-token backend would
+    #[derive(Debug)]
+    struct DerivedTokenAuthenticator;
+
+    fn derived_token() -> String {
+        tools::to_hex(&Sha256::digest(b"shared secret"))
+    }
+
+    #[async_trait]
+    impl Authenticator for DerivedTokenAuthenticator {
+        async fn authenticate(&self, token: &str) -> bool {
+            token == derived_token()
+        }
+    }
+
+    /// Connect to a server using `authenticator` while presenting `token`, returning
+    /// whether a request on the connection is answered
+    async fn answered_with_token(authenticator: Arc<dyn Authenticator>, token: &str) -> bool {
+        let workspace = std::env::temp_dir();
+        let tool_context = ToolContext::new(
+            Some(workspace),
+            SecurityPolicy::default(),
+            EditorBridge::new(),
+        )
+        .unwrap();
+        let state = Arc::new(ServerState::new(
+            tool_context,
+            authenticator,
+            WebSocketOptions::default(),
+        ));
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, peer_addr) = listener.accept().await.unwrap();
+            handle_connection(Box::new(stream), PeerAddr::Tcp(peer_addr), None, state).await
+        });
+
+        let mut request = format!("ws://{}", address).into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert(AUTH_HEADER, token.parse().unwrap());
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (mut client, _) = tokio_tungstenite::client_async(request, stream)
+            .await
+            .unwrap();
+        let list = json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+        client.send(Message::Text(list.to_string())).await.ok();
+        loop {
+            match client.next().await {
+                Some(Ok(Message::Text(_))) => return true,
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                _ => return false,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_authenticators_decide_who_connects() {
+        let authenticator: Arc<dyn Authenticator> = Arc::new(DerivedTokenAuthenticator);
+
+        assert!(answered_with_token(authenticator.clone(), &derived_token()).await);
+        assert!(!answered_with_token(authenticator, "anything else").await);
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Files some tool is writing right now, so writes to one path happen one at a time
+/// while writes to different paths run side by side.
+///
+/// Tool handlers run on blocking threads, so waiting for a path blocks the thread
+/// rather than the runtime. A path is only tracked while a guard holds it.
+#[derive(Debug, Clone, Default)]
+pub struct WriteLocks {
+    state: Arc<LockState>,
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    held: Mutex<HashSet<PathBuf>>,
+    released: Condvar,
+}
+
+/// Keeps the paths it was given locked until dropped
+#[derive(Debug)]
+pub struct WriteGuard {
+    state: Arc<LockState>,
+    paths: Vec<PathBuf>,
+}
+
+impl WriteLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait until none of `paths` is being written, then hold them all.
+    ///
+    /// Taking every path at once, rather than one after another, means two tools
+    /// writing overlapping sets of files can never wait on each other forever.
+    pub fn lock<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> WriteGuard {
+        let mut paths: Vec<PathBuf> = paths.into_iter().map(Path::to_path_buf).collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut held = self.state.held.lock().unwrap();
+        while paths.iter().any(|path| held.contains(path)) {
+            held = self.state.released.wait(held).unwrap();
+        }
+        held.extend(paths.iter().cloned());
+
+        WriteGuard {
+            state: self.state.clone(),
+            paths,
+        }
+    }
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        let mut held = self.state.held.lock().unwrap();
+        for path in &self.paths {
+            held.remove(path);
+        }
+        self.state.released.notify_all();
+    }
+}
@@ -0,0 +1,311 @@
+//! LSP harness.
+//!
+//! `LspClient` runs `claude-code-server lsp` and speaks JSON-RPC to it over
+//! stdio with `Content-Length` framing, as Zed does.
+
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+/// How long to wait for a response
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Numbers the log files of servers started by this test binary
+static NEXT_LOG: AtomicUsize = AtomicUsize::new(0);
+
+pub struct LspClient {
+    /// Capabilities the server announced in its initialize response
+    pub capabilities: Value,
+    /// Notifications received while waiting for responses, oldest first
+    pub notifications: Vec<Value>,
+    /// Requests the server made of the client, oldest first
+    pub server_requests: Vec<Value>,
+    child: Child,
+    /// File the server's stderr is written to
+    log: PathBuf,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl LspClient {
+    /// Start the server for `worktree` and complete the initialize handshake
+    pub async fn start(worktree: &Path) -> Self {
+        Self::start_with_options(worktree, Value::Null).await
+    }
+
+    /// Like `start`, passing `options` as the client's `initializationOptions`
+    pub async fn start_with_options(worktree: &Path, options: Value) -> Self {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_claude-code-server"));
+        command.arg("lsp").arg("--worktree").arg(worktree);
+        Self::launch(command, worktree, options).await
+    }
+
+    /// Like `start`, with `env` added to the server's environment
+    pub async fn start_with_env(worktree: &Path, env: &[(&str, String)]) -> Self {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_claude-code-server"));
+        command.arg("lsp").arg("--worktree").arg(worktree);
+        command.envs(env.iter().map(|(name, value)| (name, value)));
+        Self::launch(command, worktree, Value::Null).await
+    }
+
+    /// Start in hybrid mode, whose WebSocket half writes its lock file below `home`
+    pub async fn start_hybrid(worktree: &Path, home: &Path) -> Self {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_claude-code-server"));
+        command
+            .args(["hybrid", "--port", "0", "--worktree"])
+            .arg(worktree)
+            .env("HOME", home)
+            .env("USERPROFILE", home);
+        Self::launch(command, worktree, Value::Null).await
+    }
+
+    /// Start with `options` but no subcommand, so the server picks its default mode;
+    /// a WebSocket half writes its lock file below `home`
+    pub async fn start_without_mode(worktree: &Path, home: &Path, options: &[&str]) -> Self {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_claude-code-server"));
+        command
+            .args(options)
+            .arg("--worktree")
+            .arg(worktree)
+            .env("HOME", home)
+            .env("USERPROFILE", home);
+        Self::launch(command, worktree, Value::Null).await
+    }
+
+    async fn launch(mut command: Command, worktree: &Path, options: Value) -> Self {
+        let log = std::env::temp_dir().join(format!(
+            "claude-code-server-lsp-{}-{}.log",
+            std::process::id(),
+            NEXT_LOG.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(fs::File::create(&log).unwrap())
+            .env_remove("RUST_LOG")
+            .kill_on_drop(true)
+            .spawn()
+            .expect("failed to launch claude-code-server");
+
+        let mut client = Self {
+            capabilities: Value::Null,
+            notifications: Vec::new(),
+            server_requests: Vec::new(),
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            log,
+            next_id: 1,
+        };
+        let root = url(worktree);
+        let initialize = client
+            .request(
+                "initialize",
+                json!({
+                    "processId": null,
+                    "rootUri": root,
+                    "capabilities": {
+                        "workspace": { "didChangeWatchedFiles": { "dynamicRegistration": true } },
+                        "window": { "workDoneProgress": true }
+                    },
+                    "initializationOptions": options
+                }),
+            )
+            .await;
+        client.capabilities = initialize["result"]["capabilities"].clone();
+        client.notify("initialized", json!({})).await;
+        client
+    }
+
+    /// Tell the server about a buffer, as the editor does when a file is opened
+    pub async fn open(&mut self, path: &Path, language_id: &str, text: &str) {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": url(path),
+                    "languageId": language_id,
+                    "version": 1,
+                    "text": text
+                }
+            }),
+        )
+        .await;
+    }
+
+    /// Send a request and return its result, collecting notifications and accepting
+    /// server requests along the way
+    pub async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .await;
+
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                if let Some(response) = self.dispatch().await {
+                    if response["id"] == json!(id) {
+                        return response;
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("no response to {}", method))
+    }
+
+    /// Take the oldest notification named `method`, waiting for one if none has arrived.
+    ///
+    /// The server's notifications and responses are written independently, so a
+    /// notification sent while handling a request may follow its response.
+    pub async fn notification(&mut self, method: &str) -> Value {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                if let Some(i) = self
+                    .notifications
+                    .iter()
+                    .position(|n| n["method"] == method)
+                {
+                    return self.notifications.remove(i);
+                }
+                self.dispatch().await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("no {} notification", method))
+    }
+
+    /// The first request the server made named `method`, waiting for it if necessary
+    pub async fn server_request(&mut self, method: &str) -> Value {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                if let Some(request) = self.server_requests.iter().find(|r| r["method"] == method) {
+                    return request.clone();
+                }
+                self.dispatch().await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("server never sent {}", method))
+    }
+
+    /// Read one message, returning it if it is a response; notifications are collected
+    /// and server requests accepted
+    async fn dispatch(&mut self) -> Option<Value> {
+        let message = self.next_message().await;
+        match (message.get("method"), message.get("id")) {
+            (None, _) => return Some(message),
+            (Some(_), None) => self.notifications.push(message),
+            (Some(_), Some(request_id)) => {
+                let reply = json!({ "jsonrpc": "2.0", "id": request_id, "result": null });
+                self.send(reply).await;
+                self.server_requests.push(message);
+            }
+        }
+        None
+    }
+
+    pub async fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+            .await;
+    }
+
+    /// Everything the server has logged so far
+    pub fn log(&self) -> String {
+        fs::read_to_string(&self.log).unwrap_or_default()
+    }
+
+    pub async fn shutdown(mut self) {
+        self.request("shutdown", Value::Null).await;
+        self.notify("exit", Value::Null).await;
+        self.child.kill().await.ok();
+        fs::remove_file(&self.log).ok();
+    }
+
+    /// End the session as the editor does and wait for the server to exit on its own
+    pub async fn exit(mut self) -> std::process::ExitStatus {
+        self.request("shutdown", Value::Null).await;
+        self.notify("exit", Value::Null).await;
+        drop(self.stdin);
+        let status = tokio::time::timeout(TIMEOUT, self.child.wait())
+            .await
+            .expect("server did not exit")
+            .unwrap();
+        fs::remove_file(&self.log).ok();
+        status
+    }
+
+    async fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        self.stdin.write_all(framed.as_bytes()).await.unwrap();
+        self.stdin.flush().await.unwrap();
+    }
+
+    async fn next_message(&mut self) -> Value {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.stdout.read_line(&mut header).await.unwrap() == 0 {
+                panic!("server closed stdout");
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = Some(value.trim().parse::<usize>().unwrap());
+            }
+        }
+
+        let mut body = vec![0; length.expect("message without Content-Length")];
+        self.stdout.read_exact(&mut body).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+}
+
+pub fn url(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+/// A scratch directory for one test, used as `$HOME`, with an empty worktree at
+/// `workspace/` inside it. Removed when dropped.
+pub struct TestHome {
+    home: PathBuf,
+    worktree: PathBuf,
+}
+
+impl TestHome {
+    /// A fresh home named after `name` and this test process
+    pub fn new(name: &str) -> Self {
+        let home = std::env::temp_dir().join(format!(
+            "claude-code-server-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let worktree = home.join("workspace");
+        fs::remove_dir_all(&home).ok();
+        fs::create_dir_all(&worktree).unwrap();
+        Self { home, worktree }
+    }
+
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+
+    pub fn worktree(&self) -> &Path {
+        &self.worktree
+    }
+}
+
+impl Drop for TestHome {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.home).ok();
+    }
+}
@@ -0,0 +1,9 @@
+//! Harnesses for end-to-end tests against the real server binary
+
+// Each test crate uses only part of the harness
+#![allow(dead_code)]
+
+#[cfg(feature = "lsp")]
+pub mod lsp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
@@ -0,0 +1,666 @@
+//! WebSocket harness.
+//!
+//! Each `TestServer` runs the binary with its own `HOME`, so lock files never
+//! touch the developer's `~/.claude`, and binds port 0 so parallel tests cannot
+//! race for a port. The bound port and auth token come from the lock file.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{Data, OpCode};
+use tokio_tungstenite::tungstenite::protocol::frame::Frame;
+use tokio_tungstenite::tungstenite::Error;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+const AUTH_HEADER: &str = "x-claude-code-ide-authorization";
+const RECONNECT_HEADER: &str = "x-claude-code-ide-reconnect-token";
+const PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// How long to wait for the server to come up or answer a request
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Name of the configuration file in a server's home directory
+const CONFIG_FILE: &str = "config.json";
+
+/// Name of the file a server's stderr is written to in its home directory
+const LOG_FILE: &str = "server.log";
+
+/// Connection to the server over TCP or a Unix socket
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+pub struct TestServer {
+    child: Child,
+    home: PathBuf,
+    workspace: PathBuf,
+    socket: Option<PathBuf>,
+    /// Port in the lock file's name, or 0 when serving a Unix socket
+    pub port: u16,
+    pub auth_token: String,
+    /// Contents of the server's lock file
+    pub lock_file: Value,
+}
+
+impl TestServer {
+    /// Start `claude-code-server websocket` on a free port and wait for its lock file
+    pub async fn start() -> Self {
+        Self::start_with(0, |_| {}).await
+    }
+
+    /// Start on `port` once `prepare` has set up the server's home directory.
+    ///
+    /// Lock files `prepare` leaves behind are not mistaken for the server's own unless
+    /// the server rewrites them.
+    pub async fn start_with(port: u16, prepare: impl FnOnce(&Path)) -> Self {
+        let args = ["--port".to_string(), port.to_string()];
+        Self::launch(new_home(), &[], &args, &[], None, prepare).await
+    }
+
+    /// Start on a free port with `options` passed before the subcommand
+    pub async fn start_with_options(options: &[&str]) -> Self {
+        let options: Vec<String> = options.iter().map(|option| option.to_string()).collect();
+        let args = ["--port".to_string(), "0".to_string()];
+        Self::launch(new_home(), &options, &args, &[], None, |_| {}).await
+    }
+
+    /// Start on a free port with `config` written to the file named by `--config`
+    pub async fn start_with_config(config: &Value) -> Self {
+        Self::start_with_config_and_env(config, &[]).await
+    }
+
+    /// Like `start_with_config`, with `env` added to the server's environment
+    pub async fn start_with_config_and_env(config: &Value, env: &[(&str, String)]) -> Self {
+        let home = new_home();
+        let path = home.join(CONFIG_FILE);
+        let options = ["--config".to_string(), path.display().to_string()];
+        let args = ["--port".to_string(), "0".to_string()];
+        let config = config.to_string();
+        Self::launch(home, &options, &args, env, None, |home| {
+            fs::write(home.join(CONFIG_FILE), config).unwrap()
+        })
+        .await
+    }
+
+    /// Start with `--http` on a free port, with `config` written to the file named by
+    /// `--config`, so clients use `http_client`
+    pub async fn start_http_with_config(config: &Value) -> Self {
+        let home = new_home();
+        let path = home.join(CONFIG_FILE);
+        let options = [
+            "--http".to_string(),
+            "--config".to_string(),
+            path.display().to_string(),
+        ];
+        let args = ["--port".to_string(), "0".to_string()];
+        let config = config.to_string();
+        Self::launch(home, &options, &args, &[], None, |home| {
+            fs::write(home.join(CONFIG_FILE), config).unwrap()
+        })
+        .await
+    }
+
+    /// Start listening on a Unix domain socket instead of a port
+    #[cfg(unix)]
+    pub async fn start_on_socket() -> Self {
+        let home = new_home();
+        let socket = home.join("server.sock");
+        let args = ["--socket".to_string(), socket.display().to_string()];
+        Self::launch(home, &[], &args, &[], Some(socket), |_| {}).await
+    }
+
+    async fn launch(
+        home: PathBuf,
+        options: &[String],
+        args: &[String],
+        env: &[(&str, String)],
+        socket: Option<PathBuf>,
+        prepare: impl FnOnce(&Path),
+    ) -> Self {
+        let workspace = home.join("workspace");
+        fs::create_dir_all(&workspace).unwrap();
+        prepare(&home);
+        let existing: Vec<(PathBuf, Option<String>)> = lock_files(&home)
+            .into_iter()
+            .map(|path| {
+                let contents = fs::read_to_string(&path).ok();
+                (path, contents)
+            })
+            .collect();
+        let log = fs::File::create(home.join(LOG_FILE)).unwrap();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_claude-code-server"))
+            .args(options)
+            .arg("websocket")
+            .args(args)
+            .current_dir(&workspace)
+            .env("HOME", &home)
+            .env("USERPROFILE", &home)
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(log)
+            .kill_on_drop(true)
+            .spawn()
+            .expect("failed to launch claude-code-server");
+
+        let (port, lock_file) = tokio::time::timeout(TIMEOUT, wait_for_lock_file(&home, &existing))
+            .await
+            .expect("server did not write a lock file");
+
+        Self {
+            child,
+            home,
+            workspace,
+            socket,
+            port,
+            auth_token: lock_file["authToken"].as_str().unwrap().to_string(),
+            lock_file,
+        }
+    }
+
+    /// Directory the server was started in, and so its workspace folder
+    pub fn workspace(&self) -> &Path {
+        &self.workspace
+    }
+
+    /// File passed with `--config` by `start_with_config`
+    pub fn config_path(&self) -> PathBuf {
+        self.home.join(CONFIG_FILE)
+    }
+
+    /// Everything the server has logged so far
+    pub fn log(&self) -> String {
+        fs::read_to_string(self.home.join(LOG_FILE)).unwrap()
+    }
+
+    /// Whether the server exits successfully on its own within `wait`
+    pub async fn exited_within(&mut self, wait: Duration) -> bool {
+        match tokio::time::timeout(wait, self.child.wait()).await {
+            Ok(status) => status.unwrap().success(),
+            Err(_) => false,
+        }
+    }
+
+    /// Directory the server writes its lock files to
+    pub fn lock_dir(&self) -> PathBuf {
+        self.home.join(".claude").join("ide")
+    }
+
+    /// Whether the server's lock file is still in place
+    pub fn has_lock_file(&self) -> bool {
+        !lock_files(&self.home).is_empty()
+    }
+
+    /// Connect a WebSocket client that presents the lock file token
+    pub async fn connect(&self) -> TestClient {
+        connect(self.port, &self.auth_token, self.socket.as_deref()).await
+    }
+
+    /// Connect presenting `auth_token` instead of the one in the lock file at startup
+    pub async fn connect_with_token(&self, auth_token: &str) -> TestClient {
+        connect(self.port, auth_token, self.socket.as_deref()).await
+    }
+
+    /// Connect presenting only `reconnect_token`, as a client resuming its session does
+    pub async fn connect_with_reconnect_token(&self, reconnect_token: &str) -> TestClient {
+        let mut request = format!("ws://127.0.0.1:{}", self.port)
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert(RECONNECT_HEADER, reconnect_token.parse().unwrap());
+        handshake(request, self.port, self.socket.as_deref())
+            .await
+            .expect("WebSocket handshake failed")
+    }
+
+    /// A client for a server started with `--http`, presenting the lock file token
+    pub fn http_client(&self) -> HttpClient {
+        HttpClient {
+            port: self.port,
+            auth_token: self.auth_token.clone(),
+        }
+    }
+
+    /// Connect as a browser page from `origin` would, returning the handshake error
+    /// if the server refuses
+    pub async fn try_connect_from_origin(&self, origin: &str) -> Result<TestClient, Error> {
+        let mut request = client_request(self.port, &self.auth_token);
+        request
+            .headers_mut()
+            .insert("Origin", origin.parse().unwrap());
+        handshake(request, self.port, self.socket.as_deref()).await
+    }
+
+    pub async fn shutdown(mut self) {
+        self.child.kill().await.ok();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.child.start_kill().ok();
+        fs::remove_dir_all(&self.home).ok();
+    }
+}
+
+/// Connect to a server started some other way, such as in hybrid mode, once it
+/// has written its lock file below `home`
+pub async fn connect_with_lock_file(home: &Path) -> TestClient {
+    let (port, lock_file) = tokio::time::timeout(TIMEOUT, wait_for_lock_file(home, &[]))
+        .await
+        .expect("server did not write a lock file");
+    connect(port, lock_file["authToken"].as_str().unwrap(), None).await
+}
+
+async fn connect(port: u16, auth_token: &str, socket: Option<&Path>) -> TestClient {
+    handshake(client_request(port, auth_token), port, socket)
+        .await
+        .expect("WebSocket handshake failed")
+}
+
+/// Handshake request presenting `auth_token`
+fn client_request(port: u16, auth_token: &str) -> Request {
+    let mut request = format!("ws://127.0.0.1:{}", port)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert(AUTH_HEADER, auth_token.parse().unwrap());
+    request
+}
+
+async fn handshake(
+    request: Request,
+    port: u16,
+    socket: Option<&Path>,
+) -> Result<TestClient, Error> {
+    let io: Box<dyn Io> = match socket {
+        #[cfg(unix)]
+        Some(socket) => Box::new(tokio::net::UnixStream::connect(socket).await.unwrap()),
+        _ => Box::new(TcpStream::connect(("127.0.0.1", port)).await.unwrap()),
+    };
+    let (stream, _) = tokio_tungstenite::client_async(request, io).await?;
+
+    Ok(TestClient {
+        stream,
+        next_id: 1,
+        skipped: VecDeque::new(),
+    })
+}
+
+fn new_home() -> PathBuf {
+    std::env::temp_dir().join(format!("claude-code-server-e2e-{}", uuid::Uuid::new_v4()))
+}
+
+/// Lock files under `home`
+fn lock_files(home: &Path) -> Vec<PathBuf> {
+    let lock_dir = home.join(".claude").join("ide");
+    fs::read_dir(lock_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lock"))
+        .collect()
+}
+
+/// Poll `home` for a new lock file, returning the port in its name and its contents
+async fn wait_for_lock_file(home: &Path, existing: &[(PathBuf, Option<String>)]) -> (u16, Value) {
+    loop {
+        for path in lock_files(home) {
+            let unchanged = existing.iter().any(|(existing, contents)| {
+                *existing == path && *contents == fs::read_to_string(&path).ok()
+            });
+            if unchanged {
+                continue;
+            }
+            if let Some(found) = read_lock_file(&path) {
+                return found;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// `None` until the lock file has been written completely
+fn read_lock_file(path: &Path) -> Option<(u16, Value)> {
+    // Socket servers' lock files are not named after a port
+    let port = path.file_stem()?.to_str()?.parse().unwrap_or(0);
+    let contents: Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    contents.get("authToken")?.as_str()?;
+    Some((port, contents))
+}
+
+pub struct TestClient {
+    stream: WebSocketStream<Box<dyn Io>>,
+    next_id: u64,
+    /// Notifications that arrived while a response was awaited
+    skipped: VecDeque<Value>,
+}
+
+impl TestClient {
+    /// Send a JSON-RPC request and return its response, keeping notifications for `notification`
+    pub async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        self.send(&request.to_string()).await;
+
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let response = self.next_message().await;
+                if response.get("id") == Some(&json!(id)) {
+                    return response;
+                }
+                if response.get("id").is_none() {
+                    self.skipped.push_back(response);
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("no response to {}", method))
+    }
+
+    /// Send a JSON-RPC notification, which expects no response
+    pub async fn notify(&mut self, method: &str, params: Value) {
+        let notification = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        self.send(&notification.to_string()).await;
+    }
+
+    /// Send `text` as-is and return the next message the server replies with
+    pub async fn send_raw(&mut self, text: &str) -> Value {
+        self.send(text).await;
+        tokio::time::timeout(TIMEOUT, self.next_message())
+            .await
+            .expect("no reply to raw message")
+    }
+
+    /// Send one text message split into `fragments` across continuation frames and
+    /// return the next message the server replies with
+    pub async fn send_fragmented(&mut self, fragments: &[&str]) -> Value {
+        for (i, fragment) in fragments.iter().enumerate() {
+            let opcode = if i == 0 { Data::Text } else { Data::Continue };
+            let is_final = i + 1 == fragments.len();
+            let frame =
+                Frame::message(fragment.as_bytes().to_vec(), OpCode::Data(opcode), is_final);
+            self.stream.send(Message::Frame(frame)).await.unwrap();
+        }
+        tokio::time::timeout(TIMEOUT, self.next_message())
+            .await
+            .expect("no reply to fragmented message")
+    }
+
+    /// Send `text` as is and return the reply's text exactly as the server framed it
+    pub async fn send_raw_text(&mut self, text: &str) -> String {
+        self.send(text).await;
+        tokio::time::timeout(TIMEOUT, self.next_text())
+            .await
+            .expect("no reply to raw message")
+    }
+
+    /// Wait for the next notification named `method`, skipping other messages
+    pub async fn notification(&mut self, method: &str) -> Value {
+        if let Some(index) = self.skipped.iter().position(|m| m["method"] == method) {
+            return self.skipped.remove(index).unwrap();
+        }
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let message = self.next_message().await;
+                if message["method"] == method {
+                    return message;
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("no {} notification", method))
+    }
+
+    /// Every notification up to and including the next `method` notification
+    pub async fn notifications_until(&mut self, method: &str) -> Vec<Value> {
+        let mut received: Vec<Value> = self.skipped.drain(..).collect();
+        if let Some(index) = received.iter().position(|m| m["method"] == method) {
+            let later = received.split_off(index + 1);
+            self.skipped.extend(later);
+            return received;
+        }
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let message = self.next_message().await;
+                if message.get("method").is_none() {
+                    continue;
+                }
+                let done = message["method"] == method;
+                received.push(message);
+                if done {
+                    return received;
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("no {} notification", method))
+    }
+
+    async fn send(&mut self, text: &str) {
+        self.stream
+            .send(Message::Text(text.to_string()))
+            .await
+            .unwrap();
+    }
+
+    /// Wait for the next text message, skipping pings and other control frames
+    async fn next_message(&mut self) -> Value {
+        serde_json::from_str(&self.next_text().await).unwrap()
+    }
+
+    async fn next_text(&mut self) -> String {
+        loop {
+            let message = self
+                .stream
+                .next()
+                .await
+                .expect("server closed the connection")
+                .unwrap();
+            if let Message::Text(text) = message {
+                return text;
+            }
+        }
+    }
+
+    /// Whether the server closes the connection within `wait`, without answering its pings
+    pub async fn closed_within(&mut self, wait: Duration) -> bool {
+        tokio::time::timeout(wait, async {
+            loop {
+                match self.stream.next().await {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                }
+            }
+        })
+        .await
+        .is_ok()
+    }
+
+    pub async fn initialize(&mut self) -> Value {
+        self.request("initialize", json!({ "protocolVersion": PROTOCOL_VERSION }))
+            .await
+    }
+
+    /// Call a tool and parse the JSON text of its first content block
+    pub async fn call_tool(&mut self, name: &str, arguments: Value) -> Value {
+        let response = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await;
+        let text = response["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap_or_else(|| panic!("{} returned no text content: {}", name, response));
+        serde_json::from_str(text).unwrap()
+    }
+
+    pub async fn close(mut self) {
+        self.stream.close(None).await.ok();
+    }
+}
+
+/// Client of the HTTP transport. The server closes each connection after one
+/// response, so every request opens its own.
+pub struct HttpClient {
+    port: u16,
+    auth_token: String,
+}
+
+pub struct HttpReply {
+    pub status: u16,
+    /// Header values keyed by lowercase name
+    pub headers: Vec<(String, String)>,
+    /// The body parsed as JSON, or null if it is empty
+    pub body: Value,
+}
+
+impl HttpReply {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl HttpClient {
+    /// The same client presenting `token` instead of the server's
+    pub fn with_auth_token(mut self, token: &str) -> Self {
+        self.auth_token = token.to_string();
+        self
+    }
+
+    /// POST `body` to `/mcp`, in `session` unless this starts one
+    pub async fn post(&self, session: Option<&str>, body: &str) -> HttpReply {
+        let mut stream = self.send("POST", session, body).await;
+        let mut response = Vec::new();
+        tokio::time::timeout(TIMEOUT, stream.read_to_end(&mut response))
+            .await
+            .expect("no HTTP response")
+            .unwrap();
+        parse_http_reply(&response)
+    }
+
+    /// POST a JSON-RPC request named `method` with `params`
+    pub async fn request(&self, session: Option<&str>, method: &str, params: Value) -> HttpReply {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        self.post(session, &request.to_string()).await
+    }
+
+    /// End `session`
+    pub async fn delete(&self, session: &str) -> HttpReply {
+        let mut stream = self.send("DELETE", Some(session), "").await;
+        let mut response = Vec::new();
+        tokio::time::timeout(TIMEOUT, stream.read_to_end(&mut response))
+            .await
+            .expect("no HTTP response")
+            .unwrap();
+        parse_http_reply(&response)
+    }
+
+    /// Open the event stream of `session`, returning its status line and the stream
+    pub async fn events(&self, session: &str) -> (String, EventStream) {
+        let stream = self.send("GET", Some(session), "").await;
+        let mut reader = BufReader::new(stream);
+        let mut status = String::new();
+        tokio::time::timeout(TIMEOUT, reader.read_line(&mut status))
+            .await
+            .expect("no HTTP response")
+            .unwrap();
+        // Skip the rest of the head
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+        }
+        (status.trim_end().to_string(), EventStream { reader })
+    }
+
+    async fn send(&self, method: &str, session: Option<&str>, body: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).await.unwrap();
+        let mut head = format!(
+            "{} /mcp HTTP/1.1\r\nHost: 127.0.0.1\r\n{}: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+            method,
+            AUTH_HEADER,
+            self.auth_token,
+            body.len()
+        );
+        if let Some(session) = session {
+            head.push_str(&format!("Mcp-Session-Id: {}\r\n", session));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body.as_bytes()).await.unwrap();
+        stream
+    }
+}
+
+fn parse_http_reply(response: &[u8]) -> HttpReply {
+    let text = String::from_utf8_lossy(response);
+    let (head, body) = text
+        .split_once("\r\n\r\n")
+        .expect("incomplete HTTP response");
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|code| code.parse().ok())
+        .expect("malformed status line");
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(body).unwrap()
+    };
+    HttpReply {
+        status,
+        headers,
+        body,
+    }
+}
+
+/// Server-sent events of an HTTP session
+pub struct EventStream {
+    reader: BufReader<TcpStream>,
+}
+
+impl EventStream {
+    /// The data of the next event, skipping keepalive comments
+    pub async fn next_event(&mut self) -> Value {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line).await.unwrap() == 0 {
+                    panic!("event stream closed");
+                }
+                if let Some(data) = line.trim_end().strip_prefix("data: ") {
+                    return serde_json::from_str(data).unwrap();
+                }
+            }
+        })
+        .await
+        .expect("no event")
+    }
+}
@@ -0,0 +1,3196 @@
+#![cfg(feature = "websocket")]
+
+mod common;
+
+use common::websocket::TestServer;
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use std::fs;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+async fn initialize_list_and_call_tools() {
+    let server = TestServer::start().await;
+    fs::write(server.workspace().join("hello.txt"), "hello from e2e\n").unwrap();
+    let mut client = server.connect().await;
+
+    let initialize = client.initialize().await;
+    assert_eq!(initialize["result"]["protocolVersion"], "2025-03-26");
+    assert_eq!(
+        initialize["result"]["serverInfo"]["name"],
+        "claude-code-server"
+    );
+
+    let tools = client.request("tools/list", json!({})).await;
+    let names: Vec<&str> = tools["result"]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|tool| tool["name"].as_str())
+        .collect();
+    assert!(names.contains(&"getFileContents"), "{:?}", names);
+
+    let contents = client
+        .call_tool("getFileContents", json!({ "filePath": "hello.txt" }))
+        .await;
+    assert_eq!(contents["success"], true);
+    assert_eq!(contents["content"], "hello from e2e\n");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn protocol_versions_are_echoed_downgraded_or_rejected() {
+    let server = TestServer::start().await;
+
+    for (requested, negotiated) in [
+        ("2024-11-05", "2024-11-05"),
+        ("2025-05-01", "2025-03-26"),
+        ("2030-01-01", "2025-06-18"),
+    ] {
+        let mut client = server.connect().await;
+        let initialize = client
+            .request("initialize", json!({ "protocolVersion": requested }))
+            .await;
+        assert_eq!(
+            initialize["result"]["protocolVersion"], negotiated,
+            "{}: {}",
+            requested, initialize
+        );
+        client.close().await;
+    }
+
+    let mut client = server.connect().await;
+    let rejected = client
+        .request("initialize", json!({ "protocolVersion": "2024-01-01" }))
+        .await;
+    assert_eq!(rejected["error"]["code"], -32602, "{}", rejected);
+    assert_eq!(rejected["error"]["data"]["requested"], "2024-01-01");
+    assert_eq!(
+        rejected["error"]["data"]["supported"],
+        json!(["2025-06-18", "2025-03-26", "2024-11-05"])
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn tool_results_are_structured_for_clients_that_negotiate_it() {
+    let server = TestServer::start().await;
+    fs::write(server.workspace().join("hello.txt"), "hello\n").unwrap();
+    let call = json!({ "name": "getFileContents", "arguments": { "filePath": "hello.txt" } });
+
+    let mut capable = server.connect().await;
+    let initialize = capable
+        .request("initialize", json!({ "protocolVersion": "2025-06-18" }))
+        .await;
+    assert_eq!(initialize["result"]["protocolVersion"], "2025-06-18");
+    let structured = capable.request("tools/call", call.clone()).await;
+    let result = &structured["result"];
+    assert_eq!(
+        result["structuredContent"]["content"], "hello\n",
+        "{}",
+        result
+    );
+    // The text block stays for clients that only read content
+    let text: serde_json::Value =
+        serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+    assert_eq!(text, result["structuredContent"]);
+
+    let mut legacy = server.connect().await;
+    legacy.initialize().await;
+    let stringified = legacy.request("tools/call", call).await;
+    let result = &stringified["result"];
+    assert!(result.get("structuredContent").is_none(), "{}", result);
+    assert!(result["content"][0]["text"].is_string(), "{}", result);
+
+    capable.close().await;
+    legacy.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn server_info_reports_the_crate_version_and_build() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+
+    let initialize = client.initialize().await;
+    let server_info = &initialize["result"]["serverInfo"];
+    assert_eq!(server_info["version"], env!("CARGO_PKG_VERSION"));
+    let git_sha = server_info["gitSha"].as_str().unwrap();
+    assert!(!git_sha.is_empty(), "{}", server_info);
+    let build_date = server_info["buildDate"].as_str().unwrap();
+    assert!(
+        build_date.len() == 20 && build_date.ends_with('Z'),
+        "{}",
+        build_date
+    );
+
+    let version = client.call_tool("getVersion", json!({})).await;
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(version["gitSha"], git_sha);
+    assert_eq!(version["buildDate"], build_date);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn reconnect_tokens_resume_the_session_until_they_expire() {
+    let server = TestServer::start_with_options(&["--reconnect-window-secs", "1"]).await;
+    let mut client = server.connect().await;
+    let initialize = client.initialize().await;
+    let token = initialize["result"]["reconnectToken"]
+        .as_str()
+        .unwrap_or_else(|| panic!("no reconnectToken: {}", initialize))
+        .to_string();
+    client.close().await;
+
+    // No auth token and no initialize, yet the session carries on
+    let mut resumed = server.connect_with_reconnect_token(&token).await;
+    let tools = resumed.request("tools/list", json!({})).await;
+    assert!(tools["result"]["tools"].is_array(), "{}", tools);
+    resumed.close().await;
+
+    // A forged signature is refused straight away
+    let (payload, _) = token.rsplit_once('.').unwrap();
+    let mut forged = server
+        .connect_with_reconnect_token(&format!("{}.{}", payload, "0".repeat(64)))
+        .await;
+    assert!(forged.closed_within(Duration::from_secs(5)).await);
+
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    let mut expired = server.connect_with_reconnect_token(&token).await;
+    assert!(expired.closed_within(Duration::from_secs(5)).await);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn resumed_sessions_keep_their_client_and_subscriptions() {
+    let server = TestServer::start().await;
+    let path = server.workspace().join("watched.txt");
+    fs::write(&path, "before\n").unwrap();
+    let uri = format!("file://{}", path.canonicalize().unwrap().display());
+    let client_info = json!({ "name": "test-client", "version": "1.2.3" });
+    let client_capabilities = json!({ "roots": { "listChanged": true } });
+
+    let mut client = server.connect().await;
+    let initialize = client
+        .request(
+            "initialize",
+            json!({
+                "protocolVersion": "2025-06-18",
+                "clientInfo": client_info,
+                "capabilities": client_capabilities
+            }),
+        )
+        .await;
+    let token = initialize["result"]["reconnectToken"]
+        .as_str()
+        .unwrap_or_else(|| panic!("no reconnectToken: {}", initialize))
+        .to_string();
+    // Subscribed after the token was issued, and still carried over
+    let subscribed = client
+        .request("resources/subscribe", json!({ "uri": uri }))
+        .await;
+    assert_eq!(subscribed["result"], json!({}), "{}", subscribed);
+    client.close().await;
+
+    let mut resumed = server.connect_with_reconnect_token(&token).await;
+    let status = resumed.request("get_status", json!({})).await;
+    let session = &status["result"]["session"];
+    assert_eq!(session["protocolVersion"], "2025-06-18", "{}", status);
+    assert_eq!(session["clientInfo"], client_info);
+    assert_eq!(session["clientCapabilities"], client_capabilities);
+    assert_eq!(session["subscriptions"], json!([uri]));
+
+    fs::write(&path, "after the change\n").unwrap();
+    let updated = resumed
+        .notification("notifications/resources/updated")
+        .await;
+    assert_eq!(updated["params"]["uri"], uri.as_str());
+
+    resumed.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn rejects_connections_without_the_lock_file_token() {
+    let server = TestServer::start().await;
+
+    let (mut stream, _) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", server.port))
+            .await
+            .unwrap();
+    match stream.next().await {
+        Some(Ok(Message::Close(Some(frame)))) => assert_eq!(frame.code, CloseCode::Policy),
+        other => panic!("expected a policy close, got {:?}", other),
+    }
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn rotated_tokens_are_written_to_the_lock_file_and_announced() {
+    let server = TestServer::start_with_options(&["--rotate-token-secs", "1"]).await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let rotated = client.notification("auth_token_rotated").await;
+    assert_eq!(rotated["params"]["graceSecs"], 60, "{}", rotated);
+    let lock_path = server.lock_dir().join(format!("{}.lock", server.port));
+    let lock_file: Value = serde_json::from_str(&fs::read_to_string(lock_path).unwrap()).unwrap();
+    let new_token = lock_file["authToken"].as_str().unwrap();
+    assert_ne!(new_token, server.auth_token);
+
+    // New clients use the new token, while the old one lasts out its grace window
+    let mut renewed = server.connect_with_token(new_token).await;
+    assert!(renewed.initialize().await["result"].is_object());
+    let mut late = server.connect().await;
+    assert!(late.initialize().await["result"].is_object());
+
+    renewed.close().await;
+    late.close().await;
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn remote_binds_only_accept_allowed_origins() {
+    let server = TestServer::start_with_options(&[
+        "--bind",
+        "0.0.0.0",
+        "--allowed-origins",
+        "https://allowed.example,https://other.example",
+    ])
+    .await;
+
+    match server.try_connect_from_origin("https://evil.example").await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status(), 403);
+        }
+        Err(other) => panic!("expected a 403 response, got {:?}", other),
+        Ok(_) => panic!("connected from a disallowed origin"),
+    }
+
+    let mut client = server
+        .try_connect_from_origin("https://allowed.example")
+        .await
+        .unwrap();
+    let initialize = client.initialize().await;
+    assert!(initialize["error"].is_null(), "{}", initialize);
+    // Clients that are not browsers send no origin and are still let in
+    server.connect().await.close().await;
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn extract_to_file_moves_the_range_into_a_new_file() {
+    let server = TestServer::start().await;
+    let source = server.workspace().join("lib.rs");
+    fs::write(
+        &source,
+        "fn keep() {}\n\nfn moved() {\n    keep();\n}\n\nfn also_keep() {}\n",
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let arguments = json!({
+        "sourcePath": "lib.rs",
+        "range": {
+            "start": { "line": 2, "character": 0 },
+            "end": { "line": 6, "character": 0 }
+        },
+        "targetPath": "extracted/moved.rs"
+    });
+    let result = client.call_tool("extractToFile", arguments.clone()).await;
+    assert_eq!(result["success"], true, "{}", result);
+    assert_eq!(
+        fs::read_to_string(server.workspace().join("extracted/moved.rs")).unwrap(),
+        "fn moved() {\n    keep();\n}\n\n"
+    );
+    assert_eq!(
+        fs::read_to_string(&source).unwrap(),
+        "fn keep() {}\n\nfn also_keep() {}\n"
+    );
+
+    // A second extraction must not clobber the file created by the first
+    let response = client
+        .request(
+            "tools/call",
+            json!({ "name": "extractToFile", "arguments": arguments }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32040, "{}", response);
+    assert_eq!(
+        fs::read_to_string(&source).unwrap(),
+        "fn keep() {}\n\nfn also_keep() {}\n"
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn get_capabilities_lists_only_implemented_tools() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client.request("get_capabilities", json!({})).await;
+    let capabilities = &response["result"];
+    let tools: Vec<&str> = capabilities["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|tool| tool.as_str())
+        .collect();
+    for implemented in ["getFileContents", "extractToFile", "tailFile", "untailFile"] {
+        assert!(
+            tools.contains(&implemented),
+            "{} missing: {:?}",
+            implemented,
+            tools
+        );
+    }
+    assert!(!tools.contains(&"executeCode"), "{:?}", tools);
+    assert_eq!(capabilities["features"]["codeExecution"], false);
+    assert!(
+        capabilities["reconnect"]["initialDelayMs"]
+            .as_u64()
+            .unwrap()
+            > 0
+    );
+    assert!(
+        capabilities["reconnect"]["maxDelayMs"].as_u64()
+            >= capabilities["reconnect"]["initialDelayMs"].as_u64()
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn malformed_requests_keep_their_id_when_it_can_be_read() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+
+    let invalid = client
+        .send_raw(r#"{"jsonrpc":"2.0","id":42,"method":["tools/list"]}"#)
+        .await;
+    assert_eq!(invalid["id"], 42, "{}", invalid);
+    assert_eq!(invalid["error"]["code"], -32600);
+
+    let unparseable = client.send_raw(r#"{"jsonrpc":"2.0","id":43,"#).await;
+    assert!(unparseable["id"].is_null(), "{}", unparseable);
+    assert_eq!(unparseable["error"]["code"], -32700);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn create_file_makes_parent_directories() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let result = client
+        .call_tool(
+            "createFile",
+            json!({ "path": "src/generated/module.rs", "content": "pub fn generated() {}\n" }),
+        )
+        .await;
+    assert_eq!(result["success"], true, "{}", result);
+    assert_eq!(result["overwritten"], false);
+    assert_eq!(
+        fs::read_to_string(server.workspace().join("src/generated/module.rs")).unwrap(),
+        "pub fn generated() {}\n"
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn create_file_refuses_existing_files_unless_overwriting() {
+    let server = TestServer::start().await;
+    let existing = server.workspace().join("existing.rs");
+    fs::write(&existing, "original\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client
+        .request(
+            "tools/call",
+            json!({
+                "name": "createFile",
+                "arguments": { "path": "existing.rs", "content": "replacement\n" }
+            }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32044, "{}", response);
+    assert_eq!(response["error"]["data"]["reason"], "file_exists");
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "original\n");
+
+    let result = client
+        .call_tool(
+            "createFile",
+            json!({ "path": "existing.rs", "content": "replacement\n", "overwrite": true }),
+        )
+        .await;
+    assert_eq!(result["overwritten"], true, "{}", result);
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "replacement\n");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn workspace_lock_files_hold_the_port_lock_file_data() {
+    let server = TestServer::start().await;
+
+    let workspace = fs::canonicalize(server.workspace()).unwrap();
+    let digest = <sha2::Sha256 as sha2::Digest>::digest(workspace.to_string_lossy().as_bytes());
+    let hash: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let by_port = server.lock_dir().join(format!("{}.lock", server.port));
+    let by_workspace = server
+        .lock_dir()
+        .join("by-workspace")
+        .join(format!("{}.lock", &hash[..16]));
+
+    let port_lock: Value = serde_json::from_str(&fs::read_to_string(by_port).unwrap()).unwrap();
+    let workspace_lock: Value =
+        serde_json::from_str(&fs::read_to_string(by_workspace).unwrap()).unwrap();
+    assert_eq!(port_lock, workspace_lock);
+    assert_eq!(workspace_lock, server.lock_file);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn skips_ports_whose_lock_file_is_taken() {
+    // A port nothing listens on, whose lock file another server already holds
+    let taken = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let other_lock = r#"{"pid":1,"authToken":"someone-else"}"#;
+    let mut other_lock_path = None;
+    let server = TestServer::start_with(taken, |home| {
+        let lock_dir = home.join(".claude").join("ide");
+        fs::create_dir_all(&lock_dir).unwrap();
+        let path = lock_dir.join(format!("{}.lock", taken));
+        fs::write(&path, other_lock).unwrap();
+        other_lock_path = Some(path);
+    })
+    .await;
+
+    assert_ne!(server.port, taken);
+    assert_eq!(
+        fs::read_to_string(other_lock_path.unwrap()).unwrap(),
+        other_lock
+    );
+    let mut client = server.connect().await;
+    assert_eq!(
+        client.initialize().await["result"]["protocolVersion"],
+        "2025-03-26"
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn reclaims_lock_files_left_by_exited_servers() {
+    let taken = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut exited = std::process::Command::new(env!("CARGO_BIN_EXE_claude-code-server"))
+        .arg("--help")
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let dead_pid = exited.id();
+    exited.wait().unwrap();
+    let stale_lock = json!({ "pid": dead_pid, "authToken": "crashed" }).to_string();
+    let server = TestServer::start_with(taken, |home| {
+        let lock_dir = home.join(".claude").join("ide");
+        fs::create_dir_all(&lock_dir).unwrap();
+        fs::write(lock_dir.join(format!("{}.lock", taken)), &stale_lock).unwrap();
+    })
+    .await;
+
+    assert_eq!(server.port, taken);
+    assert_ne!(server.lock_file["authToken"], "crashed");
+    assert!(server.log().contains("Removing stale lock file"));
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn workspace_folders_come_from_the_server_workspace() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let workspace = fs::canonicalize(server.workspace()).unwrap();
+    let folders = client.call_tool("getWorkspaceFolders", json!({})).await;
+    assert_eq!(folders["success"], true, "{}", folders);
+    assert_eq!(folders["rootPath"], workspace.to_str().unwrap());
+    let folder = &folders["folders"][0];
+    assert_eq!(folder["path"], workspace.to_str().unwrap());
+    assert_eq!(folder["name"], "workspace");
+    assert_eq!(folder["exists"], true);
+    assert_eq!(folders["folders"].as_array().unwrap().len(), 1);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn stat_file_counts_lines_of_text_files() {
+    let server = TestServer::start().await;
+    fs::write(server.workspace().join("notes.rs"), "one\ntwo\nthree").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let stat = client
+        .call_tool("statFile", json!({ "path": "notes.rs" }))
+        .await;
+    assert_eq!(stat["success"], true, "{}", stat);
+    assert_eq!(stat["size"], 13);
+    assert_eq!(stat["lineCount"], 3);
+    assert_eq!(stat["isBinary"], false);
+    assert_eq!(stat["languageId"], "rust");
+    assert!(stat["modifiedMs"].as_u64().unwrap() > 0);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn stat_file_flags_binary_files() {
+    let server = TestServer::start().await;
+    fs::write(
+        server.workspace().join("image.bin"),
+        [0x89, b'P', 0, b'\n', 0xff],
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let stat = client
+        .call_tool("statFile", json!({ "path": "image.bin" }))
+        .await;
+    assert_eq!(stat["isBinary"], true, "{}", stat);
+    assert!(stat["lineCount"].is_null());
+    assert_eq!(stat["size"], 5);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn binary_files_are_read_as_base64_and_written_back_unchanged() {
+    let server = TestServer::start().await;
+    let png = [
+        0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', 0, 0, 0, 0x0d, 0xff, 0xfe,
+    ];
+    fs::write(server.workspace().join("image.png"), png).unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let read = client
+        .call_tool("getFileContents", json!({ "filePath": "image.png" }))
+        .await;
+    assert_eq!(read["success"], true, "{}", read);
+    assert_eq!(read["encoding"], "base64");
+    assert_eq!(read["isBinary"], true);
+    assert_eq!(read["content"], "iVBORw0KGgoAAAAN//4=");
+
+    let created = client
+        .call_tool(
+            "createFile",
+            json!({ "path": "copy.png", "content": read["content"], "encoding": "base64" }),
+        )
+        .await;
+    assert_eq!(created["success"], true, "{}", created);
+    assert_eq!(fs::read(server.workspace().join("copy.png")).unwrap(), png);
+
+    let saved = client
+        .call_tool(
+            "saveDocument",
+            json!({ "filePath": "image.png", "content": read["content"], "encoding": "base64" }),
+        )
+        .await;
+    assert_eq!(saved["success"], true, "{}", saved);
+    assert_eq!(fs::read(server.workspace().join("image.png")).unwrap(), png);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn read_files_reports_missing_files_without_failing_the_batch() {
+    let server = TestServer::start().await;
+    let src = server.workspace().join("src");
+    fs::create_dir(&src).unwrap();
+    fs::write(src.join("lib.rs"), "pub mod parser;\n").unwrap();
+    fs::write(src.join("parser.rs"), "pub fn parse() {}\n").unwrap();
+    fs::write(src.join("notes.txt"), "not rust\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let batch = client
+        .call_tool(
+            "readFiles",
+            json!({ "paths": ["src/lib.rs", "src/missing.rs", "src/parser.rs"] }),
+        )
+        .await;
+    assert_eq!(batch[0]["content"], "pub mod parser;\n", "{}", batch);
+    assert_eq!(batch[0]["isBinary"], false);
+    assert!(batch[1]["path"].as_str().unwrap().ends_with("missing.rs"));
+    assert!(batch[1]["error"].is_string(), "{}", batch);
+    assert!(batch[1]["content"].is_null(), "{}", batch);
+    assert_eq!(batch[2]["content"], "pub fn parse() {}\n", "{}", batch);
+
+    let batch = client
+        .call_tool("readFiles", json!({ "glob": "src/*.rs" }))
+        .await;
+    let paths: Vec<&str> = batch
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths.len(), 2, "{}", batch);
+    assert!(paths[0].ends_with("src/lib.rs"), "{:?}", paths);
+    assert!(paths[1].ends_with("src/parser.rs"), "{:?}", paths);
+    assert_eq!(batch[1]["content"], "pub fn parse() {}\n");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn unix_socket_clients_can_initialize() {
+    let server = TestServer::start_on_socket().await;
+    assert_eq!(server.lock_file["transport"], "unix");
+    let socket = server.lock_file["socketPath"].as_str().unwrap();
+    assert!(std::path::Path::new(socket).exists(), "{}", socket);
+
+    let mut client = server.connect().await;
+    let initialize = client.initialize().await;
+    assert_eq!(initialize["result"]["protocolVersion"], "2025-03-26");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn socket_path_naming_a_regular_file_is_left_alone() {
+    let home = std::env::temp_dir().join(format!(
+        "claude-code-server-socket-{}",
+        uuid::Uuid::new_v4()
+    ));
+    fs::create_dir_all(&home).unwrap();
+    let file = home.join("notes.txt");
+    fs::write(&file, "keep me\n").unwrap();
+    let run = tokio::process::Command::new(env!("CARGO_BIN_EXE_claude-code-server"))
+        .arg("websocket")
+        .arg("--socket")
+        .arg(&file)
+        .current_dir(&home)
+        .env("HOME", &home)
+        .env("USERPROFILE", &home)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(Duration::from_secs(10), run)
+        .await
+        .expect("server did not refuse the socket path")
+        .unwrap();
+    let kept = fs::read_to_string(&file);
+    fs::remove_dir_all(&home).ok();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("exists and is not a socket"), "{}", stderr);
+    assert_eq!(kept.unwrap(), "keep me\n");
+}
+
+#[tokio::test]
+async fn subscribed_resources_report_updates() {
+    let server = TestServer::start().await;
+    let path = server.workspace().join("watched.txt");
+    fs::write(&path, "before\n").unwrap();
+    let uri = format!("file://{}", path.canonicalize().unwrap().display());
+    let mut client = server.connect().await;
+    let initialize = client.initialize().await;
+    assert_eq!(
+        initialize["result"]["capabilities"]["resources"]["subscribe"],
+        true
+    );
+
+    let response = client
+        .request("resources/subscribe", json!({ "uri": uri }))
+        .await;
+    assert_eq!(response["result"], json!({}), "{}", response);
+
+    fs::write(&path, "after the change\n").unwrap();
+    let updated = client.notification("notifications/resources/updated").await;
+    assert_eq!(updated["params"]["uri"], uri.as_str());
+
+    let response = client
+        .request("resources/unsubscribe", json!({ "uri": uri }))
+        .await;
+    assert_eq!(response["result"], json!({}), "{}", response);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn replace_in_files_edits_every_file() {
+    let server = TestServer::start().await;
+    fs::write(server.workspace().join("a.rs"), "fn old_name() {}\n").unwrap();
+    fs::write(
+        server.workspace().join("b.rs"),
+        "fn main() {\n    old_name();\n}\n",
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let rename = |line, character| {
+        json!({
+            "range": {
+                "start": { "line": line, "character": character },
+                "end": { "line": line, "character": character + 8 }
+            },
+            "newText": "new_name"
+        })
+    };
+    let result = client
+        .call_tool(
+            "replaceInFiles",
+            json!({ "files": [
+                { "path": "a.rs", "edits": [rename(0, 3)] },
+                { "path": "b.rs", "edits": [rename(1, 4)] }
+            ] }),
+        )
+        .await;
+    assert_eq!(result["success"], true, "{}", result);
+    assert_eq!(result["files"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        fs::read_to_string(server.workspace().join("a.rs")).unwrap(),
+        "fn new_name() {}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(server.workspace().join("b.rs")).unwrap(),
+        "fn main() {\n    new_name();\n}\n"
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn replace_in_files_restores_every_file_when_a_write_fails() {
+    let server = TestServer::start().await;
+    let first = server.workspace().join("first.txt");
+    let second = server.workspace().join("second.txt");
+    fs::write(&first, "first\n").unwrap();
+    fs::write(&second, "second\n").unwrap();
+    // Writes go through a temporary sibling; a directory in its place makes the second write fail
+    let pid = server.lock_file["pid"].as_u64().unwrap();
+    fs::create_dir(server.workspace().join(format!(".second.txt.{}.tmp", pid))).unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let replace_first_line = json!([{
+        "range": {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 0, "character": 5 }
+        },
+        "newText": "changed"
+    }]);
+    let response = client
+        .request(
+            "tools/call",
+            json!({
+                "name": "replaceInFiles",
+                "arguments": { "files": [
+                    { "path": "first.txt", "edits": replace_first_line },
+                    { "path": "second.txt", "edits": replace_first_line }
+                ] }
+            }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32045, "{}", response);
+    assert_eq!(response["error"]["data"]["reason"], "rolled_back");
+    assert_eq!(
+        response["error"]["data"]["restored"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(fs::read_to_string(&first).unwrap(), "first\n");
+    assert_eq!(fs::read_to_string(&second).unwrap(), "second\n");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn rename_symbol_updates_every_file() {
+    let server = TestServer::start().await;
+    let lib = server.workspace().join("lib.rs");
+    let main = server.workspace().join("main.rs");
+    fs::write(&lib, "pub fn old_name() {}\n").unwrap();
+    fs::write(&main, "fn main() {\n    old_name();\n    old_name();\n}\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let rename = |new_name: &str| {
+        json!({
+            "name": "renameSymbol",
+            "arguments": { "path": "lib.rs", "line": 0, "character": 8, "newName": new_name }
+        })
+    };
+    let response = client.request("tools/call", rename("fn")).await;
+    assert_eq!(response["error"]["code"], -32602, "{}", response);
+    assert_eq!(response["error"]["data"]["reason"], "invalid_identifier");
+
+    let result = client
+        .call_tool("renameSymbol", rename("new_name")["arguments"].clone())
+        .await;
+    assert_eq!(result["success"], true, "{}", result);
+    assert_eq!(result["symbol"], "old_name");
+    assert_eq!(result["editCount"], 3);
+    assert_eq!(result["files"].as_array().unwrap().len(), 2);
+    assert_eq!(fs::read_to_string(&lib).unwrap(), "pub fn new_name() {}\n");
+    assert_eq!(
+        fs::read_to_string(&main).unwrap(),
+        "fn main() {\n    new_name();\n    new_name();\n}\n"
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn find_callers_lists_call_sites_but_not_the_definition() {
+    let server = TestServer::start().await;
+    fs::write(
+        server.workspace().join("lib.rs"),
+        "pub fn tally(count: u32) -> u32 {\n    count + 1\n}\n\npub fn tally_twice() -> u32 {\n    tally(tally(0))\n}\n",
+    )
+    .unwrap();
+    fs::write(
+        server.workspace().join("main.rs"),
+        "fn main() {\n    let tally = 1;\n    println!(\"{}\", tally (tally));\n}\n",
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let result = client
+        .call_tool("findCallers", json!({ "functionName": "tally" }))
+        .await;
+    assert_eq!(result["success"], true, "{}", result);
+    let callers: Vec<(&str, u64)> = result["callers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|caller| {
+            let path = caller["path"].as_str().unwrap();
+            (
+                path.rsplit('/').next().unwrap(),
+                caller["line"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    // Both calls on lib.rs line 5, and the call but not the variables in main.rs
+    assert_eq!(callers, [("lib.rs", 5), ("lib.rs", 5), ("main.rs", 2)]);
+    assert_eq!(
+        result["callers"][2]["lineText"],
+        "    println!(\"{}\", tally (tally));"
+    );
+
+    // A position names the function as well
+    let by_position = client
+        .call_tool(
+            "findCallers",
+            json!({ "path": "lib.rs", "line": 0, "character": 8 }),
+        )
+        .await;
+    assert_eq!(by_position["functionName"], "tally");
+    assert_eq!(by_position["count"], 3);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn list_workspace_files_filters_by_glob_and_skips_ignored_files() {
+    let server = TestServer::start_with_options(&["--path-style", "relative"]).await;
+    let workspace = server.workspace();
+    fs::create_dir_all(workspace.join("src/nested")).unwrap();
+    fs::create_dir_all(workspace.join("target/debug")).unwrap();
+    fs::write(workspace.join(".gitignore"), "generated.rs\n").unwrap();
+    for file in [
+        "src/lib.rs",
+        "src/nested/mod.rs",
+        "src/generated.rs",
+        "src/notes.md",
+        "target/debug/build.rs",
+    ] {
+        fs::write(workspace.join(file), "").unwrap();
+    }
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let result = client
+        .call_tool("listWorkspaceFiles", json!({ "glob": "*.rs" }))
+        .await;
+    assert_eq!(result["success"], true, "{}", result);
+    assert_eq!(result["files"], json!(["src/lib.rs", "src/nested/mod.rs"]));
+    assert_eq!(result["total"], 2);
+    assert!(result.get("nextCursor").is_none(), "{}", result);
+
+    // Patterns with a slash match the whole relative path, one component per `*`
+    let result = client
+        .call_tool("listWorkspaceFiles", json!({ "glob": "src/*" }))
+        .await;
+    assert_eq!(result["files"], json!(["src/lib.rs", "src/notes.md"]));
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn ignore_files_take_negated_patterns_back_in() {
+    let server = TestServer::start_with_options(&["--path-style", "relative"]).await;
+    let workspace = server.workspace();
+    fs::write(
+        workspace.join(".gitignore"),
+        "*.log\n!keep.log\nbuild-[0-9]/\n",
+    )
+    .unwrap();
+    // Read after .gitignore, so it overrides it
+    fs::write(workspace.join(".claudeignore"), "!debug.log\n").unwrap();
+    for dir in ["build-1", "build-x", "src"] {
+        fs::create_dir_all(workspace.join(dir)).unwrap();
+    }
+    for file in [
+        "app.log",
+        "keep.log",
+        "debug.log",
+        "build-1/out.rs",
+        "build-x/out.rs",
+        "src/lib.rs",
+    ] {
+        fs::write(workspace.join(file), "").unwrap();
+    }
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let result = client.call_tool("listWorkspaceFiles", json!({})).await;
+    assert_eq!(
+        result["files"],
+        json!(["build-x/out.rs", "debug.log", "keep.log", "src/lib.rs"]),
+        "{}",
+        result
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn find_references_returns_every_use_with_its_line_text() {
+    let server = TestServer::start_with_options(&["--path-style", "relative"]).await;
+    let workspace = server.workspace();
+    fs::write(workspace.join("lib.rs"), "pub fn helper() {}\n").unwrap();
+    fs::write(
+        workspace.join("main.rs"),
+        "fn main() {\n    helper();\n    let f = helper;\n}\n",
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let found = client
+        .call_tool(
+            "findReferences",
+            json!({ "path": "main.rs", "line": 1, "character": 6 }),
+        )
+        .await;
+    assert_eq!(found["symbol"], "helper", "{}", found);
+    let mut references: Vec<(String, u64, u64, String)> = found["references"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|reference| {
+            (
+                reference["path"].as_str().unwrap().to_string(),
+                reference["line"].as_u64().unwrap(),
+                reference["character"].as_u64().unwrap(),
+                reference["lineText"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    references.sort();
+    let reference = |path: &str, line, character, text: &str| {
+        (path.to_string(), line, character, text.to_string())
+    };
+    assert_eq!(
+        references,
+        [
+            reference("lib.rs", 0, 7, "pub fn helper() {}"),
+            reference("main.rs", 1, 4, "    helper();"),
+            reference("main.rs", 2, 12, "    let f = helper;"),
+        ]
+    );
+
+    let uses = client
+        .call_tool(
+            "findReferences",
+            json!({ "path": "main.rs", "line": 1, "character": 6, "excludeDefinition": true }),
+        )
+        .await;
+    assert_eq!(uses["count"], 2, "{}", uses);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn definition_context_returns_the_definition_and_surrounding_lines() {
+    let server = TestServer::start_with_options(&["--path-style", "relative"]).await;
+    let workspace = server.workspace();
+    fs::write(
+        workspace.join("util.rs"),
+        "use std::fmt;\n\n/// Doubles its input\nfn helper(x: i32) -> i32 {\n    x * 2\n}\n\nfn unrelated() {}\n",
+    )
+    .unwrap();
+    fs::write(
+        workspace.join("main.rs"),
+        "fn main() {\n    let y = helper(21);\n}\n",
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let result = client
+        .call_tool(
+            "getDefinitionContext",
+            json!({ "path": "main.rs", "line": 1, "character": 13, "contextLines": 1 }),
+        )
+        .await;
+    assert_eq!(result["success"], true, "{}", result);
+    assert_eq!(result["symbol"], "helper");
+    assert_eq!(result["definition"]["path"], "util.rs");
+    assert_eq!(result["definition"]["range"]["start"]["line"], 3);
+    assert_eq!(result["context"]["startLine"], 2);
+    assert_eq!(result["context"]["endLine"], 4);
+    assert_eq!(
+        result["context"]["lines"],
+        json!([
+            { "line": 2, "text": "/// Doubles its input" },
+            { "line": 3, "text": "fn helper(x: i32) -> i32 {" },
+            { "line": 4, "text": "    x * 2" }
+        ])
+    );
+
+    // Without contextLines, ten lines either side are returned, cut at the file's end
+    let result = client
+        .call_tool(
+            "getDefinitionContext",
+            json!({ "path": "main.rs", "line": 1, "character": 13 }),
+        )
+        .await;
+    assert_eq!(result["context"]["startLine"], 0, "{}", result);
+    assert_eq!(result["context"]["endLine"], 7);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn path_style_controls_how_workspace_paths_are_written() {
+    let absolute = TestServer::start().await;
+    let relative = TestServer::start_with_options(&["--path-style", "relative"]).await;
+    for server in [&absolute, &relative] {
+        fs::create_dir_all(server.workspace().join("src")).unwrap();
+        fs::write(server.workspace().join("src/lib.rs"), "").unwrap();
+    }
+
+    let mut client = absolute.connect().await;
+    client.initialize().await;
+    let result = client
+        .call_tool("listWorkspaceFiles", json!({ "glob": "lib.rs" }))
+        .await;
+    let full_path = absolute.workspace().join("src/lib.rs");
+    assert_eq!(result["files"], json!([full_path]), "{}", result);
+    client.close().await;
+
+    let mut client = relative.connect().await;
+    client.initialize().await;
+    let result = client
+        .call_tool("listWorkspaceFiles", json!({ "glob": "lib.rs" }))
+        .await;
+    assert_eq!(result["files"], json!(["src/lib.rs"]), "{}", result);
+
+    // Selections arrive with absolute paths and are reported in the same style
+    let file_path = relative.workspace().join("src/lib.rs");
+    client
+        .notify(
+            "selection_changed",
+            json!({
+                "text": "",
+                "filePath": file_path,
+                "fileUrl": format!("file://{}", file_path.display()),
+                "selection": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 0 },
+                    "isEmpty": true
+                }
+            }),
+        )
+        .await;
+    let selection = client.call_tool("getLatestSelection", json!({})).await;
+    assert_eq!(selection["filePath"], "src/lib.rs", "{}", selection);
+
+    client.close().await;
+    absolute.shutdown().await;
+    relative.shutdown().await;
+}
+
+#[tokio::test]
+async fn list_workspace_files_pages_through_results_with_a_cursor() {
+    let server = TestServer::start_with_options(&["--path-style", "relative"]).await;
+    let workspace = server.workspace();
+    fs::create_dir_all(workspace.join("pages")).unwrap();
+    for index in 0..5 {
+        fs::write(workspace.join(format!("pages/file{}.txt", index)), "").unwrap();
+    }
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let mut listed = Vec::new();
+    let mut cursor = None;
+    let mut pages = 0;
+    loop {
+        let mut arguments = json!({ "glob": "pages/*.txt", "limit": 2 });
+        if let Some(cursor) = cursor.take() {
+            arguments["cursor"] = cursor;
+        }
+        let result = client.call_tool("listWorkspaceFiles", arguments).await;
+        assert_eq!(result["total"], 5, "{}", result);
+        let files = result["files"].as_array().unwrap();
+        assert!(files.len() <= 2, "{}", result);
+        listed.extend(files.iter().map(|file| file.as_str().unwrap().to_string()));
+        pages += 1;
+        match result.get("nextCursor") {
+            Some(next) => cursor = Some(next.clone()),
+            None => break,
+        }
+    }
+    assert_eq!(pages, 3);
+    let expected: Vec<String> = (0..5)
+        .map(|index| format!("pages/file{}.txt", index))
+        .collect();
+    assert_eq!(listed, expected);
+
+    let response = client
+        .request(
+            "tools/call",
+            json!({
+                "name": "listWorkspaceFiles",
+                "arguments": { "cursor": "not-a-cursor" }
+            }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32602, "{}", response);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn rename_symbol_restores_every_file_when_a_write_fails() {
+    let server = TestServer::start().await;
+    let lib = server.workspace().join("lib.rs");
+    let main = server.workspace().join("main.rs");
+    fs::write(&lib, "pub fn old_name() {}\n").unwrap();
+    fs::write(&main, "fn main() {\n    old_name();\n}\n").unwrap();
+    // Files are written in path order, so main.rs is written after lib.rs and fails
+    let pid = server.lock_file["pid"].as_u64().unwrap();
+    fs::create_dir(server.workspace().join(format!(".main.rs.{}.tmp", pid))).unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client
+        .request(
+            "tools/call",
+            json!({
+                "name": "renameSymbol",
+                "arguments": { "path": "lib.rs", "line": 0, "character": 8, "newName": "new_name" }
+            }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32045, "{}", response);
+    assert_eq!(response["error"]["data"]["reason"], "rolled_back");
+    assert_eq!(fs::read_to_string(&lib).unwrap(), "pub fn old_name() {}\n");
+    assert_eq!(
+        fs::read_to_string(&main).unwrap(),
+        "fn main() {\n    old_name();\n}\n"
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn handshake_logging_redacts_the_auth_token() {
+    let server = TestServer::start_with_options(&["--debug"]).await;
+    let client = server.connect().await;
+    client.close().await;
+    let auth_token = server.lock_file["authToken"].as_str().unwrap();
+
+    let log = server.log();
+    assert!(log.contains("Handshake request from"), "{}", log);
+    assert!(
+        log.contains("x-claude-code-ide-authorization: [redacted]"),
+        "{}",
+        log
+    );
+    assert!(log.contains("sec-websocket-key: [redacted]"), "{}", log);
+    assert!(!log.contains(auth_token), "{}", log);
+    server.shutdown().await;
+
+    let quiet = TestServer::start_with_options(&["--debug", "--no-handshake-logging"]).await;
+    quiet.connect().await.close().await;
+    let log = quiet.log();
+    assert!(!log.contains("Handshake request from"), "{}", log);
+    quiet.shutdown().await;
+}
+
+#[tokio::test]
+async fn fragmented_requests_are_reassembled() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client
+        .send_fragmented(&[
+            r#"{"jsonrpc": "2.0", "id": 7,"#,
+            r#" "method": "tools/list","#,
+            r#" "params": {}}"#,
+        ])
+        .await;
+    assert_eq!(response["id"], 7, "{}", response);
+    assert!(response["result"]["tools"].is_array(), "{}", response);
+
+    // The connection is still usable afterwards
+    let response = client.request("tools/list", json!({})).await;
+    assert!(response["result"]["tools"].is_array(), "{}", response);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn short_ping_timeouts_close_silent_connections_sooner() {
+    let eager =
+        TestServer::start_with_options(&["--ping-interval-secs", "1", "--ping-timeout-secs", "2"])
+            .await;
+    let default = TestServer::start().await;
+    let mut eager_client = eager.connect().await;
+    let mut default_client = default.connect().await;
+    eager_client.initialize().await;
+    default_client.initialize().await;
+
+    // Neither client reads its pings meanwhile, so neither answers them
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    assert!(eager_client.closed_within(Duration::from_secs(3)).await);
+    let response = default_client.request("tools/list", json!({})).await;
+    assert!(response["result"]["tools"].is_array(), "{}", response);
+
+    default_client.close().await;
+    eager.shutdown().await;
+    default.shutdown().await;
+}
+
+#[tokio::test]
+async fn many_busy_connections_survive_concurrent_keepalive_ticks() {
+    let server =
+        TestServer::start_with_options(&["--ping-interval-secs", "1", "--ping-timeout-secs", "5"])
+            .await;
+    let mut clients = Vec::new();
+    for _ in 0..24 {
+        let mut client = server.connect().await;
+        client.initialize().await;
+        clients.push(client);
+    }
+
+    // Every client keeps requesting across several keepalive ticks; a deadlock
+    // between the ticks and request handling leaves a request unanswered
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+    let busy = clients.iter_mut().map(|client| async move {
+        while tokio::time::Instant::now() < deadline {
+            let response = client.request("tools/list", json!({})).await;
+            assert!(response["result"]["tools"].is_array(), "{}", response);
+        }
+    });
+    futures_util::future::join_all(busy).await;
+
+    let mut late = server.connect().await;
+    late.initialize().await;
+    late.close().await;
+    for client in clients {
+        client.close().await;
+    }
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn clipboard_methods_need_a_connected_editor() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let written = client
+        .request("set_clipboard", json!({ "text": "copied" }))
+        .await;
+    assert_eq!(written["error"]["code"], -32042, "{}", written);
+    let read = client.request("get_clipboard", json!({})).await;
+    assert_eq!(read["error"]["code"], -32042, "{}", read);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn answered_pings_record_the_round_trip_time() {
+    let server =
+        TestServer::start_with_options(&["--ping-interval-secs", "1", "--ping-timeout-secs", "5"])
+            .await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let rtt = |status: &Value| {
+        let connections = status["result"]["connections"].as_array().unwrap();
+        assert_eq!(connections.len(), 1, "{}", status);
+        assert_eq!(connections[0]["current"], true);
+        connections[0]["rttMs"].clone()
+    };
+
+    // Reading lets the client answer the pings that arrived meanwhile
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let measured = loop {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let status = client.request("get_status", json!({})).await;
+        if let Some(measured) = rtt(&status).as_f64() {
+            break measured;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "{}", status);
+    };
+    assert!((0.0..1000.0).contains(&measured), "{}", measured);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn connection_traffic_counts_messages_and_bytes() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let status_request =
+        json!({ "jsonrpc": "2.0", "id": "status", "method": "get_status" }).to_string();
+    let traffic = |reply: &str| {
+        let status: Value = serde_json::from_str(reply).unwrap();
+        let connection = status["result"]["connections"][0].clone();
+        let count = |direction: &str, field: &str| connection[direction][field].as_u64().unwrap();
+        (
+            count("received", "messages"),
+            count("received", "bytes"),
+            count("sent", "messages"),
+            count("sent", "bytes"),
+        )
+    };
+
+    let first_status = client.send_raw_text(&status_request).await;
+    let before = traffic(&first_status);
+    let echo_request = json!({
+        "jsonrpc": "2.0",
+        "id": "echo",
+        "method": "tools/call",
+        "params": { "name": "echo", "arguments": { "text": "x".repeat(4096) } }
+    })
+    .to_string();
+    let echo_reply = client.send_raw_text(&echo_request).await;
+    let after = traffic(&client.send_raw_text(&status_request).await);
+
+    // Requests count on arrival but replies only once sent, so a status reply omits itself
+    assert_eq!(after.0, before.0 + 2);
+    assert_eq!(
+        after.1,
+        before.1 + (echo_request.len() + status_request.len()) as u64
+    );
+    assert_eq!(after.2, before.2 + 2);
+    assert_eq!(
+        after.3,
+        before.3 + (first_status.len() + echo_reply.len()) as u64
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn removed_workspaces_are_reported_instead_of_failing_with_os_errors() {
+    let server = TestServer::start().await;
+    fs::write(server.workspace().join("main.rs"), "fn main() {}\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let listed = client.call_tool("listWorkspaceFiles", json!({})).await;
+    assert!(listed.to_string().contains("main.rs"), "{}", listed);
+
+    fs::remove_dir_all(server.workspace()).unwrap();
+    let removed = client.notification("workspace_removed").await;
+    let path = removed["params"]["path"].as_str().unwrap();
+    assert!(path.ends_with("workspace"), "{}", removed);
+
+    let response = client
+        .request(
+            "tools/call",
+            json!({ "name": "listWorkspaceFiles", "arguments": {} }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32043, "{}", response);
+    assert_eq!(
+        response["error"]["data"]["reason"], "workspace_unavailable",
+        "{}",
+        response
+    );
+    assert_eq!(response["error"]["data"]["path"], path);
+
+    // Tools that don't touch the workspace keep working
+    let folders = client.call_tool("getWorkspaceFolders", json!({})).await;
+    assert_eq!(folders["folders"][0]["exists"], false, "{}", folders);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn open_file_answers_with_one_shape_either_way() {
+    let server = TestServer::start().await;
+    fs::write(server.workspace().join("main.rs"), "fn main() {}\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let frontmost = client
+        .call_tool(
+            "openFile",
+            json!({ "filePath": "main.rs", "makeFrontmost": true }),
+        )
+        .await;
+    let background = client
+        .call_tool(
+            "openFile",
+            json!({ "filePath": "main.rs", "makeFrontmost": false }),
+        )
+        .await;
+    let keys = |response: &serde_json::Value| {
+        let mut keys: Vec<String> = response.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    };
+    assert_eq!(keys(&frontmost), keys(&background));
+    assert_eq!(frontmost["makeFrontmost"], true);
+    assert_eq!(background["makeFrontmost"], false);
+    assert_eq!(frontmost["lineCount"], 1);
+    assert_eq!(frontmost["languageId"], "rust");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+/// Run git in `dir` as a throwaway identity, panicking if it fails
+fn git(dir: &std::path::Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args(["-c", "user.name=e2e", "-c", "user.email=e2e@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+/// Turn `dir` into a repository with `files` committed
+fn commit_files(dir: &std::path::Path, files: &[(&str, &str)]) {
+    git(dir, &["init", "--quiet"]);
+    for (name, content) in files {
+        fs::write(dir.join(name), content).unwrap();
+    }
+    git(dir, &["add", "--all"]);
+    git(dir, &["commit", "--quiet", "--message", "initial"]);
+}
+
+#[tokio::test]
+async fn git_diff_shows_unstaged_changes() {
+    let server = TestServer::start().await;
+    commit_files(
+        server.workspace(),
+        &[("lib.rs", "fn old() {}\n"), ("other.rs", "fn other() {}\n")],
+    );
+    fs::write(server.workspace().join("lib.rs"), "fn new() {}\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let diff = client.call_tool("getGitDiff", json!({})).await;
+    assert_eq!(diff["success"], true, "{}", diff);
+    assert_eq!(diff["hasChanges"], true);
+    assert_eq!(
+        diff["files"],
+        json!([{ "path": "lib.rs", "additions": 1, "deletions": 1 }])
+    );
+    let unified = diff["diff"].as_str().unwrap();
+    assert!(unified.contains("-fn old() {}"), "{}", unified);
+    assert!(unified.contains("+fn new() {}"), "{}", unified);
+
+    let staged = client
+        .call_tool("getGitDiff", json!({ "staged": true }))
+        .await;
+    assert_eq!(staged["hasChanges"], false, "{}", staged);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn file_history_lists_commits_newest_first_across_renames() {
+    let server = TestServer::start().await;
+    let workspace = server.workspace();
+    commit_files(workspace, &[("lib.rs", "fn first() {}\n")]);
+    fs::write(workspace.join("lib.rs"), "fn second() {}\n").unwrap();
+    git(
+        workspace,
+        &["commit", "--quiet", "--all", "--message", "second"],
+    );
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let history = client
+        .call_tool("getFileHistory", json!({ "path": "lib.rs" }))
+        .await;
+    assert_eq!(history["success"], true, "{}", history);
+    assert_eq!(history["tracked"], true);
+    let messages = |history: &Value| -> Vec<String> {
+        history["commits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|commit| commit["message"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(messages(&history), ["second", "initial"], "{}", history);
+    assert_eq!(history["commits"][0]["author"], "e2e");
+    assert!(history["commits"][0]["commit"].as_str().unwrap().len() >= 40);
+
+    git(workspace, &["mv", "lib.rs", "core.rs"]);
+    git(workspace, &["commit", "--quiet", "--message", "rename"]);
+    let renamed = client
+        .call_tool("getFileHistory", json!({ "path": "core.rs", "limit": 2 }))
+        .await;
+    assert_eq!(messages(&renamed), ["rename", "second"], "{}", renamed);
+
+    fs::write(workspace.join("scratch.rs"), "fn scratch() {}\n").unwrap();
+    let untracked = client
+        .call_tool("getFileHistory", json!({ "path": "scratch.rs" }))
+        .await;
+    assert_eq!(untracked["tracked"], false, "{}", untracked);
+    assert_eq!(untracked["commits"], json!([]));
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn diff_two_files_compares_arbitrary_files() {
+    let server = TestServer::start().await;
+    fs::write(server.workspace().join("left.txt"), "one\ntwo\nthree\n").unwrap();
+    fs::write(server.workspace().join("right.txt"), "one\n2\nthree\n").unwrap();
+    fs::write(server.workspace().join("same.txt"), "one\ntwo\nthree\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let differing = client
+        .call_tool(
+            "diffTwoFiles",
+            json!({ "leftPath": "left.txt", "rightPath": "right.txt" }),
+        )
+        .await;
+    assert_eq!(differing["identical"], false, "{}", differing);
+    let diff = differing["diff"].as_str().unwrap();
+    assert!(
+        diff.ends_with("@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n"),
+        "{}",
+        diff
+    );
+
+    let identical = client
+        .call_tool(
+            "diffTwoFiles",
+            json!({ "leftPath": "left.txt", "rightPath": "same.txt" }),
+        )
+        .await;
+    assert_eq!(identical["identical"], true, "{}", identical);
+    assert_eq!(identical["diff"], "");
+
+    let missing = client
+        .call_tool(
+            "diffTwoFiles",
+            json!({ "leftPath": "left.txt", "rightPath": "gone.txt" }),
+        )
+        .await;
+    assert_eq!(missing["rightExists"], false, "{}", missing);
+    let diff = missing["diff"].as_str().unwrap();
+    assert!(
+        diff.ends_with("+++ /dev/null\n@@ -1,3 +0,0 @@\n-one\n-two\n-three\n"),
+        "{}",
+        diff
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn git_diff_of_a_clean_repository_is_empty() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let outside = client.call_tool("getGitDiff", json!({})).await;
+    assert_eq!(outside["success"], false, "{}", outside);
+
+    commit_files(server.workspace(), &[("lib.rs", "fn main() {}\n")]);
+    let diff = client
+        .call_tool("getGitDiff", json!({ "path": "lib.rs" }))
+        .await;
+    assert_eq!(diff["success"], true, "{}", diff);
+    assert_eq!(diff["hasChanges"], false);
+    assert_eq!(diff["files"], json!([]));
+    assert_eq!(diff["diff"], "");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn frames_with_control_characters_are_rejected() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+
+    let rejected = client
+        .send_raw("{\"jsonrpc\":\"2.0\",\"id\":5,\"method\":\"tools/list\u{0}\"}")
+        .await;
+    assert_eq!(rejected["error"]["code"], -32700, "{}", rejected);
+    let details = rejected["error"]["data"]["details"].as_str().unwrap();
+    assert!(details.contains("U+0000"), "{}", details);
+
+    // The connection survives the bad frame
+    assert_eq!(
+        client.initialize().await["result"]["protocolVersion"],
+        "2025-03-26"
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn deeply_nested_params_are_rejected() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let depth = 100_000;
+    let nested = format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+    let rejected = client
+        .send_raw(&format!(
+            r#"{{"jsonrpc":"2.0","id":6,"method":"tools/list","params":{}}}"#,
+            nested
+        ))
+        .await;
+    assert_eq!(rejected["error"]["code"], -32700, "{}", rejected);
+    let details = rejected["error"]["data"]["details"].as_str().unwrap();
+    assert!(details.contains("Nesting exceeds"), "{}", details);
+
+    // Brackets inside strings don't count towards the depth
+    let brackets = "[".repeat(depth);
+    let response = client
+        .request("tools/list", json!({ "note": brackets }))
+        .await;
+    assert!(response["result"]["tools"].is_array(), "{}", response);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn save_document_writes_text() {
+    let server = TestServer::start().await;
+    let path = server.workspace().join("notes.md");
+    fs::write(&path, "draft\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let saved = client
+        .call_tool(
+            "saveDocument",
+            json!({ "filePath": "notes.md", "content": "final � version\n" }),
+        )
+        .await;
+    assert_eq!(saved["saved"], true, "{}", saved);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "final � version\n");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn save_document_refuses_decoding_damage_to_binary_files() {
+    let server = TestServer::start().await;
+    let path = server.workspace().join("logo.png");
+    let original = [0x89, b'P', b'N', b'G', 0, 0xff, 0xfe];
+    fs::write(&path, original).unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    // What a lossy UTF-8 decode of the file above looks like
+    let damaged = String::from_utf8_lossy(&original).into_owned();
+    let response = client
+        .request(
+            "tools/call",
+            json!({
+                "name": "saveDocument",
+                "arguments": { "filePath": "logo.png", "content": damaged }
+            }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32046, "{}", response);
+    assert_eq!(
+        response["error"]["data"]["reason"],
+        "content_corruption_risk"
+    );
+    assert_eq!(fs::read(&path).unwrap(), original);
+
+    let forced = client
+        .call_tool(
+            "saveDocument",
+            json!({ "filePath": "logo.png", "content": damaged, "force": true }),
+        )
+        .await;
+    assert_eq!(forced["saved"], true, "{}", forced);
+    assert_eq!(fs::read_to_string(&path).unwrap(), damaged);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn listed_tools_follow_the_project_type() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let listed = |tools: &serde_json::Value, name: &str| {
+        tools["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|tool| tool["name"] == name)
+            .cloned()
+    };
+
+    let bare = client.request("tools/list", json!({})).await;
+    assert!(listed(&bare, "runTests").is_none(), "{}", bare);
+    assert!(listed(&bare, "getGitDiff").is_none(), "{}", bare);
+
+    fs::write(
+        server.workspace().join("Cargo.toml"),
+        "[package]\nname = \"listed\"\n",
+    )
+    .unwrap();
+    fs::create_dir(server.workspace().join(".git")).unwrap();
+    let cargo = client.request("tools/list", json!({})).await;
+    let run_tests = listed(&cargo, "runTests").expect("runTests is listed");
+    assert!(
+        run_tests["description"].as_str().unwrap().contains("cargo"),
+        "{}",
+        run_tests
+    );
+    assert!(listed(&cargo, "getGitDiff").is_some(), "{}", cargo);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn unchanged_files_are_read_from_the_cache() {
+    let server = TestServer::start().await;
+    let path = server.workspace().join("large.txt");
+    fs::write(&path, "first version\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let read = json!({ "filePath": "large.txt" });
+
+    let first = client.call_tool("getFileContents", read.clone()).await;
+    assert_eq!(first["cached"], false, "{}", first);
+    let second = client.call_tool("getFileContents", read.clone()).await;
+    assert_eq!(second["cached"], true, "{}", second);
+    assert_eq!(second["content"], "first version\n");
+    assert_eq!(second["hash"], first["hash"]);
+
+    fs::write(&path, "second, longer version\n").unwrap();
+    let changed = client.call_tool("getFileContents", read).await;
+    assert_eq!(changed["cached"], false, "{}", changed);
+    assert_eq!(changed["content"], "second, longer version\n");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn spellings_of_the_same_path_share_a_cache_entry() {
+    let server = TestServer::start().await;
+    let workspace = server.workspace().canonicalize().unwrap();
+    fs::write(workspace.join("a.txt"), "contents\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let spellings = [
+        format!("file://{}/./a.txt", workspace.display()),
+        format!("{}/a.txt/", workspace.display()),
+        format!("{}/a.txt", workspace.display()),
+    ];
+    let first = client
+        .call_tool("getFileContents", json!({ "filePath": spellings[0] }))
+        .await;
+    assert_eq!(first["cached"], false, "{}", first);
+    for spelling in &spellings[1..] {
+        let read = client
+            .call_tool("getFileContents", json!({ "filePath": spelling }))
+            .await;
+        assert_eq!(read["cached"], true, "{}: {}", spelling, read);
+        assert_eq!(read["content"], "contents\n");
+    }
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn format_on_edit_formats_written_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Stand-ins for rustfmt, which squeezes runs of spaces, and black, which fails
+    let bin = std::env::temp_dir().join(format!(
+        "claude-code-server-formatters-{}",
+        uuid::Uuid::new_v4()
+    ));
+    fs::create_dir_all(&bin).unwrap();
+    for (name, script) in [
+        ("rustfmt", "#!/bin/sh\nexec tr -s ' '\n"),
+        (
+            "black",
+            "#!/bin/sh\ncat >/dev/null\necho 'cannot parse' >&2\nexit 1\n",
+        ),
+    ] {
+        let path = bin.join(name);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let server =
+        TestServer::start_with_config_and_env(&json!({ "formatOnEdit": true }), &[("PATH", path)])
+            .await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let saved = client
+        .call_tool(
+            "saveDocument",
+            json!({ "filePath": "main.rs", "content": "fn   main()  {}\n" }),
+        )
+        .await;
+    assert_eq!(saved["formatted"], true, "{}", saved);
+    assert_eq!(saved["content"], "fn main() {}\n");
+    assert_eq!(
+        fs::read_to_string(server.workspace().join("main.rs")).unwrap(),
+        "fn main() {}\n"
+    );
+
+    // A failing formatter leaves the edit in place and says why
+    let created = client
+        .call_tool(
+            "createFile",
+            json!({ "path": "script.py", "content": "x  =  1\n" }),
+        )
+        .await;
+    assert_eq!(created["success"], true, "{}", created);
+    assert_eq!(created["formatted"], false, "{}", created);
+    assert!(
+        created["warning"]
+            .as_str()
+            .unwrap()
+            .contains("cannot parse"),
+        "{}",
+        created
+    );
+    assert_eq!(
+        fs::read_to_string(server.workspace().join("script.py")).unwrap(),
+        "x  =  1\n"
+    );
+
+    // Languages without a formatter are written as given
+    let plain = client
+        .call_tool(
+            "saveDocument",
+            json!({ "filePath": "notes.txt", "content": "a   b\n" }),
+        )
+        .await;
+    assert!(plain.get("formatted").is_none(), "{}", plain);
+
+    client.close().await;
+    server.shutdown().await;
+    fs::remove_dir_all(&bin).ok();
+}
+
+#[tokio::test]
+async fn save_document_refuses_paths_outside_the_workspace() {
+    let outside = std::env::temp_dir().join(format!(
+        "claude-code-server-allowed-{}",
+        uuid::Uuid::new_v4()
+    ));
+    fs::create_dir_all(&outside).unwrap();
+    let allowed = outside.to_str().unwrap();
+    let server = TestServer::start_with_options(&["--allow-paths", allowed]).await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let save = |file_path: &str| {
+        json!({
+            "name": "saveDocument",
+            "arguments": { "filePath": file_path, "content": "written\n" }
+        })
+    };
+
+    for escape in [
+        "../../etc/passwd",
+        "../escape.txt",
+        "/etc/claude-code-server-escape",
+    ] {
+        let response = client.request("tools/call", save(escape)).await;
+        assert_eq!(response["error"]["code"], -32041, "{}", response);
+        assert_eq!(
+            response["error"]["data"]["reason"], "path_outside_workspace",
+            "{}",
+            response
+        );
+    }
+    assert!(!server.workspace().join("../escape.txt").exists());
+
+    let inside = client
+        .request("tools/call", save("sub/../inside.txt"))
+        .await;
+    assert!(inside["error"].is_null(), "{}", inside);
+    assert_eq!(
+        fs::read_to_string(server.workspace().join("inside.txt")).unwrap(),
+        "written\n"
+    );
+
+    // Directories named with --allow-paths are writable even though outside the workspace
+    let target = outside.join("allowed.txt");
+    let permitted = client
+        .request("tools/call", save(target.to_str().unwrap()))
+        .await;
+    assert!(permitted["error"].is_null(), "{}", permitted);
+    assert_eq!(fs::read_to_string(&target).unwrap(), "written\n");
+
+    client.close().await;
+    server.shutdown().await;
+    fs::remove_dir_all(&outside).ok();
+}
+
+#[tokio::test]
+async fn expanded_read_paths_stay_inside_the_workspace() {
+    let server = TestServer::start().await;
+    // The harness points HOME at the directory holding the workspace
+    let home = server.workspace().parent().unwrap().to_path_buf();
+    fs::write(server.workspace().join("notes.txt"), "inside\n").unwrap();
+    fs::write(home.join("secret.txt"), "outside\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    for path in [
+        "~/workspace/notes.txt",
+        "$HOME/workspace/notes.txt",
+        "${HOME}/workspace/notes.txt",
+    ] {
+        let read = client
+            .call_tool("getFileContents", json!({ "filePath": path }))
+            .await;
+        assert_eq!(read["content"], "inside\n", "{}: {}", path, read);
+    }
+
+    for path in [
+        "~/secret.txt",
+        "$HOME/secret.txt",
+        "~/workspace/../secret.txt",
+    ] {
+        for (tool, key) in [("getFileContents", "filePath"), ("statFile", "path")] {
+            let response = client
+                .request(
+                    "tools/call",
+                    json!({ "name": tool, "arguments": { key: path } }),
+                )
+                .await;
+            assert_eq!(
+                response["error"]["code"], -32041,
+                "{} {}: {}",
+                tool, path, response
+            );
+            assert_eq!(
+                response["error"]["data"]["reason"], "path_outside_workspace",
+                "{}",
+                response
+            );
+        }
+    }
+
+    let batch = client
+        .call_tool(
+            "readFiles",
+            json!({ "paths": ["~/workspace/notes.txt", "$HOME/secret.txt"] }),
+        )
+        .await;
+    assert_eq!(batch[0]["content"], "inside\n", "{}", batch);
+    assert_eq!(batch[1]["reason"], "path_outside_workspace", "{}", batch);
+    assert!(batch[1]["content"].is_null(), "{}", batch);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn tool_failures_report_their_kind_and_code() {
+    let server = TestServer::start_with_options(&["--no-exec"]).await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let call =
+        |name: &str, arguments: serde_json::Value| json!({ "name": name, "arguments": arguments });
+
+    let cases = [
+        (
+            call("getFileContents", json!({ "filePath": "missing.txt" })),
+            -32603,
+            "io",
+        ),
+        (call("runTests", json!({})), -32041, "policy_denied"),
+        (
+            call(
+                "findReferences",
+                json!({ "path": "a.rs", "line": "one", "character": 0 }),
+            ),
+            -32602,
+            "invalid_params",
+        ),
+        (
+            call("getFileContents", json!({ "filePath": "../outside.txt" })),
+            -32041,
+            "path_outside_workspace",
+        ),
+    ];
+    for (params, code, reason) in cases {
+        let response = client.request("tools/call", params).await;
+        assert_eq!(response["error"]["code"], code, "{}", response);
+        assert_eq!(response["error"]["data"]["reason"], reason, "{}", response);
+    }
+
+    let unparseable = client.send_raw(r#"{"jsonrpc":"2.0","id":7,"#).await;
+    assert_eq!(unparseable["error"]["code"], -32700, "{}", unparseable);
+    assert_eq!(unparseable["error"]["data"]["reason"], "json");
+
+    let http_server = TestServer::start_http_with_config(&json!({})).await;
+    let unauthorized = http_server
+        .http_client()
+        .with_auth_token("not-the-token")
+        .request(None, "initialize", json!({}))
+        .await;
+    assert_eq!(unauthorized.status, 401);
+    assert_eq!(
+        unauthorized.body["error"]["code"], -32001,
+        "{}",
+        unauthorized.body
+    );
+    assert_eq!(unauthorized.body["error"]["data"]["reason"], "unauthorized");
+
+    http_server.shutdown().await;
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn run_tests_parses_cargo_results() {
+    // The server runs with a scratch HOME, so point cargo and rustup at the real ones
+    let home = std::path::PathBuf::from(std::env::var("HOME").unwrap());
+    let toolchain_env = |name: &str, default: &str| {
+        std::env::var(name).unwrap_or_else(|_| home.join(default).display().to_string())
+    };
+    let env = [
+        ("CARGO_HOME", toolchain_env("CARGO_HOME", ".cargo")),
+        ("RUSTUP_HOME", toolchain_env("RUSTUP_HOME", ".rustup")),
+    ];
+    let server = TestServer::start_with_config_and_env(&json!({}), &env).await;
+    fs::write(
+        server.workspace().join("Cargo.toml"),
+        "[package]\nname = \"fixture\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+    )
+    .unwrap();
+    fs::create_dir(server.workspace().join("src")).unwrap();
+    fs::write(
+        server.workspace().join("src/lib.rs"),
+        "#[test]\nfn passes() {}\n\n#[test]\nfn fails() {\n    panic!(\"expected failure\");\n}\n",
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let run = client.call_tool("runTests", json!({})).await;
+    assert_eq!(run["framework"], "cargo", "{}", run);
+    assert_eq!(run["passed"], 1, "{}", run);
+    assert_eq!(run["failed"], 1, "{}", run);
+    let tests = run["tests"].as_array().unwrap();
+    let failed = tests.iter().find(|test| test["name"] == "fails").unwrap();
+    assert_eq!(failed["status"], "failed");
+    assert!(
+        failed["message"]
+            .as_str()
+            .unwrap()
+            .contains("expected failure"),
+        "{}",
+        run
+    );
+    let passed = tests.iter().find(|test| test["name"] == "passes").unwrap();
+    assert_eq!(passed["status"], "passed");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_runs_past_their_timeout_fail_as_retryable() {
+    let server = TestServer::start().await;
+    fs::write(
+        server.workspace().join("package.json"),
+        json!({ "scripts": { "test": "sleep 30" } }).to_string(),
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client
+        .request(
+            "tools/call",
+            json!({ "name": "runTests", "arguments": { "timeoutSeconds": 1 } }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32048, "{}", response);
+    assert_eq!(response["error"]["data"]["reason"], "timeout");
+    assert_eq!(response["error"]["data"]["retryable"], true);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn slow_tool_calls_outlast_the_ping_timeout() {
+    let server =
+        TestServer::start_with_options(&["--ping-interval-secs", "1", "--ping-timeout-secs", "2"])
+            .await;
+    fs::write(
+        server.workspace().join("package.json"),
+        json!({ "scripts": { "test": "sleep 4" } }).to_string(),
+    )
+    .unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client
+        .request("tools/call", json!({ "name": "runTests", "arguments": {} }))
+        .await;
+    assert!(response["error"].is_null(), "{}", response);
+    assert!(!server.log().contains("timed out, closing"));
+    let status = client.request("get_status", json!({})).await;
+    assert!(status["error"].is_null(), "{}", status);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn tail_file_reads_the_last_lines_and_follows_appends() {
+    let server = TestServer::start().await;
+    let log = server.workspace().join("app.log");
+    fs::write(&log, "one\ntwo\nthree\nfour\nfive\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let once = client
+        .call_tool("tailFile", json!({ "path": "app.log", "lines": 2 }))
+        .await;
+    assert_eq!(once["lines"], json!(["four", "five"]), "{}", once);
+    assert!(once["tailId"].is_null(), "{}", once);
+
+    let followed = client
+        .call_tool(
+            "tailFile",
+            json!({ "path": "app.log", "lines": 1, "follow": true }),
+        )
+        .await;
+    assert_eq!(followed["lines"], json!(["five"]), "{}", followed);
+    let tail_id = followed["tailId"].as_str().unwrap().to_string();
+
+    let mut file = fs::OpenOptions::new().append(true).open(&log).unwrap();
+    std::io::Write::write_all(&mut file, b"six\nseven\n").unwrap();
+    let appended = client.notification("file_appended").await;
+    assert_eq!(appended["params"]["tailId"], tail_id.as_str());
+    assert_eq!(appended["params"]["lines"], json!(["six", "seven"]));
+
+    let stopped = client
+        .call_tool("untailFile", json!({ "tailId": tail_id }))
+        .await;
+    assert_eq!(stopped["success"], true, "{}", stopped);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn following_too_many_files_is_rate_limited() {
+    let server = TestServer::start().await;
+    fs::write(server.workspace().join("app.log"), "started\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let follow = json!({ "path": "app.log", "follow": true });
+    let mut tail_ids = Vec::new();
+    for _ in 0..32 {
+        let followed = client.call_tool("tailFile", follow.clone()).await;
+        tail_ids.push(followed["tailId"].as_str().unwrap().to_string());
+    }
+    let refused = client
+        .request(
+            "tools/call",
+            json!({ "name": "tailFile", "arguments": follow.clone() }),
+        )
+        .await;
+    assert_eq!(refused["error"]["code"], -32049, "{}", refused);
+    assert_eq!(refused["error"]["data"]["reason"], "rate_limited");
+    assert_eq!(refused["error"]["data"]["retryable"], true);
+
+    // Stopping a follow makes room for another
+    client
+        .call_tool("untailFile", json!({ "tailId": tail_ids[0] }))
+        .await;
+    let followed = client.call_tool("tailFile", follow).await;
+    assert!(followed["tailId"].is_string(), "{}", followed);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn internal_tool_errors_name_the_tool_and_its_argument_keys() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client
+        .request(
+            "tools/call",
+            json!({
+                "name": "getFileContents",
+                "arguments": { "filePath": "missing.txt", "note": "do not echo me" }
+            }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32603, "{}", response);
+    let data = &response["error"]["data"];
+    assert_eq!(data["tool"], "getFileContents", "{}", data);
+    assert_eq!(
+        data["argumentKeys"],
+        json!(["filePath", "note"]),
+        "{}",
+        data
+    );
+    assert!(
+        data["cause"].as_str().unwrap().contains("missing.txt"),
+        "{}",
+        data
+    );
+    // What the failure already said about itself survives the added context
+    assert_eq!(data["reason"], "io", "{}", data);
+    assert_eq!(data["retryable"], false, "{}", data);
+    assert!(
+        !response.to_string().contains("do not echo me"),
+        "{}",
+        response
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn tool_calls_without_arguments_name_what_is_missing() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client
+        .request("tools/call", json!({ "name": "openFile" }))
+        .await;
+    assert_eq!(response["error"]["code"], -32602, "{}", response);
+    let message = response["error"]["message"].as_str().unwrap();
+    assert!(message.contains("Missing arguments"), "{}", message);
+    assert_eq!(response["error"]["data"]["required"], json!(["filePath"]));
+    for (tool, required) in [
+        ("saveDocument", json!(["filePath"])),
+        ("checkDocumentDirty", json!(["filePath"])),
+        ("close_tab", json!(["tab_name"])),
+        (
+            "openDiff",
+            json!(["old_file_path", "new_file_path", "new_file_contents"]),
+        ),
+    ] {
+        let response = client.request("tools/call", json!({ "name": tool })).await;
+        assert_eq!(
+            response["error"]["data"]["required"], required,
+            "{}",
+            response
+        );
+    }
+
+    // An arguments object without the path is refused rather than given a placeholder
+    let response = client
+        .request(
+            "tools/call",
+            json!({ "name": "saveDocument", "arguments": { "content": "text\n" } }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32602, "{}", response);
+    assert!(
+        response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("filePath is required"),
+        "{}",
+        response
+    );
+    assert_eq!(fs::read_dir(server.workspace()).unwrap().count(), 0);
+
+    // Tools whose inputs are all optional still run on their defaults
+    let response = client
+        .request("tools/call", json!({ "name": "getOpenEditors" }))
+        .await;
+    assert!(response["error"].is_null(), "{}", response);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn get_environment_reports_allowlisted_variables_but_never_secrets() {
+    let server = TestServer::start_with_config_and_env(
+        &json!({ "exposeEnv": ["PROJECT_FLAVOR", "SECRET_KEY"] }),
+        &[
+            ("RUSTUP_TOOLCHAIN", "nightly".to_string()),
+            ("PROJECT_FLAVOR", "vanilla".to_string()),
+            ("SECRET_KEY", "hunter2".to_string()),
+            ("UNLISTED_VAR", "hidden".to_string()),
+        ],
+    )
+    .await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let environment = client.call_tool("getEnvironment", json!({})).await;
+    let variables = &environment["variables"];
+    assert_eq!(variables["RUSTUP_TOOLCHAIN"], "nightly", "{}", environment);
+    assert_eq!(variables["PROJECT_FLAVOR"], "vanilla", "{}", environment);
+    assert!(variables.get("SECRET_KEY").is_none(), "{}", environment);
+    assert!(variables.get("UNLISTED_VAR").is_none(), "{}", environment);
+    assert!(!environment.to_string().contains("hunter2"));
+    if variables.get("PATH").is_some() {
+        assert!(!environment["pathEntries"].as_array().unwrap().is_empty());
+    }
+
+    let narrowed = client
+        .call_tool(
+            "getEnvironment",
+            json!({ "names": ["RUSTUP_TOOLCHAIN", "SECRET_KEY"] }),
+        )
+        .await;
+    assert_eq!(
+        narrowed["variables"],
+        json!({ "RUSTUP_TOOLCHAIN": "nightly" })
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn reloading_the_config_updates_tools_for_live_connections() {
+    let server = TestServer::start_with_config(&json!({ "disabledTools": [] })).await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let listed = |client_tools: &serde_json::Value| {
+        client_tools["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|tool| tool["name"] == "getEnvironment")
+    };
+    let before = client.request("tools/list", json!({})).await;
+    assert!(listed(&before), "{}", before);
+
+    fs::write(
+        server.config_path(),
+        json!({ "disabledTools": ["getEnvironment"] }).to_string(),
+    )
+    .unwrap();
+    let reloaded = client.request("reloadConfig", json!({})).await;
+    assert_eq!(reloaded["result"]["toolsChanged"], true, "{}", reloaded);
+    client
+        .notification("notifications/tools/list_changed")
+        .await;
+
+    let after = client.request("tools/list", json!({})).await;
+    assert!(!listed(&after), "{}", after);
+    let call = client
+        .request(
+            "tools/call",
+            json!({ "name": "getEnvironment", "arguments": {} }),
+        )
+        .await;
+    assert!(call["error"].is_object(), "{}", call);
+
+    let unchanged = client.request("reloadConfig", json!({})).await;
+    assert_eq!(unchanged["result"]["toolsChanged"], false, "{}", unchanged);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn http_clients_initialize_stream_events_and_end_their_session() {
+    let server = TestServer::start_http_with_config(&json!({ "disabledTools": [] })).await;
+    assert_eq!(server.lock_file["transport"], "http");
+    let http = server.http_client();
+
+    let initialize = http
+        .request(
+            None,
+            "initialize",
+            json!({ "protocolVersion": "2025-03-26" }),
+        )
+        .await;
+    assert_eq!(initialize.status, 200, "{}", initialize.body);
+    assert_eq!(initialize.body["result"]["protocolVersion"], "2025-03-26");
+    let session = initialize
+        .header("mcp-session-id")
+        .expect("initialize started no session")
+        .to_string();
+
+    let (status, mut events) = http.events(&session).await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    fs::write(
+        server.config_path(),
+        json!({ "disabledTools": ["getEnvironment"] }).to_string(),
+    )
+    .unwrap();
+    let reloaded = http
+        .request(Some(&session), "reloadConfig", json!({}))
+        .await;
+    assert_eq!(
+        reloaded.body["result"]["toolsChanged"], true,
+        "{}",
+        reloaded.body
+    );
+    let event = events.next_event().await;
+    assert_eq!(
+        event["method"], "notifications/tools/list_changed",
+        "{}",
+        event
+    );
+
+    // Bodies get the same control character check as WebSocket frames
+    let garbled = http
+        .post(
+            Some(&session),
+            "{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"tools/list\u{1}\"}",
+        )
+        .await;
+    assert_eq!(garbled.status, 400);
+    assert_eq!(garbled.body["error"]["code"], -32700, "{}", garbled.body);
+    assert!(
+        garbled.body["error"]["data"]["details"]
+            .as_str()
+            .unwrap()
+            .contains("control character U+0001"),
+        "{}",
+        garbled.body
+    );
+
+    assert_eq!(http.delete(&session).await.status, 200);
+    let ended = http.request(Some(&session), "tools/list", json!({})).await;
+    assert_eq!(ended.status, 404, "{}", ended.body);
+    assert_eq!(http.delete(&session).await.status, 404);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn close_tab_closes_a_diff_opened_under_that_name() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let opened = client
+        .request(
+            "tools/call",
+            json!({
+                "name": "openDiff",
+                "arguments": {
+                    "old_file_path": "lib.rs",
+                    "new_file_path": "lib.rs",
+                    "new_file_contents": "fn main() {}\n",
+                    "tab_name": "lib.rs (proposed)"
+                }
+            }),
+        )
+        .await;
+    assert!(opened["error"].is_null(), "{}", opened);
+
+    let closed = client
+        .request(
+            "tools/call",
+            json!({ "name": "close_tab", "arguments": { "tab_name": "lib.rs (proposed)" } }),
+        )
+        .await;
+    assert_eq!(
+        closed["result"]["content"][0]["text"], "TAB_CLOSED",
+        "{}",
+        closed
+    );
+
+    // Closing it again finds nothing
+    let again = client
+        .request(
+            "tools/call",
+            json!({ "name": "close_tab", "arguments": { "tab_name": "lib.rs (proposed)" } }),
+        )
+        .await;
+    assert_eq!(
+        again["error"]["data"]["reason"], "tab_not_found",
+        "{}",
+        again
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn close_tab_reports_unknown_tabs() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let response = client
+        .request(
+            "tools/call",
+            json!({ "name": "close_tab", "arguments": { "tab_name": "never opened" } }),
+        )
+        .await;
+    assert_eq!(response["error"]["code"], -32602, "{}", response);
+    assert_eq!(response["error"]["data"]["reason"], "tab_not_found");
+    assert_eq!(response["error"]["data"]["tabName"], "never opened");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn notifications_sent_with_an_id_are_acknowledged() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let selection = json!({
+        "text": "",
+        "filePath": "main.rs",
+        "fileUrl": "file:///main.rs",
+        "selection": {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 0, "character": 0 },
+            "isEmpty": true
+        }
+    });
+
+    let response = client.request("selection_changed", selection.clone()).await;
+    assert_eq!(response["result"], json!({}), "{}", response);
+    assert!(response["error"].is_null(), "{}", response);
+
+    // Without an id nothing comes back, so the next reply answers the next request
+    client.notify("selection_changed", selection).await;
+    let reply = client
+        .send_raw(r#"{"jsonrpc":"2.0","id":"after","method":"get_status"}"#)
+        .await;
+    assert_eq!(reply["id"], "after", "{}", reply);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn malformed_selections_are_rejected_without_replacing_the_stored_one() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let selection = json!({
+        "text": "fn main",
+        "filePath": "/workspace/main.rs",
+        "fileUrl": "file:///workspace/main.rs",
+        "selection": {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 0, "character": 7 },
+            "isEmpty": false
+        }
+    });
+    client.notify("selection_changed", selection.clone()).await;
+
+    let stored = client.call_tool("getCurrentSelection", json!({})).await;
+    assert_eq!(stored["success"], true, "{}", stored);
+    assert_eq!(stored["text"], "fn main");
+
+    // Missing fields, and a range ending before it starts
+    client
+        .notify("selection_changed", json!({ "text": "oops" }))
+        .await;
+    let mut backwards = selection.clone();
+    backwards["text"] = json!("backwards");
+    backwards["selection"]["end"] = json!({ "line": 0, "character": 0 });
+    backwards["selection"]["start"] = json!({ "line": 3, "character": 0 });
+    client.notify("selection_changed", backwards.clone()).await;
+
+    let stored = client.call_tool("getCurrentSelection", json!({})).await;
+    assert_eq!(stored["text"], "fn main", "{}", stored);
+    assert_eq!(stored["selection"], selection["selection"]);
+
+    // Sent with an id, the rejection comes back as an error
+    let response = client.request("selection_changed", backwards).await;
+    assert_eq!(response["error"]["code"], -32602, "{}", response);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn pretty_responses_are_indented_and_still_parse() {
+    let server = TestServer::start_with_options(&["--pretty-responses"]).await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let text = client
+        .send_raw_text(r#"{"jsonrpc":"2.0","id":7,"method":"tools/list"}"#)
+        .await;
+    assert!(text.contains("\n  \"jsonrpc\": \"2.0\""), "{}", text);
+    let response: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(response["id"], 7);
+    assert!(response["result"]["tools"].as_array().is_some(), "{}", text);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn handled_requests_log_their_method_and_latency() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+    client.call_tool("getWorkspaceFolders", json!({})).await;
+
+    let log = server.log();
+    let handled = log
+        .lines()
+        .find(|line| line.contains("Handled MCP request") && line.contains("method=tools/call"))
+        .unwrap_or_else(|| panic!("no latency logged for tools/call:\n{}", log));
+    assert!(handled.contains("latency_us="), "{}", handled);
+    assert!(handled.contains("id="), "{}", handled);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn idle_servers_shut_down_and_remove_their_lock_file() {
+    let mut server = TestServer::start_with_options(&["--idle-timeout", "1"]).await;
+    assert!(server.has_lock_file());
+
+    // A connection holds the server open past its timeout
+    let client = server.connect().await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(!server.exited_within(Duration::ZERO).await);
+    client.close().await;
+
+    assert!(server.exited_within(Duration::from_secs(5)).await);
+    assert!(!server.has_lock_file());
+}
+
+#[tokio::test]
+async fn startup_report_names_the_port_mode_and_worktree_but_not_the_token() {
+    let server = TestServer::start().await;
+
+    let log = server.log();
+    let startup = log
+        .lines()
+        .find(|line| line.contains(" startup "))
+        .unwrap_or_else(|| panic!("no startup report:\n{}", log));
+    for field in [
+        format!("port={}", server.port),
+        "mode=\"websocket\"".to_string(),
+        format!("worktree={}", server.workspace().display()),
+        "auth_token=\"[redacted]\"".to_string(),
+    ] {
+        assert!(startup.contains(&field), "{} missing: {}", field, startup);
+    }
+    assert!(!log.contains(&server.auth_token), "{}", log);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn print_startup_info_writes_the_report_as_json() {
+    let home = std::env::temp_dir().join(format!(
+        "claude-code-server-startup-{}",
+        uuid::Uuid::new_v4()
+    ));
+    fs::create_dir_all(&home).unwrap();
+    let run = tokio::process::Command::new(env!("CARGO_BIN_EXE_claude-code-server"))
+        .args(["--idle-timeout", "1", "websocket", "--port", "0"])
+        .arg("--print-startup-info")
+        .current_dir(&home)
+        .env("HOME", &home)
+        .env("USERPROFILE", &home)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(Duration::from_secs(10), run)
+        .await
+        .expect("server outlived its idle timeout")
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let report: Value = serde_json::from_str(stdout.lines().next().unwrap()).unwrap();
+    let port = report["port"].as_u64().unwrap();
+    assert!(port > 0, "{}", report);
+    assert_eq!(report["mode"], "websocket");
+    assert_eq!(report["worktree"], home.to_str().unwrap());
+    assert_eq!(
+        report["lockPath"],
+        home.join(".claude/ide")
+            .join(format!("{}.lock", port))
+            .to_str()
+            .unwrap()
+    );
+    assert_eq!(report["authToken"], "[redacted]");
+    fs::remove_dir_all(&home).ok();
+}
+
+#[tokio::test]
+async fn stopping_the_server_ends_its_background_tasks() {
+    let websocket = [
+        "--debug",
+        "--idle-timeout",
+        "1",
+        "--rotate-token-secs",
+        "60",
+        "websocket",
+        "--port",
+        "0",
+    ];
+    let hybrid = ["--debug", "--idle-timeout", "1", "hybrid", "--port", "0"];
+    for (args, tasks) in [
+        (
+            &websocket[..],
+            &[
+                "Keepalive task stopped",
+                "Workspace watch task stopped",
+                "Token rotation task stopped",
+            ][..],
+        ),
+        (
+            &hybrid[..],
+            &[
+                "Keepalive task stopped",
+                "Workspace watch task stopped",
+                "IDE notification relay stopped",
+            ][..],
+        ),
+    ] {
+        let home =
+            std::env::temp_dir().join(format!("claude-code-server-stop-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&home).unwrap();
+        let mut child = tokio::process::Command::new(env!("CARGO_BIN_EXE_claude-code-server"))
+            .args(args)
+            .current_dir(&home)
+            .env("HOME", &home)
+            .env("USERPROFILE", &home)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        // Held open so the hybrid LSP server keeps running until the WebSocket side stops
+        let _stdin = child.stdin.take();
+        let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+        let mut log = String::new();
+        let mut remaining: Vec<&str> = tasks.to_vec();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !remaining.is_empty() {
+                let line = stderr.next_line().await.unwrap().expect("server exited");
+                remaining.retain(|task| !line.contains(task));
+                log.push_str(&line);
+                log.push('\n');
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("{:?} did not stop: {}", remaining, log));
+
+        // The hybrid LSP server waits on stdin, so only a WebSocket server exits by itself
+        if args.contains(&"websocket") {
+            let status = tokio::time::timeout(Duration::from_secs(10), child.wait())
+                .await
+                .expect("server outlived its idle timeout")
+                .unwrap();
+            assert!(status.success());
+        }
+        fs::remove_dir_all(&home).ok();
+    }
+}
+
+#[tokio::test]
+async fn read_tools_refuse_files_over_the_size_limit() {
+    let server = TestServer::start_with_options(&["--max-file-size", "64"]).await;
+    fs::write(server.workspace().join("small.txt"), "fits\n").unwrap();
+    fs::write(server.workspace().join("large.txt"), "x".repeat(100)).unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let small = client
+        .call_tool("getFileContents", json!({ "filePath": "small.txt" }))
+        .await;
+    assert_eq!(small["content"], "fits\n", "{}", small);
+
+    let large = client
+        .request(
+            "tools/call",
+            json!({ "name": "getFileContents", "arguments": { "filePath": "large.txt" } }),
+        )
+        .await;
+    assert_eq!(large["error"]["code"], -32047, "{}", large);
+    assert_eq!(large["error"]["data"]["reason"], "file_too_large");
+    assert_eq!(large["error"]["data"]["size"], 100);
+    assert_eq!(large["error"]["data"]["limit"], 64);
+
+    let batch = client
+        .call_tool("readFiles", json!({ "paths": ["small.txt", "large.txt"] }))
+        .await;
+    assert_eq!(batch[0]["content"], "fits\n", "{}", batch);
+    assert_eq!(batch[1]["reason"], "file_too_large", "{}", batch);
+
+    // Tools that load a file to edit or compare it are held to the same limit
+    let range = json!({
+        "start": { "line": 0, "character": 0 },
+        "end": { "line": 0, "character": 1 }
+    });
+    for (tool, arguments) in [
+        (
+            "replaceInFiles",
+            json!({ "files": [{ "path": "large.txt", "edits": [{ "range": range, "newText": "y" }] }] }),
+        ),
+        (
+            "extractToFile",
+            json!({ "sourcePath": "large.txt", "range": range, "targetPath": "out.txt" }),
+        ),
+        (
+            "saveDocument",
+            json!({ "filePath": "large.txt", "content": "y", "expectedHash": "0" }),
+        ),
+    ] {
+        let response = client
+            .request(
+                "tools/call",
+                json!({ "name": tool, "arguments": arguments }),
+            )
+            .await;
+        assert_eq!(response["error"]["code"], -32047, "{}", response);
+    }
+    assert_eq!(
+        fs::read_to_string(server.workspace().join("large.txt")).unwrap(),
+        "x".repeat(100)
+    );
+
+    // Lines are counted without loading the file
+    let opened = client
+        .call_tool("openFile", json!({ "filePath": "large.txt" }))
+        .await;
+    assert_eq!(opened["lineCount"], 1, "{}", opened);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn connections_that_never_initialize_are_closed() {
+    let server = TestServer::start_with_options(&["--initialize-timeout-secs", "1"]).await;
+    let mut idle = server.connect().await;
+    let mut initialized = server.connect().await;
+    initialized.initialize().await;
+
+    let refused = idle.request("tools/list", json!({})).await;
+    assert_eq!(refused["error"]["code"], -32002, "{}", refused);
+
+    assert!(idle.closed_within(Duration::from_secs(5)).await);
+    let response = initialized.request("tools/list", json!({})).await;
+    assert!(response["result"]["tools"].is_array(), "{}", response);
+
+    initialized.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn identical_read_tool_calls_reuse_the_result_until_something_changes() {
+    let server = TestServer::start_with_options(&["--tool-cache-ttl-ms", "60000"]).await;
+    fs::write(server.workspace().join("notes.txt"), "one\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let first = client.call_tool("getWorkspaceFolders", json!({})).await;
+    let second = client.call_tool("getWorkspaceFolders", json!({})).await;
+    assert_eq!(first, second);
+    let status = client.request("get_status", json!({})).await;
+    assert_eq!(status["result"]["toolCache"]["hits"], 1, "{}", status);
+
+    // A write may change what read tools see, so the next call recomputes
+    let stat = client
+        .call_tool("statFile", json!({ "path": "./notes.txt" }))
+        .await;
+    assert_eq!(stat["size"], 4, "{}", stat);
+    client
+        .call_tool(
+            "createFile",
+            json!({ "path": "notes.txt", "content": "one\ntwo\n", "overwrite": true }),
+        )
+        .await;
+    let stat = client
+        .call_tool("statFile", json!({ "path": "notes.txt" }))
+        .await;
+    assert_eq!(stat["size"], 8, "{}", stat);
+    let third = client.call_tool("getWorkspaceFolders", json!({})).await;
+    assert_eq!(first, third);
+
+    let status = client.request("get_status", json!({})).await;
+    let stats = &status["result"]["toolCache"];
+    assert_eq!(stats["hits"], 1, "{}", status);
+    assert_eq!(stats["misses"], 4, "{}", status);
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn supported_languages_report_only_the_features_that_work() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.initialize().await;
+
+    let result = client.call_tool("getSupportedLanguages", json!({})).await;
+    assert_eq!(result["success"], true, "{}", result);
+    // Kotlin definitions are indexed, but there is no formatter or test runner for it
+    assert_eq!(
+        result["languages"]["kotlin"],
+        json!(["symbols"]),
+        "{}",
+        result
+    );
+    // Without an editor attached nothing reports diagnostics
+    assert_eq!(
+        result["languages"]["rust"],
+        json!(["symbols", "format"]),
+        "{}",
+        result
+    );
+
+    fs::write(server.workspace().join("Cargo.toml"), "[package]\n").unwrap();
+    let result = client.call_tool("getSupportedLanguages", json!({})).await;
+    assert_eq!(
+        result["languages"]["rust"],
+        json!(["symbols", "format", "runTests"]),
+        "{}",
+        result
+    );
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn save_document_checks_the_expected_hash() {
+    let server = TestServer::start().await;
+    let path = server.workspace().join("notes.txt");
+    fs::write(&path, "first\n").unwrap();
+    let mut client = server.connect().await;
+    client.initialize().await;
+    let read = client
+        .call_tool("getFileContents", json!({ "filePath": "notes.txt" }))
+        .await;
+    let hash = read["hash"].clone();
+
+    let saved = client
+        .call_tool(
+            "saveDocument",
+            json!({ "filePath": "notes.txt", "content": "second\n", "expectedHash": hash }),
+        )
+        .await;
+    assert_eq!(saved["saved"], true, "{}", saved);
+    assert_ne!(saved["hash"], hash);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+
+    // The hash read before the save no longer matches what is on disk
+    fs::write(&path, "changed elsewhere\n").unwrap();
+    let rejected = client
+        .request(
+            "tools/call",
+            json!({
+                "name": "saveDocument",
+                "arguments": { "filePath": "notes.txt", "content": "third\n", "expectedHash": saved["hash"] }
+            }),
+        )
+        .await;
+    assert_eq!(rejected["error"]["code"], -32040, "{}", rejected);
+    let data = &rejected["error"]["data"];
+    assert_eq!(data["currentContent"], "changed elsewhere\n", "{}", data);
+    assert_eq!(data["expectedHash"], saved["hash"]);
+    let current = client
+        .call_tool("getFileContents", json!({ "filePath": "notes.txt" }))
+        .await;
+    assert_eq!(data["currentHash"], current["hash"]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "changed elsewhere\n");
+
+    client.close().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn concurrent_writes_to_one_file_run_one_after_the_other() {
+    let server = TestServer::start().await;
+    let mut first = server.connect().await;
+    let mut second = server.connect().await;
+    first.initialize().await;
+    second.initialize().await;
+    let original = "o".repeat(2 * 1024 * 1024);
+    let a = "a".repeat(2 * 1024 * 1024);
+    let b = "b".repeat(1024 * 1024);
+    // Both writers claim to have seen the original, so only the first may succeed
+    let save = |path: &str, content: &str, expected_hash: &Value| {
+        json!({
+            "name": "saveDocument",
+            "arguments": { "filePath": path, "content": content, "expectedHash": expected_hash }
+        })
+    };
+
+    for _ in 0..3 {
+        let path = server.workspace().join("shared.txt");
+        fs::write(&path, &original).unwrap();
+        let read = first
+            .call_tool("getFileContents", json!({ "filePath": "shared.txt" }))
+            .await;
+        let hash = &read["hash"];
+
+        let (left, right) = tokio::join!(
+            first.request("tools/call", save("shared.txt", &a, hash)),
+            second.request("tools/call", save("shared.txt", &b, hash)),
+        );
+        let conflicts = [&left, &right]
+            .iter()
+            .filter(|response| response["error"]["code"] == -32040)
+            .count();
+        assert_eq!(conflicts, 1, "{}\n{}", left["error"], right["error"]);
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written == a || written == b, "writes interleaved");
+    }
+
+    // Writes to different files don't wait on each other
+    let save = |path: &str, content: &str| json!({ "filePath": path, "content": content });
+    let (left, right) = tokio::join!(
+        first.call_tool("saveDocument", save("left.txt", &a)),
+        second.call_tool("saveDocument", save("right.txt", &b)),
+    );
+    assert_eq!(left["success"], true);
+    assert_eq!(right["success"], true);
+    assert!(fs::read_to_string(server.workspace().join("left.txt")).unwrap() == a);
+    assert!(fs::read_to_string(server.workspace().join("right.txt")).unwrap() == b);
+
+    first.close().await;
+    second.close().await;
+    server.shutdown().await;
+}
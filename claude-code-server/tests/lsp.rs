@@ -0,0 +1,1255 @@
+#![cfg(feature = "lsp")]
+
+mod common;
+
+use common::lsp::{url, LspClient, TestHome};
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// Decode relative semantic tokens into `(text, token type)` pairs
+fn decode_tokens<'a>(
+    text: &'a str,
+    data: &[Value],
+    legend: &'a [Value],
+) -> Vec<(&'a str, &'a str)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let (mut line, mut start) = (0, 0);
+    data.chunks(5)
+        .map(|token| {
+            let field = |i: usize| token[i].as_u64().unwrap() as usize;
+            if field(0) > 0 {
+                line += field(0);
+                start = field(1);
+            } else {
+                start += field(1);
+            }
+            // The snippets are ASCII, so UTF-16 columns are byte offsets
+            let token_text = &lines[line][start..start + field(2)];
+            (token_text, legend[field(3)].as_str().unwrap())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn semantic_tokens_classify_keywords_and_comments() {
+    let worktree = std::env::temp_dir();
+    let mut client = LspClient::start(&worktree).await;
+    let legend = client.capabilities["semanticTokensProvider"]["legend"]["tokenTypes"]
+        .as_array()
+        .cloned()
+        .expect("semantic tokens are not advertised");
+
+    let path = worktree.join("semantic_tokens_example.rs");
+    let text = "// add one\nfn add_one(x: i32) -> i32 {\n    let y = x + 1; // done\n    y\n}\n";
+    client.open(&path, "rust", text).await;
+    let response = client
+        .request(
+            "textDocument/semanticTokens/full",
+            json!({ "textDocument": { "uri": url(&path) } }),
+        )
+        .await;
+    let data = response["result"]["data"].as_array().unwrap();
+    let tokens = decode_tokens(text, data, &legend);
+
+    for expected in [
+        ("// add one", "comment"),
+        ("fn", "keyword"),
+        ("add_one", "variable"),
+        ("let", "keyword"),
+        ("1", "number"),
+        ("// done", "comment"),
+    ] {
+        assert!(
+            tokens.contains(&expected),
+            "{:?} missing from {:?}",
+            expected,
+            tokens
+        );
+    }
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn only_implemented_capabilities_are_advertised() {
+    let dirs = TestHome::new("capabilities");
+    let worktree = dirs.worktree();
+    let path = worktree.join("lib.rs");
+    let mut client = LspClient::start(worktree).await;
+    client.open(&path, "rust", "fn main() {}\n").await;
+    let position = json!({
+        "textDocument": { "uri": url(&path) },
+        "position": { "line": 0, "character": 3 },
+        "context": { "includeDeclaration": true }
+    });
+
+    for (capability, method) in [
+        ("definitionProvider", "textDocument/definition"),
+        ("documentSymbolProvider", "textDocument/documentSymbol"),
+    ] {
+        assert!(
+            client.capabilities[capability].is_null(),
+            "{}",
+            client.capabilities
+        );
+        let response = client.request(method, position.clone()).await;
+        assert_eq!(response["error"]["code"], -32601, "{}", response);
+    }
+    for (capability, method) in [
+        ("hoverProvider", "textDocument/hover"),
+        ("referencesProvider", "textDocument/references"),
+    ] {
+        assert!(
+            !client.capabilities[capability].is_null(),
+            "{}",
+            client.capabilities
+        );
+        let response = client.request(method, position.clone()).await;
+        assert!(response["error"].is_null(), "{}", response);
+    }
+
+    client.shutdown().await;
+}
+
+/// Fill the worktree of `dirs` with symbols `handle`, 100 `handler_N` functions and
+/// `h_and_le`, returning the worktree
+fn symbol_worktree(dirs: &TestHome) -> &Path {
+    let worktree = dirs.worktree();
+    let mut text = String::from("fn h_and_le() {}\nfn handle() {}\n");
+    for i in 0..100 {
+        text.push_str(&format!("fn handler_{}() {{}}\n", i));
+    }
+    std::fs::write(worktree.join("lib.rs"), text).unwrap();
+    worktree
+}
+
+fn symbol_names(symbols: &Value) -> Vec<&str> {
+    symbols
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| symbol["name"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn workspace_symbols_are_capped_with_exact_matches_first() {
+    let dirs = TestHome::new("workspace-symbols");
+    let worktree = symbol_worktree(&dirs);
+    let mut client =
+        LspClient::start_with_options(worktree, json!({ "workspaceSymbolLimit": 50 })).await;
+
+    let response = client
+        .request("workspace/symbol", json!({ "query": "handle" }))
+        .await;
+    let names = symbol_names(&response["result"]);
+    assert_eq!(names.len(), 50, "{:?}", names);
+    assert_eq!(names[0], "handle");
+    assert!(
+        names[1..].iter().all(|name| name.starts_with("handler_")),
+        "{:?}",
+        names
+    );
+    assert!(
+        !names.contains(&"h_and_le"),
+        "fuzzy match ranked ahead of prefixes"
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn large_symbol_responses_arrive_intact() {
+    let dirs = TestHome::new("large-response");
+    let worktree = dirs.worktree();
+    let text: String = (0..4000)
+        .map(|i| format!("fn generated_symbol_with_a_rather_long_name_{}() {{}}\n", i))
+        .collect();
+    std::fs::write(worktree.join("generated.rs"), text).unwrap();
+    let mut client =
+        LspClient::start_with_options(worktree, json!({ "workspaceSymbolLimit": 10000 })).await;
+
+    // Each opened document is logged to the client, so notifications share the
+    // output with the response
+    for i in 0..20 {
+        let path = worktree.join(format!("open_{}.rs", i));
+        client.open(&path, "rust", "fn opened() {}\n").await;
+    }
+    let response = client
+        .request("workspace/symbol", json!({ "query": "generated_symbol" }))
+        .await;
+    assert!(response.to_string().len() > 512 * 1024);
+    let mut names = symbol_names(&response["result"]);
+    names.sort_unstable();
+    names.dedup();
+    assert_eq!(names.len(), 4000);
+    let logged = client
+        .notifications
+        .iter()
+        .filter(|notification| notification["method"] == "window/logMessage")
+        .count();
+    assert!(logged >= 20, "{}", logged);
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn workspace_symbols_stream_as_partial_results() {
+    let dirs = TestHome::new("workspace-symbol-pages");
+    let worktree = symbol_worktree(&dirs);
+    let mut client = LspClient::start(worktree).await;
+
+    let response = client
+        .request(
+            "workspace/symbol",
+            json!({ "query": "handle", "partialResultToken": "symbols" }),
+        )
+        .await;
+    assert_eq!(response["result"], json!([]));
+
+    let mut pages = 0;
+    let mut names: Vec<String> = Vec::new();
+    while names.len() < 102 {
+        let progress = client.notification("$/progress").await;
+        // Startup indexing reports its own progress under another token
+        if progress["params"]["token"] != "symbols" {
+            continue;
+        }
+        let page = symbol_names(&progress["params"]["value"]);
+        names.extend(page.into_iter().map(str::to_string));
+        pages += 1;
+    }
+    assert!(pages > 1, "expected several pages, got {}", pages);
+    assert_eq!(names.len(), 102);
+    assert_eq!(names[0], "handle");
+    assert_eq!(names.last().unwrap(), "h_and_le");
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn deleted_watched_files_leave_the_symbol_index() {
+    let dirs = TestHome::new("watched-files");
+    let worktree = symbol_worktree(&dirs);
+    let doomed = worktree.join("doomed.rs");
+    std::fs::write(&doomed, "fn doomed_symbol() {}\n").unwrap();
+    let mut client = LspClient::start(worktree).await;
+
+    let response = client
+        .request("workspace/symbol", json!({ "query": "doomed_symbol" }))
+        .await;
+    assert_eq!(symbol_names(&response["result"]), ["doomed_symbol"]);
+    let registration = client.server_request("client/registerCapability").await;
+    assert_eq!(
+        registration["params"]["registrations"][0]["method"],
+        "workspace/didChangeWatchedFiles"
+    );
+
+    std::fs::remove_file(&doomed).unwrap();
+    client
+        .notify(
+            "workspace/didChangeWatchedFiles",
+            json!({ "changes": [{ "uri": url(&doomed), "type": 3 }] }),
+        )
+        .await;
+    let response = client
+        .request("workspace/symbol", json!({ "query": "doomed_symbol" }))
+        .await;
+    assert_eq!(response["result"], json!([]));
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn renamed_documents_move_to_their_new_uri() {
+    let dirs = TestHome::new("renamed-files");
+    let worktree = dirs.worktree();
+    let (old, new) = (worktree.join("old.rs"), worktree.join("new.rs"));
+    std::fs::write(&old, "fn moved_symbol() {}\n").unwrap();
+    let mut client = LspClient::start(worktree).await;
+    assert_eq!(
+        client.capabilities["workspace"]["fileOperations"]["didRename"]["filters"][0]["scheme"],
+        "file"
+    );
+    client.open(&old, "rust", "fn moved_symbol() {}\n").await;
+
+    std::fs::rename(&old, &new).unwrap();
+    client
+        .notify(
+            "workspace/didRenameFiles",
+            json!({ "files": [{ "oldUri": url(&old), "newUri": url(&new) }] }),
+        )
+        .await;
+
+    let response = client
+        .request("workspace/symbol", json!({ "query": "moved_symbol" }))
+        .await;
+    let symbols = response["result"].as_array().unwrap();
+    assert_eq!(symbols.len(), 1, "{}", response);
+    assert_eq!(symbols[0]["location"]["uri"], url(&new));
+    let tokens = |path: &Path| json!({ "textDocument": { "uri": url(path) } });
+    let response = client
+        .request("textDocument/semanticTokens/full", tokens(&new))
+        .await;
+    assert!(!response["result"]["data"].as_array().unwrap().is_empty());
+    let response = client
+        .request("textDocument/semanticTokens/full", tokens(&old))
+        .await;
+    assert!(response["result"].is_null(), "{}", response);
+
+    client.shutdown().await;
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn selections_on_multi_megabyte_lines_are_read_quickly() {
+    let dirs = TestHome::new("long-line");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+    // Non-ASCII text around the selection rules out the ASCII shortcut
+    let path = worktree.join("minified.js");
+    let line = format!("{}needle😀{}", "π".repeat(1_000_000), "😀".repeat(500_000));
+    std::fs::write(&path, line).unwrap();
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+
+    let started = std::time::Instant::now();
+    lsp.request(
+        "textDocument/codeAction",
+        json!({
+            "textDocument": { "uri": url(&path) },
+            "range": {
+                "start": { "line": 0, "character": 1_000_000 },
+                "end": { "line": 0, "character": 1_000_008 }
+            },
+            "context": { "diagnostics": [] }
+        }),
+    )
+    .await;
+    let elapsed = started.elapsed();
+
+    let selection = websocket.notification("selection_changed").await;
+    assert_eq!(selection["params"]["text"], "needle😀");
+    assert!(
+        elapsed < std::time::Duration::from_secs(2),
+        "reading the selection took {:?}",
+        elapsed
+    );
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn selection_context_surrounds_the_latest_selection() {
+    let dirs = TestHome::new("selection-context");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+    let path = worktree.join("lines.txt");
+    let text: String = (0..10).map(|i| format!("line {}\n", i)).collect();
+    std::fs::write(&path, text).unwrap();
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.initialize().await;
+
+    let none = websocket
+        .request(
+            "tools/call",
+            json!({ "name": "getSelectionContext", "arguments": {} }),
+        )
+        .await;
+    assert_eq!(none["error"]["data"]["reason"], "no_selection", "{}", none);
+
+    lsp.request(
+        "textDocument/codeAction",
+        json!({
+            "textDocument": { "uri": url(&path) },
+            "range": {
+                "start": { "line": 4, "character": 0 },
+                "end": { "line": 5, "character": 6 }
+            },
+            "context": { "diagnostics": [] }
+        }),
+    )
+    .await;
+    websocket.notification("selection_changed").await;
+
+    let context = websocket
+        .call_tool("getSelectionContext", json!({ "contextLines": 2 }))
+        .await;
+    assert_eq!(context["success"], true, "{}", context);
+    assert_eq!(context["text"], "line 4\nline 5");
+    let lines: Vec<(u64, &str)> = context["context"]["lines"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|line| {
+            (
+                line["line"].as_u64().unwrap(),
+                line["text"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (2, "line 2"),
+            (3, "line 3"),
+            (4, "line 4"),
+            (5, "line 5"),
+            (6, "line 6"),
+            (7, "line 7")
+        ]
+    );
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn hybrid_mode_removes_its_lock_file_when_the_editor_exits() {
+    let dirs = TestHome::new("lock-guard");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+
+    let lsp = LspClient::start_hybrid(worktree, home).await;
+    let websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.close().await;
+
+    // Nothing calls the cleanup on this path; the WebSocket task is simply dropped
+    let status = lsp.exit().await;
+    assert!(status.success(), "{}", status);
+    let lock_dir = home.join(".claude").join("ide");
+    let remaining: Vec<_> = std::fs::read_dir(&lock_dir)
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lock"))
+        .collect();
+    assert!(remaining.is_empty(), "{:?}", remaining);
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn no_subcommand_runs_the_documented_default() {
+    let dirs = TestHome::new("default-mode");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+    let lock_files = || {
+        std::fs::read_dir(home.join(".claude").join("ide"))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "lock"))
+                    .count()
+            })
+            .unwrap_or(0)
+    };
+
+    // Given a worktree, the server stays the LSP server editors were configured for
+    let mut lsp = LspClient::start_without_mode(worktree, home, &[]).await;
+    let symbols = lsp
+        .request("workspace/symbol", json!({ "query": "anything" }))
+        .await;
+    assert!(symbols["error"].is_null(), "{}", symbols);
+    assert!(
+        lsp.log()
+            .contains("a worktree was given, defaulting to lsp mode"),
+        "{}",
+        lsp.log()
+    );
+    assert_eq!(lock_files(), 0);
+    lsp.shutdown().await;
+
+    let lsp = LspClient::start_without_mode(worktree, home, &["--default-mode", "hybrid"]).await;
+    let websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.close().await;
+    assert!(
+        lsp.log()
+            .contains("running hybrid mode as --default-mode asks"),
+        "{}",
+        lsp.log()
+    );
+    lsp.exit().await;
+    assert_eq!(lock_files(), 0);
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn selections_made_over_lsp_are_read_by_websocket_tools() {
+    let dirs = TestHome::new("shared-selection");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+    let path = worktree.join("greeting.txt");
+    std::fs::write(&path, "hello\nworld\n").unwrap();
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.initialize().await;
+
+    let none = websocket.call_tool("getCurrentSelection", json!({})).await;
+    assert_eq!(none["success"], false, "{}", none);
+
+    lsp.request(
+        "textDocument/codeAction",
+        json!({
+            "textDocument": { "uri": url(&path) },
+            "range": {
+                "start": { "line": 1, "character": 0 },
+                "end": { "line": 1, "character": 5 }
+            },
+            "context": { "diagnostics": [] }
+        }),
+    )
+    .await;
+
+    // The LSP server stores the selection before answering, so the tool sees it
+    // without waiting for the selection_changed notification
+    let selection = websocket.call_tool("getCurrentSelection", json!({})).await;
+    assert_eq!(selection["success"], true, "{}", selection);
+    assert_eq!(selection["text"], "world");
+    assert_eq!(selection["fileUrl"], url(&path));
+    assert_eq!(
+        selection["selection"],
+        json!({
+            "start": { "line": 1, "character": 0 },
+            "end": { "line": 1, "character": 5 },
+            "isEmpty": false
+        })
+    );
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn check_document_dirty_compares_the_buffer_with_the_disk() {
+    let dirs = TestHome::new("document-dirty");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+    let path = worktree.join("notes.txt");
+    std::fs::write(&path, "saved\n").unwrap();
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.initialize().await;
+
+    lsp.open(&path, "plaintext", "saved\n").await;
+    let edit = |version: i32, text: &str| {
+        json!({
+            "textDocument": { "uri": url(&path), "version": version },
+            "contentChanges": [{ "text": text }]
+        })
+    };
+    // A request answered after a notification means the notification was handled
+    let settle = json!({ "query": "" });
+
+    lsp.request("workspace/symbol", settle.clone()).await;
+    let clean = websocket
+        .call_tool("checkDocumentDirty", json!({ "filePath": "notes.txt" }))
+        .await;
+    assert_eq!(clean["isDirty"], false, "{}", clean);
+    assert_eq!(clean["isUntitled"], false, "{}", clean);
+
+    lsp.notify("textDocument/didChange", edit(2, "edited\n"))
+        .await;
+    lsp.request("workspace/symbol", settle.clone()).await;
+    let edited = websocket
+        .call_tool("checkDocumentDirty", json!({ "filePath": "notes.txt" }))
+        .await;
+    assert_eq!(edited["isDirty"], true, "{}", edited);
+
+    // Undoing the edit leaves the dirty bit set, but the buffer matches the disk again
+    lsp.notify("textDocument/didChange", edit(3, "saved\n"))
+        .await;
+    lsp.request("workspace/symbol", settle.clone()).await;
+    let undone = websocket
+        .call_tool("checkDocumentDirty", json!({ "filePath": "notes.txt" }))
+        .await;
+    assert_eq!(undone["isDirty"], false, "{}", undone);
+
+    let untitled = websocket
+        .call_tool("checkDocumentDirty", json!({ "filePath": "missing.txt" }))
+        .await;
+    assert_eq!(untitled["isUntitled"], true, "{}", untitled);
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn explain_sends_the_selected_code_to_claude() {
+    let dirs = TestHome::new("explain");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+    let path = worktree.join("math.rs");
+    std::fs::write(&path, "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n").unwrap();
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.initialize().await;
+
+    lsp.request(
+        "textDocument/codeAction",
+        json!({
+            "textDocument": { "uri": url(&path) },
+            "range": {
+                "start": { "line": 1, "character": 4 },
+                "end": { "line": 1, "character": 9 }
+            },
+            "context": { "diagnostics": [] }
+        }),
+    )
+    .await;
+    // Without arguments the command targets the latest selection
+    lsp.request(
+        "workspace/executeCommand",
+        json!({ "command": "claude-code.explain", "arguments": [] }),
+    )
+    .await;
+
+    let ask = websocket.notification("ask").await;
+    assert_eq!(ask["params"]["action"], "explain", "{}", ask);
+    assert_eq!(ask["params"]["text"], "a + b");
+    let prompt = ask["params"]["prompt"].as_str().unwrap();
+    assert!(prompt.starts_with("Explain"), "{}", prompt);
+    assert!(prompt.contains("a + b"), "{}", prompt);
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn file_change_bursts_drop_the_oldest_and_report_one_overflow() {
+    let dirs = TestHome::new("watch-overflow");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.initialize().await;
+
+    let changes = |names: &[String]| {
+        let changes: Vec<Value> = names
+            .iter()
+            .map(|name| json!({ "uri": url(&worktree.join(name)), "type": 2 }))
+            .collect();
+        json!({ "changes": changes })
+    };
+    let burst: Vec<String> = (0..1000).map(|i| format!("generated_{}.txt", i)).collect();
+    lsp.notify("workspace/didChangeWatchedFiles", changes(&burst))
+        .await;
+
+    let received = websocket.notifications_until("watch_overflow").await;
+    let changed: Vec<&Value> = received
+        .iter()
+        .filter(|m| m["method"] == "file_changed")
+        .collect();
+    assert_eq!(changed.len(), 64);
+    // The newest changes are the ones kept
+    assert_eq!(
+        changed.last().unwrap()["params"]["filePath"],
+        worktree.join("generated_999.txt").display().to_string()
+    );
+    assert_eq!(received.last().unwrap()["params"]["dropped"], 1000 - 64);
+
+    lsp.notify(
+        "workspace/didChangeWatchedFiles",
+        changes(&["after.txt".to_string()]),
+    )
+    .await;
+    let received = websocket.notifications_until("file_changed").await;
+    assert!(
+        received.iter().all(|m| m["method"] != "watch_overflow"),
+        "{:?}",
+        received
+    );
+    assert_eq!(
+        received.last().unwrap()["params"]["filePath"],
+        worktree.join("after.txt").display().to_string()
+    );
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[tokio::test]
+async fn claude_completions_are_only_offered_after_an_at() {
+    let dirs = TestHome::new("completion");
+    let worktree = dirs.worktree();
+    let path = worktree.join("main.rs");
+    let mut client = LspClient::start(worktree).await;
+    client
+        .open(
+            &path,
+            "rust",
+            "fn main() {\n    let value = 1;\n    // @\n}\n",
+        )
+        .await;
+    assert_eq!(
+        client.capabilities["completionProvider"]["triggerCharacters"],
+        json!(["@"])
+    );
+    let completion = |line: u64, character: u64, context: Value| {
+        json!({
+            "textDocument": { "uri": url(&path) },
+            "position": { "line": line, "character": character },
+            "context": context
+        })
+    };
+    let labels = |response: &Value| -> Vec<String> {
+        response["result"]
+            .as_array()
+            .unwrap_or_else(|| panic!("no completions: {}", response))
+            .iter()
+            .map(|item| item["label"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    // Typing inside an identifier
+    let typed = client
+        .request(
+            "textDocument/completion",
+            completion(1, 10, json!({ "triggerKind": 1 })),
+        )
+        .await;
+    assert!(typed["result"].is_null(), "{}", typed);
+    let other_trigger = client
+        .request(
+            "textDocument/completion",
+            completion(1, 10, json!({ "triggerKind": 2, "triggerCharacter": "." })),
+        )
+        .await;
+    assert!(other_trigger["result"].is_null(), "{}", other_trigger);
+
+    let triggered = client
+        .request(
+            "textDocument/completion",
+            completion(2, 8, json!({ "triggerKind": 2, "triggerCharacter": "@" })),
+        )
+        .await;
+    let triggered = labels(&triggered);
+    assert!(!triggered.is_empty());
+    assert!(triggered.iter().all(|label| label.starts_with("@claude")));
+
+    // Invoking completion by hand right after an @ offers the same items
+    let invoked = client
+        .request(
+            "textDocument/completion",
+            completion(2, 8, json!({ "triggerKind": 1 })),
+        )
+        .await;
+    assert_eq!(labels(&invoked), triggered);
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn hover_shows_diagnostics_at_the_position() {
+    let dirs = TestHome::new("hover");
+    let worktree = dirs.worktree();
+    let path = worktree.join("main.rs");
+    std::fs::write(&path, "fn main() {\n    let x = missing();\n}\n").unwrap();
+    let mut client = LspClient::start(worktree).await;
+
+    client
+        .request(
+            "textDocument/codeAction",
+            json!({
+                "textDocument": { "uri": url(&path) },
+                "range": {
+                    "start": { "line": 1, "character": 0 },
+                    "end": { "line": 1, "character": 22 }
+                },
+                "context": {
+                    "diagnostics": [{
+                        "range": {
+                            "start": { "line": 1, "character": 12 },
+                            "end": { "line": 1, "character": 19 }
+                        },
+                        "severity": 1,
+                        "source": "rustc",
+                        "message": "cannot find function `missing`"
+                    }]
+                }
+            }),
+        )
+        .await;
+    let hover = |line: u64, character: u64| {
+        json!({
+            "textDocument": { "uri": url(&path) },
+            "position": { "line": line, "character": character }
+        })
+    };
+
+    let on_error = client.request("textDocument/hover", hover(1, 14)).await;
+    let contents = on_error["result"]["contents"]["value"].as_str().unwrap();
+    assert!(
+        contents.contains("**error** (rustc): cannot find function `missing`"),
+        "{}",
+        contents
+    );
+
+    let elsewhere = client.request("textDocument/hover", hover(0, 4)).await;
+    assert!(
+        !elsewhere.to_string().contains("cannot find"),
+        "{}",
+        elsewhere
+    );
+
+    client.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn range_formatting_edits_only_the_selected_lines() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dirs = TestHome::new("range-formatting");
+    let worktree = dirs.worktree();
+    // Stand-in for rustfmt that squeezes runs of spaces
+    let bin = dirs.home().join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let rustfmt = bin.join("rustfmt");
+    std::fs::write(&rustfmt, "#!/bin/sh\nexec tr -s ' '\n").unwrap();
+    std::fs::set_permissions(&rustfmt, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path_env = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    let path = worktree.join("lib.rs");
+    let mut client = LspClient::start_with_env(worktree, &[("PATH", path_env)]).await;
+    assert_eq!(client.capabilities["documentRangeFormattingProvider"], true);
+    client
+        .open(&path, "rust", "fn  first()  {}\n\nfn   second()  {}\n")
+        .await;
+
+    // A range inside the line grows to the whole line, and the first function is untouched
+    let response = client
+        .request(
+            "textDocument/rangeFormatting",
+            json!({
+                "textDocument": { "uri": url(&path) },
+                "range": {
+                    "start": { "line": 2, "character": 5 },
+                    "end": { "line": 2, "character": 9 }
+                },
+                "options": { "tabSize": 4, "insertSpaces": true }
+            }),
+        )
+        .await;
+    assert_eq!(
+        response["result"],
+        json!([{
+            "range": {
+                "start": { "line": 2, "character": 0 },
+                "end": { "line": 2, "character": 17 }
+            },
+            "newText": "fn second() {}"
+        }]),
+        "{}",
+        response
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn will_save_trims_trailing_whitespace() {
+    let dirs = TestHome::new("will-save");
+    let worktree = dirs.worktree();
+    let path = worktree.join("main.rs");
+    let mut client = LspClient::start(worktree).await;
+    client
+        .open(&path, "rust", "fn main() {  \n    let x = 1;\t\n}\n")
+        .await;
+
+    let response = client
+        .request(
+            "textDocument/willSaveWaitUntil",
+            json!({ "textDocument": { "uri": url(&path) }, "reason": 1 }),
+        )
+        .await;
+    let edit = |line: u64, start: u64, end: u64| {
+        json!({
+            "range": {
+                "start": { "line": line, "character": start },
+                "end": { "line": line, "character": end }
+            },
+            "newText": ""
+        })
+    };
+    assert_eq!(
+        response["result"],
+        json!([edit(0, 11, 13), edit(1, 14, 15)]),
+        "{}",
+        response
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn startup_indexing_finds_symbols_in_unopened_files() {
+    let dirs = TestHome::new("startup-index");
+    let worktree = dirs.worktree();
+    std::fs::create_dir_all(worktree.join("src")).unwrap();
+    std::fs::create_dir_all(worktree.join("ignored")).unwrap();
+    std::fs::write(worktree.join(".gitignore"), "ignored/\n").unwrap();
+    std::fs::write(
+        worktree.join("src/deep.rs"),
+        "pub fn startup_only_symbol() {}\n",
+    )
+    .unwrap();
+    std::fs::write(worktree.join("ignored/skip.rs"), "fn ignored_symbol() {}\n").unwrap();
+    let main = worktree.join("main.rs");
+    std::fs::write(
+        &main,
+        "fn main() {\n    startup_only_symbol();\n    ignored_symbol();\n}\n",
+    )
+    .unwrap();
+
+    let mut client = LspClient::start(worktree).await;
+    client
+        .server_request("window/workDoneProgress/create")
+        .await;
+    loop {
+        let progress = client.notification("$/progress").await;
+        if progress["params"]["value"]["kind"] == "end" {
+            break;
+        }
+    }
+
+    // Hover reads the index without rescanning, so only the startup pass can fill it
+    let hover = |line: u64| {
+        json!({
+            "textDocument": { "uri": url(&main) },
+            "position": { "line": line, "character": 8 }
+        })
+    };
+    let found = client.request("textDocument/hover", hover(1)).await;
+    let contents = found["result"]["contents"]["value"]
+        .as_str()
+        .unwrap_or_default();
+    assert!(contents.contains("deep.rs"), "{}", found);
+
+    let ignored = client.request("textDocument/hover", hover(2)).await;
+    assert_eq!(ignored["result"], Value::Null, "{}", ignored);
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn closed_stdin_exits_with_an_error() {
+    let run = tokio::process::Command::new(env!("CARGO_BIN_EXE_claude-code-server"))
+        .arg("lsp")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(std::time::Duration::from_secs(10), run)
+        .await
+        .expect("server kept waiting on a closed stdin")
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("must be started by an editor"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn open_file_passes_its_flags_to_the_editor() {
+    let dirs = TestHome::new("open-file");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+    let path = worktree.join("main.rs");
+    std::fs::write(&path, "fn main() {}\n").unwrap();
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.initialize().await;
+
+    let opened = websocket
+        .call_tool(
+            "openFile",
+            json!({ "filePath": "main.rs", "preview": true, "makeFrontmost": false }),
+        )
+        .await;
+    assert_eq!(opened["success"], true, "{}", opened);
+
+    let notification = lsp.notification("open_file").await;
+    assert_eq!(
+        notification["params"],
+        json!({
+            "filePath": path.canonicalize().unwrap(),
+            "preview": true,
+            "makeFrontmost": false
+        })
+    );
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn set_clipboard_asks_the_editor_to_write_the_text() {
+    let dirs = TestHome::new("set-clipboard");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.initialize().await;
+
+    let written = websocket
+        .request("set_clipboard", json!({ "text": "copied\ntext" }))
+        .await;
+    assert_eq!(written["result"]["success"], true, "{}", written);
+
+    let notification = lsp.notification("clipboard_write").await;
+    assert_eq!(notification["params"], json!({ "text": "copied\ntext" }));
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn diagnostics_for_selection_keep_only_overlapping_ones() {
+    let dirs = TestHome::new("diagnostics-for-selection");
+    let (home, worktree) = (dirs.home(), dirs.worktree());
+    let path = worktree.join("lines.txt");
+    let text: String = (0..10).map(|i| format!("line {}\n", i)).collect();
+    std::fs::write(&path, text).unwrap();
+    let diagnostic = |line: u64, message: &str| {
+        json!({
+            "range": {
+                "start": { "line": line, "character": 0 },
+                "end": { "line": line, "character": 4 }
+            },
+            "severity": 1,
+            "message": message
+        })
+    };
+
+    let mut lsp = LspClient::start_hybrid(worktree, home).await;
+    let mut websocket = common::websocket::connect_with_lock_file(home).await;
+    websocket.initialize().await;
+
+    lsp.request(
+        "textDocument/codeAction",
+        json!({
+            "textDocument": { "uri": url(&path) },
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 9, "character": 6 }
+            },
+            "context": {
+                "diagnostics": [
+                    diagnostic(1, "first"),
+                    diagnostic(5, "second"),
+                    diagnostic(8, "third")
+                ]
+            }
+        }),
+    )
+    .await;
+    websocket.notification("selection_changed").await;
+
+    let scoped = websocket
+        .call_tool(
+            "diagnosticsForSelection",
+            json!({
+                "path": "lines.txt",
+                "range": {
+                    "start": { "line": 4, "character": 0 },
+                    "end": { "line": 6, "character": 0 }
+                }
+            }),
+        )
+        .await;
+    let messages: Vec<&str> = scoped["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(messages, ["second"], "{}", scoped);
+
+    // Without a range the selection, which spans the whole file, is used
+    let selected = websocket
+        .call_tool("diagnosticsForSelection", json!({ "path": "lines.txt" }))
+        .await;
+    assert_eq!(
+        selected["diagnostics"].as_array().unwrap().len(),
+        3,
+        "{}",
+        selected
+    );
+
+    websocket.close().await;
+    lsp.shutdown().await;
+}
+
+#[tokio::test]
+async fn inlay_hints_mark_each_function() {
+    let dirs = TestHome::new("inlay");
+    let worktree = dirs.worktree();
+    let path = worktree.join("lib.rs");
+    std::fs::write(
+        &path,
+        "fn first() {}\n\nimpl Thing {\n    fn second(&self) {}\n}\n\nstruct Thing;\n",
+    )
+    .unwrap();
+    let params = json!({
+        "textDocument": { "uri": url(&path) },
+        "range": {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 10, "character": 0 }
+        }
+    });
+
+    let mut client = LspClient::start(worktree).await;
+    let response = client
+        .request("textDocument/inlayHint", params.clone())
+        .await;
+    let hints = response["result"].as_array().expect("inlay hints");
+    let positions: Vec<_> = hints.iter().map(|hint| hint["position"].clone()).collect();
+    assert_eq!(
+        positions,
+        vec![
+            json!({ "line": 0, "character": 0 }),
+            json!({ "line": 3, "character": 4 })
+        ],
+        "{}",
+        response
+    );
+    assert_eq!(
+        hints[1]["label"][0]["command"]["command"], "claude-code.explain",
+        "{}",
+        response
+    );
+    client.shutdown().await;
+
+    let mut client = LspClient::start_with_options(worktree, json!({ "inlayHints": false })).await;
+    let disabled = client.request("textDocument/inlayHint", params).await;
+    assert_eq!(disabled["result"], Value::Null, "{}", disabled);
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn rapid_changes_reindex_symbols_once_after_a_pause() {
+    let dirs = TestHome::new("debounced-reindex");
+    let worktree = dirs.worktree();
+    let path = worktree.join("lib.rs");
+    let mut client = LspClient::start(worktree).await;
+    client
+        .notify("$/setTrace", json!({ "value": "verbose" }))
+        .await;
+    client.open(&path, "rust", "fn typed_0() {}\n").await;
+    let reindexes = |log: String| log.matches("Reindexing symbols for").count();
+    let typed = json!({ "query": "typed_" });
+
+    for version in 1..=10 {
+        client
+            .notify(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": url(&path), "version": version },
+                    "contentChanges": [{ "text": format!("fn typed_{}() {{}}\n", version) }]
+                }),
+            )
+            .await;
+    }
+    let response = client.request("workspace/symbol", typed.clone()).await;
+    assert_eq!(symbol_names(&response["result"]), ["typed_0"]);
+
+    tokio::time::sleep(std::time::Duration::from_millis(800)).await;
+    let response = client.request("workspace/symbol", typed).await;
+    assert_eq!(symbol_names(&response["result"]), ["typed_10"]);
+    assert_eq!(reindexes(client.log()), 1, "{}", client.log());
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn code_lenses_sit_above_top_level_symbols() {
+    let dirs = TestHome::new("code-lens");
+    let worktree = dirs.worktree();
+    let path = worktree.join("lib.rs");
+    let text = "fn first() {}\n\npub fn second(x: u8) -> u8 {\n    x\n}\n\nstruct Point;\n\nimpl Point {\n    fn method(&self) {}\n}\n";
+    let mut client = LspClient::start(worktree).await;
+    client.open(&path, "rust", text).await;
+
+    let response = client
+        .request(
+            "textDocument/codeLens",
+            json!({ "textDocument": { "uri": url(&path) } }),
+        )
+        .await;
+    let lenses: Vec<(&str, &str, Value)> = response["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|lens| {
+            let command = &lens["command"];
+            assert_eq!(command["arguments"][0]["range"], lens["range"]);
+            (
+                command["command"].as_str().unwrap(),
+                command["arguments"][0]["name"].as_str().unwrap(),
+                lens["range"].clone(),
+            )
+        })
+        .collect();
+    let range = |line: u32, start: u32, end: u32| {
+        json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end }
+        })
+    };
+
+    // The indented method gets no lens of its own
+    assert_eq!(
+        lenses,
+        [
+            ("claude-code.explain", "first", range(0, 3, 8)),
+            ("claude-code.explain", "second", range(2, 7, 13)),
+            ("claude-code.ask", "Point", range(6, 7, 12)),
+        ]
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn set_trace_verbose_raises_the_log_level() {
+    let dirs = TestHome::new("set-trace");
+    let worktree = dirs.worktree();
+    let mut client = LspClient::start(worktree).await;
+    let traced = |log: String| log.matches(" TRACE ").count();
+    let symbols = json!({ "query": "anything" });
+
+    client.request("workspace/symbol", symbols.clone()).await;
+    assert_eq!(traced(client.log()), 0, "{}", client.log());
+
+    // Notifications are handled in order, so the request after one sees its effect
+    client
+        .notify("$/setTrace", json!({ "value": "verbose" }))
+        .await;
+    client.request("workspace/symbol", symbols.clone()).await;
+    assert!(traced(client.log()) > 0, "{}", client.log());
+
+    client.notify("$/setTrace", json!({ "value": "off" })).await;
+    client.request("workspace/symbol", symbols.clone()).await;
+    let before = traced(client.log());
+    client.request("workspace/symbol", symbols).await;
+    assert_eq!(traced(client.log()), before, "{}", client.log());
+
+    client.shutdown().await;
+}